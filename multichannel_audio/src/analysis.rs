@@ -0,0 +1,532 @@
+use std::f64::consts::PI;
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use crate::sample_formats::Sample;
+use crate::time_align::AlignedPair;
+
+/// The transfer function between a stimulus and a response, from 0 Hz to the Nyquist frequency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferFunction {
+    /// The frequency of each bin in Hz
+    pub frequencies: Vec<f64>,
+    /// The gain of each bin as a ratio
+    pub magnitude: Vec<f64>,
+    /// The phase of each bin in radians, from -pi to pi
+    pub phase: Vec<f64>,
+    /// The magnitude-squared coherence of each bin, from 0 to 1. Values well below 1 mean the
+    /// response at that frequency is dominated by noise or is not linear.
+    pub coherence: Vec<f64>,
+}
+
+impl TransferFunction {
+    /// The gain of each bin in dB.
+    pub fn magnitude_db(&self) -> Vec<f64> {
+        self.magnitude.iter().map(|m| 20.0 * m.log10()).collect()
+    }
+}
+
+/// Estimate the transfer function H(f) from a stimulus to a response with Welch's method.
+///
+/// The signals are split into Hann-windowed segments with 50% overlap and the H1 estimate
+/// (the averaged cross spectrum divided by the averaged stimulus spectrum) is returned. Longer
+/// segments give a finer frequency resolution, shorter segments average out more noise.
+/// The stimulus must be broadband, e.g. white noise, and both signals must be on the same time
+/// base, as in an `AlignedPair`.
+///
+/// # Arguments
+/// stimulus: &[i32] - the played signal
+/// response: &[i32] - the recorded signal
+/// fs: u32 - the sample rate of the signals
+/// segment_length: usize - the number of samples per segment, which sets the number of bins
+///
+/// # Errors
+/// Returns an error if the signals have different lengths
+/// Returns an error if the signals are shorter than one segment
+pub fn transfer_function(
+    stimulus: &[i32],
+    response: &[i32],
+    fs: u32,
+    segment_length: usize,
+) -> Result<TransferFunction, anyhow::Error> {
+    if stimulus.len() != response.len() {
+        return Err(anyhow::anyhow!(
+            "The stimulus is {} samples long but the response is {}",
+            stimulus.len(),
+            response.len()
+        ));
+    }
+    if segment_length < 2 || stimulus.len() < segment_length {
+        return Err(anyhow::anyhow!(
+            "Segments of {} samples don't fit in a signal of {} samples",
+            segment_length,
+            stimulus.len()
+        ));
+    }
+
+    let fft = FftPlanner::new().plan_fft_forward(segment_length);
+    let window = hann_window(segment_length);
+    let bins = segment_length / 2 + 1;
+    let hop = segment_length / 2;
+
+    let mut stimulus_power = vec![0.0; bins];
+    let mut response_power = vec![0.0; bins];
+    let mut cross_spectrum = vec![Complex::new(0.0, 0.0); bins];
+
+    let mut start = 0;
+    while start + segment_length <= stimulus.len() {
+        let windowed = |signal: &[i32]| -> Vec<Complex<f64>> {
+            signal[start..start + segment_length]
+                .iter()
+                .zip(&window)
+                .map(|(&sample, w)| Complex::new(sample as f64 / i32::MAX as f64 * w, 0.0))
+                .collect()
+        };
+        let mut x = windowed(stimulus);
+        let mut y = windowed(response);
+        fft.process(&mut x);
+        fft.process(&mut y);
+
+        for bin in 0..bins {
+            stimulus_power[bin] += x[bin].norm_sqr();
+            response_power[bin] += y[bin].norm_sqr();
+            cross_spectrum[bin] += x[bin].conj() * y[bin];
+        }
+        start += hop;
+    }
+
+    let mut transfer_function = TransferFunction {
+        frequencies: (0..bins)
+            .map(|bin| bin as f64 * fs as f64 / segment_length as f64)
+            .collect(),
+        magnitude: Vec::with_capacity(bins),
+        phase: Vec::with_capacity(bins),
+        coherence: Vec::with_capacity(bins),
+    };
+    for bin in 0..bins {
+        // bins the stimulus has no energy in have no defined gain
+        let h = if stimulus_power[bin] > 0.0 {
+            cross_spectrum[bin] / stimulus_power[bin]
+        } else {
+            Complex::new(0.0, 0.0)
+        };
+        let power = stimulus_power[bin] * response_power[bin];
+        transfer_function.magnitude.push(h.norm());
+        transfer_function.phase.push(h.arg());
+        transfer_function.coherence.push(if power > 0.0 {
+            cross_spectrum[bin].norm_sqr() / power
+        } else {
+            0.0
+        });
+    }
+
+    Ok(transfer_function)
+}
+
+impl AlignedPair {
+    /// Estimate the transfer function from the stimulus to every response channel.
+    ///
+    /// See `transfer_function` for details.
+    ///
+    /// # Returns
+    /// One transfer function per response channel
+    pub fn transfer_functions(
+        &self,
+        fs: u32,
+        segment_length: usize,
+    ) -> Result<Vec<TransferFunction>, anyhow::Error> {
+        self.response
+            .iter()
+            .map(|response| transfer_function(&self.stimulus, response, fs, segment_length))
+            .collect()
+    }
+}
+
+/// The window applied to each segment before its FFT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Window {
+    /// No window, for signals that are periodic in the segment
+    Rectangular,
+    /// A good default, with low leakage far from a tone
+    #[default]
+    Hann,
+    Hamming,
+    /// A four term window with very low leakage, for measuring small tones next to large ones
+    BlackmanHarris,
+}
+
+impl Window {
+    /// The periodic window of a segment.
+    pub fn coefficients(self, length: usize) -> Vec<f64> {
+        match self {
+            Window::Rectangular => vec![1.0; length],
+            Window::Hann => hann_window(length),
+            Window::Hamming => cosine_window(&[0.54, 0.46], length),
+            Window::BlackmanHarris => cosine_window(&[0.35875, 0.48829, 0.14128, 0.01168], length),
+        }
+    }
+}
+
+/// The FFT of a whole signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    /// The frequency of each bin in Hz, from 0 Hz to the Nyquist frequency
+    pub frequencies: Vec<f64>,
+    /// The complex value of each bin, scaled so a full-scale sine in the middle of a bin has a
+    /// magnitude of 1.0
+    pub bins: Vec<Complex<f64>>,
+}
+
+impl Spectrum {
+    /// The amplitude of each bin, where 1.0 is full scale.
+    pub fn magnitude(&self) -> Vec<f64> {
+        self.bins.iter().map(|bin| bin.norm()).collect()
+    }
+
+    /// The amplitude of each bin in dBFS.
+    pub fn magnitude_db(&self) -> Vec<f64> {
+        self.bins
+            .iter()
+            .map(|bin| 20.0 * bin.norm().log10())
+            .collect()
+    }
+
+    /// The phase of each bin in radians, from -pi to pi.
+    pub fn phase(&self) -> Vec<f64> {
+        self.bins.iter().map(|bin| bin.arg()).collect()
+    }
+}
+
+/// A power spectral density averaged over the segments of a signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerSpectrum {
+    /// The frequency of each bin in Hz, from 0 Hz to the Nyquist frequency
+    pub frequencies: Vec<f64>,
+    /// The one-sided power spectral density of each bin, in full scale squared per Hz
+    pub power: Vec<f64>,
+}
+
+impl PowerSpectrum {
+    /// The power spectral density of each bin in dBFS/Hz.
+    pub fn power_db(&self) -> Vec<f64> {
+        self.power.iter().map(|p| 10.0 * p.log10()).collect()
+    }
+}
+
+/// The power spectral density of a signal over time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrogram {
+    /// The frequency of each bin in Hz, from 0 Hz to the Nyquist frequency
+    pub frequencies: Vec<f64>,
+    /// The time of the middle of each segment in seconds
+    pub times: Vec<f64>,
+    /// The one-sided power spectral density of each bin of each segment, in full scale squared
+    /// per Hz. The outer vector is the segments and the inner vector is the bins.
+    pub power: Vec<Vec<f64>>,
+}
+
+/// The windowed FFT of a whole signal.
+///
+/// The signal can be any sample type, e.g. a recorded channel or f32 samples from -1.0 to 1.0.
+///
+/// # Arguments
+/// signal: &[T] - the samples to analyse
+/// fs: u32 - the sample rate of the signal
+/// window: Window - the window applied before the FFT
+pub fn fft<T: Sample>(signal: &[T], fs: u32, window: Window) -> Spectrum {
+    let length = signal.len();
+    if length == 0 {
+        return Spectrum {
+            frequencies: Vec::new(),
+            bins: Vec::new(),
+        };
+    }
+    let coefficients = window.coefficients(length);
+    let mut values = windowed_segment(signal, &coefficients);
+    FftPlanner::new()
+        .plan_fft_forward(length)
+        .process(&mut values);
+
+    // a sine of amplitude A is split between two bins of A * sum(w) / 2 each
+    let window_sum: f64 = coefficients.iter().sum();
+    let bins = length / 2 + 1;
+    let values = values
+        .into_iter()
+        .take(bins)
+        .enumerate()
+        .map(|(bin, value)| {
+            let one_sided = if bin == 0 || 2 * bin == length {
+                1.0
+            } else {
+                2.0
+            };
+            value * one_sided / window_sum
+        })
+        .collect();
+
+    Spectrum {
+        frequencies: bin_frequencies(bins, length, fs),
+        bins: values,
+    }
+}
+
+/// Estimate the power spectral density of a signal with Welch's method.
+///
+/// The signal is split into windowed segments with 50% overlap and the power spectra of the
+/// segments are averaged. Longer segments give a finer frequency resolution, shorter segments
+/// average out more noise.
+///
+/// # Arguments
+/// signal: &[T] - the samples to analyse
+/// fs: u32 - the sample rate of the signal
+/// segment_length: usize - the number of samples per segment, which sets the number of bins
+/// window: Window - the window applied to each segment
+///
+/// # Errors
+/// Returns an error if the signal is shorter than one segment
+pub fn power_spectrum<T: Sample>(
+    signal: &[T],
+    fs: u32,
+    segment_length: usize,
+    window: Window,
+) -> Result<PowerSpectrum, anyhow::Error> {
+    let spectrogram = spectrogram(signal, fs, segment_length, segment_length / 2, window)?;
+    let segments = spectrogram.power.len() as f64;
+    let mut power = vec![0.0; spectrogram.frequencies.len()];
+    for segment in spectrogram.power.iter() {
+        for (total, p) in power.iter_mut().zip(segment) {
+            *total += p / segments;
+        }
+    }
+
+    Ok(PowerSpectrum {
+        frequencies: spectrogram.frequencies,
+        power,
+    })
+}
+
+/// Estimate the power spectral density of every channel of a recording. See `power_spectrum`.
+///
+/// # Returns
+/// One power spectrum per channel
+pub fn power_spectra<T: Sample>(
+    recording: &[Vec<T>],
+    fs: u32,
+    segment_length: usize,
+    window: Window,
+) -> Result<Vec<PowerSpectrum>, anyhow::Error> {
+    recording
+        .iter()
+        .map(|channel| power_spectrum(channel, fs, segment_length, window))
+        .collect()
+}
+
+/// The power spectral density of each segment of a signal.
+///
+/// # Arguments
+/// signal: &[T] - the samples to analyse
+/// fs: u32 - the sample rate of the signal
+/// segment_length: usize - the number of samples per segment, which sets the number of bins
+/// hop: usize - the number of samples between the starts of segments
+/// window: Window - the window applied to each segment
+///
+/// # Errors
+/// Returns an error if the signal is shorter than one segment or the hop is 0
+pub fn spectrogram<T: Sample>(
+    signal: &[T],
+    fs: u32,
+    segment_length: usize,
+    hop: usize,
+    window: Window,
+) -> Result<Spectrogram, anyhow::Error> {
+    if segment_length < 2 || signal.len() < segment_length {
+        return Err(anyhow::anyhow!(
+            "Segments of {} samples don't fit in a signal of {} samples",
+            segment_length,
+            signal.len()
+        ));
+    }
+    if hop == 0 {
+        return Err(anyhow::Error::msg("The hop must be at least 1 sample"));
+    }
+
+    let fft = FftPlanner::new().plan_fft_forward(segment_length);
+    let coefficients = window.coefficients(segment_length);
+    let bins = segment_length / 2 + 1;
+    // scale to a density, so the result doesn't depend on the window or segment length
+    let scale = 1.0 / (fs as f64 * coefficients.iter().map(|w| w * w).sum::<f64>());
+
+    let mut times = Vec::new();
+    let mut power = Vec::new();
+    let mut start = 0;
+    while start + segment_length <= signal.len() {
+        let mut values = windowed_segment(&signal[start..start + segment_length], &coefficients);
+        fft.process(&mut values);
+        power.push(
+            values
+                .iter()
+                .take(bins)
+                .enumerate()
+                .map(|(bin, value)| {
+                    let one_sided = if bin == 0 || 2 * bin == segment_length {
+                        1.0
+                    } else {
+                        2.0
+                    };
+                    value.norm_sqr() * scale * one_sided
+                })
+                .collect(),
+        );
+        times.push((start as f64 + segment_length as f64 / 2.0) / fs as f64);
+        start += hop;
+    }
+
+    Ok(Spectrogram {
+        frequencies: bin_frequencies(bins, segment_length, fs),
+        times,
+        power,
+    })
+}
+
+/// A periodic Hann window.
+fn hann_window(length: usize) -> Vec<f64> {
+    (0..length)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / length as f64).cos())
+        .collect()
+}
+
+/// A periodic window made of a sum of cosines with alternating signs.
+fn cosine_window(terms: &[f64], length: usize) -> Vec<f64> {
+    (0..length)
+        .map(|n| {
+            terms
+                .iter()
+                .enumerate()
+                .map(|(k, a)| {
+                    let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                    sign * a * (2.0 * PI * (k * n) as f64 / length as f64).cos()
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Apply a window to a segment, scaling the samples so full scale is 1.0.
+fn windowed_segment<T: Sample>(segment: &[T], window: &[f64]) -> Vec<Complex<f64>> {
+    segment
+        .iter()
+        .zip(window)
+        .map(|(&sample, w)| Complex::new(f64::from_i32(sample.to_i32()) * w, 0.0))
+        .collect()
+}
+
+/// The frequency of each bin of an FFT of `length` samples.
+fn bin_frequencies(bins: usize, length: usize, fs: u32) -> Vec<f64> {
+    (0..bins)
+        .map(|bin| bin as f64 * fs as f64 / length as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods::generate_gaussian_white_noise;
+
+    #[test]
+    fn test_transfer_function_of_gain_and_delay() {
+        let stimulus = generate_gaussian_white_noise(2.0, 48000, None);
+        let delay = 3;
+        let response: Vec<i32> = std::iter::repeat(0)
+            .take(delay)
+            .chain(stimulus.iter().map(|&sample| sample / 2))
+            .take(stimulus.len())
+            .collect();
+
+        let segment_length = 1024;
+        let tf = transfer_function(&stimulus, &response, 48000, segment_length).unwrap();
+
+        assert_eq!(tf.frequencies.len(), 513);
+        assert_eq!(tf.frequencies[512], 24000.0);
+        let bin = 64;
+        assert!((tf.magnitude[bin] - 0.5).abs() < 0.02);
+        assert!((tf.magnitude_db()[bin] + 6.02).abs() < 0.5);
+        let expected_phase = -2.0 * PI * bin as f64 * delay as f64 / segment_length as f64;
+        assert!((tf.phase[bin] - expected_phase).abs() < 0.05);
+        assert!(tf.coherence[bin] > 0.95);
+    }
+
+    #[test]
+    fn test_transfer_function_errors() {
+        assert!(transfer_function(&[0; 100], &[0; 99], 48000, 64).is_err());
+        assert!(transfer_function(&[0; 100], &[0; 100], 48000, 128).is_err());
+    }
+
+    #[test]
+    fn test_fft_of_sine() {
+        let fs = 48000;
+        // 1 kHz is in the middle of bin 64 of a 3072 sample FFT
+        let signal: Vec<f64> = (0..3072)
+            .map(|n| 0.5 * (2.0 * PI * 1000.0 * n as f64 / fs as f64).sin())
+            .collect();
+
+        let spectrum = fft(&signal, fs, Window::Rectangular);
+        assert_eq!(spectrum.frequencies.len(), 1537);
+        assert_eq!(spectrum.frequencies[64], 1000.0);
+        assert!((spectrum.magnitude()[64] - 0.5).abs() < 1e-6);
+        assert!(spectrum.magnitude()[100] < 1e-6);
+
+        let spectrum = fft(&signal, fs, Window::BlackmanHarris);
+        assert!((spectrum.magnitude_db()[64] + 6.02).abs() < 0.01);
+        assert!(spectrum.magnitude_db()[80] < -90.0);
+    }
+
+    #[test]
+    fn test_power_spectrum_of_white_noise() {
+        let fs = 48000;
+        let noise: Vec<f32> = generate_gaussian_white_noise(4.0, fs, None)
+            .into_iter()
+            .map(f32::from_i32)
+            .collect();
+        let variance: f64 =
+            noise.iter().map(|&x| (x as f64).powi(2)).sum::<f64>() / noise.len() as f64;
+
+        let spectra = power_spectra(&[noise.clone(), noise], fs, 1024, Window::Hann).unwrap();
+        assert_eq!(spectra.len(), 2);
+        // white noise is flat, and the density integrates to the variance
+        let expected = variance / (fs as f64 / 2.0);
+        let average: f64 = spectra[0].power[10..500].iter().sum::<f64>() / 490.0;
+        assert!((average / expected - 1.0).abs() < 0.05);
+        assert!(power_spectrum(&[0i32; 100], fs, 128, Window::Hann).is_err());
+    }
+
+    #[test]
+    fn test_spectrogram_follows_a_tone() {
+        let fs = 8000;
+        let mut signal: Vec<i32> = vec![0; 4000];
+        signal.extend((0..4000).map(|n| {
+            (0.5 * (2.0 * PI * 1000.0 * n as f64 / fs as f64).sin() * i32::MAX as f64) as i32
+        }));
+
+        let result = spectrogram(&signal, fs, 256, 256, Window::Hann).unwrap();
+        assert_eq!(result.power.len(), 31);
+        assert_eq!(result.times[0], 128.0 / 8000.0);
+        // 1 kHz is bin 32
+        assert_eq!(result.power[0][32], 0.0);
+        assert!(result.power[30][32] > 1e-3);
+        assert!(spectrogram(&signal, fs, 256, 0, Window::Hann).is_err());
+    }
+
+    #[test]
+    fn test_aligned_pair_transfer_functions() {
+        let stimulus = generate_gaussian_white_noise(1.0, 48000, None);
+        let pair = AlignedPair {
+            response: vec![stimulus.clone(), vec![0; stimulus.len()]],
+            stimulus,
+        };
+
+        let tfs = pair.transfer_functions(48000, 256).unwrap();
+        assert_eq!(tfs.len(), 2);
+        assert!((tfs[0].magnitude[20] - 1.0).abs() < 1e-6);
+        assert_eq!(tfs[1].magnitude[20], 0.0);
+    }
+}
@@ -1,11 +1,111 @@
-use crate::{methods::set_host_and_audio_device, stream_controller::StreamController};
-
-use super::methods::{DEVICE_NAME, HOST};
-use anyhow::Ok;
+use crate::{
+    backend::{AudioBackend, MockBackend, MockDevice, StreamState},
+    builder::AudioInstanceBuilder,
+    callback_load::{CallbackMonitor, StreamDirection},
+    channel::{
+        ChannelIndex, ChannelLabels, InputChannel, InputSelector, OutputChannel, OutputSelector,
+    },
+    device_lock::DeviceLock,
+    device_monitor::{disconnected_error, DeviceMonitor},
+    events::EventHub,
+    fades::Fades,
+    frame_queue::FrameQueueSlot,
+    input_processing::InputChainLane,
+    interlock::{self, Interlock},
+    latency::LatencyInfo,
+    limiter::LimiterLane,
+    methods::{format_signals_for_multichannel, interleave_on_channels, set_host_and_audio_device},
+    mixer::MixerLane,
+    output_processing::OutputProcessors,
+    passthrough::PassthroughLane,
+    pre_record::PreRecordBuffer,
+    progress::ProgressMonitor,
+    signal::Signal,
+    silence_watchdog::SilenceMonitor,
+    stream_controller::{
+        BackgroundLane, CaptureSink, InputCapture, PlayGate, PlaybackSchedule, StreamCommand,
+        StreamController, StreamType, HEALTH_CHECK_INTERVAL,
+    },
+    time_align::AlignmentConfig,
+    timestamp_map::BufferTimestamp,
+    trigger::LevelTrigger,
+};
+
+use super::methods::{DEVICE_NAME, HOST, INPUT_DEVICE_NAME};
 use cpal::traits::{DeviceTrait, HostTrait};
-use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The smallest buffer the input callback is expected to get, for reserving room for the
+/// timestamp of every buffer of a recording before the buffer size is known.
+const MIN_CALLBACK_FRAMES: usize = 32;
+
+/// The buffer size to request from the audio driver.
+///
+/// Smaller buffers give lower latency but are more likely to glitch.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BufferSize {
+    /// Use the buffer size set in the driver
+    #[default]
+    Default,
+    /// Request a fixed number of frames per callback
+    Fixed(u32),
+}
+
+impl From<BufferSize> for cpal::BufferSize {
+    fn from(buffer_size: BufferSize) -> Self {
+        match buffer_size {
+            BufferSize::Default => cpal::BufferSize::Default,
+            BufferSize::Fixed(frames) => cpal::BufferSize::Fixed(frames),
+        }
+    }
+}
+
+/// The number of channels to open a stream with.
+///
+/// Drivers often default to fewer channels than the device has, e.g. 2 for an ASIO interface with
+/// 18 inputs, so the full count has to be requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelCount {
+    /// Use the channel count of the default configuration of the device
+    #[default]
+    Default,
+    /// Use the most channels the device supports at the sample rate
+    Maximum,
+    /// Request an exact number of channels
+    Exact(u16),
+}
+
+impl From<u16> for ChannelCount {
+    fn from(channels: u16) -> Self {
+        ChannelCount::Exact(channels)
+    }
+}
+
+/// The configuration an audio instance is running with, from `AudioInstance::config`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceConfig {
+    /// The sample rate of the streams in Hz
+    pub sample_rate: u32,
+    /// The number of input channels of the input device
+    pub channels_in: u16,
+    /// The number of output channels of the output device
+    pub channels_out: u16,
+    /// The sample format of the output stream
+    pub sample_format: cpal::SampleFormat,
+    /// The sample format of the input stream
+    pub input_sample_format: cpal::SampleFormat,
+    /// The frames per callback granted by the driver, or None before the first callback
+    pub buffer_size: Option<usize>,
+    /// Whether input and output share a single stream controller
+    pub duplex: bool,
+    /// The name of the output device and, if it is different, the input device
+    pub device_names: Vec<String>,
+}
 
-enum StreamControllerType {
+pub(super) enum StreamControllerType {
     Input,
     Output,
 }
@@ -13,15 +113,52 @@ enum StreamControllerType {
 #[derive(Clone)]
 /// Audio class for handling audio input and output
 pub struct AudioInstance {
-    input_buffer: Arc<Mutex<Vec<i32>>>,
-    output_buffer: Arc<Mutex<Vec<i32>>>,
-    input_stream_controller: Option<StreamController>,
-    output_stream_controller: Option<StreamController>,
-    play_wait_pair: Arc<(Mutex<bool>, std::sync::Condvar)>,
+    pub(super) input_buffer: Arc<Mutex<InputCapture>>,
+    pub(super) output_buffer: Arc<Mutex<Signal>>,
+    input_stream_controller: Option<Arc<dyn AudioBackend>>,
+    output_stream_controller: Option<Arc<dyn AudioBackend>>,
+    pub(super) play_gate: Arc<PlayGate>,
     pub(super) sample_rate: u32,
-    record_wait_pair: Arc<(Mutex<bool>, std::sync::Condvar)>,
-    number_of_output_channels: u16,
-    number_of_input_channels: u16,
+    pub(super) record_wait_pair: Arc<(Mutex<bool>, std::sync::Condvar)>,
+    pub(super) number_of_output_channels: u16,
+    pub(super) number_of_input_channels: u16,
+    output_format: cpal::SampleFormat,
+    input_format: cpal::SampleFormat,
+    pub(super) duplex: bool,
+    pub(super) latency: Arc<Mutex<Option<LatencyInfo>>>,
+    buffer_frames: Arc<AtomicUsize>,
+    enabled_input_channels: Arc<Mutex<Vec<usize>>>,
+    interlock: Arc<Mutex<Interlock>>,
+    pub(super) loop_state: Arc<AtomicU8>,
+    pub(super) output_queue: Arc<Mutex<VecDeque<Signal>>>,
+    pub(super) schedule: Arc<PlaybackSchedule>,
+    pub(super) capture_timestamps: Arc<Mutex<Vec<BufferTimestamp>>>,
+    pub(super) capture_sink: Arc<Mutex<Option<CaptureSink>>>,
+    pub(super) background: Arc<BackgroundLane>,
+    pub(super) output_dither: Arc<AtomicU8>,
+    pub(super) limiter: Arc<LimiterLane>,
+    pub(super) mixer: Arc<MixerLane>,
+    pub(super) passthrough: Arc<PassthroughLane>,
+    pub(super) output_processors: Arc<Mutex<OutputProcessors>>,
+    pub(super) input_chain: Arc<InputChainLane>,
+    pub(super) input_tap: Arc<FrameQueueSlot>,
+    pub(super) frame_queue: Arc<FrameQueueSlot>,
+    pub(super) pre_record: Arc<PreRecordBuffer>,
+    pub(super) trigger: Arc<Mutex<Option<LevelTrigger>>>,
+    pub(super) fades: Arc<Mutex<Option<Fades>>>,
+    pub(super) alignment: Arc<Mutex<AlignmentConfig>>,
+    // held so no other process can use the devices while this instance exists
+    _device_locks: Arc<Vec<DeviceLock>>,
+    pub(super) callback_monitor: Arc<CallbackMonitor>,
+    pub(super) silence_monitor: Arc<SilenceMonitor>,
+    pub(super) progress_monitor: Arc<ProgressMonitor>,
+    pub(super) events: Arc<EventHub>,
+    pub(super) healthy: Arc<AtomicBool>,
+    /// The names of the output device and, if it is different, the input device
+    pub(super) device_names: Vec<String>,
+    pub(super) device_monitor: Arc<Mutex<Option<DeviceMonitor>>>,
+    /// The configuration the instance was built with, used to rebuild the streams
+    config: AudioInstanceBuilder,
 }
 
 // TODO: figure out how to wrap streams in a struct to safely implement Send for AudioInstance
@@ -38,79 +175,793 @@ impl AudioInstance {
     /// Returns an error if the audio instance already exists
     /// Returns an error if the host has not been initialized
     /// Returns an error if the device is not found
+    /// Returns `DeviceLockError::DeviceLockedByOtherProcess` if another process is using the device
     pub fn new(fs: u32) -> Result<Self, anyhow::Error> {
-        // audio overhead - set up the audio device
-        let mut device_name = DEVICE_NAME.lock().unwrap().clone();
-        let mut binding = HOST.lock().unwrap();
-        if binding.is_none() || device_name.is_empty() {
-            set_host_and_audio_device()?;
+        AudioInstanceBuilder::new().sample_rate(fs).build()
+    }
 
-            device_name = DEVICE_NAME.lock().unwrap().clone();
-            binding = HOST.lock().unwrap();
-        }
+    /// Create a new audio instance with a specific buffer size.
+    ///
+    /// The buffer size actually granted by the driver is available from `buffer_size` once the
+    /// output stream is running.
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate of the audio device
+    /// buffer_size: BufferSize - the number of frames per callback to request
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    /// Returns an error if the device is not found
+    /// Returns an error if the device does not support the buffer size
+    pub fn new_with_buffer_size(fs: u32, buffer_size: BufferSize) -> Result<Self, anyhow::Error> {
+        AudioInstanceBuilder::new()
+            .sample_rate(fs)
+            .buffer_size(buffer_size)
+            .build()
+    }
 
-        let host = binding
-            .as_ref()
-            .ok_or_else(|| anyhow::Error::msg("Host not initialized"))?;
+    /// Create a new audio instance that plays and records with a single duplex stream controller.
+    ///
+    /// In duplex mode `play_record` starts capturing in the same audio callback cycle that playback
+    /// starts, instead of relying on two independent streams starting at the same time.
+    /// This gives a consistent latency between runs on backends that drive input and output from
+    /// a single callback (ASIO, JACK).
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate of the audio device
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    /// Returns an error if the device is not found
+    pub fn new_duplex(fs: u32) -> Result<Self, anyhow::Error> {
+        AudioInstanceBuilder::new()
+            .sample_rate(fs)
+            .duplex(true)
+            .build()
+    }
+
+    /// Create a new audio instance that only plays, for devices without inputs.
+    ///
+    /// No input stream is opened, so the device doesn't need an input configuration. The instance
+    /// has no input channels, and the functions that record return an error.
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate of the audio device
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    /// Returns an error if the device is not found or has no output configuration
+    pub fn new_output_only(fs: u32) -> Result<Self, anyhow::Error> {
+        AudioInstanceBuilder::new()
+            .sample_rate(fs)
+            .output_only()
+            .build()
+    }
+
+    /// Create a new audio instance that only records, for devices without outputs.
+    ///
+    /// No output stream is opened, so the device doesn't need an output configuration. The
+    /// instance has no output channels, and the functions that play return an error. The input
+    /// device is used if one is set, e.g. with `set_input_device`.
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate of the audio device
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    /// Returns an error if the device is not found or has no input configuration
+    pub fn new_input_only(fs: u32) -> Result<Self, anyhow::Error> {
+        AudioInstanceBuilder::new()
+            .sample_rate(fs)
+            .input_only()
+            .build()
+    }
 
-        let device = host
-            .output_devices()?
-            .find(|d| d.name().unwrap_or_default() == device_name)
-            .ok_or(anyhow::Error::msg("Device not found"))?;
+    pub(crate) fn create(config: AudioInstanceBuilder) -> Result<Self, anyhow::Error> {
+        let (device_name, input_device_name) = match config.context {
+            // a mock device doesn't need a host
+            _ if config.mock.is_some() => ("Mock".to_string(), None),
+            // instances from a context don't touch the global device selection
+            Some(ref context) => (
+                match config.device {
+                    Some(ref name) => name.clone(),
+                    None => context.default_output_device_name()?,
+                },
+                config.input_device.clone(),
+            ),
+            None => {
+                // audio overhead - set up the audio device
+                let mut device_name = DEVICE_NAME.lock().unwrap().clone();
+                if HOST.lock().unwrap().is_none()
+                    || (device_name.is_empty() && config.device.is_none())
+                {
+                    set_host_and_audio_device()?;
+                    device_name = DEVICE_NAME.lock().unwrap().clone();
+                }
 
-        let mut output_config = device.default_output_config()?.config();
-        output_config.sample_rate = cpal::SampleRate(fs);
-        let mut input_config = device.default_input_config()?.config();
-        input_config.sample_rate = cpal::SampleRate(fs);
+                // on macOS input and output are usually separate devices
+                match config.device {
+                    Some(ref name) => (name.clone(), config.input_device.clone()),
+                    None => (
+                        device_name,
+                        config
+                            .input_device
+                            .clone()
+                            .or(INPUT_DEVICE_NAME.lock().unwrap().clone()),
+                    ),
+                }
+            }
+        };
+        // a single direction only needs one device
+        let (device_name, input_device_name) = match config.direction {
+            Some(StreamDirection::Output) => (device_name, None),
+            Some(StreamDirection::Input) => (input_device_name.unwrap_or(device_name), None),
+            None => (device_name, input_device_name),
+        };
+        if config.duplex && config.direction.is_some() {
+            return Err(anyhow::Error::msg(
+                "Duplex mode needs both input and output",
+            ));
+        }
+        if config.duplex && input_device_name.is_some() {
+            return Err(anyhow::Error::msg(
+                "Duplex mode needs input and output on the same device. On macOS, combine them into an aggregate device in Audio MIDI Setup.",
+            ));
+        }
+
+        // make sure no other process is using the devices. Mock devices are never shared
+        let mut device_locks = Vec::new();
+        if config.mock.is_none() {
+            device_locks.push(DeviceLock::acquire_with_mode(&device_name)?);
+            if let Some(ref input_device_name) = input_device_name {
+                device_locks.push(DeviceLock::acquire_with_mode(input_device_name)?);
+            }
+        }
 
         // create an instance now to add the streams to later
         let mut zsi_audio_instance = AudioInstance {
-            input_buffer: Arc::new(Mutex::new(Vec::new())),
-            output_buffer: Arc::new(Mutex::new(Vec::new())),
+            input_buffer: Arc::new(Mutex::new(InputCapture::default())),
+            output_buffer: Arc::new(Mutex::new(Signal::default())),
             input_stream_controller: None,
             output_stream_controller: None,
-            play_wait_pair: Arc::new((Mutex::new(true), std::sync::Condvar::new())),
-            sample_rate: fs,
+            play_gate: Arc::new(PlayGate::new(true)),
+            sample_rate: config.sample_rate,
             record_wait_pair: Arc::new((Mutex::new(false), std::sync::Condvar::new())),
-            number_of_output_channels: output_config.channels,
-            number_of_input_channels: input_config.channels,
+            number_of_output_channels: 0,
+            number_of_input_channels: 0,
+            // set when the streams are opened
+            output_format: cpal::SampleFormat::I32,
+            input_format: cpal::SampleFormat::I32,
+            duplex: config.duplex,
+            latency: Arc::new(Mutex::new(None)),
+            buffer_frames: Arc::new(AtomicUsize::new(0)),
+            enabled_input_channels: Arc::new(Mutex::new(Vec::new())),
+            interlock: Arc::new(Mutex::new(Interlock::default())),
+            loop_state: Arc::new(AtomicU8::new(super::stream_controller::LOOP_OFF)),
+            output_queue: Arc::new(Mutex::new(VecDeque::new())),
+            schedule: Arc::new(PlaybackSchedule::default()),
+            capture_timestamps: Arc::new(Mutex::new(Vec::new())),
+            capture_sink: Arc::new(Mutex::new(None)),
+            background: Arc::new(BackgroundLane::default()),
+            output_dither: Arc::new(AtomicU8::new(0)),
+            limiter: Arc::new(LimiterLane::default()),
+            mixer: Arc::new(MixerLane::default()),
+            passthrough: Arc::new(PassthroughLane::default()),
+            output_processors: Arc::new(Mutex::new(OutputProcessors::new())),
+            input_chain: Arc::new(InputChainLane::default()),
+            input_tap: Arc::new(Mutex::new(None)),
+            frame_queue: Arc::new(Mutex::new(None)),
+            pre_record: Arc::new(PreRecordBuffer::default()),
+            trigger: Arc::new(Mutex::new(None)),
+            fades: Arc::new(Mutex::new(None)),
+            alignment: Arc::new(Mutex::new(AlignmentConfig::default())),
+            _device_locks: Arc::new(device_locks),
+            callback_monitor: Arc::new(CallbackMonitor::default()),
+            silence_monitor: Arc::new(SilenceMonitor::default()),
+            progress_monitor: Arc::new(ProgressMonitor::default()),
+            events: Arc::new(EventHub::default()),
+            healthy: Arc::new(AtomicBool::new(true)),
+            device_names: std::iter::once(device_name)
+                .chain(input_device_name)
+                .collect(),
+            device_monitor: Arc::new(Mutex::new(None)),
+            config,
         };
+        zsi_audio_instance.open_streams()?;
 
-        // create the output stream
-        let output_buffer_clone = Arc::clone(&zsi_audio_instance.output_buffer);
-        let play_wait_clone = Arc::clone(&zsi_audio_instance.play_wait_pair);
+        Ok(zsi_audio_instance)
+    }
 
-        let output_stream_controller = StreamController::new(
-            super::stream_controller::StreamType::Output {
-                output_buffer: output_buffer_clone,
-                play_wait: play_wait_clone,
-            },
-            device.clone(),
-            output_config,
-        );
-        output_stream_controller.send_command(super::stream_controller::StreamCommand::Play);
+    /// Rebuild the streams after a device has been disconnected and plugged back in.
+    ///
+    /// The streams are recreated on the same devices with the same configuration.
+    /// The buffers and settings of the instance, such as the enabled input channels, the playback
+    /// queue and the background signal, are kept. The old streams are closed, so clones of the
+    /// instance made before reconnecting can't play or record anymore.
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    /// Returns an error if a device is still not connected
+    /// Returns an error if the device has come back with a different number of channels
+    pub fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        // stop the old streams before opening the device again
+        self.close_streams()?;
+
+        self.open_streams()?;
+        self.healthy.store(true, Ordering::Release);
+        Ok(())
+    }
 
-        // create the input stream
-        let input_buffer_clone = Arc::clone(&zsi_audio_instance.input_buffer);
-        let record_wait_clone = Arc::clone(&zsi_audio_instance.record_wait_pair);
+    /// Stop the streams and wait for the threads that run them to finish.
+    ///
+    /// Dropping the last clone of an instance does the same, but can't report errors. Clones of the
+    /// instance can't play or record once it is closed.
+    ///
+    /// # Errors
+    /// Returns an error if a stream thread panicked
+    pub fn close(mut self) -> Result<(), anyhow::Error> {
+        self.close_streams()
+    }
 
-        let input_stream_controller = StreamController::new(
-            super::stream_controller::StreamType::Input {
-                input_buffer: input_buffer_clone,
-                record_wait: record_wait_clone,
-            },
-            device,
-            input_config,
-        );
-        input_stream_controller.send_command(super::stream_controller::StreamCommand::Play);
+    /// The state of the output stream, or the duplex stream of a duplex instance. The state of the
+    /// input stream for an input-only instance.
+    ///
+    /// The state only changes once the stream has carried out a command, so it is Playing only if
+    /// the stream really started.
+    pub fn stream_state(&self) -> StreamState {
+        self.output_stream_controller
+            .as_ref()
+            .or(self.input_stream_controller.as_ref())
+            .map_or(StreamState::Closed, |controller| controller.get_state())
+    }
+
+    /// Close the stream controllers of the instance and remove them.
+    fn close_streams(&mut self) -> Result<(), anyhow::Error> {
+        let output = self.output_stream_controller.take();
+        let input = self.input_stream_controller.take();
+        // a duplex controller is both, and is closed once
+        for controller in output.iter().chain(input.iter()) {
+            controller.close()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the instance runs on a `MockDevice`.
+    pub(crate) fn is_mock(&self) -> bool {
+        self.config.mock.is_some()
+    }
+
+    /// Find the devices of this instance on its host, the global `HOST` or that of its context.
+    ///
+    /// # Returns
+    /// The output device and the input device, which are the same unless a separate input device
+    /// is set. An input-only instance returns its input device as both.
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized or a device is not found
+    pub(crate) fn find_devices(&self) -> Result<(cpal::Device, cpal::Device), anyhow::Error> {
+        let global_host;
+        let context_host;
+        let host = match self.config.context {
+            Some(ref context) => {
+                context_host = context.host();
+                &*context_host
+            }
+            None => {
+                global_host = HOST.lock().unwrap();
+                global_host
+                    .as_ref()
+                    .ok_or_else(|| anyhow::Error::msg("Host not initialized"))?
+            }
+        };
+
+        // an input-only instance has only an input device
+        let device_name = &self.device_names[0];
+        let is_device = |d: &cpal::Device| d.name().unwrap_or_default() == *device_name;
+        let device = if self.config.has_output() {
+            host.output_devices()?.find(is_device)
+        } else {
+            host.input_devices()?.find(is_device)
+        }
+        .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_name))?;
+        let input_device = match self.device_names.get(1) {
+            Some(input_device_name) => host
+                .input_devices()?
+                .find(|d| d.name().unwrap_or_default() == *input_device_name)
+                .ok_or_else(|| anyhow::anyhow!("Input device not found: {}", input_device_name))?,
+            None => device.clone(),
+        };
+        Ok((device, input_device))
+    }
+
+    /// Create the stream controllers for the devices of this instance and start them.
+    fn open_streams(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(device) = self.config.mock.clone() {
+            return self.open_mock_streams(&device);
+        }
+
+        let (has_output, has_input) = (self.config.has_output(), self.config.has_input());
+        let (device, input_device) = self.find_devices()?;
+
+        // streams at an unsupported rate fail to build with an error that doesn't say why
+        let nearest = self.config.nearest_sample_rate;
+        let mut sample_rate = self.sample_rate;
+        if has_output {
+            sample_rate = check_sample_rate_supported(
+                device.supported_output_configs()?,
+                sample_rate,
+                nearest,
+                "output",
+            )?;
+        }
+        if has_input {
+            let input_rate = check_sample_rate_supported(
+                input_device.supported_input_configs()?,
+                sample_rate,
+                nearest,
+                "input",
+            )?;
+            // the output must run at the rate the input fell back to
+            if has_output && input_rate != sample_rate {
+                check_sample_rate_supported(
+                    device.supported_output_configs()?,
+                    input_rate,
+                    false,
+                    "output",
+                )?;
+            }
+            sample_rate = input_rate;
+        }
+        if sample_rate != self.sample_rate {
+            tracing::warn!(
+                requested = self.sample_rate,
+                sample_rate,
+                "the device does not support the sample rate, using the nearest one"
+            );
+            self.sample_rate = sample_rate;
+        }
+
+        let output = if has_output {
+            let supported_config = select_config(
+                device.supported_output_configs()?,
+                device.default_output_config()?,
+                self.config.output_channels,
+                self.sample_rate,
+                "output",
+            )?;
+            Some(configure_stream(
+                supported_config,
+                self.sample_rate,
+                self.config.buffer_size,
+            )?)
+        } else {
+            None
+        };
+        let input = if has_input {
+            let supported_config = select_config(
+                input_device.supported_input_configs()?,
+                input_device.default_input_config()?,
+                self.config.input_channels,
+                self.sample_rate,
+                "input",
+            )?;
+            Some(configure_stream(
+                supported_config,
+                self.sample_rate,
+                self.config.buffer_size,
+            )?)
+        } else {
+            None
+        };
+        let output_channels = output.as_ref().map_or(0, |(config, _)| config.channels);
+        let input_channels = input.as_ref().map_or(0, |(config, _)| config.channels);
 
-        // add the streams to the instance
+        // the channel counts are 0 until the streams are first opened
+        if self.number_of_output_channels + self.number_of_input_channels != 0
+            && (output_channels != self.number_of_output_channels
+                || input_channels != self.number_of_input_channels)
         {
-            zsi_audio_instance.output_stream_controller = Some(output_stream_controller);
-            zsi_audio_instance.input_stream_controller = Some(input_stream_controller);
+            return Err(anyhow::anyhow!(
+                "The device has reconnected with {} output and {} input channels, expected {} and {}",
+                output_channels,
+                input_channels,
+                self.number_of_output_channels,
+                self.number_of_input_channels
+            ));
         }
+        self.number_of_output_channels = output_channels;
+        self.number_of_input_channels = input_channels;
+
+        match (output, input) {
+            (Some(output), Some(input)) if self.duplex => {
+                self.output_format = output.1;
+                self.input_format = input.1;
+
+                // a single controller owns both streams, so share it between input and output
+                let duplex_stream_controller: Arc<dyn AudioBackend> = Arc::new(
+                    StreamController::new_duplex(self.duplex_stream_type(), device, output, input),
+                );
+                duplex_stream_controller.send_command(StreamCommand::Play)?;
+
+                self.output_stream_controller = Some(Arc::clone(&duplex_stream_controller));
+                self.input_stream_controller = Some(duplex_stream_controller);
+            }
+            (output, input) => {
+                // create the output stream
+                if let Some((output_config, output_format)) = output {
+                    self.output_format = output_format;
+                    let output_stream_controller = StreamController::new(
+                        self.output_stream_type(),
+                        device,
+                        output_config,
+                        output_format,
+                    );
+                    output_stream_controller.send_command(StreamCommand::Play)?;
+                    self.output_stream_controller = Some(Arc::new(output_stream_controller));
+                }
 
-        Ok(zsi_audio_instance)
+                // create the input stream
+                if let Some((input_config, input_format)) = input {
+                    self.input_format = input_format;
+                    let input_stream_controller = StreamController::new(
+                        self.input_stream_type(),
+                        input_device,
+                        input_config,
+                        input_format,
+                    );
+                    input_stream_controller.send_command(StreamCommand::Play)?;
+                    self.input_stream_controller = Some(Arc::new(input_stream_controller));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the callbacks on a mock device instead of opening streams.
+    fn open_mock_streams(&mut self, device: &MockDevice) -> Result<(), anyhow::Error> {
+        // a single direction runs the device without the channels of the other
+        let mut device = device.clone();
+        if !self.config.has_output() {
+            device.output_channels = 0;
+        }
+        if !self.config.has_input() {
+            device.input_channels = 0;
+        }
+        self.number_of_output_channels = device.output_channels;
+        self.number_of_input_channels = device.input_channels;
+
+        let (output, input) = if self.duplex {
+            (self.duplex_stream_type(), self.duplex_stream_type())
+        } else {
+            (self.output_stream_type(), self.input_stream_type())
+        };
+        let backend: Arc<dyn AudioBackend> =
+            Arc::new(MockBackend::new(&device, &output, &input, self.sample_rate));
+        backend.send_command(StreamCommand::Play)?;
+
+        self.output_stream_controller =
+            Some(Arc::clone(&backend)).filter(|_| self.config.has_output());
+        self.input_stream_controller = Some(backend).filter(|_| self.config.has_input());
+
+        Ok(())
+    }
+
+    /// The state shared with the output callback.
+    fn output_stream_type(&self) -> StreamType {
+        StreamType::Output {
+            output_buffer: Arc::clone(&self.output_buffer),
+            play_gate: Arc::clone(&self.play_gate),
+            buffer_frames: Arc::clone(&self.buffer_frames),
+            loop_state: Arc::clone(&self.loop_state),
+            output_queue: Arc::clone(&self.output_queue),
+            schedule: Arc::clone(&self.schedule),
+            background: Arc::clone(&self.background),
+            dither: Arc::clone(&self.output_dither),
+            limiter: Arc::clone(&self.limiter),
+            mixer: Arc::clone(&self.mixer),
+            monitor: Arc::clone(&self.callback_monitor),
+            progress: Arc::clone(&self.progress_monitor),
+            events: Arc::clone(&self.events),
+        }
+    }
+
+    /// The state shared with the input callback.
+    fn input_stream_type(&self) -> StreamType {
+        StreamType::Input {
+            input_buffer: Arc::clone(&self.input_buffer),
+            record_wait: Arc::clone(&self.record_wait_pair),
+            capture_timestamps: Arc::clone(&self.capture_timestamps),
+            capture_sink: Arc::clone(&self.capture_sink),
+            input_chain: Arc::clone(&self.input_chain),
+            input_tap: Arc::clone(&self.input_tap),
+            frame_queue: Arc::clone(&self.frame_queue),
+            pre_record: Arc::clone(&self.pre_record),
+            trigger: Arc::clone(&self.trigger),
+            monitor: Arc::clone(&self.callback_monitor),
+            progress: Arc::clone(&self.progress_monitor),
+            events: Arc::clone(&self.events),
+        }
+    }
+
+    /// The state shared with the callbacks of a duplex stream.
+    fn duplex_stream_type(&self) -> StreamType {
+        StreamType::Duplex {
+            record_wait: Arc::clone(&self.record_wait_pair),
+            input_buffer: Arc::clone(&self.input_buffer),
+            capture_timestamps: Arc::clone(&self.capture_timestamps),
+            capture_sink: Arc::clone(&self.capture_sink),
+            input_chain: Arc::clone(&self.input_chain),
+            input_tap: Arc::clone(&self.input_tap),
+            frame_queue: Arc::clone(&self.frame_queue),
+            pre_record: Arc::clone(&self.pre_record),
+            trigger: Arc::clone(&self.trigger),
+            output_buffer: Arc::clone(&self.output_buffer),
+            play_gate: Arc::clone(&self.play_gate),
+            buffer_frames: Arc::clone(&self.buffer_frames),
+            loop_state: Arc::clone(&self.loop_state),
+            output_queue: Arc::clone(&self.output_queue),
+            schedule: Arc::clone(&self.schedule),
+            background: Arc::clone(&self.background),
+            dither: Arc::clone(&self.output_dither),
+            limiter: Arc::clone(&self.limiter),
+            mixer: Arc::clone(&self.mixer),
+            passthrough: Arc::clone(&self.passthrough),
+            monitor: Arc::clone(&self.callback_monitor),
+            progress: Arc::clone(&self.progress_monitor),
+            events: Arc::clone(&self.events),
+        }
+    }
+
+    /// The number of frames per callback granted by the driver.
+    ///
+    /// Returns None until the output stream has run its first callback.
+    pub fn buffer_size(&self) -> Option<usize> {
+        match self.buffer_frames.load(Ordering::Relaxed) {
+            0 => None,
+            frames => Some(frames),
+        }
+    }
+
+    /// The number of input channels of the input device, which is the number of channels in a
+    /// recording when every channel is enabled.
+    pub fn channels_in(&self) -> u16 {
+        self.number_of_input_channels
+    }
+
+    /// The number of output channels of the output device, which is the number of channels
+    /// `play` expects.
+    pub fn channels_out(&self) -> u16 {
+        self.number_of_output_channels
+    }
+
+    /// The sample rate of the streams in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The channel labels the instance was built with. See `AudioInstanceBuilder::input_label`.
+    pub fn channel_labels(&self) -> &ChannelLabels {
+        &self.config.labels
+    }
+
+    /// The output channel a number or label refers to.
+    ///
+    /// # Errors
+    /// Returns an error if no output channel has the label
+    pub fn output_channel(
+        &self,
+        channel: impl OutputSelector,
+    ) -> Result<OutputChannel, anyhow::Error> {
+        self.config.labels.output(&channel)
+    }
+
+    /// The input channel a number or label refers to.
+    ///
+    /// # Errors
+    /// Returns an error if no input channel has the label
+    pub fn input_channel(
+        &self,
+        channel: impl InputSelector,
+    ) -> Result<InputChannel, anyhow::Error> {
+        self.config.labels.input(&channel)
+    }
+
+    /// The index of an output channel in the data passed to `play`.
+    ///
+    /// # Errors
+    /// Returns an error if the channel is out of range for the device or the label is unknown
+    pub fn output_index(
+        &self,
+        channel: impl OutputSelector,
+    ) -> Result<ChannelIndex, anyhow::Error> {
+        ChannelIndex::output(
+            self.output_channel(channel)?,
+            self.number_of_output_channels as usize,
+        )
+    }
+
+    /// The index of an input channel of the device. While only some input channels are enabled,
+    /// recordings are indexed by their position in `set_enabled_input_channels` instead.
+    ///
+    /// # Errors
+    /// Returns an error if the channel is out of range for the device or the label is unknown
+    pub fn input_index(&self, channel: impl InputSelector) -> Result<ChannelIndex, anyhow::Error> {
+        ChannelIndex::input(
+            self.input_channel(channel)?,
+            self.number_of_input_channels as usize,
+        )
+    }
+
+    /// The sample format of the output stream. Samples are always passed in and out of the
+    /// instance as i32, and converted to this format in the callback.
+    pub fn sample_format(&self) -> cpal::SampleFormat {
+        self.output_format
+    }
+
+    /// The sample format of the input stream.
+    pub fn input_sample_format(&self) -> cpal::SampleFormat {
+        self.input_format
+    }
+
+    /// The full configuration of the instance.
+    pub fn config(&self) -> InstanceConfig {
+        InstanceConfig {
+            sample_rate: self.sample_rate,
+            channels_in: self.number_of_input_channels,
+            channels_out: self.number_of_output_channels,
+            sample_format: self.output_format,
+            input_sample_format: self.input_format,
+            buffer_size: self.buffer_size(),
+            duplex: self.duplex,
+            device_names: self.device_names.clone(),
+        }
+    }
+
+    /// Only record the given input channels.
+    ///
+    /// Disabled channels are dropped in the input callback and never stored, which saves memory
+    /// on interfaces with many inputs where only a few are connected. Recordings contain only the
+    /// enabled channels, in the order given here, so channel numbers passed to the alignment
+    /// functions refer to positions in `channels`. A recording that is already running keeps the
+    /// channels it started with.
+    ///
+    /// # Arguments
+    /// channels: &[impl InputSelector] - the input channels to record, by number or label
+    ///
+    /// # Errors
+    /// Returns an error if `channels` is empty, a channel is out of range or a label is unknown
+    pub fn set_enabled_input_channels(
+        &self,
+        channels: &[impl InputSelector],
+    ) -> Result<(), anyhow::Error> {
+        *self.enabled_input_channels.lock().unwrap() = self.input_indices(channels)?;
+        Ok(())
+    }
+
+    /// The device channel of each selected input channel, in order.
+    ///
+    /// # Errors
+    /// Returns an error if `channels` is empty, a channel is out of range or a label is unknown
+    fn input_indices(&self, channels: &[impl InputSelector]) -> Result<Vec<usize>, anyhow::Error> {
+        if channels.is_empty() {
+            return Err(anyhow::Error::msg(
+                "At least one input channel must be enabled",
+            ));
+        }
+
+        let mut indices = Vec::with_capacity(channels.len());
+        for channel in channels {
+            indices.push(self.input_index(channel)?.get());
+        }
+        Ok(indices)
+    }
+
+    /// Record every input channel of the device. This is the default.
+    pub fn enable_all_input_channels(&self) {
+        self.enabled_input_channels.lock().unwrap().clear();
+    }
+
+    /// The number of channels stored for each recorded frame.
+    pub(crate) fn recorded_channel_count(&self) -> usize {
+        match self.enabled_input_channels.lock().unwrap().len() {
+            0 => self.number_of_input_channels as usize,
+            enabled => enabled,
+        }
+    }
+
+    /// An empty recording of the enabled input channels with room for a number of frames. The
+    /// channels are fixed for the whole recording, whatever is enabled while it runs.
+    pub(super) fn capture_frames(&self, frames: usize) -> InputCapture {
+        let channels = self.enabled_input_channels.lock().unwrap().clone();
+        InputCapture::new(channels, self.number_of_input_channels as usize, frames)
+    }
+
+    /// Hand a new recording to the input callback, which fills it once recording starts.
+    ///
+    /// Room is reserved for the timestamp of every buffer of the recording, so the callback never
+    /// allocates for them. Some hosts deliver buffers smaller than the granted size, so half of it
+    /// is assumed; the timestamps of any buffers past the reserved room are dropped.
+    pub(super) fn start_capture(&self, capture: InputCapture) {
+        let recorded_frames = capture.samples.capacity() / capture.frame_size.max(1);
+        let frames = recorded_frames * (self.sample_rate / self.recorded_sample_rate()) as usize;
+        let buffer_frames =
+            (self.buffer_frames.load(Ordering::Relaxed) / 2).max(MIN_CALLBACK_FRAMES);
+        *self.capture_timestamps.lock().unwrap() =
+            Vec::with_capacity(frames.div_ceil(buffer_frames) + 1);
+        *self.input_buffer.lock().unwrap() = capture;
+    }
+
+    /// Take the recording from the input callback.
+    pub(super) fn take_capture(&self) -> InputCapture {
+        std::mem::take(&mut *self.input_buffer.lock().unwrap())
+    }
+
+    /// Block until the input callback has finished recording.
+    ///
+    /// # Arguments
+    /// recording: MutexGuard<bool> - the locked flag of `record_wait_pair`, set while recording
+    /// finished: impl Fn(bool) -> bool - whether the wait is over, given the flag
+    ///
+    /// # Errors
+    /// Returns an error if the device is disconnected first. The recording is stopped
+    pub(super) fn wait_for_recording(
+        &self,
+        mut recording: MutexGuard<'_, bool>,
+        finished: impl Fn(bool) -> bool,
+    ) -> Result<(), anyhow::Error> {
+        let (_, cvar) = &*self.record_wait_pair;
+        while !finished(*recording) {
+            if !self.is_healthy() {
+                *recording = false;
+                return Err(disconnected_error());
+            }
+            recording = cvar
+                .wait_timeout(recording, HEALTH_CHECK_INTERVAL)
+                .unwrap()
+                .0;
+        }
+        Ok(())
+    }
+
+    /// Turn on the safety interlock.
+    ///
+    /// While the interlock is on, playing anything with a peak above `max_unarmed_level_dbfs`
+    /// returns an error unless the interlock has been armed with `arm`. This prevents accidental
+    /// full-scale playback into sensitive devices or listeners.
+    ///
+    /// # Arguments
+    /// max_unarmed_level_dbfs: f64 - the highest peak level that can be played without arming
+    pub fn enable_interlock(&self, max_unarmed_level_dbfs: f64) {
+        self.interlock
+            .lock()
+            .unwrap()
+            .enable(max_unarmed_level_dbfs);
+    }
+
+    /// Turn off the safety interlock.
+    pub fn disable_interlock(&self) {
+        self.interlock.lock().unwrap().disable();
+    }
+
+    /// Allow playback above the interlock level for `duration`.
+    ///
+    /// The interlock disarms itself once `duration` has passed.
+    pub fn arm(&self, duration: Duration) {
+        self.interlock.lock().unwrap().arm(duration, Instant::now());
+    }
+
+    /// Disarm the interlock before the arm duration has passed.
+    pub fn disarm(&self) {
+        self.interlock.lock().unwrap().disarm();
+    }
+
+    pub(super) fn check_interlock(&self, output_data: &[Vec<i32>]) -> Result<(), anyhow::Error> {
+        self.check_interlock_level(interlock::peak(output_data))
+    }
+
+    pub(super) fn check_interlock_level(&self, peak: i32) -> Result<(), anyhow::Error> {
+        self.interlock.lock().unwrap().check(peak, Instant::now())
     }
 
     /// Play multiple channels of audio data.
@@ -119,40 +970,122 @@ impl AudioInstance {
     /// The length of each channel must be the same.
     ///
     /// The audio data is played in the order of the channels.
-    /// This function blocks until the audio has finished playing.
+    /// This function blocks until the audio has finished playing. Use `play_mixed` to play
+    /// several signals at once.
     ///
     /// # Arguments
     /// output_data: Vec<Vec<i32> - the audio data to play. The outer vector represents the channels and the inner vector represents the samples.
-    pub fn play(&self, output_data: Vec<Vec<i32>>) -> Result<(), anyhow::Error> {
+    #[tracing::instrument(skip_all, err, fields(frames = output_data.first().map_or(0, Vec::len)))]
+    pub fn play(&self, mut output_data: Vec<Vec<i32>>) -> Result<(), anyhow::Error> {
+        let _overloads = self.callback_monitor.report_overloads();
         if self.number_of_output_channels != output_data.len() as u16 {
             return Err(anyhow::Error::msg("Number of channels does not match"));
         }
+        self.process_output(&mut output_data, 0..self.number_of_output_channels as usize);
+        self.check_interlock(&output_data)?;
 
         // ensure the stream is running
         self.ensure_stream_running(StreamControllerType::Output)?;
 
-        let flattened_output_data = self.flatten_output_data(output_data);
+        let mut flattened_output_data = self.flatten_output_data(output_data);
+        self.fade_output(&mut flattened_output_data);
 
         // initialize the output buffer
-        *self.output_buffer.lock().unwrap() = flattened_output_data;
-
-        let play_wait_pair_clone = Arc::clone(&self.play_wait_pair);
-        let (lock, cvar) = &*play_wait_pair_clone;
-        let mut play_wait = lock.lock().unwrap();
+        *self.output_buffer.lock().unwrap() = self.output_signal(flattened_output_data)?;
 
         // start playing audio
-        *play_wait = true;
-        while *play_wait {
-            play_wait = cvar.wait(play_wait).unwrap();
-        }
+        self.play_gate.start();
+        self.play_gate.wait(&self.healthy)
+    }
 
-        Ok(())
+    /// Play audio on some of the output channels, leaving the others silent.
+    ///
+    /// Unlike `play`, the data only needs one vector per channel being played. The other device
+    /// channels are zero-filled when the data is interleaved.
+    /// This function blocks until the audio has finished playing.
+    ///
+    /// # Arguments
+    /// output_data: Vec<Vec<i32>> - one vector of samples per channel in `channels`
+    /// channels: &[impl OutputSelector] - the output channel to play each vector on, by number or label
+    ///
+    /// # Errors
+    /// Returns an error if the data doesn't match `channels`, a channel is out of range or a
+    /// label is unknown
+    #[tracing::instrument(skip_all, err, fields(?channels))]
+    pub fn play_on_channels(
+        &self,
+        mut output_data: Vec<Vec<i32>>,
+        channels: &[impl OutputSelector],
+    ) -> Result<(), anyhow::Error> {
+        let _overloads = self.callback_monitor.report_overloads();
+        let channels = channels
+            .iter()
+            .map(|channel| self.config.labels.output(channel))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.process_output(
+            &mut output_data,
+            channels.iter().filter_map(|channel| channel.index().ok()),
+        );
+        let mut interleaved = interleave_on_channels(
+            &output_data,
+            &channels,
+            self.number_of_output_channels as usize,
+        )?;
+        self.check_interlock(&output_data)?;
+        self.fade_output(&mut interleaved);
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        *self.output_buffer.lock().unwrap() = self.output_signal(interleaved)?;
+
+        self.play_gate.start();
+        self.play_gate.wait(&self.healthy)
     }
 
-    fn ensure_stream_running(
+    /// Play a stereo signal on a pair of output channels, leaving the others silent.
+    ///
+    /// Pair 1 is outputs 1 and 2, pair 2 is outputs 3 and 4, and so on.
+    /// This function blocks until the audio has finished playing.
+    ///
+    /// # Arguments
+    /// left: Vec<i32> - the samples of the left channel
+    /// right: Vec<i32> - the samples of the right channel, the same length as `left`
+    /// pair: usize - the 1-based stereo pair to play on
+    ///
+    /// # Errors
+    /// Returns an error if the channels have different lengths or the pair is out of range
+    pub fn play_stereo(
+        &self,
+        left: Vec<i32>,
+        right: Vec<i32>,
+        pair: usize,
+    ) -> Result<(), anyhow::Error> {
+        self.play_on_channels(vec![left, right], &OutputChannel::stereo_pair(pair)?)
+    }
+
+    /// Play audio on a named group of output channels, leaving the others silent.
+    ///
+    /// This function blocks until the audio has finished playing.
+    ///
+    /// # Arguments
+    /// output_data: Vec<Vec<i32>> - one vector of samples per channel of the group
+    /// group: &str - the name of the group. See `AudioInstanceBuilder::output_group`
+    ///
+    /// # Errors
+    /// Returns an error if no group has the name, or the data doesn't match the group
+    pub fn play_group(&self, output_data: Vec<Vec<i32>>, group: &str) -> Result<(), anyhow::Error> {
+        let channels = self.config.labels.output_group(group)?.to_vec();
+        self.play_on_channels(output_data, &channels)
+    }
+
+    pub(super) fn ensure_stream_running(
         &self,
         stream_controller_type: StreamControllerType,
     ) -> Result<(), anyhow::Error> {
+        // don't wait on a stream whose device has been unplugged
+        self.check_healthy()?;
+
         // confirm the stream is running
         let stream_controller = match stream_controller_type {
             StreamControllerType::Input => &self.input_stream_controller,
@@ -160,13 +1093,22 @@ impl AudioInstance {
         };
 
         match stream_controller {
-            Some(ref s) => {
-                if s.get_state() == super::stream_controller::StreamState::Stopped {
-                    s.send_command(super::stream_controller::StreamCommand::Play);
+            Some(ref s) => match s.get_state() {
+                StreamState::Playing => {}
+                StreamState::Stopped => s.send_command(StreamCommand::Play)?,
+                StreamState::Closed => {
+                    return Err(anyhow::Error::msg("The audio instance has been closed"));
                 }
-            }
+            },
             None => {
-                return Err(anyhow::Error::msg("Output stream controller not found"));
+                return Err(match stream_controller_type {
+                    StreamControllerType::Input => {
+                        anyhow::Error::msg("The audio instance has no input stream")
+                    }
+                    StreamControllerType::Output => {
+                        anyhow::Error::msg("The audio instance has no output stream")
+                    }
+                });
             }
         }
         Ok(())
@@ -181,40 +1123,94 @@ impl AudioInstance {
     ///
     /// # Returns
     /// A vector of channels where each channel is a vector of samples
+    #[tracing::instrument(skip(self), err)]
     pub fn record(&self, duration: f64) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        self.record_frames(self.recorded_frames(duration))
+    }
+
+    /// Record an exact number of frames of every channel.
+    ///
+    /// This function blocks until the audio has finished recording.
+    ///
+    /// # Arguments
+    /// frames: usize - the number of frames to record, at `recorded_sample_rate`
+    ///
+    /// # Returns
+    /// A vector of channels where each channel is a vector of exactly `frames` samples
+    #[tracing::instrument(skip(self), err)]
+    pub fn record_frames(&self, frames: usize) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        self.record_capture(self.capture_frames(frames), frames)
+    }
+
+    /// Record `frames` frames into a new recording, which decides the channels recorded.
+    fn record_capture(
+        &self,
+        capture: InputCapture,
+        frames: usize,
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        // the input callback only finishes a recording once it has stored a frame
+        if frames == 0 {
+            return Ok(capture.into_channels());
+        }
+        let _overloads = self.callback_monitor.report_overloads();
         // ensure the stream is running
         self.ensure_stream_running(StreamControllerType::Input)?;
 
-        let sample_rate = self.sample_rate;
-
         // ensure the buffer is empty
-        *self.input_buffer.lock().unwrap() = Vec::<i32>::with_capacity(
-            (sample_rate as f64 * duration) as usize * self.number_of_input_channels as usize,
-        );
+        self.start_capture(capture);
 
-        let record_wait_pair_clone = Arc::clone(&self.record_wait_pair);
-        let (lock, cvar) = &*record_wait_pair_clone;
+        let (lock, _) = &*self.record_wait_pair;
         let mut start_recording = lock.lock().unwrap();
         // start recording audio
         *start_recording = true;
 
         // wait until start_recording is set to false
-        while *start_recording {
-            start_recording = cvar.wait(start_recording).unwrap();
-        }
-        drop(start_recording);
+        self.wait_for_recording(start_recording, |recording| !recording)?;
 
-        let recorded_data = self.input_buffer.lock().unwrap().clone();
+        // the allocator may give the buffer more capacity than was asked for
+        let mut channel_recordings = self.take_capture().into_channels();
+        for channel in channel_recordings.iter_mut() {
+            channel.truncate(frames);
+        }
 
-        let channel_recordings = self.convert_to_channel_data(recorded_data);
+        Ok(channel_recordings)
+    }
 
-        return Ok(channel_recordings);
+    /// Record only some of the input channels.
+    ///
+    /// Channels that are not selected are dropped in the input callback and never stored.
+    /// The selection only applies to this recording, the channels enabled with
+    /// `set_enabled_input_channels` are left as they are.
+    ///
+    /// # Arguments
+    /// duration: f64 - the duration of the recording in seconds
+    /// channels: &[impl InputSelector] - the input channels to record, by number or label
+    ///
+    /// # Returns
+    /// A vector with one vector of samples per selected channel, in the order of `channels`
+    pub fn record_channels(
+        &self,
+        duration: f64,
+        channels: &[impl InputSelector],
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        let frames = self.recorded_frames(duration);
+        let capture = InputCapture::new(
+            self.input_indices(channels)?,
+            self.number_of_input_channels as usize,
+            frames,
+        );
+        self.record_capture(capture, frames)
     }
 
     /// Play and record multiple channels of audio data.
     ///
     /// Play and record simultaneously. See the play and record functions for more details.
-    pub fn play_record(&self, output_data: Vec<Vec<i32>>) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    #[tracing::instrument(skip_all, err, fields(frames = output_data.first().map_or(0, Vec::len)))]
+    pub fn play_record(
+        &self,
+        mut output_data: Vec<Vec<i32>>,
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        let _overloads = self.callback_monitor.report_overloads();
         if self.number_of_output_channels != output_data.len() as u16 {
             return Err(anyhow::Error::msg(format!(
                 "Number of channels does not match\n\tExpected: {}, Actual: {}",
@@ -222,6 +1218,8 @@ impl AudioInstance {
                 output_data.len()
             )));
         }
+        self.process_output(&mut output_data, 0..self.number_of_output_channels as usize);
+        self.check_interlock(&output_data)?;
 
         // ensure the streams are running
         self.ensure_stream_running(StreamControllerType::Output)?;
@@ -232,58 +1230,93 @@ impl AudioInstance {
         // since output_data is a vector of channels, we need the length of one of the channels not the outer length
         let duration = output_data[0].len() as f64 / self.sample_rate as f64;
 
+        if self.duplex {
+            return self.duplex_play_record(output_data, duration);
+        }
+
         // Set up the output buffer
-        let flattened_data = self.flatten_output_data(output_data);
-        *self.output_buffer.lock().unwrap() = flattened_data;
+        let mut flattened_data = self.flatten_output_data(output_data);
+        self.fade_output(&mut flattened_data);
+        *self.output_buffer.lock().unwrap() = self.output_signal(flattened_data)?;
 
         // Start playback in a separate thread
         let play_handle = {
-            let play_wait_clone = Arc::clone(&self.play_wait_pair);
+            let play_gate_clone = Arc::clone(&self.play_gate);
+            let healthy = Arc::clone(&self.healthy);
 
             std::thread::spawn(move || {
-                let (lock, cvar) = &*play_wait_clone;
-                let mut play_wait = lock.lock().unwrap();
-                *play_wait = true;
-
-                while *play_wait {
-                    play_wait = cvar.wait(play_wait).unwrap();
-                }
+                play_gate_clone.start();
+                play_gate_clone.wait(&healthy)
             })
         };
 
         // Set up the input buffer
-        let input_buffer_capacity =
-            (self.sample_rate as f64 * duration) as usize * self.number_of_input_channels as usize;
-        *self.input_buffer.lock().unwrap() = Vec::<i32>::with_capacity(input_buffer_capacity);
+        self.start_capture(self.input_capture(duration));
 
-        // Create condition variables to synchronize play and record
-        let record_wait_pair_clone = Arc::clone(&self.record_wait_pair);
+        // Start recording while the playback thread starts playing
+        let (lock, _) = &*self.record_wait_pair;
+        let mut record_wait = lock.lock().unwrap();
+        *record_wait = true;
+        let recorded = self.wait_for_recording(record_wait, |recording| !recording);
 
-        // Start recording in a separate thread
-        let record_handle = {
-            std::thread::spawn(move || {
-                let (lock, cvar) = &*record_wait_pair_clone;
-                let mut record_wait = lock.lock().unwrap();
-                *record_wait = true;
+        // Wait for playback to complete
+        play_handle.join().unwrap()?;
+        recorded?;
 
-                while *record_wait {
-                    record_wait = cvar.wait(record_wait).unwrap();
-                }
-            })
-        };
+        // Get the recorded data
+        let capture = self.take_capture();
+        let channels = capture.input_channels();
+        let channel_recordings = capture.into_channels();
+        self.check_silence(&channel_recordings, &channels)?;
 
-        // Wait for both threads to complete
-        play_handle.join().unwrap();
-        record_handle.join().unwrap();
+        Ok(channel_recordings)
+    }
 
-        // Get the recorded data
-        let input_buffer = self.input_buffer.lock().unwrap().clone();
-        let channel_recordings = self.convert_to_channel_data(input_buffer);
+    /// Play and record on a duplex stream.
+    ///
+    /// The output callback starts the capture when it starts playing, so only playback is started here.
+    fn duplex_play_record(
+        &self,
+        output_data: Vec<Vec<i32>>,
+        duration: f64,
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        // Set up the input buffer before playback starts, since the capture starts with it
+        self.start_capture(self.input_capture(duration));
+
+        let mut flattened_data = self.flatten_output_data(output_data);
+        self.fade_output(&mut flattened_data);
+        *self.output_buffer.lock().unwrap() = self.output_signal(flattened_data)?;
+
+        // wait for playback to finish
+        self.play_gate.start();
+        self.play_gate.wait(&self.healthy)?;
+
+        // the recording was started with playback, wait for it to fill the buffer
+        let (lock, _) = &*self.record_wait_pair;
+        self.wait_for_recording(lock.lock().unwrap(), |recording| !recording)?;
+
+        let capture = self.take_capture();
+        let channels = capture.input_channels();
+        let channel_recordings = capture.into_channels();
+        self.check_silence(&channel_recordings, &channels)?;
 
         Ok(channel_recordings)
     }
 
-    fn flatten_output_data(&self, output_data: Vec<Vec<i32>>) -> Vec<i32> {
+    /// Place several single channel signals on the output channels of the device.
+    ///
+    /// See `methods::format_signals_for_multichannel`.
+    ///
+    /// # Errors
+    /// Returns an error if a channel is out of range for the device or has more than one signal
+    pub fn format_signals(
+        &self,
+        signals: Vec<(Vec<i32>, OutputChannel)>,
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        format_signals_for_multichannel(signals, self.number_of_output_channels as usize)
+    }
+
+    pub(super) fn flatten_output_data(&self, output_data: Vec<Vec<i32>>) -> Vec<i32> {
         // convert from vector of channels to vector of samples
         let mut flattened_output_data: Vec<i32> = Vec::new();
         for sample_index in 0..output_data[0].len() {
@@ -294,115 +1327,509 @@ impl AudioInstance {
         flattened_output_data
     }
 
-    fn convert_to_channel_data(&self, input_buffer: Vec<i32>) -> Vec<Vec<i32>> {
-        // convert recording to a vector of channels
-        let mut channel_recordings: Vec<Vec<i32>> =
-            vec![Vec::new(); self.number_of_input_channels as usize];
-        for chunk in input_buffer.chunks_exact(self.number_of_input_channels as usize) {
-            for (channel_index, &sample) in chunk.iter().enumerate() {
-                channel_recordings[channel_index].push(sample);
-            }
+    /// Wrap interleaved output data for the output callback.
+    pub(super) fn output_signal(&self, interleaved: Vec<i32>) -> Result<Signal, anyhow::Error> {
+        Signal::from_interleaved(interleaved, self.number_of_output_channels as usize)
+    }
+}
+
+/// The configuration of a stream at a sample rate and buffer size, from the default configuration
+/// of its device.
+///
+/// # Errors
+/// Returns an error if the device does not support the buffer size
+fn configure_stream(
+    default_config: cpal::SupportedStreamConfig,
+    sample_rate: u32,
+    buffer_size: BufferSize,
+) -> Result<(cpal::StreamConfig, cpal::SampleFormat), anyhow::Error> {
+    if let (BufferSize::Fixed(frames), cpal::SupportedBufferSize::Range { min, max }) =
+        (buffer_size, default_config.buffer_size())
+    {
+        if frames < *min || frames > *max {
+            return Err(anyhow::anyhow!(
+                "Buffer size of {} frames is not supported. The device supports {} to {} frames.",
+                frames,
+                min,
+                max
+            ));
+        }
+    }
+
+    let mut config = default_config.config();
+    config.sample_rate = cpal::SampleRate(sample_rate);
+    config.buffer_size = buffer_size.into();
+    Ok((config, default_config.sample_format()))
+}
+
+/// Check a device can open a stream at a sample rate.
+///
+/// # Arguments
+/// configs: impl Iterator<Item = cpal::SupportedStreamConfigRange> - the configurations the device
+/// supports
+/// sample_rate: u32 - the requested sample rate
+/// nearest: bool - whether to use the nearest supported rate if the device doesn't support it
+/// direction: &str - "input" or "output", for the error message
+///
+/// # Returns
+/// The sample rate, or the nearest supported rate
+///
+/// # Errors
+/// Returns an error listing the supported rates if the device does not support the sample rate
+/// and `nearest` is false
+fn check_sample_rate_supported(
+    configs: impl Iterator<Item = cpal::SupportedStreamConfigRange>,
+    sample_rate: u32,
+    nearest: bool,
+    direction: &str,
+) -> Result<u32, anyhow::Error> {
+    let mut ranges: Vec<(u32, u32)> = configs
+        .map(|config| (config.min_sample_rate().0, config.max_sample_rate().0))
+        .collect();
+    ranges.sort_unstable();
+    ranges.dedup();
+
+    let closest = ranges
+        .iter()
+        .map(|&(min, max)| sample_rate.clamp(min, max))
+        .min_by_key(|rate| rate.abs_diff(sample_rate));
+    match closest {
+        Some(rate) if rate == sample_rate || nearest => Ok(rate),
+        _ => {
+            let supported: Vec<String> = ranges
+                .iter()
+                .map(|&(min, max)| {
+                    if min == max {
+                        min.to_string()
+                    } else {
+                        format!("{}-{}", min, max)
+                    }
+                })
+                .collect();
+            Err(anyhow::anyhow!(
+                "The device does not support {} at {} Hz. Supported sample rates: {} Hz",
+                direction,
+                sample_rate,
+                supported.join(", ")
+            ))
         }
-        channel_recordings
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use crate::methods::set_host_and_audio_device;
-
-//     use super::*;
-
-//     lazy_static::lazy_static! { static ref TESTING_AUDIO_INSTANCE: Mutex<Option<AudioInstance>> = Mutex::new(None);}
-
-//     fn get_audio_instance() -> AudioInstance {
-//         let mut audio_instance = TESTING_AUDIO_INSTANCE.lock().unwrap();
-//         if audio_instance.is_none() {
-//             println!("Creating new audio instance");
-//             let sample_rate = 44100;
-//             set_host_and_audio_device().unwrap();
-//             let new_audio_instance = AudioInstance::new(sample_rate).unwrap();
-//             *audio_instance = Some(new_audio_instance);
-//         }
-
-//         assert!(audio_instance.is_some());
-//         audio_instance.as_mut().unwrap().clone()
-//     }
-
-//     #[test]
-//     fn test_audio_instance_new() {
-//         let sample_rate = 44100;
-//         let audio_instance = AudioInstance::new(sample_rate);
-//         assert!(audio_instance.is_ok());
-//     }
-
-//     #[test]
-//     fn test_play() {
-//         let sample_rate = 44100;
-//         let audio_instance: AudioInstance = get_audio_instance();
-
-//         let output_data =
-//             vec![vec![0; sample_rate]; audio_instance.number_of_output_channels as usize];
-//         let result = audio_instance.play(output_data);
-//         assert!(result.is_ok());
-//     }
-
-//     #[test]
-//     fn test_record() {
-//         let audio_instance: AudioInstance = get_audio_instance();
-
-//         let duration = 1.0;
-//         let result = audio_instance.record(duration);
-//         assert!(result.is_ok());
-//         let recorded_data = result.unwrap();
-//         assert_eq!(
-//             recorded_data.len(),
-//             audio_instance.number_of_input_channels as usize
-//         );
-//     }
-
-//     #[test]
-//     fn test_play_record() {
-//         let audio_instance: AudioInstance = get_audio_instance();
-
-//         let output_data = vec![vec![0; 44100]; audio_instance.number_of_output_channels as usize];
-//         let result = audio_instance.play_record(output_data);
-//         assert!(result.is_ok());
-//         let recorded_data = result.unwrap();
-//         assert_eq!(
-//             recorded_data.len(),
-//             audio_instance.number_of_input_channels as usize
-//         );
-//     }
-
-//     #[test]
-//     fn test_play_invalid_channels() {
-//         let audio_instance: AudioInstance = get_audio_instance();
-
-//         let output_data =
-//             vec![vec![0; 44100]; (audio_instance.number_of_output_channels - 1) as usize];
-//         let result = audio_instance.play(output_data);
-//         assert!(result.is_err());
-//     }
-
-//     #[test]
-//     fn test_record_duration() {
-//         let sample_rate = 44100;
-//         let audio_instance: AudioInstance = get_audio_instance();
-
-//         let duration = 2.0;
-//         let result = audio_instance.record(duration);
-//         assert!(result.is_ok());
-//         let recorded_data = result.unwrap();
-//         let expected_samples = (sample_rate as f64 * duration) as usize;
-//         assert_eq!(recorded_data[0].len(), expected_samples);
-//     }
-
-//     #[test]
-//     fn test_drop() {
-//         let audio_instance: AudioInstance = get_audio_instance();
-
-//         // drop the instance
-//         drop(audio_instance);
-//     }
-// }
+/// Choose the configuration to open a stream with for a channel count.
+///
+/// When a channel count is requested, the configurations with that many channels at the sample
+/// rate are used, preferring the sample format of the default configuration.
+///
+/// # Arguments
+/// configs: impl Iterator<Item = cpal::SupportedStreamConfigRange> - the configurations the device
+/// supports
+/// default_config: cpal::SupportedStreamConfig - the default configuration of the device
+/// channels: ChannelCount - the requested channel count
+/// sample_rate: u32 - the sample rate of the stream
+/// direction: &str - "input" or "output", for the error message
+///
+/// # Errors
+/// Returns an error listing the supported channel counts if the device does not support an exact
+/// channel count at the sample rate
+fn select_config(
+    configs: impl Iterator<Item = cpal::SupportedStreamConfigRange>,
+    default_config: cpal::SupportedStreamConfig,
+    channels: ChannelCount,
+    sample_rate: u32,
+    direction: &str,
+) -> Result<cpal::SupportedStreamConfig, anyhow::Error> {
+    let rate = cpal::SampleRate(sample_rate);
+    let candidates: Vec<cpal::SupportedStreamConfigRange> = configs
+        .filter(|config| config.min_sample_rate() <= rate && rate <= config.max_sample_rate())
+        .collect();
+    let channels = match channels {
+        ChannelCount::Default => return Ok(default_config),
+        ChannelCount::Exact(channels) => channels,
+        ChannelCount::Maximum => match candidates.iter().map(|config| config.channels()).max() {
+            Some(channels) => channels,
+            None => return Ok(default_config),
+        },
+    };
+
+    let default_format = default_config.sample_format();
+    candidates
+        .iter()
+        .filter(|config| config.channels() == channels)
+        .min_by_key(|config| config.sample_format() != default_format)
+        .map(|config| config.with_sample_rate(rate))
+        .ok_or_else(|| {
+            let mut supported: Vec<u16> =
+                candidates.iter().map(|config| config.channels()).collect();
+            supported.sort_unstable();
+            supported.dedup();
+            let supported: Vec<String> = supported.iter().map(u16::to_string).collect();
+            anyhow::anyhow!(
+                "The device does not support {} {} channels at {} Hz. Supported channel counts: {}",
+                channels,
+                direction,
+                sample_rate,
+                supported.join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_audio_instance() -> AudioInstance {
+        AudioInstanceBuilder::new()
+            .sample_rate(44100)
+            .mock(MockDevice::new(2, 4))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_audio_instance_new() {
+        let audio_instance = get_audio_instance();
+        assert_eq!(audio_instance.channels_in(), 2);
+        assert_eq!(audio_instance.channels_out(), 4);
+    }
+
+    #[test]
+    fn test_play() {
+        let sample_rate = 44100;
+        let audio_instance: AudioInstance = get_audio_instance();
+
+        let output_data =
+            vec![vec![0; sample_rate]; audio_instance.number_of_output_channels as usize];
+        let result = audio_instance.play(output_data);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_record() {
+        let audio_instance: AudioInstance = get_audio_instance();
+
+        let duration = 1.0;
+        let result = audio_instance.record(duration);
+        assert!(result.is_ok());
+        let recorded_data = result.unwrap();
+        assert_eq!(
+            recorded_data.len(),
+            audio_instance.number_of_input_channels as usize
+        );
+    }
+
+    #[test]
+    fn test_play_record() {
+        let audio_instance: AudioInstance = get_audio_instance();
+
+        let output_data = vec![vec![0; 44100]; audio_instance.number_of_output_channels as usize];
+        let result = audio_instance.play_record(output_data);
+        assert!(result.is_ok());
+        let recorded_data = result.unwrap();
+        assert_eq!(
+            recorded_data.len(),
+            audio_instance.number_of_input_channels as usize
+        );
+    }
+
+    #[test]
+    fn test_play_invalid_channels() {
+        let audio_instance: AudioInstance = get_audio_instance();
+
+        let output_data =
+            vec![vec![0; 44100]; (audio_instance.number_of_output_channels - 1) as usize];
+        let result = audio_instance.play(output_data);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_record_duration() {
+        let sample_rate = 44100;
+        let audio_instance: AudioInstance = get_audio_instance();
+
+        let duration = 2.0;
+        let result = audio_instance.record(duration);
+        assert!(result.is_ok());
+        let recorded_data = result.unwrap();
+        let expected_samples = (sample_rate as f64 * duration) as usize;
+        assert_eq!(recorded_data[0].len(), expected_samples);
+    }
+
+    #[test]
+    fn test_record_frames() {
+        let audio_instance: AudioInstance = get_audio_instance();
+
+        let recorded_data = audio_instance.record_frames(1001).unwrap();
+        assert_eq!(recorded_data.len(), 2);
+        assert!(recorded_data.iter().all(|channel| channel.len() == 1001));
+        // 0.57 * 44100 is just under 25137 in floating point
+        assert_eq!(audio_instance.record(0.57).unwrap()[0].len(), 25137);
+
+        // an empty recording returns straight away
+        assert_eq!(
+            audio_instance.record_frames(0).unwrap(),
+            vec![Vec::<i32>::new(); 2]
+        );
+        assert_eq!(
+            audio_instance.record(0.0).unwrap(),
+            vec![Vec::<i32>::new(); 2]
+        );
+    }
+
+    #[test]
+    fn test_enabled_channels_fixed_per_recording() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(3, 1).noise(1 << 20, 7))
+            .build()
+            .unwrap();
+        audio_instance
+            .set_enabled_input_channels(&[InputChannel(3), InputChannel(1)])
+            .unwrap();
+        let guard = audio_instance.begin_record(0.1).unwrap();
+        // changing the channels mid-recording only applies to the next recording
+        audio_instance.enable_all_input_channels();
+        let recording = guard.wait().unwrap();
+        assert_eq!(recording.len(), 2);
+        assert!(recording.iter().all(|channel| channel.len() == 4800));
+        assert_eq!(audio_instance.record(0.01).unwrap().len(), 3);
+
+        // a selection for one recording leaves the enabled channels alone
+        audio_instance
+            .set_enabled_input_channels(&[InputChannel(1)])
+            .unwrap();
+        let recording = audio_instance
+            .record_channels(0.01, &[InputChannel(2), InputChannel(3)])
+            .unwrap();
+        assert_eq!(recording.len(), 2);
+        assert_eq!(audio_instance.recorded_channel_count(), 1);
+    }
+
+    #[test]
+    fn test_waits_end_on_disconnect() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 1))
+            .build()
+            .unwrap();
+        let disconnect_soon = || {
+            std::thread::sleep(Duration::from_millis(50));
+            audio_instance.healthy.store(false, Ordering::Release);
+        };
+
+        // long enough that only the disconnect can end them in time
+        std::thread::scope(|scope| {
+            scope.spawn(disconnect_soon);
+            let start = Instant::now();
+            assert!(audio_instance.record(60.0).is_err());
+            assert!(start.elapsed() < Duration::from_secs(5));
+        });
+
+        audio_instance.healthy.store(true, Ordering::Release);
+        std::thread::scope(|scope| {
+            scope.spawn(disconnect_soon);
+            let start = Instant::now();
+            assert!(audio_instance.play(vec![vec![0; 48000 * 60]]).is_err());
+            assert!(start.elapsed() < Duration::from_secs(5));
+        });
+    }
+
+    #[test]
+    fn test_timestamp_capacity() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .build()
+            .unwrap();
+        audio_instance.play(vec![vec![0; 480]; 2]).unwrap();
+        let buffer_frames = audio_instance.buffer_size().unwrap();
+
+        // every buffer of a second at the full rate, and of a decimated second
+        audio_instance.start_capture(audio_instance.capture_frames(48000));
+        let capacity = audio_instance.capture_timestamps.lock().unwrap().capacity();
+        assert!(capacity >= 48000 / buffer_frames);
+        audio_instance.set_input_decimation(4).unwrap();
+        audio_instance.start_capture(audio_instance.capture_frames(12000));
+        let capacity = audio_instance.capture_timestamps.lock().unwrap().capacity();
+        assert!(capacity >= 48000 / buffer_frames);
+    }
+
+    #[test]
+    fn test_channel_labels() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .sample_rate(44100)
+            .mock(MockDevice::new(2, 4))
+            .output_label("Speaker L", OutputChannel(3))
+            .input_label("Ref Mic", InputChannel(2))
+            .build()
+            .unwrap();
+        assert_eq!(
+            audio_instance.output_channel("Speaker L").unwrap(),
+            OutputChannel(3)
+        );
+        assert!(audio_instance.input_channel("Speaker L").is_err());
+
+        assert!(audio_instance
+            .play_on_channels(vec![vec![0; 441]], &["Speaker L"])
+            .is_ok());
+        assert!(audio_instance
+            .play_on_channels(vec![vec![0; 441]], &["Speaker R"])
+            .is_err());
+        let recording = audio_instance.record_channels(0.01, &["Ref Mic"]).unwrap();
+        assert_eq!(recording.len(), 1);
+    }
+
+    #[test]
+    fn test_channel_groups() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 4))
+            .output_group("Speakers A", &[OutputChannel(3), OutputChannel(4)])
+            .build()
+            .unwrap();
+
+        assert!(audio_instance
+            .play_stereo(vec![0; 480], vec![0; 480], 2)
+            .is_ok());
+        assert!(audio_instance
+            .play_stereo(vec![0; 480], vec![0; 480], 3)
+            .is_err());
+        assert!(audio_instance
+            .play_group(vec![vec![0; 480]; 2], "Speakers A")
+            .is_ok());
+        assert!(audio_instance
+            .play_group(vec![vec![0; 480]], "Speakers A")
+            .is_err());
+        assert!(audio_instance
+            .play_group(vec![vec![0; 480]; 2], "Speakers B")
+            .is_err());
+    }
+
+    #[test]
+    fn test_single_direction() {
+        let output_only = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .output_only()
+            .build()
+            .unwrap();
+        assert_eq!(output_only.channels_in(), 0);
+        assert_eq!(output_only.channels_out(), 2);
+        output_only.play(vec![vec![0; 100]; 2]).unwrap();
+        assert!(output_only.record(0.01).is_err());
+
+        let input_only = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .input_only()
+            .build()
+            .unwrap();
+        assert_eq!(input_only.channels_in(), 2);
+        assert_eq!(input_only.channels_out(), 0);
+        assert_eq!(input_only.stream_state(), StreamState::Playing);
+        assert_eq!(input_only.record(0.01).unwrap().len(), 2);
+        assert!(input_only.play(vec![]).is_err());
+
+        assert!(AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .output_only()
+            .duplex(true)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_sample_rate_supported() {
+        let range = |min, max| {
+            cpal::SupportedStreamConfigRange::new(
+                2,
+                cpal::SampleRate(min),
+                cpal::SampleRate(max),
+                cpal::SupportedBufferSize::Unknown,
+                cpal::SampleFormat::I32,
+            )
+        };
+        let configs = || {
+            [
+                range(44100, 44100),
+                range(48000, 48000),
+                range(88200, 96000),
+            ]
+        };
+
+        assert_eq!(
+            check_sample_rate_supported(configs().into_iter(), 90000, false, "output").unwrap(),
+            90000
+        );
+        let error = check_sample_rate_supported(configs().into_iter(), 47000, false, "output")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("44100, 48000, 88200-96000 Hz"));
+        assert_eq!(
+            check_sample_rate_supported(configs().into_iter(), 47000, true, "output").unwrap(),
+            48000
+        );
+        assert_eq!(
+            check_sample_rate_supported(configs().into_iter(), 192000, true, "input").unwrap(),
+            96000
+        );
+        assert!(check_sample_rate_supported(std::iter::empty(), 48000, true, "input").is_err());
+    }
+
+    #[test]
+    fn test_select_config() {
+        let range = |channels, sample_format| {
+            cpal::SupportedStreamConfigRange::new(
+                channels,
+                cpal::SampleRate(44100),
+                cpal::SampleRate(96000),
+                cpal::SupportedBufferSize::Unknown,
+                sample_format,
+            )
+        };
+        let configs = || {
+            [
+                range(2, cpal::SampleFormat::I32),
+                range(8, cpal::SampleFormat::F32),
+                range(18, cpal::SampleFormat::F32),
+                range(18, cpal::SampleFormat::I32),
+            ]
+        };
+        let default_config =
+            range(2, cpal::SampleFormat::I32).with_sample_rate(cpal::SampleRate(48000));
+        let select = |channels| {
+            select_config(
+                configs().into_iter(),
+                default_config.clone(),
+                channels,
+                48000,
+                "input",
+            )
+        };
+
+        assert_eq!(select(ChannelCount::Default).unwrap(), default_config);
+        let config = select(ChannelCount::Maximum).unwrap();
+        assert_eq!(config.channels(), 18);
+        assert_eq!(config.sample_format(), cpal::SampleFormat::I32);
+        assert_eq!(config.sample_rate(), cpal::SampleRate(48000));
+        let config = select(ChannelCount::Exact(8)).unwrap();
+        assert_eq!(config.sample_format(), cpal::SampleFormat::F32);
+
+        let error = select(ChannelCount::Exact(4)).unwrap_err().to_string();
+        assert!(
+            error.contains("Supported channel counts: 2, 8, 18"),
+            "{error}"
+        );
+        // nothing runs at 192 kHz, so an exact count fails and the maximum falls back to the default
+        assert!(select_config(
+            configs().into_iter(),
+            default_config.clone(),
+            ChannelCount::Exact(2),
+            192000,
+            "input"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_drop() {
+        let audio_instance: AudioInstance = get_audio_instance();
+
+        // drop the instance
+        drop(audio_instance);
+    }
+}
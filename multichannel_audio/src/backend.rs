@@ -0,0 +1,527 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{mpsc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::channel::{InputChannel, OutputChannel};
+use crate::stream_controller::{InputCallback, OutputCallback, StreamCommand, StreamType};
+
+/// The pause between the buffers of a mock device, so an idle mock doesn't spin.
+const MOCK_BUFFER_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Runs the audio callbacks of an instance and starts and stops them.
+///
+/// `StreamController` runs the callbacks on a device through cpal, and `MockBackend` runs them on
+/// a simulated device so instances can be used without audio hardware.
+pub(crate) trait AudioBackend: Send + Sync {
+    /// Send a command to the streams and wait for them to carry it out.
+    ///
+    /// # Errors
+    /// Returns an error if the streams could not be started or stopped, or have been closed
+    fn send_command(&self, command: StreamCommand) -> Result<(), anyhow::Error>;
+
+    /// The state of the streams after the last command they carried out.
+    fn get_state(&self) -> StreamState;
+
+    /// Stop the streams and join the thread that runs them. Commands sent afterwards fail.
+    ///
+    /// # Errors
+    /// Returns an error if the thread panicked
+    fn close(&self) -> Result<(), anyhow::Error>;
+}
+
+/// The possible states of an audio stream.
+///
+/// Playing means the stream is currently running.
+///
+/// Stopped means the stream is not currently running.
+///
+/// Closed means the stream has been shut down and can't be started again.
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub enum StreamState {
+    Playing,
+    Stopped,
+    Closed,
+}
+
+/// A command sent to the thread of a `StreamWorker`, with the channel to acknowledge it on.
+pub(crate) struct CommandRequest {
+    pub command: StreamCommand,
+    reply: mpsc::Sender<Result<(), anyhow::Error>>,
+}
+
+impl CommandRequest {
+    /// Tell the sender whether the command was carried out.
+    pub fn reply(self, result: Result<(), anyhow::Error>) {
+        // the sender only stops waiting if it has been dropped
+        let _ = self.reply.send(result);
+    }
+}
+
+/// The thread that owns the streams of a backend and the channel its commands are sent on.
+pub(crate) struct StreamWorker {
+    command_sender: Mutex<Option<mpsc::Sender<CommandRequest>>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+    state: Mutex<StreamState>,
+}
+
+impl StreamWorker {
+    /// Spawn a thread that handles the commands sent to the worker. It must reply to every one.
+    pub fn spawn(run: impl FnOnce(mpsc::Receiver<CommandRequest>) + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || run(receiver));
+        StreamWorker {
+            command_sender: Mutex::new(Some(sender)),
+            handle: Mutex::new(Some(handle)),
+            state: Mutex::new(StreamState::Stopped),
+        }
+    }
+
+    /// Send a command to the thread and wait for it to be carried out.
+    ///
+    /// # Errors
+    /// Returns an error if the thread failed to carry out the command or has stopped
+    pub fn send(&self, command: StreamCommand) -> Result<(), anyhow::Error> {
+        let new_state = match command {
+            StreamCommand::Play => StreamState::Playing,
+            StreamCommand::Stop => StreamState::Stopped,
+        };
+        let (reply, response) = mpsc::channel();
+        {
+            let sender = self.command_sender.lock().unwrap();
+            let sender = sender
+                .as_ref()
+                .ok_or_else(|| anyhow::Error::msg("The streams have been closed"))?;
+            sender
+                .send(CommandRequest { command, reply })
+                .map_err(|_| anyhow::Error::msg("The stream thread has stopped"))?;
+        }
+        response
+            .recv()
+            .map_err(|_| anyhow::Error::msg("The stream thread has stopped"))??;
+
+        *self.state.lock().unwrap() = new_state;
+        Ok(())
+    }
+
+    pub fn state(&self) -> StreamState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Close the command channel and wait for the thread to handle the commands left in it.
+    ///
+    /// # Errors
+    /// Returns an error if the thread panicked
+    pub fn close(&self) -> Result<(), anyhow::Error> {
+        // the thread stops once the sender is dropped
+        self.command_sender.lock().unwrap().take();
+        *self.state.lock().unwrap() = StreamState::Closed;
+        match self.handle.lock().unwrap().take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| anyhow::Error::msg("The stream thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A simulated audio device that plays its output straight back into its input.
+///
+/// By default each output channel is copied to the input channel with the same number, after a
+/// delay and with noise added. Input channels without a matching output channel only get the
+/// noise. `channel` gives an input channel its own model instead. The noise comes from a seeded
+/// generator, so a mock device is the same on every run.
+///
+/// The buffers are run back to back instead of in real time, so tests don't wait for the audio.
+///
+/// # Example
+/// ```
+/// use multichannel_audio::backend::MockDevice;
+/// use multichannel_audio::builder::AudioInstanceBuilder;
+///
+/// let audio_instance = AudioInstanceBuilder::new()
+///     .mock(MockDevice::new(2, 2).delay(64))
+///     .build()
+///     .unwrap();
+/// let recording = audio_instance.record(0.1).unwrap();
+/// assert_eq!(recording.len(), 2);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockDevice {
+    pub(crate) input_channels: u16,
+    pub(crate) output_channels: u16,
+    pub(crate) buffer_frames: usize,
+    pub(crate) delay_frames: usize,
+    pub(crate) noise: i32,
+    pub(crate) seed: u64,
+    /// The models of input channels that don't use the default, by channel number
+    pub(crate) channel_models: BTreeMap<usize, ChannelModel>,
+}
+
+impl MockDevice {
+    /// A mock device with 256 frame buffers and no delay or noise.
+    ///
+    /// # Arguments
+    /// input_channels: u16 - the number of input channels
+    /// output_channels: u16 - the number of output channels
+    pub fn new(input_channels: u16, output_channels: u16) -> Self {
+        MockDevice {
+            input_channels,
+            output_channels,
+            buffer_frames: 256,
+            delay_frames: 0,
+            noise: 0,
+            seed: 1,
+            channel_models: BTreeMap::new(),
+        }
+    }
+
+    /// The number of frames between an output sample and its copy in the input, for every channel.
+    pub fn delay(mut self, frames: usize) -> Self {
+        self.delay_frames = frames;
+        self
+    }
+
+    /// Add noise to every input sample of every channel.
+    ///
+    /// # Arguments
+    /// amplitude: i32 - the peak amplitude of the noise
+    /// seed: u64 - the seed of the noise generator
+    pub fn noise(mut self, amplitude: i32, seed: u64) -> Self {
+        self.noise = amplitude.saturating_abs();
+        self.seed = seed;
+        self
+    }
+
+    /// The number of frames per callback.
+    pub fn buffer_frames(mut self, frames: usize) -> Self {
+        self.buffer_frames = frames.max(1);
+        self
+    }
+
+    /// Set what an input channel hears. Models of channels the device doesn't have are ignored.
+    ///
+    /// # Arguments
+    /// channel: InputChannel - the input channel
+    /// model: ChannelModel - the path from the outputs to the channel
+    pub fn channel(mut self, channel: InputChannel, model: ChannelModel) -> Self {
+        self.channel_models.insert(channel.0, model);
+        self
+    }
+
+    /// The model of an input channel.
+    fn channel_model(&self, channel: InputChannel) -> ChannelModel {
+        match self.channel_models.get(&channel.0) {
+            Some(&model) => model,
+            None => ChannelModel::from_output(OutputChannel(channel.0)),
+        }
+    }
+}
+
+/// The path from the outputs of a mock device to one of its inputs.
+///
+/// The delay and noise are added to those of the device.
+///
+/// # Example
+/// ```
+/// use multichannel_audio::backend::{ChannelModel, MockDevice};
+/// use multichannel_audio::channel::{InputChannel, OutputChannel};
+///
+/// // a microphone 1 ms from the speaker on output 1, and a loopback from output 2
+/// let device = MockDevice::new(2, 2)
+///     .channel(
+///         InputChannel(1),
+///         ChannelModel::from_output(OutputChannel(1)).delay(48).gain(0.25).noise(100),
+///     )
+///     .channel(InputChannel(2), ChannelModel::from_output(OutputChannel(2)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelModel {
+    /// The output channel the input hears, or None if it only hears noise
+    pub source: Option<OutputChannel>,
+    /// The delay from the output to the input in frames
+    pub delay_frames: usize,
+    /// The linear gain from the output to the input
+    pub gain: f64,
+    /// The peak amplitude of the noise added to the input
+    pub noise: i32,
+}
+
+impl ChannelModel {
+    /// An input that hears an output channel with no delay or gain.
+    pub fn from_output(channel: OutputChannel) -> Self {
+        ChannelModel {
+            source: Some(channel),
+            delay_frames: 0,
+            gain: 1.0,
+            noise: 0,
+        }
+    }
+
+    /// An input that doesn't hear any output.
+    pub fn unconnected() -> Self {
+        ChannelModel {
+            source: None,
+            ..Self::from_output(OutputChannel(1))
+        }
+    }
+
+    /// The delay from the output to the input in frames.
+    pub fn delay(mut self, frames: usize) -> Self {
+        self.delay_frames = frames;
+        self
+    }
+
+    /// The linear gain from the output to the input.
+    pub fn gain(mut self, gain: f64) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// The peak amplitude of the noise added to the input.
+    pub fn noise(mut self, amplitude: i32) -> Self {
+        self.noise = amplitude.saturating_abs();
+        self
+    }
+}
+
+/// Runs the callbacks of an instance on a thread in place of a device.
+pub(crate) struct MockBackend {
+    worker: StreamWorker,
+}
+
+impl Drop for MockBackend {
+    fn drop(&mut self) {
+        if let Err(error) = self.close() {
+            tracing::warn!(%error, "the mock device did not shut down cleanly");
+        }
+    }
+}
+
+impl MockBackend {
+    /// Spawn the thread that runs the callbacks.
+    ///
+    /// For a duplex instance, pass its `StreamType::Duplex` as both `output` and `input`.
+    pub fn new(
+        device: &MockDevice,
+        output: &StreamType,
+        input: &StreamType,
+        sample_rate: u32,
+    ) -> Self {
+        let output_channels = device.output_channels as usize;
+        // a device without inputs or outputs has no callback for them
+        let mut output_callback = OutputCallback::new(output, output_channels, sample_rate)
+            .filter(|_| output_channels > 0);
+        let mut input_callback =
+            InputCallback::new(input, device.input_channels as usize, sample_rate)
+                .filter(|_| device.input_channels > 0);
+        let mut loopback = Loopback::new(device);
+        let frames = device.buffer_frames;
+
+        let worker = StreamWorker::spawn(move |receiver| {
+            let mut output = vec![0; frames * output_channels];
+            let mut playing = false;
+            loop {
+                // wait for a command while stopped, and check for one between buffers while playing
+                let request = if playing {
+                    match receiver.try_recv() {
+                        Ok(request) => Some(request),
+                        Err(mpsc::TryRecvError::Empty) => None,
+                        Err(mpsc::TryRecvError::Disconnected) => return,
+                    }
+                } else {
+                    match receiver.recv() {
+                        Ok(request) => Some(request),
+                        Err(_) => return,
+                    }
+                };
+                if let Some(request) = request {
+                    playing = matches!(request.command, StreamCommand::Play);
+                    request.reply(Ok(()));
+                }
+                if !playing {
+                    continue;
+                }
+
+                if let Some(ref mut callback) = output_callback {
+                    callback.process(&mut output, None);
+                }
+                let input = loopback.run(&output, frames);
+                if let Some(ref mut callback) = input_callback {
+                    callback.process(&input, None);
+                }
+                thread::sleep(MOCK_BUFFER_INTERVAL);
+            }
+        });
+
+        MockBackend { worker }
+    }
+}
+
+impl AudioBackend for MockBackend {
+    fn send_command(&self, command: StreamCommand) -> Result<(), anyhow::Error> {
+        self.worker.send(command)
+    }
+
+    fn get_state(&self) -> StreamState {
+        self.worker.state()
+    }
+
+    fn close(&self) -> Result<(), anyhow::Error> {
+        // the mock thread stops when the command channel closes, whether it is playing or not
+        self.worker.close()
+    }
+}
+
+/// Copies the output of a mock device to its inputs.
+struct Loopback {
+    output_channels: usize,
+    channels: Vec<InputPath>,
+    /// The state of the xorshift noise generator, which must not be 0
+    random: u64,
+}
+
+/// The path from the outputs to one input of a mock device.
+struct InputPath {
+    /// The index of the output channel the input hears
+    source: Option<usize>,
+    gain: f64,
+    noise: i32,
+    /// Samples waiting for the delay to pass
+    delay_line: VecDeque<i32>,
+}
+
+impl Loopback {
+    fn new(device: &MockDevice) -> Self {
+        let channels = (1..=device.input_channels as usize)
+            .map(|channel| {
+                let model = device.channel_model(InputChannel(channel));
+                InputPath {
+                    source: model.source.and_then(|source| source.index().ok()),
+                    gain: model.gain,
+                    noise: model.noise.saturating_add(device.noise),
+                    delay_line: std::iter::repeat(0)
+                        .take(model.delay_frames + device.delay_frames)
+                        .collect(),
+                }
+            })
+            .collect();
+        Loopback {
+            output_channels: device.output_channels as usize,
+            channels,
+            random: device.seed.max(1),
+        }
+    }
+
+    /// The input for a buffer of output.
+    fn run(&mut self, output: &[i32], frames: usize) -> Vec<i32> {
+        let mut input = Vec::with_capacity(frames * self.channels.len());
+        for frame in 0..frames {
+            for path in self.channels.iter_mut() {
+                let sample = match path.source {
+                    Some(source) if source < self.output_channels => {
+                        (output[frame * self.output_channels + source] as f64 * path.gain).round()
+                            as i32
+                    }
+                    _ => 0,
+                };
+                let noise = next_noise(&mut self.random, path.noise);
+                path.delay_line.push_back(sample.saturating_add(noise));
+                input.push(path.delay_line.pop_front().unwrap_or_default());
+            }
+        }
+        input
+    }
+}
+
+/// Uniform noise up to an amplitude from a xorshift generator.
+fn next_noise(random: &mut u64, amplitude: i32) -> i32 {
+    if amplitude == 0 {
+        return 0;
+    }
+    *random ^= *random << 13;
+    *random ^= *random >> 7;
+    *random ^= *random << 17;
+    let range = 2 * amplitude as u64 + 1;
+    ((*random % range) as i64 - amplitude as i64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::AudioInstanceBuilder;
+
+    #[test]
+    fn test_loopback_delay_and_noise() {
+        let mut loopback = Loopback::new(&MockDevice::new(3, 2).delay(2));
+        let input = loopback.run(&[1, 2, 3, 4], 2);
+        assert_eq!(input, vec![0; 6]);
+        let input = loopback.run(&[0; 4], 2);
+        assert_eq!(input, vec![1, 2, 0, 3, 4, 0]);
+
+        // input 1 hears output 2 one frame later at half the level
+        let device = MockDevice::new(2, 2).channel(
+            InputChannel(1),
+            ChannelModel::from_output(OutputChannel(2))
+                .delay(1)
+                .gain(0.5),
+        );
+        let input = Loopback::new(&device).run(&[10, 20, 30, 40], 2);
+        assert_eq!(input, vec![0, 20, 10, 40]);
+
+        let device = MockDevice::new(1, 1).noise(10, 42);
+        let noise = Loopback::new(&device).run(&[0; 64], 64);
+        assert!(noise.iter().all(|sample| sample.abs() <= 10));
+        assert!(noise.iter().any(|&sample| sample != 0));
+        assert_eq!(Loopback::new(&device).run(&[0; 64], 64), noise);
+    }
+
+    #[test]
+    fn test_mock_play_record() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2).delay(100).buffer_frames(64))
+            .duplex(true)
+            .build()
+            .unwrap();
+
+        let mut impulse = vec![vec![0; 1000]; 2];
+        impulse[0][0] = 1000;
+        impulse[1][10] = -1000;
+        let recording = audio_instance.play_record(impulse).unwrap();
+        assert_eq!(recording[0].len(), 1000);
+        assert_eq!(recording[0][100], 1000);
+        assert_eq!(recording[1][110], -1000);
+        assert_eq!(
+            recording[0].iter().filter(|&&sample| sample != 0).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_close() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .build()
+            .unwrap();
+        let clone = audio_instance.clone();
+        assert_eq!(audio_instance.record(0.01).unwrap().len(), 2);
+        assert_eq!(audio_instance.stream_state(), StreamState::Playing);
+
+        audio_instance.close().unwrap();
+        assert_eq!(clone.stream_state(), StreamState::Closed);
+        assert!(clone.record(0.01).is_err());
+        assert!(clone.play(vec![vec![0; 10]; 2]).is_err());
+
+        let worker = StreamWorker::spawn(|receiver| {
+            if receiver.recv().is_ok() {
+                panic!("the worker failed");
+            }
+        });
+        assert!(worker.send(StreamCommand::Play).is_err());
+        assert_eq!(worker.state(), StreamState::Stopped);
+        assert!(worker.close().is_err());
+        assert_eq!(worker.state(), StreamState::Closed);
+        assert!(worker.close().is_ok());
+        assert!(worker.send(StreamCommand::Play).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::channel::OutputChannel;
+use crate::conversions::{db_to_linear, linear_to_db};
+
+impl AudioInstance {
+    /// Start a continuous background signal, e.g. masking noise, on some of the output channels.
+    ///
+    /// The background loops without a gap and is mixed underneath everything played with `play`,
+    /// `play_record` and the other playback functions, so the foreground stimulus still starts and
+    /// stops on exact samples. Calling this again replaces the background. This function returns
+    /// immediately.
+    ///
+    /// # Arguments
+    /// signal: Vec<Vec<i32>> - one vector of samples per channel in `channels`. All channels must be the same length.
+    /// channels: &[OutputChannel] - the output channels to play the background on
+    ///
+    /// # Errors
+    /// Returns an error if the number of signals and channels differ, a channel is out of range or
+    /// the signal is empty
+    pub fn play_background(
+        &self,
+        signal: Vec<Vec<i32>>,
+        channels: &[OutputChannel],
+    ) -> Result<(), anyhow::Error> {
+        if signal.len() != channels.len() {
+            return Err(anyhow::anyhow!(
+                "Number of channels does not match\n\tExpected: {}, Actual: {}",
+                channels.len(),
+                signal.len()
+            ));
+        }
+        let length = signal.first().map_or(0, |channel| channel.len());
+        if length == 0 {
+            return Err(anyhow::Error::msg("Background signal is empty"));
+        }
+        if signal.iter().any(|channel| channel.len() != length) {
+            return Err(anyhow::Error::msg("All channels must be the same length"));
+        }
+
+        // spread the signal over every output channel, silent where there is no background
+        let mut output_data = vec![vec![0i32; length]; self.number_of_output_channels as usize];
+        for (&channel, samples) in channels.iter().zip(signal) {
+            output_data[self.output_index(channel)?.get()] = samples;
+        }
+        self.check_interlock(&output_data)?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        let flattened_output_data = self.flatten_output_data(output_data);
+        self.background.set_signal(flattened_output_data);
+
+        Ok(())
+    }
+
+    /// Stop the background signal.
+    pub fn stop_background(&self) {
+        self.background.set_signal(Vec::new());
+    }
+
+    /// Set the gain of the background signal, independent of the foreground.
+    ///
+    /// The change takes effect at the next audio callback.
+    ///
+    /// # Arguments
+    /// gain_db: f64 - the gain in dB. 0 dB plays the background at the level it was given
+    pub fn set_background_gain(&self, gain_db: f64) {
+        self.background.set_gain(db_to_linear(gain_db) as f32);
+    }
+
+    /// The gain of the background signal in dB.
+    pub fn background_gain(&self) -> f64 {
+        linear_to_db(self.background.gain() as f64)
+    }
+}
@@ -0,0 +1,166 @@
+use std::f64::consts::PI;
+
+/// A second order IIR filter section.
+///
+/// The coefficients are normalized so a0 is 1. The filter keeps its state between calls to
+/// `process`, so a signal can be filtered in blocks as it arrives. Call `reset` before filtering
+/// an unrelated signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /// Create a filter from the coefficients of its transfer function.
+    ///
+    /// # Errors
+    /// Returns an error if a0 is zero
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Result<Self, anyhow::Error> {
+        let a0 = a[0];
+        if a0 == 0.0 {
+            return Err(anyhow::Error::msg("The a0 coefficient can't be zero"));
+        }
+        Ok(Biquad {
+            b0: b[0] / a0,
+            b1: b[1] / a0,
+            b2: b[2] / a0,
+            a1: a[1] / a0,
+            a2: a[2] / a0,
+            z1: 0.0,
+            z2: 0.0,
+        })
+    }
+
+    /// A high-pass filter, e.g. to protect a speaker from frequencies below its range.
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate
+    /// cutoff: f64 - the -3 dB frequency in Hz for a Q of 0.707
+    /// q: f64 - the quality factor. 0.707 gives a Butterworth response
+    pub fn high_pass(fs: u32, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::intermediates(fs, cutoff, q);
+        Self::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// A low-pass filter.
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate
+    /// cutoff: f64 - the -3 dB frequency in Hz for a Q of 0.707
+    /// q: f64 - the quality factor. 0.707 gives a Butterworth response
+    pub fn low_pass(fs: u32, cutoff: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::intermediates(fs, cutoff, q);
+        Self::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// A peaking EQ filter that boosts or cuts around a centre frequency.
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate
+    /// centre: f64 - the centre frequency in Hz
+    /// q: f64 - the quality factor, which sets the bandwidth
+    /// gain_db: f64 - the gain at the centre frequency
+    pub fn peaking(fs: u32, centre: f64, q: f64, gain_db: f64) -> Self {
+        let (cos, alpha) = Self::intermediates(fs, centre, q);
+        let a = 10f64.powf(gain_db / 40.0);
+        Self::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    /// Filter samples in place, continuing from the state left by the previous call.
+    ///
+    /// The output saturates rather than wrapping if the filter adds gain.
+    pub fn process(&mut self, samples: &mut [i32]) {
+        for sample in samples.iter_mut() {
+            *sample = self.process_sample(*sample as f64).round() as i32;
+        }
+    }
+
+    /// Filter one sample. Uses the transposed direct form II.
+    pub fn process_sample(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    /// Clear the state so the next sample is filtered as the start of a new signal.
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    /// The cosine of the normalized frequency and the alpha of the audio EQ cookbook.
+    fn intermediates(fs: u32, frequency: f64, q: f64) -> (f64, f64) {
+        let w0 = 2.0 * PI * frequency / fs as f64;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        // a0 is always positive for the cookbook filters
+        Self::new(b, a).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods::generate_sine_wave;
+
+    fn half_scale_sine(frequency: u32, duration: f32) -> Vec<i32> {
+        generate_sine_wave(frequency, duration, 48000)
+            .into_iter()
+            .map(|x| x / 2)
+            .collect()
+    }
+
+    fn rms(samples: &[i32]) -> f64 {
+        (samples.iter().map(|&x| (x as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn test_high_pass() {
+        let mut low = half_scale_sine(20, 1.0);
+        let mut high = half_scale_sine(1000, 1.0);
+        let (low_before, high_before) = (rms(&low), rms(&high));
+
+        let mut filter = Biquad::high_pass(48000, 200.0, 0.707);
+        filter.process(&mut low);
+        filter.reset();
+        filter.process(&mut high);
+
+        // 20 Hz is more than three octaves below the cutoff of a 12 dB/octave filter
+        assert!(rms(&low[24000..]) < low_before / 50.0);
+        assert!((rms(&high[24000..]) / high_before - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_peaking_gain() {
+        let mut signal = half_scale_sine(1000, 1.0);
+        let before = rms(&signal);
+
+        Biquad::peaking(48000, 1000.0, 1.0, -6.0).process(&mut signal);
+
+        let gain_db = 20.0 * (rms(&signal[24000..]) / before).log10();
+        assert!((gain_db + 6.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_invalid_coefficients() {
+        assert!(Biquad::new([1.0, 0.0, 0.0], [0.0, 0.0, 0.0]).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::audio_class::{AudioInstance, BufferSize, ChannelCount};
+use crate::backend::MockDevice;
+use crate::callback_load::StreamDirection;
+use crate::channel::{ChannelLabels, InputChannel, OutputChannel};
+use crate::context::AudioContext;
+
+/// Configuration for creating an `AudioInstance`.
+///
+/// Options that aren't set use the device set with `set_host_and_audio_device` and its default
+/// configuration, or the default device of the context if one is set.
+///
+/// # Example
+/// ```no_run
+/// use multichannel_audio::audio_class::BufferSize;
+/// use multichannel_audio::builder::AudioInstanceBuilder;
+///
+/// let audio_instance = AudioInstanceBuilder::new()
+///     .sample_rate(96000)
+///     .buffer_size(BufferSize::Fixed(256))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInstanceBuilder {
+    pub(crate) context: Option<AudioContext>,
+    pub(crate) device: Option<String>,
+    pub(crate) input_device: Option<String>,
+    pub(crate) sample_rate: u32,
+    pub(crate) nearest_sample_rate: bool,
+    pub(crate) buffer_size: BufferSize,
+    pub(crate) input_channels: ChannelCount,
+    pub(crate) output_channels: ChannelCount,
+    pub(crate) duplex: bool,
+    /// The only direction to open a stream for, or None for both
+    pub(crate) direction: Option<StreamDirection>,
+    pub(crate) mock: Option<MockDevice>,
+    pub(crate) labels: ChannelLabels,
+}
+
+impl Default for AudioInstanceBuilder {
+    fn default() -> Self {
+        AudioInstanceBuilder {
+            context: None,
+            device: None,
+            input_device: None,
+            sample_rate: 48000,
+            nearest_sample_rate: false,
+            buffer_size: BufferSize::Default,
+            input_channels: ChannelCount::Default,
+            output_channels: ChannelCount::Default,
+            duplex: false,
+            direction: None,
+            mock: None,
+            labels: ChannelLabels::new(),
+        }
+    }
+}
+
+impl AudioInstanceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the host of a context instead of the global `HOST`. See `AudioContext`.
+    pub fn context(mut self, context: &AudioContext) -> Self {
+        self.context = Some(context.clone());
+        self
+    }
+
+    /// Use a device by name instead of the one set with `set_host_and_audio_device`.
+    /// The device is used for input too, unless `input_device` is set.
+    pub fn device(mut self, name: &str) -> Self {
+        self.device = Some(name.to_string());
+        self
+    }
+
+    /// Record from a different device to the one used for playback.
+    pub fn input_device(mut self, name: &str) -> Self {
+        self.input_device = Some(name.to_string());
+        self
+    }
+
+    /// The sample rate of the streams. The default is 48 kHz.
+    pub fn sample_rate(mut self, fs: u32) -> Self {
+        self.sample_rate = fs;
+        self
+    }
+
+    /// Use the supported sample rate nearest to `sample_rate` if the device doesn't support it,
+    /// instead of returning an error. The rate used is available from `AudioInstance::sample_rate`.
+    pub fn nearest_sample_rate(mut self, nearest: bool) -> Self {
+        self.nearest_sample_rate = nearest;
+        self
+    }
+
+    /// The number of frames per callback to request from the driver.
+    pub fn buffer_size(mut self, buffer_size: BufferSize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// The number of input channels to open, instead of the default of the device. Pass a number,
+    /// or `ChannelCount::Maximum` to open every input, e.g. for an ASIO interface whose driver
+    /// defaults to 2 channels.
+    pub fn input_channels(mut self, channels: impl Into<ChannelCount>) -> Self {
+        self.input_channels = channels.into();
+        self
+    }
+
+    /// The number of output channels to open, instead of the default of the device. Pass a
+    /// number, or `ChannelCount::Maximum` to open every output.
+    pub fn output_channels(mut self, channels: impl Into<ChannelCount>) -> Self {
+        self.output_channels = channels.into();
+        self
+    }
+
+    /// Play and record with a single duplex stream controller. See `AudioInstance::new_duplex`.
+    pub fn duplex(mut self, duplex: bool) -> Self {
+        self.duplex = duplex;
+        self
+    }
+
+    /// Only open an output stream, for devices without inputs. See `AudioInstance::new_output_only`.
+    pub fn output_only(mut self) -> Self {
+        self.direction = Some(StreamDirection::Output);
+        self
+    }
+
+    /// Only open an input stream, for devices without outputs. See `AudioInstance::new_input_only`.
+    pub fn input_only(mut self) -> Self {
+        self.direction = Some(StreamDirection::Input);
+        self
+    }
+
+    /// Run the instance on a simulated device instead of a real one, e.g. to test without audio
+    /// hardware. The host and device options are ignored. See `MockDevice`.
+    pub fn mock(mut self, device: MockDevice) -> Self {
+        self.mock = Some(device);
+        self
+    }
+
+    /// Name an input channel, so the record and alignment functions accept the label in place of
+    /// the channel number, e.g. `"Ref Mic"`.
+    pub fn input_label(mut self, label: &str, channel: InputChannel) -> Self {
+        self.labels.set_input(label, channel);
+        self
+    }
+
+    /// Name an output channel, so the play and alignment functions accept the label in place of
+    /// the channel number, e.g. `"Loopback"`.
+    pub fn output_label(mut self, label: &str, channel: OutputChannel) -> Self {
+        self.labels.set_output(label, channel);
+        self
+    }
+
+    /// Name a group of output channels, so `play_group` can play them by name, e.g.
+    /// `"Speakers A"` for outputs 3 and 4.
+    pub fn output_group(mut self, label: &str, channels: &[OutputChannel]) -> Self {
+        self.labels.set_output_group(label, channels);
+        self
+    }
+
+    /// Use a set of channel labels, replacing any labels set before. See `AudioConfig::channel_labels`.
+    pub fn channel_labels(mut self, labels: ChannelLabels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Create the audio instance.
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    /// Returns an error if the device is not found
+    /// Returns an error if the device does not support the sample rate, buffer size or channel
+    /// counts
+    /// Returns `DeviceLockError::DeviceLockedByOtherProcess` if another process is using the device
+    pub fn build(&self) -> Result<AudioInstance, anyhow::Error> {
+        AudioInstance::create(self.clone())
+    }
+
+    /// Whether the instance has an output stream.
+    pub(crate) fn has_output(&self) -> bool {
+        self.direction != Some(StreamDirection::Input)
+    }
+
+    /// Whether the instance has an input stream.
+    pub(crate) fn has_input(&self) -> bool {
+        self.direction != Some(StreamDirection::Output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_options() {
+        let builder = AudioInstanceBuilder::new()
+            .device("Focusrite")
+            .sample_rate(96000)
+            .buffer_size(BufferSize::Fixed(128))
+            .output_channels(8)
+            .output_label("Loopback", OutputChannel(8))
+            .duplex(true);
+
+        assert_eq!(builder.device.as_deref(), Some("Focusrite"));
+        assert_eq!(builder.input_device, None);
+        assert_eq!(builder.sample_rate, 96000);
+        assert_eq!(builder.buffer_size, BufferSize::Fixed(128));
+        assert_eq!(builder.output_channels, ChannelCount::Exact(8));
+        assert_eq!(builder.input_channels, ChannelCount::Default);
+        let builder = builder.input_channels(ChannelCount::Maximum);
+        assert_eq!(builder.input_channels, ChannelCount::Maximum);
+        assert!(builder.duplex);
+        assert!(builder.has_output() && builder.has_input());
+        assert_eq!(
+            builder.labels.output(&"Loopback").unwrap(),
+            OutputChannel(8)
+        );
+
+        let builder = AudioInstanceBuilder::new().output_only();
+        assert!(builder.has_output());
+        assert!(!builder.has_input());
+        let builder = builder.input_only();
+        assert!(!builder.has_output());
+        assert!(builder.has_input());
+    }
+}
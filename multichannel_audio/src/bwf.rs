@@ -0,0 +1,335 @@
+use std::io::Cursor;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::methods::merge_channels;
+
+/// The lengths of the fixed text fields of a bext chunk.
+const DESCRIPTION_LENGTH: usize = 256;
+const ORIGINATOR_LENGTH: usize = 32;
+const REFERENCE_LENGTH: usize = 32;
+const DATE_LENGTH: usize = 10;
+const TIME_LENGTH: usize = 8;
+/// The length of the UMID and the reserved bytes after it, which are left as zeros.
+const UMID_LENGTH: usize = 64;
+const RESERVED_LENGTH: usize = 190;
+/// The length of a bext chunk without its coding history.
+const BEXT_LENGTH: usize = DESCRIPTION_LENGTH
+    + ORIGINATOR_LENGTH
+    + REFERENCE_LENGTH
+    + DATE_LENGTH
+    + TIME_LENGTH
+    + 8
+    + 2
+    + UMID_LENGTH
+    + RESERVED_LENGTH;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// The Broadcast Wave (bext) metadata of a WAV file.
+///
+/// The time reference places the first sample of the file on a timeline, so recordings from the
+/// same measurement can be lined up and traced back to when they were made.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BroadcastExtension {
+    /// A free text description of the recording, up to 256 characters
+    pub description: String,
+    /// The program or organisation that made the file, up to 32 characters
+    pub originator: String,
+    /// A reference to the recording, e.g. a serial number, up to 32 characters
+    pub originator_reference: String,
+    /// The date the recording was made, as yyyy-mm-dd
+    pub origination_date: String,
+    /// The time the recording was made, as hh:mm:ss
+    pub origination_time: String,
+    /// The number of samples since midnight at the first sample of the file
+    pub time_reference: u64,
+    /// The processing the audio has been through, one line per step
+    pub coding_history: String,
+}
+
+impl BroadcastExtension {
+    /// Metadata for a recording made at `timestamp`, with the date, time and time reference in UTC.
+    ///
+    /// # Arguments
+    /// originator: &str - the program or organisation making the file
+    /// timestamp: SystemTime - when the first sample was recorded
+    /// sample_rate: u32 - the sample rate of the recording, for the time reference
+    pub fn new(originator: &str, timestamp: SystemTime, sample_rate: u32) -> Self {
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let days = since_epoch.as_secs() / SECONDS_PER_DAY;
+        let seconds = since_epoch.as_secs() % SECONDS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+
+        BroadcastExtension {
+            originator: originator.to_string(),
+            origination_date: format!("{:04}-{:02}-{:02}", year, month, day),
+            origination_time: format!(
+                "{:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ),
+            time_reference: ((seconds as f64 + since_epoch.subsec_nanos() as f64 * 1e-9)
+                * sample_rate as f64) as u64,
+            ..Self::default()
+        }
+    }
+
+    /// Add a line to the coding history, e.g. "A=PCM,F=48000,W=32,M=mono,T=multichannel_audio".
+    pub fn add_coding_history(&mut self, line: &str) {
+        self.coding_history.push_str(line);
+        self.coding_history.push_str("\r\n");
+    }
+
+    /// The body of the bext chunk. Text fields longer than their field are truncated.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(BEXT_LENGTH + self.coding_history.len());
+        write_text(&mut bytes, &self.description, DESCRIPTION_LENGTH);
+        write_text(&mut bytes, &self.originator, ORIGINATOR_LENGTH);
+        write_text(&mut bytes, &self.originator_reference, REFERENCE_LENGTH);
+        write_text(&mut bytes, &self.origination_date, DATE_LENGTH);
+        write_text(&mut bytes, &self.origination_time, TIME_LENGTH);
+        bytes.extend_from_slice(&self.time_reference.to_le_bytes());
+        // version 1 of the chunk, which has no loudness fields
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.resize(bytes.len() + UMID_LENGTH + RESERVED_LENGTH, 0);
+        bytes.extend_from_slice(self.coding_history.as_bytes());
+        bytes
+    }
+
+    /// Parse the body of a bext chunk.
+    ///
+    /// # Errors
+    /// Returns an error if the chunk is too short
+    fn from_bytes(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        if bytes.len() < BEXT_LENGTH {
+            return Err(anyhow::anyhow!(
+                "The bext chunk is {} bytes, but must be at least {}",
+                bytes.len(),
+                BEXT_LENGTH
+            ));
+        }
+        let mut fields = bytes;
+        let mut text = |length| {
+            let (field, rest) = fields.split_at(length);
+            fields = rest;
+            read_text(field)
+        };
+        let description = text(DESCRIPTION_LENGTH);
+        let originator = text(ORIGINATOR_LENGTH);
+        let originator_reference = text(REFERENCE_LENGTH);
+        let origination_date = text(DATE_LENGTH);
+        let origination_time = text(TIME_LENGTH);
+
+        let offset = BEXT_LENGTH - 8 - 2 - UMID_LENGTH - RESERVED_LENGTH;
+        let time_reference = u64::from_le_bytes(bytes[offset..offset + 8].try_into()?);
+
+        Ok(BroadcastExtension {
+            description,
+            originator,
+            originator_reference,
+            origination_date,
+            origination_time,
+            time_reference,
+            coding_history: read_text(&bytes[BEXT_LENGTH..]),
+        })
+    }
+}
+
+/// The format and metadata of a WAV file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavMetadata {
+    pub spec: hound::WavSpec,
+    /// The number of frames of audio in the file
+    pub frames: u32,
+    /// The Broadcast Wave metadata, if the file has any
+    pub bext: Option<BroadcastExtension>,
+}
+
+/// Save multiple channels to a Broadcast Wave file, a WAV file with a bext chunk.
+///
+/// The samples are saved as by `save_channels_to_wav`, and can be read back with the same
+/// readers.
+///
+/// # Errors
+/// Returns an error if the file can't be written
+pub fn save_channels_to_bwf(
+    data: Vec<Vec<i32>>,
+    filename: &str,
+    sample_rate: u32,
+    bext: &BroadcastExtension,
+) -> Result<(), anyhow::Error> {
+    std::fs::write(filename, bwf_bytes(data, sample_rate, bext)?)?;
+    Ok(())
+}
+
+/// Encode multiple channels as a Broadcast Wave file in memory.
+///
+/// # Errors
+/// Returns an error if the samples can't be encoded
+pub fn bwf_bytes(
+    data: Vec<Vec<i32>>,
+    sample_rate: u32,
+    bext: &BroadcastExtension,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let spec = hound::WavSpec {
+        channels: data.len() as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut wav, spec)?;
+    for sample in merge_channels(data) {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    let wav = wav.into_inner();
+
+    // the bext chunk goes after the format chunk, before the data chunk
+    let data_position = find_chunk(&wav, b"data")?
+        .map(|(position, _)| position)
+        .ok_or_else(|| anyhow::Error::msg("The WAV file has no data chunk"))?;
+    let mut chunk = bext.to_bytes();
+    // end an odd length coding history with a zero rather than a pad byte outside the chunk,
+    // since hound doesn't skip pad bytes
+    if chunk.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    let length = chunk.len() as u32;
+    let mut bytes = Vec::with_capacity(wav.len() + chunk.len() + 8);
+    bytes.extend_from_slice(&wav[..data_position]);
+    bytes.extend_from_slice(b"bext");
+    bytes.extend_from_slice(&length.to_le_bytes());
+    bytes.extend_from_slice(&chunk);
+    bytes.extend_from_slice(&wav[data_position..]);
+
+    let riff_length = (bytes.len() - 8) as u32;
+    bytes[4..8].copy_from_slice(&riff_length.to_le_bytes());
+    Ok(bytes)
+}
+
+/// Read the format and metadata of a WAV file from a file path.
+///
+/// # Errors
+/// Returns an error if the file can't be read or is not a valid WAV file
+pub fn read_wav_metadata(filepath: &Path) -> Result<WavMetadata, anyhow::Error> {
+    read_wav_metadata_bytes(&std::fs::read(filepath)?)
+}
+
+/// Read the format and metadata of a WAV file from a byte array.
+///
+/// # Errors
+/// Returns an error if the bytes are not a valid WAV file
+pub fn read_wav_metadata_bytes(byte_data: &[u8]) -> Result<WavMetadata, anyhow::Error> {
+    let reader = hound::WavReader::new(Cursor::new(byte_data))?;
+
+    let bext = match find_chunk(byte_data, b"bext")? {
+        Some((position, length)) => {
+            let start = position + 8;
+            let end = (start + length).min(byte_data.len());
+            Some(BroadcastExtension::from_bytes(&byte_data[start..end])?)
+        }
+        None => None,
+    };
+
+    Ok(WavMetadata {
+        spec: reader.spec(),
+        frames: reader.duration(),
+        bext,
+    })
+}
+
+/// The position and length of the first chunk of a kind in a RIFF file, if it has one.
+fn find_chunk(bytes: &[u8], id: &[u8; 4]) -> Result<Option<(usize, usize)>, anyhow::Error> {
+    // skip the RIFF header
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let length = u32::from_le_bytes(bytes[position + 4..position + 8].try_into()?) as usize;
+        if &bytes[position..position + 4] == id {
+            return Ok(Some((position, length)));
+        }
+        // chunks are padded to an even length
+        position += 8 + length + length % 2;
+    }
+    Ok(None)
+}
+
+/// Write text to a fixed length field, truncated or padded with zeros.
+fn write_text(bytes: &mut Vec<u8>, text: &str, length: usize) {
+    let text = text.as_bytes();
+    let text = &text[..text.len().min(length)];
+    bytes.extend_from_slice(text);
+    bytes.resize(bytes.len() + length - text.len(), 0);
+}
+
+/// Read text from a field, up to the first zero.
+fn read_text(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// The year, month and day of a number of days since 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // shift the epoch to 0000-03-01, so leap days are at the end of each year
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods::split_interleaved_wav;
+    use std::time::Duration;
+
+    #[test]
+    fn test_broadcast_extension_timestamp() {
+        // 2024-02-29 13:45:30.5 UTC
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_709_214_330_500);
+        let bext = BroadcastExtension::new("multichannel_audio", timestamp, 48000);
+        assert_eq!(bext.origination_date, "2024-02-29");
+        assert_eq!(bext.origination_time, "13:45:30");
+        assert_eq!(bext.time_reference, 49530 * 48000 + 24000);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+    }
+
+    #[test]
+    fn test_bwf_round_trip() {
+        let mut bext = BroadcastExtension::new("multichannel_audio", SystemTime::now(), 48000);
+        bext.description = "x".repeat(300);
+        bext.originator_reference = "DUT 0042".to_string();
+        bext.add_coding_history("A=PCM,F=48000,W=32,M=stereo");
+
+        let data = vec![vec![1, 2, 3], vec![-1, -2, -3]];
+        let bytes = bwf_bytes(data.clone(), 48000, &bext).unwrap();
+
+        let metadata = read_wav_metadata_bytes(&bytes).unwrap();
+        assert_eq!(metadata.spec.channels, 2);
+        assert_eq!(metadata.frames, 3);
+        let read = metadata.bext.unwrap();
+        assert_eq!(read.description.len(), 256);
+        assert_eq!(read.originator_reference, "DUT 0042");
+        assert_eq!(read.time_reference, bext.time_reference);
+        assert_eq!(read.coding_history, bext.coding_history);
+
+        // the samples read back as from a plain WAV file
+        assert_eq!(split_interleaved_wav(bytes, 48000).unwrap(), data);
+    }
+}
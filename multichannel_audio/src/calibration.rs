@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::channel::InputChannel;
+
+/// The reference pressure for dB SPL, in pascals.
+pub const REFERENCE_PRESSURE: f64 = 20e-6;
+
+/// The first line of a calibration file.
+const FILE_HEADER: &str = "# multichannel_audio calibration v1";
+
+/// Per-input-channel sensitivities for converting recordings to sound pressure.
+///
+/// A sensitivity is the pressure in pascals that gives a full-scale sample. It is usually measured
+/// by recording a calibrator tone of a known level with `calibrate_channel`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Calibration {
+    sensitivities: BTreeMap<InputChannel, f64>,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the sensitivity of a channel directly.
+    ///
+    /// # Arguments
+    /// channel: InputChannel - the channel to set
+    /// pascals_per_full_scale: f64 - the pressure that gives a full-scale sample
+    ///
+    /// # Errors
+    /// Returns an error if the sensitivity is not a positive number
+    pub fn set_sensitivity(
+        &mut self,
+        channel: InputChannel,
+        pascals_per_full_scale: f64,
+    ) -> Result<(), anyhow::Error> {
+        channel.index()?;
+        if !pascals_per_full_scale.is_finite() || pascals_per_full_scale <= 0.0 {
+            return Err(anyhow::anyhow!(
+                "The sensitivity of {} must be positive, got {}",
+                channel,
+                pascals_per_full_scale
+            ));
+        }
+        self.sensitivities.insert(channel, pascals_per_full_scale);
+        Ok(())
+    }
+
+    /// The sensitivity of a channel in pascals per full scale, if it has been calibrated.
+    pub fn sensitivity(&self, channel: InputChannel) -> Option<f64> {
+        self.sensitivities.get(&channel).copied()
+    }
+
+    /// The calibrated channels, in order.
+    pub fn channels(&self) -> impl Iterator<Item = InputChannel> + '_ {
+        self.sensitivities.keys().copied()
+    }
+
+    /// Calibrate a channel from a recording of a calibrator tone, e.g. 94 dB SPL at 1 kHz.
+    ///
+    /// # Arguments
+    /// channel: InputChannel - the channel the calibrator was recorded on
+    /// recording: &[i32] - the recording of the calibrator tone
+    /// reference_db_spl: f64 - the level of the calibrator
+    ///
+    /// # Returns
+    /// The sensitivity of the channel in pascals per full scale
+    ///
+    /// # Errors
+    /// Returns an error if the recording is silent
+    pub fn calibrate_channel(
+        &mut self,
+        channel: InputChannel,
+        recording: &[i32],
+        reference_db_spl: f64,
+    ) -> Result<f64, anyhow::Error> {
+        let level = rms_full_scale(recording);
+        if level == 0.0 {
+            return Err(anyhow::anyhow!(
+                "The calibration recording on {} is silent",
+                channel
+            ));
+        }
+
+        let pressure = REFERENCE_PRESSURE * 10f64.powf(reference_db_spl / 20.0);
+        let sensitivity = pressure / level;
+        self.set_sensitivity(channel, sensitivity)?;
+        Ok(sensitivity)
+    }
+
+    /// Convert a recording on a channel to pascals.
+    ///
+    /// # Errors
+    /// Returns an error if the channel has not been calibrated
+    pub fn to_pascals(
+        &self,
+        channel: InputChannel,
+        recording: &[i32],
+    ) -> Result<Vec<f64>, anyhow::Error> {
+        let sensitivity = self.get(channel)?;
+        Ok(recording
+            .iter()
+            .map(|&sample| sample as f64 / i32::MAX as f64 * sensitivity)
+            .collect())
+    }
+
+    /// Convert a multichannel recording to pascals.
+    ///
+    /// # Arguments
+    /// channels: &[InputChannel] - the input channel each recorded channel came from
+    /// recording: &[Vec<i32>] - the recorded channels
+    ///
+    /// # Errors
+    /// Returns an error if the number of channels doesn't match the recording
+    /// Returns an error if a channel has not been calibrated
+    pub fn to_pascals_channels(
+        &self,
+        channels: &[InputChannel],
+        recording: &[Vec<i32>],
+    ) -> Result<Vec<Vec<f64>>, anyhow::Error> {
+        if channels.len() != recording.len() {
+            return Err(anyhow::anyhow!(
+                "Got {} channel numbers for a recording with {} channels",
+                channels.len(),
+                recording.len()
+            ));
+        }
+        channels
+            .iter()
+            .zip(recording)
+            .map(|(&channel, samples)| self.to_pascals(channel, samples))
+            .collect()
+    }
+
+    /// Convert a level in dBFS on a channel to dB SPL.
+    ///
+    /// # Errors
+    /// Returns an error if the channel has not been calibrated
+    pub fn dbfs_to_db_spl(&self, channel: InputChannel, dbfs: f64) -> Result<f64, anyhow::Error> {
+        let sensitivity = self.get(channel)?;
+        Ok(dbfs + 20.0 * (sensitivity / REFERENCE_PRESSURE).log10())
+    }
+
+    /// The RMS level of a recording on a channel in dB SPL.
+    ///
+    /// # Errors
+    /// Returns an error if the channel has not been calibrated
+    pub fn db_spl(&self, channel: InputChannel, recording: &[i32]) -> Result<f64, anyhow::Error> {
+        self.dbfs_to_db_spl(channel, 20.0 * rms_full_scale(recording).log10())
+    }
+
+    /// Save the calibration to a text file with one `channel sensitivity` pair per line.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut contents = format!("{}\n", FILE_HEADER);
+        for (channel, sensitivity) in &self.sensitivities {
+            contents.push_str(&format!("{} {}\n", channel.0, sensitivity));
+        }
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Load a calibration saved with `save`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or is not a calibration file
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(FILE_HEADER) {
+            return Err(anyhow::anyhow!(
+                "{} is not a calibration file",
+                path.display()
+            ));
+        }
+
+        let mut calibration = Calibration::new();
+        for (line_number, line) in lines.enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(channel, sensitivity)| {
+                Some((channel.parse().ok()?, sensitivity.trim().parse().ok()?))
+            });
+            let Some((channel, sensitivity)) = parsed else {
+                return Err(anyhow::anyhow!(
+                    "Invalid calibration on line {} of {}",
+                    line_number + 2,
+                    path.display()
+                ));
+            };
+            calibration.set_sensitivity(InputChannel(channel), sensitivity)?;
+        }
+        Ok(calibration)
+    }
+
+    fn get(&self, channel: InputChannel) -> Result<f64, anyhow::Error> {
+        self.sensitivity(channel)
+            .ok_or(anyhow::anyhow!("{} has not been calibrated", channel))
+    }
+}
+
+/// The RMS of a signal as a fraction of full scale.
+fn rms_full_scale(signal: &[i32]) -> f64 {
+    if signal.is_empty() {
+        return 0.0;
+    }
+    let sum_of_squares: f64 = signal
+        .iter()
+        .map(|&sample| (sample as f64 / i32::MAX as f64).powi(2))
+        .sum();
+    (sum_of_squares / signal.len() as f64).sqrt()
+}
+
+#[cfg(feature = "device")]
+impl crate::audio_class::AudioInstance {
+    /// Record from calibrated input channels and return the recording in pascals.
+    ///
+    /// # Arguments
+    /// duration: f64 - the length of the recording in seconds
+    /// channels: &[InputChannel] - the input channels to record
+    /// calibration: &Calibration - the sensitivities of the channels
+    ///
+    /// # Errors
+    /// Returns an error if a channel has not been calibrated
+    pub fn record_calibrated(
+        &self,
+        duration: f64,
+        channels: &[InputChannel],
+        calibration: &Calibration,
+    ) -> Result<Vec<Vec<f64>>, anyhow::Error> {
+        // fail before recording rather than after
+        for &channel in channels {
+            calibration.get(channel)?;
+        }
+        let recording = self.record_channels(duration, channels)?;
+        calibration.to_pascals_channels(channels, &recording)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f64) -> Vec<i32> {
+        (0..48000)
+            .map(|n| {
+                let phase = 2.0 * std::f64::consts::PI * 1000.0 * n as f64 / 48000.0;
+                (amplitude * i32::MAX as f64 * phase.sin()) as i32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_calibrate_channel() {
+        let mut calibration = Calibration::new();
+        let tone = sine(0.5);
+        calibration
+            .calibrate_channel(InputChannel(1), &tone, 94.0)
+            .unwrap();
+
+        assert!((calibration.db_spl(InputChannel(1), &tone).unwrap() - 94.0).abs() < 0.01);
+        // 6 dB quieter at half the amplitude
+        let quieter = calibration.db_spl(InputChannel(1), &sine(0.25)).unwrap();
+        assert!((quieter - 87.98).abs() < 0.01);
+
+        // a 94 dB SPL tone is about 1 Pa RMS
+        let pascals = calibration.to_pascals(InputChannel(1), &tone).unwrap();
+        let rms = (pascals.iter().map(|p| p * p).sum::<f64>() / pascals.len() as f64).sqrt();
+        assert!((rms - 1.0024).abs() < 0.001);
+
+        assert!(calibration.db_spl(InputChannel(2), &tone).is_err());
+        assert!(calibration
+            .calibrate_channel(InputChannel(2), &[0; 10], 94.0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!(
+            "multichannel_audio_calibration_{}.txt",
+            std::process::id()
+        ));
+        let mut calibration = Calibration::new();
+        calibration.set_sensitivity(InputChannel(1), 12.5).unwrap();
+        calibration.set_sensitivity(InputChannel(4), 0.75).unwrap();
+        calibration.save(&path).unwrap();
+
+        assert_eq!(Calibration::load(&path).unwrap(), calibration);
+
+        std::fs::write(&path, "1 12.5\n").unwrap();
+        assert!(Calibration::load(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audio_class::AudioInstance;
+use crate::stream_stats::GapCounter;
+use crate::warm_up::ArrivalTracker;
+
+/// The number of callbacks kept for the rolling statistics.
+const WINDOW_SIZE: usize = 1024;
+
+/// The direction of an audio stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamDirection {
+    Input,
+    Output,
+}
+
+/// Sent when a callback takes longer than the overload threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OverloadWarning {
+    pub direction: StreamDirection,
+    /// The time spent in the callback
+    pub elapsed: Duration,
+    /// The duration of the buffer, which is the time the callback has before audio drops out
+    pub deadline: Duration,
+}
+
+impl OverloadWarning {
+    /// The fraction of the deadline that was used.
+    pub fn load(&self) -> f64 {
+        self.elapsed.as_secs_f64() / self.deadline.as_secs_f64()
+    }
+}
+
+/// Rolling statistics of the time spent in the callbacks of one stream, as a fraction of the
+/// buffer duration. A load of 1.0 or more means audio dropped out.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CallbackLoadStats {
+    /// The number of callbacks the statistics are over
+    pub callbacks: usize,
+    pub max: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub p99: f64,
+    /// The number of callbacks over the overload threshold since the instance was created
+    pub overloads: usize,
+}
+
+/// The timing the driver reports for the buffers of one stream.
+pub(crate) struct StreamTiming {
+    /// The frames in the last buffer, or 0 before the first
+    frames: AtomicUsize,
+    /// The latency reported with the last buffer in nanoseconds, or u64::MAX before one is reported
+    latency: AtomicU64,
+}
+
+impl Default for StreamTiming {
+    fn default() -> Self {
+        StreamTiming {
+            frames: AtomicUsize::new(0),
+            latency: AtomicU64::new(u64::MAX),
+        }
+    }
+}
+
+impl StreamTiming {
+    /// Note the latency the driver reported for a buffer. Called from the audio callbacks.
+    pub fn set_latency(&self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX - 1);
+        self.latency.store(nanos, Ordering::Relaxed);
+    }
+
+    /// The latency reported with the last buffer, or None if the driver hasn't reported one.
+    pub fn latency(&self) -> Option<Duration> {
+        match self.latency.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// The frames in the last buffer, or None before the first.
+    pub fn frames(&self) -> Option<usize> {
+        match self.frames.load(Ordering::Relaxed) {
+            0 => None,
+            frames => Some(frames),
+        }
+    }
+}
+
+/// Measures callback load for the input and output streams.
+///
+/// The callbacks only use `try_lock`, so a measurement is dropped rather than blocking the audio
+/// thread if the user thread is reading the statistics.
+pub(crate) struct CallbackMonitor {
+    input: Mutex<VecDeque<f64>>,
+    output: Mutex<VecDeque<f64>>,
+    input_overloads: AtomicUsize,
+    output_overloads: AtomicUsize,
+    /// The overload threshold as the bits of an f32
+    threshold: AtomicU32,
+    warnings: Mutex<Option<mpsc::Sender<OverloadWarning>>>,
+    input_arrivals: Mutex<ArrivalTracker>,
+    output_arrivals: Mutex<ArrivalTracker>,
+    input_gaps: GapCounter,
+    output_gaps: GapCounter,
+    input_timing: StreamTiming,
+    output_timing: StreamTiming,
+}
+
+impl Default for CallbackMonitor {
+    fn default() -> Self {
+        CallbackMonitor {
+            input: Mutex::new(VecDeque::with_capacity(WINDOW_SIZE)),
+            output: Mutex::new(VecDeque::with_capacity(WINDOW_SIZE)),
+            input_overloads: AtomicUsize::new(0),
+            output_overloads: AtomicUsize::new(0),
+            threshold: AtomicU32::new(0.8f32.to_bits()),
+            warnings: Mutex::new(None),
+            input_arrivals: Mutex::new(ArrivalTracker::default()),
+            output_arrivals: Mutex::new(ArrivalTracker::default()),
+            input_gaps: GapCounter::default(),
+            output_gaps: GapCounter::default(),
+            input_timing: StreamTiming::default(),
+            output_timing: StreamTiming::default(),
+        }
+    }
+}
+
+/// Records the time spent in a callback when dropped.
+pub(crate) struct CallbackTimer<'a> {
+    monitor: &'a CallbackMonitor,
+    direction: StreamDirection,
+    start: Instant,
+    deadline: Duration,
+}
+
+impl Drop for CallbackTimer<'_> {
+    fn drop(&mut self) {
+        self.monitor
+            .record(self.direction, self.start.elapsed(), self.deadline);
+    }
+}
+
+/// Logs a warning when dropped if any callbacks overran while it was held.
+///
+/// The callbacks only count overloads, so nothing is logged from the audio thread. The blocking
+/// play and record functions hold one so the warning is logged inside their span.
+pub(crate) struct OverloadReport<'a> {
+    monitor: &'a CallbackMonitor,
+    input_overloads: usize,
+    output_overloads: usize,
+}
+
+impl Drop for OverloadReport<'_> {
+    fn drop(&mut self) {
+        let input = self.monitor.input_overloads.load(Ordering::Relaxed) - self.input_overloads;
+        let output = self.monitor.output_overloads.load(Ordering::Relaxed) - self.output_overloads;
+        if input > 0 || output > 0 {
+            tracing::warn!(
+                input_overloads = input,
+                output_overloads = output,
+                "callbacks overran their deadline"
+            );
+        }
+    }
+}
+
+impl CallbackMonitor {
+    /// Start counting overloads for an `OverloadReport`.
+    pub fn report_overloads(&self) -> OverloadReport<'_> {
+        OverloadReport {
+            monitor: self,
+            input_overloads: self.input_overloads.load(Ordering::Relaxed),
+            output_overloads: self.output_overloads.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Start timing a callback. Called from the audio callbacks.
+    pub fn time(
+        &self,
+        direction: StreamDirection,
+        frames: usize,
+        sample_rate: u32,
+    ) -> CallbackTimer<'_> {
+        let start = Instant::now();
+        let deadline = Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+        if let Ok(mut arrivals) = self.arrivals(direction).try_lock() {
+            arrivals.arrive(start, deadline);
+        }
+        self.timing(direction)
+            .frames
+            .store(frames, Ordering::Relaxed);
+        CallbackTimer {
+            monitor: self,
+            direction,
+            start,
+            deadline,
+        }
+    }
+
+    /// The gaps between the buffers of a stream.
+    pub fn gaps(&self, direction: StreamDirection) -> &GapCounter {
+        match direction {
+            StreamDirection::Input => &self.input_gaps,
+            StreamDirection::Output => &self.output_gaps,
+        }
+    }
+
+    /// The buffer size and latency the driver reported for a stream.
+    pub fn timing(&self, direction: StreamDirection) -> &StreamTiming {
+        match direction {
+            StreamDirection::Input => &self.input_timing,
+            StreamDirection::Output => &self.output_timing,
+        }
+    }
+
+    /// The arrival times of the callbacks of a stream.
+    pub fn arrivals(&self, direction: StreamDirection) -> &Mutex<ArrivalTracker> {
+        match direction {
+            StreamDirection::Input => &self.input_arrivals,
+            StreamDirection::Output => &self.output_arrivals,
+        }
+    }
+
+    fn record(&self, direction: StreamDirection, elapsed: Duration, deadline: Duration) {
+        if deadline.is_zero() {
+            return;
+        }
+        let load = elapsed.as_secs_f64() / deadline.as_secs_f64();
+
+        let (window, overloads) = match direction {
+            StreamDirection::Input => (&self.input, &self.input_overloads),
+            StreamDirection::Output => (&self.output, &self.output_overloads),
+        };
+        if let Ok(mut window) = window.try_lock() {
+            if window.len() == WINDOW_SIZE {
+                window.pop_front();
+            }
+            window.push_back(load);
+        }
+
+        if load > self.threshold() {
+            overloads.fetch_add(1, Ordering::Relaxed);
+            if let Ok(warnings) = self.warnings.try_lock() {
+                if let Some(ref sender) = *warnings {
+                    let _ = sender.send(OverloadWarning {
+                        direction,
+                        elapsed,
+                        deadline,
+                    });
+                }
+            }
+        }
+    }
+
+    fn threshold(&self) -> f64 {
+        f32::from_bits(self.threshold.load(Ordering::Relaxed)) as f64
+    }
+
+    fn stats(&self, direction: StreamDirection) -> CallbackLoadStats {
+        let (window, overloads) = match direction {
+            StreamDirection::Input => (&self.input, &self.input_overloads),
+            StreamDirection::Output => (&self.output, &self.output_overloads),
+        };
+        let mut loads: Vec<f64> = window.lock().unwrap().iter().copied().collect();
+        let mut stats = summarize(&mut loads);
+        stats.overloads = overloads.load(Ordering::Relaxed);
+        stats
+    }
+}
+
+/// Calculate the statistics of a set of callback loads.
+fn summarize(loads: &mut [f64]) -> CallbackLoadStats {
+    if loads.is_empty() {
+        return CallbackLoadStats::default();
+    }
+    loads.sort_by(f64::total_cmp);
+
+    let percentile = |p: f64| loads[((loads.len() - 1) as f64 * p).round() as usize];
+    CallbackLoadStats {
+        callbacks: loads.len(),
+        max: loads[loads.len() - 1],
+        median: percentile(0.5),
+        p95: percentile(0.95),
+        p99: percentile(0.99),
+        overloads: 0,
+    }
+}
+
+impl AudioInstance {
+    /// Statistics of the time spent in the callbacks of a stream over the last 1024 callbacks.
+    ///
+    /// Use this to find the smallest buffer size that the system can keep up with.
+    pub fn callback_load(&self, direction: StreamDirection) -> CallbackLoadStats {
+        self.callback_monitor.stats(direction)
+    }
+
+    /// Set the fraction of the buffer duration a callback can use before it counts as an overload.
+    /// The default is 0.8.
+    pub fn set_overload_threshold(&self, threshold: f64) {
+        self.callback_monitor
+            .threshold
+            .store((threshold as f32).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Receive an `OverloadWarning` whenever a callback is over the overload threshold.
+    ///
+    /// Only the most recently returned receiver gets warnings.
+    pub fn overload_warnings(&self) -> mpsc::Receiver<OverloadWarning> {
+        let (sender, receiver) = mpsc::channel();
+        *self.callback_monitor.warnings.lock().unwrap() = Some(sender);
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let mut loads: Vec<f64> = (1..=100).rev().map(|x| x as f64 / 100.0).collect();
+        let stats = summarize(&mut loads);
+
+        assert_eq!(stats.callbacks, 100);
+        assert_eq!(stats.max, 1.0);
+        assert_eq!(stats.p99, 0.99);
+        assert!((stats.median - 0.5).abs() < 0.011);
+    }
+
+    #[test]
+    fn test_overload_warning() {
+        let monitor = CallbackMonitor::default();
+        let (sender, receiver) = mpsc::channel();
+        *monitor.warnings.lock().unwrap() = Some(sender);
+
+        monitor.record(
+            StreamDirection::Output,
+            Duration::from_millis(1),
+            Duration::from_millis(10),
+        );
+        monitor.record(
+            StreamDirection::Output,
+            Duration::from_millis(9),
+            Duration::from_millis(10),
+        );
+
+        let warning = receiver.try_recv().unwrap();
+        assert!((warning.load() - 0.9).abs() < 1e-9);
+        assert!(receiver.try_recv().is_err());
+
+        let stats = monitor.stats(StreamDirection::Output);
+        assert_eq!(stats.callbacks, 2);
+        assert_eq!(stats.overloads, 1);
+        assert_eq!(monitor.stats(StreamDirection::Input).callbacks, 0);
+    }
+}
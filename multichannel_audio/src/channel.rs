@@ -0,0 +1,357 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A 1-based output channel number, as labelled on the audio interface.
+///
+/// Output and input channels are separate types so an input channel can't be passed where an
+/// output channel is expected, which is the usual cause of loopback timing errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OutputChannel(pub usize);
+
+/// A 1-based input channel number, as labelled on the audio interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputChannel(pub usize);
+
+impl OutputChannel {
+    /// The 0-based index of this channel in multichannel data.
+    ///
+    /// # Errors
+    /// Returns an error if the channel number is 0
+    pub fn index(self) -> Result<usize, anyhow::Error> {
+        self.0.checked_sub(1).ok_or(anyhow::anyhow!(
+            "Output channels are numbered from 1, got 0"
+        ))
+    }
+
+    /// The output channel at a 0-based index.
+    pub fn from_index(index: usize) -> Self {
+        OutputChannel(index + 1)
+    }
+
+    /// The left and right channels of a 1-based stereo pair, e.g. outputs 3 and 4 for pair 2.
+    ///
+    /// # Errors
+    /// Returns an error if the pair number is 0
+    pub fn stereo_pair(pair: usize) -> Result<[OutputChannel; 2], anyhow::Error> {
+        if pair == 0 {
+            return Err(anyhow::Error::msg(
+                "Stereo pairs are numbered from 1, got 0",
+            ));
+        }
+        Ok([OutputChannel(2 * pair - 1), OutputChannel(2 * pair)])
+    }
+}
+
+impl InputChannel {
+    /// The 0-based index of this channel in multichannel data.
+    ///
+    /// # Errors
+    /// Returns an error if the channel number is 0
+    pub fn index(self) -> Result<usize, anyhow::Error> {
+        self.0
+            .checked_sub(1)
+            .ok_or(anyhow::anyhow!("Input channels are numbered from 1, got 0"))
+    }
+
+    /// The input channel at a 0-based index.
+    pub fn from_index(index: usize) -> Self {
+        InputChannel(index + 1)
+    }
+}
+
+impl fmt::Display for OutputChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "output {}", self.0)
+    }
+}
+
+impl fmt::Display for InputChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "input {}", self.0)
+    }
+}
+
+/// A 0-based channel index that has been checked against the number of channels.
+///
+/// Channel numbers are 1-based to match the labels on the interface, while multichannel data is
+/// indexed from 0. Converting through `ChannelIndex` subtracts 1 and checks the range in one place,
+/// so data can't be indexed off by one or past the last channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChannelIndex(usize);
+
+impl ChannelIndex {
+    /// The index of an output channel.
+    ///
+    /// # Arguments
+    /// channel: OutputChannel - the channel
+    /// channels: usize - the number of output channels
+    ///
+    /// # Errors
+    /// Returns an error if the channel is 0 or more than `channels`
+    pub fn output(channel: OutputChannel, channels: usize) -> Result<Self, anyhow::Error> {
+        Self::checked(channel.index()?, channels, channel, "output")
+    }
+
+    /// The index of an input channel.
+    ///
+    /// # Arguments
+    /// channel: InputChannel - the channel
+    /// channels: usize - the number of input channels
+    ///
+    /// # Errors
+    /// Returns an error if the channel is 0 or more than `channels`
+    pub fn input(channel: InputChannel, channels: usize) -> Result<Self, anyhow::Error> {
+        Self::checked(channel.index()?, channels, channel, "input")
+    }
+
+    fn checked(
+        index: usize,
+        channels: usize,
+        channel: impl fmt::Display,
+        direction: &str,
+    ) -> Result<Self, anyhow::Error> {
+        if index >= channels {
+            return Err(anyhow::anyhow!(
+                "Channel {} is out of range. There are {} {} channels.",
+                channel,
+                channels,
+                direction
+            ));
+        }
+        Ok(ChannelIndex(index))
+    }
+
+    /// The index into multichannel data.
+    pub fn get(self) -> usize {
+        self.0
+    }
+}
+
+/// A channel given by its number or by a label to look up in `ChannelLabels`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelSelection<'a, C> {
+    Channel(C),
+    Label(&'a str),
+}
+
+/// An output channel given by its number or its label, e.g. `OutputChannel(2)` or `"Speaker L"`.
+///
+/// The play and alignment functions accept either, and look labels up in the labels the
+/// instance was built with. See `AudioInstanceBuilder::output_label`.
+pub trait OutputSelector: fmt::Debug {
+    fn selection(&self) -> ChannelSelection<'_, OutputChannel>;
+}
+
+/// An input channel given by its number or its label, e.g. `InputChannel(1)` or `"Ref Mic"`.
+///
+/// The record and alignment functions accept either, and look labels up in the labels the
+/// instance was built with. See `AudioInstanceBuilder::input_label`.
+pub trait InputSelector: fmt::Debug {
+    fn selection(&self) -> ChannelSelection<'_, InputChannel>;
+}
+
+impl OutputSelector for OutputChannel {
+    fn selection(&self) -> ChannelSelection<'_, OutputChannel> {
+        ChannelSelection::Channel(*self)
+    }
+}
+
+impl InputSelector for InputChannel {
+    fn selection(&self) -> ChannelSelection<'_, InputChannel> {
+        ChannelSelection::Channel(*self)
+    }
+}
+
+impl OutputSelector for &str {
+    fn selection(&self) -> ChannelSelection<'_, OutputChannel> {
+        ChannelSelection::Label(self)
+    }
+}
+
+impl InputSelector for &str {
+    fn selection(&self) -> ChannelSelection<'_, InputChannel> {
+        ChannelSelection::Label(self)
+    }
+}
+
+impl<T: OutputSelector> OutputSelector for &T {
+    fn selection(&self) -> ChannelSelection<'_, OutputChannel> {
+        (*self).selection()
+    }
+}
+
+impl<T: InputSelector> InputSelector for &T {
+    fn selection(&self) -> ChannelSelection<'_, InputChannel> {
+        (*self).selection()
+    }
+}
+
+impl OutputSelector for String {
+    fn selection(&self) -> ChannelSelection<'_, OutputChannel> {
+        ChannelSelection::Label(self)
+    }
+}
+
+impl InputSelector for String {
+    fn selection(&self) -> ChannelSelection<'_, InputChannel> {
+        ChannelSelection::Label(self)
+    }
+}
+
+/// Names for the channels of a device, e.g. the microphone or speaker connected to each one.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChannelLabels {
+    inputs: BTreeMap<String, InputChannel>,
+    outputs: BTreeMap<String, OutputChannel>,
+    output_groups: BTreeMap<String, Vec<OutputChannel>>,
+}
+
+impl ChannelLabels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Label an input channel, replacing any channel that had the label before.
+    pub fn set_input(&mut self, label: &str, channel: InputChannel) {
+        self.inputs.insert(label.to_string(), channel);
+    }
+
+    /// Label an output channel, replacing any channel that had the label before.
+    pub fn set_output(&mut self, label: &str, channel: OutputChannel) {
+        self.outputs.insert(label.to_string(), channel);
+    }
+
+    /// Name a group of output channels that are played together, e.g. `"Speakers A"` for outputs
+    /// 3 and 4, replacing any group that had the name before.
+    pub fn set_output_group(&mut self, label: &str, channels: &[OutputChannel]) {
+        self.output_groups
+            .insert(label.to_string(), channels.to_vec());
+    }
+
+    /// The output channels of a group, in the order they were given.
+    ///
+    /// # Errors
+    /// Returns an error if no group has the name
+    pub fn output_group(&self, label: &str) -> Result<&[OutputChannel], anyhow::Error> {
+        self.output_groups
+            .get(label)
+            .map(|channels| channels.as_slice())
+            .ok_or_else(|| anyhow::anyhow!("No output group is named \"{}\"", label))
+    }
+
+    /// The input channel a selector refers to.
+    ///
+    /// # Errors
+    /// Returns an error if no input channel has the label
+    pub fn input(&self, channel: &impl InputSelector) -> Result<InputChannel, anyhow::Error> {
+        match channel.selection() {
+            ChannelSelection::Channel(channel) => Ok(channel),
+            ChannelSelection::Label(label) => self
+                .inputs
+                .get(label)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("No input channel is labelled \"{}\"", label)),
+        }
+    }
+
+    /// The output channel a selector refers to.
+    ///
+    /// # Errors
+    /// Returns an error if no output channel has the label
+    pub fn output(&self, channel: &impl OutputSelector) -> Result<OutputChannel, anyhow::Error> {
+        match channel.selection() {
+            ChannelSelection::Channel(channel) => Ok(channel),
+            ChannelSelection::Label(label) => self
+                .outputs
+                .get(label)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("No output channel is labelled \"{}\"", label)),
+        }
+    }
+
+    /// The label of an input channel, if it has one.
+    pub fn input_label(&self, channel: InputChannel) -> Option<&str> {
+        self.inputs
+            .iter()
+            .find(|(_, &labelled)| labelled == channel)
+            .map(|(label, _)| label.as_str())
+    }
+
+    /// The label of an output channel, if it has one.
+    pub fn output_label(&self, channel: OutputChannel) -> Option<&str> {
+        self.outputs
+            .iter()
+            .find(|(_, &labelled)| labelled == channel)
+            .map(|(label, _)| label.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_index() {
+        assert_eq!(OutputChannel(1).index().unwrap(), 0);
+        assert_eq!(InputChannel(3).index().unwrap(), 2);
+        assert!(OutputChannel(0).index().is_err());
+        assert!(InputChannel(0).index().is_err());
+    }
+
+    #[test]
+    fn test_channel_from_index() {
+        assert_eq!(OutputChannel::from_index(0), OutputChannel(1));
+        assert_eq!(InputChannel::from_index(4).index().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_channel_index_range() {
+        assert_eq!(ChannelIndex::output(OutputChannel(1), 2).unwrap().get(), 0);
+        assert_eq!(ChannelIndex::input(InputChannel(2), 2).unwrap().get(), 1);
+        assert!(ChannelIndex::output(OutputChannel(0), 2).is_err());
+        let err = ChannelIndex::input(InputChannel(3), 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Channel input 3 is out of range. There are 2 input channels."
+        );
+    }
+
+    #[test]
+    fn test_channel_labels() {
+        let mut labels = ChannelLabels::new();
+        labels.set_input("Ref Mic", InputChannel(1));
+        labels.set_output("Loopback", OutputChannel(4));
+
+        assert_eq!(labels.input(&"Ref Mic").unwrap(), InputChannel(1));
+        assert_eq!(labels.input(&InputChannel(3)).unwrap(), InputChannel(3));
+        assert_eq!(
+            labels.output(&"Loopback".to_string()).unwrap(),
+            OutputChannel(4)
+        );
+        assert!(labels.output(&"Ref Mic").is_err());
+        assert_eq!(labels.output_label(OutputChannel(4)), Some("Loopback"));
+        assert_eq!(labels.input_label(InputChannel(2)), None);
+    }
+
+    #[test]
+    fn test_output_groups() {
+        assert_eq!(
+            OutputChannel::stereo_pair(2).unwrap(),
+            [OutputChannel(3), OutputChannel(4)]
+        );
+        assert!(OutputChannel::stereo_pair(0).is_err());
+
+        let mut labels = ChannelLabels::new();
+        labels.set_output_group("Speakers A", &[OutputChannel(3), OutputChannel(4)]);
+        assert_eq!(
+            labels.output_group("Speakers A").unwrap(),
+            &[OutputChannel(3), OutputChannel(4)]
+        );
+        assert!(labels.output_group("Speakers B").is_err());
+        assert!(labels.output(&"Speakers A").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::f64::consts::{PI, TAU};
+
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+use crate::channel::InputChannel;
+#[cfg(feature = "device")]
+use crate::channel::{InputSelector, OutputSelector};
+use crate::distortion::tone_level_dbfs;
+use crate::sample_formats::Sample;
+
+/// The number of parts of the recording the phase is measured in, to see whether the skew drifts.
+const SEGMENTS: usize = 4;
+/// The fewest periods of the tone in each segment.
+const MIN_PERIODS: f64 = 8.0;
+/// Channels quieter than this didn't hear the tone, so their skew can't be measured.
+const MIN_LEVEL_DBFS: f64 = -60.0;
+
+/// The timing of one input channel relative to the reference channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelSkew {
+    pub channel: InputChannel,
+    /// The level of the tone in dBFS
+    pub level_dbfs: f64,
+    /// How many samples the channel lags the reference, including fractions of a sample. NaN if
+    /// the channel didn't hear the tone
+    pub skew_samples: f64,
+    /// How much the skew changed from the start to the end of the recording in samples. A channel
+    /// that is resampled separately from the others drifts, while a fixed delay doesn't
+    pub drift_samples: f64,
+}
+
+impl ChannelSkew {
+    /// Whether the channel heard the tone and its skew and drift are within a threshold.
+    pub fn is_coherent(&self, max_skew_samples: f64) -> bool {
+        self.level_dbfs >= MIN_LEVEL_DBFS
+            && self.skew_samples.abs() <= max_skew_samples
+            && self.drift_samples.abs() <= max_skew_samples
+    }
+}
+
+/// The result of a phase-coherence self-test of a mic array.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoherenceReport {
+    /// The frequency of the tone in Hz
+    pub frequency: f64,
+    /// The largest skew and drift a channel can have and pass
+    pub max_skew_samples: f64,
+    /// Every channel in the order they were given. The first is the reference
+    pub channels: Vec<ChannelSkew>,
+}
+
+impl CoherenceReport {
+    /// Whether every channel passed.
+    pub fn passed(&self) -> bool {
+        self.failing_channels().is_empty()
+    }
+
+    /// The channels that didn't hear the tone or whose skew or drift is above the threshold.
+    pub fn failing_channels(&self) -> Vec<InputChannel> {
+        self.channels
+            .iter()
+            .filter(|channel| !channel.is_coherent(self.max_skew_samples))
+            .map(|channel| channel.channel)
+            .collect()
+    }
+}
+
+/// The phase of a tone in a signal in radians, from a Hann windowed correlation at its frequency.
+fn tone_phase<T: Sample>(signal: &[T], fs: u32, frequency: f64) -> f64 {
+    let omega = TAU * frequency / fs as f64;
+    let length = signal.len() as f64;
+    let (mut real, mut imaginary) = (0.0, 0.0);
+    for (n, &sample) in signal.iter().enumerate() {
+        let window = 0.5 - 0.5 * (TAU * n as f64 / length).cos();
+        let value = sample.to_i32() as f64 * window;
+        real += value * (omega * n as f64).cos();
+        imaginary -= value * (omega * n as f64).sin();
+    }
+    imaginary.atan2(real)
+}
+
+/// Wrap a phase difference to between -π and π.
+fn wrap_phase(phase: f64) -> f64 {
+    (phase + PI).rem_euclid(TAU) - PI
+}
+
+/// Measure how far the recordings of a tone on each channel are skewed from the first channel.
+///
+/// The phase of the tone is compared in several parts of the recording. A constant difference is
+/// a skew and a changing one is a drift, e.g. from a driver that resamples some channels. A tone
+/// can only show skews of up to half its period, so use a frequency whose period is more than
+/// twice the largest skew to detect.
+///
+/// # Arguments
+/// recording: &[Vec<T>] - the recording of each channel, all the same length
+/// channels: &[InputChannel] - the channel of each recording, for the report
+/// fs: u32 - the sample rate of the recording
+/// frequency: f64 - the frequency of the tone in Hz
+/// max_skew_samples: f64 - the largest skew and drift a channel can have and pass
+///
+/// # Errors
+/// Returns an error if there are no recordings or the channels don't match them
+/// Returns an error if the frequency is out of range or the recording is too short for it
+/// Returns an error if the threshold is longer than half a period of the tone
+pub fn analyze_coherence<T: Sample>(
+    recording: &[Vec<T>],
+    channels: &[InputChannel],
+    fs: u32,
+    frequency: f64,
+    max_skew_samples: f64,
+) -> Result<CoherenceReport, anyhow::Error> {
+    if recording.is_empty() || recording.len() != channels.len() {
+        return Err(anyhow::Error::msg(
+            "There must be a recording for each channel, and at least one channel",
+        ));
+    }
+    let nyquist = fs as f64 / 2.0;
+    if frequency.is_nan() || frequency <= 0.0 || frequency >= nyquist {
+        return Err(anyhow::anyhow!(
+            "The frequency must be between 0 Hz and {} Hz",
+            nyquist
+        ));
+    }
+    let half_period = nyquist / frequency;
+    if max_skew_samples.is_nan() || max_skew_samples < 0.0 || max_skew_samples >= half_period {
+        return Err(anyhow::anyhow!(
+            "A {} Hz tone can only show skews of up to {:.1} samples. Use a lower frequency",
+            frequency,
+            half_period
+        ));
+    }
+    let length = recording.iter().map(Vec::len).min().unwrap_or(0);
+    let segment = length / SEGMENTS;
+    if (segment as f64) < MIN_PERIODS * 2.0 * half_period {
+        return Err(anyhow::anyhow!(
+            "A recording of {} samples is too short to measure the phase of {} Hz",
+            length,
+            frequency
+        ));
+    }
+
+    let phases = |signal: &[T]| -> Vec<f64> {
+        signal[..segment * SEGMENTS]
+            .chunks_exact(segment)
+            .map(|part| tone_phase(part, fs, frequency))
+            .collect()
+    };
+    let reference = phases(&recording[0]);
+    let samples_per_radian = fs as f64 / (TAU * frequency);
+
+    let channels = recording
+        .iter()
+        .zip(channels)
+        .map(|(signal, &channel)| {
+            let level_dbfs = tone_level_dbfs(&signal[..length], fs, frequency)?;
+            if level_dbfs < MIN_LEVEL_DBFS {
+                return Ok(ChannelSkew {
+                    channel,
+                    level_dbfs,
+                    skew_samples: f64::NAN,
+                    drift_samples: f64::NAN,
+                });
+            }
+            // a later arrival has a smaller phase
+            let skews: Vec<f64> = reference
+                .iter()
+                .zip(phases(signal))
+                .map(|(reference, phase)| wrap_phase(reference - phase) * samples_per_radian)
+                .collect();
+            Ok(ChannelSkew {
+                channel,
+                level_dbfs,
+                skew_samples: skews.iter().sum::<f64>() / SEGMENTS as f64,
+                drift_samples: skews[SEGMENTS - 1] - skews[0],
+            })
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+    Ok(CoherenceReport {
+        frequency,
+        max_skew_samples,
+        channels,
+    })
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Check that a mic array is captured in sync, e.g. before beamforming.
+    ///
+    /// Plays a sine through one speaker while recording, then measures the skew and drift of each
+    /// mic against the first with `analyze_coherence`. Place the speaker so it is the same
+    /// distance from every mic, e.g. on the axis of the array, so any skew comes from the
+    /// device rather than the room.
+    ///
+    /// # Arguments
+    /// speaker: impl OutputSelector - the output channel to play the tone on, by number or label
+    /// mics: &[impl InputSelector] - the input channels of the array. The first is the reference
+    /// frequency: f64 - the frequency of the tone in Hz, e.g. 1000
+    /// level_dbfs: f64 - the peak level of the tone in dBFS
+    /// max_skew_samples: f64 - the largest skew and drift a mic can have and pass
+    ///
+    /// # Errors
+    /// Returns an error if a channel is out of range or an unknown label, or no mics are given
+    /// Returns an error if the level is above 0 dBFS or the frequency is out of range
+    /// Returns an error if the tone is blocked by the safety interlock
+    ///
+    /// # Example
+    /// ```no_run
+    /// use multichannel_audio::audio_class::AudioInstance;
+    /// use multichannel_audio::channel::{InputChannel, OutputChannel};
+    ///
+    /// let audio_instance = AudioInstance::new(48000).unwrap();
+    /// let mics: Vec<InputChannel> = (1..=8).map(InputChannel).collect();
+    /// let report = audio_instance
+    ///     .check_phase_coherence(OutputChannel(1), &mics, 1000.0, -12.0, 0.25)
+    ///     .unwrap();
+    /// if !report.passed() {
+    ///     eprintln!("mics out of sync: {:?}", report.failing_channels());
+    /// }
+    /// ```
+    pub fn check_phase_coherence(
+        &self,
+        speaker: impl OutputSelector,
+        mics: &[impl InputSelector],
+        frequency: f64,
+        level_dbfs: f64,
+        max_skew_samples: f64,
+    ) -> Result<CoherenceReport, anyhow::Error> {
+        let output_index = self.output_index(speaker)?.get();
+        let channels = mics
+            .iter()
+            .map(|mic| self.input_channel(mic))
+            .collect::<Result<Vec<_>, _>>()?;
+        let indices = mics
+            .iter()
+            .map(|mic| Ok(self.input_index(mic)?.get()))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        let recorded_data = self.play_record_tone(output_index, frequency, level_dbfs)?;
+        let recording: Vec<Vec<i32>> = indices
+            .iter()
+            .map(|&index| recorded_data[index].clone())
+            .collect();
+        analyze_coherence(
+            &recording,
+            &channels,
+            self.sample_rate,
+            frequency,
+            max_skew_samples,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, delay: f64, length: usize) -> Vec<f64> {
+        (0..length)
+            .map(|n| 0.5 * (TAU * frequency * (n as f64 - delay) / 48000.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_analyze_coherence() {
+        let channels = [
+            InputChannel(1),
+            InputChannel(2),
+            InputChannel(3),
+            InputChannel(4),
+        ];
+        let recording = vec![
+            sine(1000.0, 0.0, 36000),
+            sine(1000.0, 0.1, 36000),
+            // a channel resampled at a slightly different rate drifts
+            sine(1000.5, 0.0, 36000),
+            vec![0.0; 36000],
+        ];
+        let report = analyze_coherence(&recording, &channels, 48000, 1000.0, 0.25).unwrap();
+
+        assert_eq!(report.channels[0].skew_samples, 0.0);
+        assert!((report.channels[1].skew_samples - 0.1).abs() < 0.01);
+        assert!(report.channels[1].drift_samples.abs() < 0.01);
+        assert!(report.channels[2].drift_samples.abs() > 0.25);
+        assert!(report.channels[3].skew_samples.is_nan());
+        assert_eq!(
+            report.failing_channels(),
+            vec![InputChannel(3), InputChannel(4)]
+        );
+        assert!(!report.passed());
+
+        // a 1 kHz tone can't show skews of half its period
+        assert!(analyze_coherence(&recording, &channels, 48000, 1000.0, 24.0).is_err());
+        assert!(analyze_coherence(&recording[..2], &channels, 48000, 1000.0, 1.0).is_err());
+        assert!(analyze_coherence(&[vec![0.0; 100]], &channels[..1], 48000, 1000.0, 1.0).is_err());
+    }
+
+    #[cfg(feature = "device")]
+    #[test]
+    fn test_check_phase_coherence() {
+        use crate::backend::{ChannelModel, MockDevice};
+        use crate::builder::AudioInstanceBuilder;
+        use crate::channel::OutputChannel;
+
+        // mic 2 is captured 3 samples late
+        let speaker = ChannelModel::from_output(OutputChannel(1));
+        let device = MockDevice::new(3, 2)
+            .channel(InputChannel(1), speaker)
+            .channel(InputChannel(2), speaker.delay(3))
+            .channel(InputChannel(3), speaker);
+        let audio_instance = AudioInstanceBuilder::new().mock(device).build().unwrap();
+
+        let mics = [InputChannel(1), InputChannel(2), InputChannel(3)];
+        let report = audio_instance
+            .check_phase_coherence(OutputChannel(1), &mics, 1000.0, -12.0, 0.5)
+            .unwrap();
+        assert!((report.channels[1].skew_samples - 3.0).abs() < 0.05);
+        assert!(report.channels[2].skew_samples.abs() < 0.05);
+        assert_eq!(report.failing_channels(), vec![InputChannel(2)]);
+    }
+}
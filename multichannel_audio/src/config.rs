@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::channel::{ChannelLabels, InputChannel, OutputChannel};
+#[cfg(feature = "device")]
+use crate::{
+    audio_class::{BufferSize, ChannelCount},
+    builder::AudioInstanceBuilder,
+    context::AudioContext,
+};
+
+/// The device choices of an application, saved to a TOML file so they persist between runs.
+///
+/// Every setting is optional, so a config only needs the settings the user has changed.
+///
+/// # Example
+/// ```toml
+/// host = "ASIO"
+/// device = "interface"
+/// sample_rate = 96000
+/// all_channels = true
+///
+/// [device_aliases]
+/// interface = "Focusrite USB ASIO"
+///
+/// [input_channels]
+/// reference_mic = 1
+///
+/// [output_groups]
+/// speakers_a = [3, 4]
+/// ```
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// The name of the host, e.g. "ASIO" or "CoreAudio", or None for the default host
+    pub host: Option<String>,
+    /// The output device, or an alias of it
+    pub device: Option<String>,
+    /// The input device, or an alias of it, if it is different to the output device
+    pub input_device: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// The number of frames per callback to request from the driver
+    pub buffer_size: Option<u32>,
+    /// Open every channel of the device, instead of the channel count its driver defaults to
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub all_channels: bool,
+    /// Short names for devices on this host, since device names change between hosts and drivers
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub device_aliases: BTreeMap<String, String>,
+    /// Names for input channels, e.g. the microphone connected to each one
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub input_channels: BTreeMap<String, usize>,
+    /// Names for output channels, e.g. the speaker connected to each one
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub output_channels: BTreeMap<String, usize>,
+    /// Names for groups of output channels that are played together, e.g. a pair of speakers
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub output_groups: BTreeMap<String, Vec<usize>>,
+}
+
+impl AudioConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a config saved with `save`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or is not a valid config
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents)
+            .map_err(|err| anyhow::anyhow!("{} is not a valid config: {}", path.display(), err))
+    }
+
+    /// Save the config to a TOML file.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The device name an alias refers to, or the name itself if it is not an alias.
+    pub fn resolve_device<'a>(&'a self, name: &'a str) -> &'a str {
+        self.device_aliases
+            .get(name)
+            .map_or(name, |device| device.as_str())
+    }
+
+    /// The input channel with a name, if there is one.
+    pub fn input_channel(&self, name: &str) -> Option<InputChannel> {
+        self.input_channels
+            .get(name)
+            .map(|&channel| InputChannel(channel))
+    }
+
+    /// The output channel with a name, if there is one.
+    pub fn output_channel(&self, name: &str) -> Option<OutputChannel> {
+        self.output_channels
+            .get(name)
+            .map(|&channel| OutputChannel(channel))
+    }
+
+    /// The channel names of the config, to use as the labels of an instance.
+    pub fn channel_labels(&self) -> ChannelLabels {
+        let mut labels = ChannelLabels::new();
+        for (name, &channel) in &self.input_channels {
+            labels.set_input(name, InputChannel(channel));
+        }
+        for (name, &channel) in &self.output_channels {
+            labels.set_output(name, OutputChannel(channel));
+        }
+        for (name, channels) in &self.output_groups {
+            let channels: Vec<_> = channels
+                .iter()
+                .map(|&channel| OutputChannel(channel))
+                .collect();
+            labels.set_output_group(name, &channels);
+        }
+        labels
+    }
+}
+
+#[cfg(feature = "device")]
+impl AudioConfig {
+    /// Start configuring an instance with the host, devices, sample rate, buffer size and channel
+    /// names of the config.
+    ///
+    /// # Errors
+    /// Returns an error if the host is not available on this platform
+    pub fn builder(&self) -> Result<AudioInstanceBuilder, anyhow::Error> {
+        let mut builder = AudioInstanceBuilder::new().channel_labels(self.channel_labels());
+        if let Some(ref host) = self.host {
+            builder = builder.context(&AudioContext::from_host_name(host)?);
+        }
+        if let Some(ref device) = self.device {
+            builder = builder.device(self.resolve_device(device));
+        }
+        if let Some(ref input_device) = self.input_device {
+            builder = builder.input_device(self.resolve_device(input_device));
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            builder = builder.sample_rate(sample_rate);
+        }
+        if let Some(frames) = self.buffer_size {
+            builder = builder.buffer_size(BufferSize::Fixed(frames));
+        }
+        if self.all_channels {
+            builder = builder
+                .input_channels(ChannelCount::Maximum)
+                .output_channels(ChannelCount::Maximum);
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!(
+            "multichannel_audio_config_{}.toml",
+            std::process::id()
+        ));
+        let mut config = AudioConfig::new();
+        config.host = Some("ASIO".to_string());
+        config.device = Some("interface".to_string());
+        config.sample_rate = Some(96000);
+        config.all_channels = true;
+        config
+            .device_aliases
+            .insert("interface".to_string(), "Focusrite USB ASIO".to_string());
+        config.input_channels.insert("reference_mic".to_string(), 3);
+        config
+            .output_groups
+            .insert("speakers_a".to_string(), vec![3, 4]);
+        config.save(&path).unwrap();
+
+        let loaded = AudioConfig::load(&path).unwrap();
+        assert_eq!(loaded, config);
+        assert_eq!(loaded.resolve_device("interface"), "Focusrite USB ASIO");
+        assert_eq!(loaded.resolve_device("Speakers"), "Speakers");
+        assert_eq!(loaded.input_channel("reference_mic"), Some(InputChannel(3)));
+        assert_eq!(loaded.output_channel("reference_mic"), None);
+        assert_eq!(
+            loaded.channel_labels().input(&"reference_mic").unwrap(),
+            InputChannel(3)
+        );
+        assert_eq!(
+            loaded.channel_labels().output_group("speakers_a").unwrap(),
+            &[OutputChannel(3), OutputChannel(4)]
+        );
+
+        std::fs::write(&path, "sample_rate = \"fast\"").unwrap();
+        assert!(AudioConfig::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_settings_are_none() {
+        let config: AudioConfig = toml::from_str("sample_rate = 44100").unwrap();
+        assert_eq!(config.sample_rate, Some(44100));
+        assert_eq!(config.device, None);
+        assert!(config.device_aliases.is_empty());
+        assert!(!config.all_channels);
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::builder::AudioInstanceBuilder;
+use crate::missing_device_error::MissingDeviceError;
+
+/// An audio host that hands out instances bound to their own devices.
+///
+/// Instances created from a context don't use the global `HOST`, `DEVICE_NAME` and
+/// `INPUT_DEVICE_NAME`, so several interfaces can be used in one process at the same time.
+/// Cloning a context shares the host.
+///
+/// # Example
+/// ```no_run
+/// use multichannel_audio::context::AudioContext;
+///
+/// let context = AudioContext::default_host();
+/// let speakers = context.instance("Focusrite USB").sample_rate(48000).build().unwrap();
+/// let microphones = context.instance("MOTU 8A").sample_rate(48000).build().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct AudioContext {
+    host: Arc<Mutex<cpal::Host>>,
+}
+
+impl AudioContext {
+    pub fn new(host: cpal::Host) -> Self {
+        AudioContext {
+            host: Arc::new(Mutex::new(host)),
+        }
+    }
+
+    /// Use the default host of the platform.
+    pub fn default_host() -> Self {
+        Self::new(cpal::default_host())
+    }
+
+    /// Use a specific host, e.g. `cpal::HostId::Asio`.
+    ///
+    /// # Errors
+    /// Returns an error if the host is not available
+    pub fn from_host_id(id: cpal::HostId) -> Result<Self, MissingDeviceError> {
+        Ok(Self::new(cpal::host_from_id(id)?))
+    }
+
+    /// Use a host by its name, e.g. "ASIO" or "CoreAudio", ignoring case.
+    ///
+    /// # Errors
+    /// Returns an error if the host is not available on this platform
+    pub fn from_host_name(name: &str) -> Result<Self, anyhow::Error> {
+        let id = cpal::available_hosts()
+            .into_iter()
+            .find(|id| id.name().eq_ignore_ascii_case(name))
+            .ok_or(anyhow::anyhow!("The {} host is not available", name))?;
+        Ok(Self::from_host_id(id)?)
+    }
+
+    /// The names of the devices that can play audio.
+    pub fn output_device_names(&self) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self
+            .host()
+            .output_devices()?
+            .filter_map(|device| device.name().ok())
+            .collect())
+    }
+
+    /// The names of the devices that can record audio.
+    pub fn input_device_names(&self) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self
+            .host()
+            .input_devices()?
+            .filter_map(|device| device.name().ok())
+            .collect())
+    }
+
+    /// Start configuring an instance bound to a device of this host.
+    pub fn instance(&self, device_name: &str) -> AudioInstanceBuilder {
+        AudioInstanceBuilder::new()
+            .context(self)
+            .device(device_name)
+    }
+
+    /// The name of the default output device of the host.
+    pub(crate) fn default_output_device_name(&self) -> Result<String, anyhow::Error> {
+        self.host()
+            .default_output_device()
+            .and_then(|device| device.name().ok())
+            .ok_or(anyhow::Error::msg("No default output device"))
+    }
+
+    pub(crate) fn host(&self) -> MutexGuard<'_, cpal::Host> {
+        self.host.lock().unwrap()
+    }
+}
+
+impl fmt::Debug for AudioContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AudioContext")
+            .field("host", &self.host().id().name())
+            .finish()
+    }
+}
+
+impl PartialEq for AudioContext {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.host, &other.host)
+    }
+}
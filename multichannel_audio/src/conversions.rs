@@ -0,0 +1,209 @@
+use crate::sample_formats::Sample;
+
+/// Convert full-scale i32 samples to f32 samples from -1.0 to 1.0.
+pub fn i32_to_f32(samples: &[i32]) -> Vec<f32> {
+    samples
+        .iter()
+        .map(|&sample| f32::from_i32(sample))
+        .collect()
+}
+
+/// Convert f32 samples from -1.0 to 1.0 to full-scale i32 samples, rounded to the nearest step.
+/// Out of range samples are clipped.
+pub fn f32_to_i32(samples: &[f32]) -> Vec<i32> {
+    samples.iter().map(|&sample| sample.to_i32()).collect()
+}
+
+/// Convert full-scale i32 samples to f64 samples from -1.0 to 1.0.
+pub fn i32_to_f64(samples: &[i32]) -> Vec<f64> {
+    samples
+        .iter()
+        .map(|&sample| f64::from_i32(sample))
+        .collect()
+}
+
+/// Convert f64 samples from -1.0 to 1.0 to full-scale i32 samples, rounded to the nearest step.
+/// Out of range samples are clipped.
+pub fn f64_to_i32(samples: &[f64]) -> Vec<i32> {
+    samples.iter().map(|&sample| sample.to_i32()).collect()
+}
+
+/// Convert full-scale i32 samples to 16-bit samples, dropping the lowest 16 bits.
+pub fn i32_to_i16(samples: &[i32]) -> Vec<i16> {
+    samples
+        .iter()
+        .map(|&sample| i16::from_i32(sample))
+        .collect()
+}
+
+/// Convert 16-bit samples to full-scale i32 samples.
+pub fn i16_to_i32(samples: &[i16]) -> Vec<i32> {
+    samples.iter().map(|&sample| sample.to_i32()).collect()
+}
+
+/// Interleave channels into frames.
+///
+/// # Arguments
+/// channels: &[Vec<T>] - the samples of each channel
+///
+/// # Errors
+/// Returns an error if the channels are not all the same length
+pub fn interleave<T: Copy>(channels: &[Vec<T>]) -> Result<Vec<T>, anyhow::Error> {
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if channels.iter().any(|channel| channel.len() != length) {
+        return Err(anyhow::Error::msg(
+            "All channels must be the same length to interleave them",
+        ));
+    }
+
+    let mut interleaved = Vec::with_capacity(length * channels.len());
+    for frame in 0..length {
+        interleaved.extend(channels.iter().map(|channel| channel[frame]));
+    }
+    Ok(interleaved)
+}
+
+/// Split interleaved frames into channels. A partial frame at the end is dropped.
+///
+/// # Arguments
+/// interleaved: &[T] - the interleaved samples
+/// channels: usize - the number of channels in a frame
+pub fn deinterleave<T: Copy>(interleaved: &[T], channels: usize) -> Vec<Vec<T>> {
+    if channels == 0 {
+        return Vec::new();
+    }
+    (0..channels)
+        .map(|channel| {
+            interleaved
+                .chunks_exact(channels)
+                .map(|frame| frame[channel])
+                .collect()
+        })
+        .collect()
+}
+
+/// Convert a level in dB to a linear gain.
+pub fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// Convert a linear gain to a level in dB. A gain of 0 is negative infinity.
+pub fn linear_to_db(linear: f64) -> f64 {
+    20.0 * linear.log10()
+}
+
+/// Convert a level in dBFS to the i32 sample value it corresponds to, clipped to full scale.
+pub fn dbfs_to_i32(dbfs: f64) -> i32 {
+    (db_to_linear(dbfs) * i32::MAX as f64).min(i32::MAX as f64) as i32
+}
+
+/// Scale a signal so its peak is at a level.
+///
+/// # Arguments
+/// samples: &mut [i32] - the signal to scale in place
+/// target_dbfs: f64 - the peak level to scale to, in dBFS
+///
+/// # Returns
+/// The gain that was applied, or 1.0 if the signal is silent
+pub fn normalize_to_peak(samples: &mut [i32], target_dbfs: f64) -> f64 {
+    let peak = samples
+        .iter()
+        .map(|&sample| sample.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+    if peak == 0 {
+        return 1.0;
+    }
+    let gain = db_to_linear(target_dbfs) * i32::MAX as f64 / peak as f64;
+    apply_gain(samples, gain);
+    gain
+}
+
+/// Scale a signal so its RMS is at a level. The peaks are clipped if the signal doesn't fit.
+///
+/// # Arguments
+/// samples: &mut [i32] - the signal to scale in place
+/// target_dbfs: f64 - the RMS level to scale to, in dBFS
+///
+/// # Returns
+/// The gain that was applied, or 1.0 if the signal is silent
+pub fn normalize_to_rms(samples: &mut [i32], target_dbfs: f64) -> f64 {
+    if samples.is_empty() {
+        return 1.0;
+    }
+    let mean_square = samples
+        .iter()
+        .map(|&sample| (sample as f64).powi(2))
+        .sum::<f64>()
+        / samples.len() as f64;
+    if mean_square == 0.0 {
+        return 1.0;
+    }
+    let gain = db_to_linear(target_dbfs) * i32::MAX as f64 / mean_square.sqrt();
+    apply_gain(samples, gain);
+    gain
+}
+
+fn apply_gain(samples: &mut [i32], gain: f64) {
+    for sample in samples.iter_mut() {
+        // float to int casts saturate, so the peaks are clipped
+        *sample = (*sample as f64 * gain).round() as i32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_float_round_trip() {
+        let samples = vec![0, i32::MAX, -i32::MAX, i32::MAX / 2];
+        let floats = i32_to_f32(&samples);
+        assert_eq!(floats[1], 1.0);
+        assert_eq!(floats[2], -1.0);
+        assert_eq!(f32_to_i32(&[2.0, -2.0]), vec![i32::MAX, i32::MIN]);
+        assert_eq!(
+            i32_to_f64(&samples),
+            vec![0.0, 1.0, -1.0, 0.5 - 0.5 / i32::MAX as f64]
+        );
+        // half of i32::MAX is half way between two steps, and rounds up
+        assert_eq!(f64_to_i32(&[0.5]), vec![i32::MAX / 2 + 1]);
+    }
+
+    #[test]
+    fn test_i16_round_trip() {
+        let samples = vec![0i16, 1, -1, i16::MAX, i16::MIN];
+        assert_eq!(i32_to_i16(&i16_to_i32(&samples)), samples);
+    }
+
+    #[test]
+    fn test_interleave_round_trip() {
+        let channels = vec![vec![1, 2, 3], vec![4, 5, 6]];
+        let interleaved = interleave(&channels).unwrap();
+        assert_eq!(interleaved, vec![1, 4, 2, 5, 3, 6]);
+        assert_eq!(deinterleave(&interleaved, 2), channels);
+        assert!(interleave(&[vec![1, 2], vec![3]]).is_err());
+    }
+
+    #[test]
+    fn test_db_conversions() {
+        assert!((db_to_linear(-6.0206) - 0.5).abs() < 1e-4);
+        assert!((linear_to_db(0.1) + 20.0).abs() < 1e-9);
+        assert_eq!(dbfs_to_i32(10.0), i32::MAX);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut samples = vec![1000, -2000, 500];
+        normalize_to_peak(&mut samples, 0.0);
+        assert_eq!(samples[1], -i32::MAX);
+
+        let mut samples = vec![1000, -1000, 1000, -1000];
+        normalize_to_rms(&mut samples, -20.0);
+        assert_eq!(samples[0], (i32::MAX as f64 * 0.1).round() as i32);
+
+        let mut silence = vec![0; 4];
+        assert_eq!(normalize_to_peak(&mut silence, 0.0), 1.0);
+        assert_eq!(normalize_to_rms(&mut silence, 0.0), 1.0);
+    }
+}
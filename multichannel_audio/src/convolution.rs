@@ -0,0 +1,361 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+#[cfg(feature = "device")]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "device")]
+use std::sync::mpsc;
+#[cfg(feature = "device")]
+use std::thread::JoinHandle;
+#[cfg(feature = "device")]
+use std::time::Duration;
+
+#[cfg(feature = "device")]
+use crate::audio_class::{AudioInstance, StreamControllerType};
+#[cfg(feature = "device")]
+use crate::channel::InputChannel;
+#[cfg(feature = "device")]
+use crate::frame_queue::{FrameQueueSlot, FrameRing};
+
+/// How much input and convolved output a `ConvolutionMonitor` holds for a slow worker or
+/// reader, in seconds. Blocks that don't fit are dropped.
+#[cfg(feature = "device")]
+const MONITOR_BUFFER_SECONDS: f64 = 2.0;
+
+/// Convolves a stream with an impulse response using uniformly partitioned overlap-save.
+///
+/// The impulse response is split into partitions of `block_size` samples, so long responses
+/// cost one FFT of twice the block size per block instead of one FFT the length of the whole
+/// response. The output is delayed by `block_size` samples.
+pub struct PartitionedConvolver {
+    block_size: usize,
+    fft: Arc<dyn Fft<f64>>,
+    ifft: Arc<dyn Fft<f64>>,
+    /// The spectrum of each partition of the impulse response
+    partitions: Vec<Vec<Complex<f64>>>,
+    /// The spectra of the most recent input blocks, newest first
+    history: VecDeque<Vec<Complex<f64>>>,
+    /// The previous and current input blocks
+    window: Vec<f64>,
+    /// Input samples waiting for a full block
+    pending: Vec<f64>,
+    /// Convolved samples waiting to be returned
+    output: VecDeque<f64>,
+}
+
+impl PartitionedConvolver {
+    /// # Arguments
+    /// impulse_response: &[f64] - the impulse response to convolve with
+    /// block_size: usize - the partition size, which is also the latency in samples
+    ///
+    /// # Errors
+    /// Returns an error if the impulse response is empty or the block size is 0
+    pub fn new(impulse_response: &[f64], block_size: usize) -> Result<Self, anyhow::Error> {
+        if impulse_response.is_empty() || block_size == 0 {
+            return Err(anyhow::Error::msg(
+                "The impulse response and block size must not be empty",
+            ));
+        }
+
+        let fft_size = 2 * block_size;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let ifft = planner.plan_fft_inverse(fft_size);
+
+        let partitions: Vec<Vec<Complex<f64>>> = impulse_response
+            .chunks(block_size)
+            .map(|partition| {
+                let mut spectrum = vec![Complex::new(0.0, 0.0); fft_size];
+                for (bin, &sample) in spectrum.iter_mut().zip(partition) {
+                    bin.re = sample;
+                }
+                fft.process(&mut spectrum);
+                spectrum
+            })
+            .collect();
+        let history = (0..partitions.len())
+            .map(|_| vec![Complex::new(0.0, 0.0); fft_size])
+            .collect();
+
+        Ok(PartitionedConvolver {
+            block_size,
+            fft,
+            ifft,
+            partitions,
+            history,
+            window: vec![0.0; fft_size],
+            pending: Vec::with_capacity(block_size),
+            output: vec![0.0; block_size].into(),
+        })
+    }
+
+    /// The delay of the output in samples.
+    pub fn latency(&self) -> usize {
+        self.block_size
+    }
+
+    /// Convolve the next input samples. Returns as many samples as were given, delayed by the
+    /// block size.
+    pub fn process(&mut self, input: &[f64]) -> Vec<f64> {
+        for &sample in input {
+            self.pending.push(sample);
+            if self.pending.len() == self.block_size {
+                self.process_block();
+            }
+        }
+        self.output.drain(..input.len()).collect()
+    }
+
+    fn process_block(&mut self) {
+        let fft_size = 2 * self.block_size;
+
+        // overlap-save: the FFT window is the previous block followed by the new one
+        self.window.copy_within(self.block_size.., 0);
+        self.window[self.block_size..].copy_from_slice(&self.pending);
+        self.pending.clear();
+
+        let mut spectrum = self.history.pop_back().unwrap();
+        for (bin, &sample) in spectrum.iter_mut().zip(&self.window) {
+            *bin = Complex::new(sample, 0.0);
+        }
+        self.fft.process(&mut spectrum);
+        self.history.push_front(spectrum);
+
+        // multiply each past block by the matching partition of the impulse response
+        let mut sum = vec![Complex::new(0.0, 0.0); fft_size];
+        for (block, partition) in self.history.iter().zip(&self.partitions) {
+            for ((total, &x), &h) in sum.iter_mut().zip(block).zip(partition) {
+                *total += x * h;
+            }
+        }
+        self.ifft.process(&mut sum);
+
+        // the first half is circular aliasing, the second half is the valid output
+        let scale = 1.0 / fft_size as f64;
+        self.output
+            .extend(sum[self.block_size..].iter().map(|bin| bin.re * scale));
+    }
+}
+
+/// Convolves input channels with impulse responses while the input stream runs.
+///
+/// Created with `AudioInstance::start_convolution_monitor`. Dropping the monitor stops it.
+#[cfg(feature = "device")]
+pub struct ConvolutionMonitor {
+    tap: Arc<FrameQueueSlot>,
+    ring: Arc<FrameRing>,
+    output: mpsc::Receiver<Vec<Vec<f64>>>,
+    dropped_outputs: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "device")]
+impl ConvolutionMonitor {
+    /// The convolved audio of each block of `block_size` frames, with one vector per filter in
+    /// the order they were given. Samples are scaled to -1.0 to 1.0 before convolving.
+    pub fn output(&self) -> &mpsc::Receiver<Vec<Vec<f64>>> {
+        &self.output
+    }
+
+    /// The number of blocks dropped because the worker or the reader of `output` fell behind.
+    pub fn dropped_blocks(&self) -> usize {
+        self.ring.dropped_blocks() + self.dropped_outputs.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "device")]
+impl Drop for ConvolutionMonitor {
+    fn drop(&mut self) {
+        // only detach the ring if a newer monitor hasn't replaced it
+        let mut tap = self.tap.lock().unwrap();
+        if tap
+            .as_ref()
+            .is_some_and(|ring| Arc::ptr_eq(ring, &self.ring))
+        {
+            *tap = None;
+        }
+        drop(tap);
+        self.stop.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Convolve input channels with impulse responses as the audio arrives, e.g. to check a
+    /// correction filter is having the expected effect.
+    ///
+    /// The input callback copies each buffer into a preallocated ring, and a worker thread runs a
+    /// `PartitionedConvolver` per filter on each block of `block_size` frames, so the audio thread
+    /// never allocates or convolves. Up to 2 seconds of input and output are held, and blocks
+    /// that don't fit are dropped and counted in `dropped_blocks`. This runs whether or not
+    /// anything is being recorded. Only one monitor can run at a time.
+    ///
+    /// # Arguments
+    /// filters: Vec<(InputChannel, Vec<f64>)> - each input channel and the impulse response to convolve it with
+    /// block_size: usize - the partition size of the convolution, which is also its latency in samples
+    ///
+    /// # Errors
+    /// Returns an error if a channel is out of range or an impulse response is empty
+    pub fn start_convolution_monitor(
+        &self,
+        filters: Vec<(InputChannel, Vec<f64>)>,
+        block_size: usize,
+    ) -> Result<ConvolutionMonitor, anyhow::Error> {
+        let channels = self.number_of_input_channels as usize;
+        let mut convolvers = Vec::with_capacity(filters.len());
+        for (channel, impulse_response) in filters.iter() {
+            convolvers.push((
+                self.input_index(*channel)?.get(),
+                PartitionedConvolver::new(impulse_response, block_size)?,
+            ));
+        }
+
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        let buffered_frames = MONITOR_BUFFER_SECONDS * self.sample_rate as f64;
+        let capacity_blocks = ((buffered_frames / block_size as f64).ceil() as usize).max(2);
+        let ring = Arc::new(FrameRing::new(channels, block_size, capacity_blocks));
+        let (output_sender, output) = mpsc::sync_channel(capacity_blocks);
+        let dropped_outputs = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        // poll a few times per block, since the input callback can't wake a waiting thread
+        let poll_interval =
+            Duration::from_secs_f64(block_size as f64 / self.sample_rate as f64 / 4.0);
+
+        let worker = {
+            let ring = Arc::clone(&ring);
+            let dropped_outputs = Arc::clone(&dropped_outputs);
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || {
+                let mut block = vec![0; ring.block_samples()];
+                while !stop.load(Ordering::Acquire) {
+                    if !ring.pop(&mut block) {
+                        std::thread::sleep(poll_interval);
+                        continue;
+                    }
+                    let convolved = convolvers
+                        .iter_mut()
+                        .map(|(index, convolver)| {
+                            let samples: Vec<f64> = block
+                                .iter()
+                                .skip(*index)
+                                .step_by(channels)
+                                .map(|&sample| sample as f64 / i32::MAX as f64)
+                                .collect();
+                            convolver.process(&samples)
+                        })
+                        .collect();
+                    match output_sender.try_send(convolved) {
+                        Ok(()) => {}
+                        Err(mpsc::TrySendError::Full(_)) => {
+                            dropped_outputs.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(mpsc::TrySendError::Disconnected(_)) => break,
+                    }
+                }
+            })
+        };
+        *self.input_tap.lock().unwrap() = Some(Arc::clone(&ring));
+
+        Ok(ConvolutionMonitor {
+            tap: Arc::clone(&self.input_tap),
+            ring,
+            output,
+            dropped_outputs,
+            stop,
+            worker: Some(worker),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "device")]
+    use crate::backend::{ChannelModel, MockDevice};
+    #[cfg(feature = "device")]
+    use crate::builder::AudioInstanceBuilder;
+
+    fn direct_convolution(signal: &[f64], impulse_response: &[f64]) -> Vec<f64> {
+        (0..signal.len())
+            .map(|n| {
+                impulse_response
+                    .iter()
+                    .enumerate()
+                    .filter(|(k, _)| *k <= n)
+                    .map(|(k, &h)| h * signal[n - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_partitioned_convolution_matches_direct() {
+        let signal: Vec<f64> = (0..1000)
+            .map(|n| ((n * 37 % 101) as f64 - 50.0) / 50.0)
+            .collect();
+        // longer than several blocks so the partitions are used
+        let impulse_response: Vec<f64> = (0..150)
+            .map(|n| 0.97f64.powi(n) * if n % 3 == 0 { 1.0 } else { -0.5 })
+            .collect();
+        let expected = direct_convolution(&signal, &impulse_response);
+
+        let mut convolver = PartitionedConvolver::new(&impulse_response, 32).unwrap();
+        // feed uneven chunks, like audio callbacks
+        let mut output = Vec::new();
+        for chunk in signal.chunks(45) {
+            output.extend(convolver.process(chunk));
+        }
+
+        assert_eq!(output.len(), signal.len());
+        let latency = convolver.latency();
+        assert!(output[..latency].iter().all(|&x| x == 0.0));
+        for (actual, expected) in output[latency..].iter().zip(&expected) {
+            assert!((actual - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_partitioned_convolver_errors() {
+        assert!(PartitionedConvolver::new(&[], 64).is_err());
+        assert!(PartitionedConvolver::new(&[1.0], 0).is_err());
+    }
+
+    #[cfg(feature = "device")]
+    #[test]
+    fn test_convolution_monitor() {
+        let device = MockDevice::new(2, 0)
+            .channel(InputChannel(2), ChannelModel::unconnected().noise(100_000));
+        let audio_instance = AudioInstanceBuilder::new().mock(device).build().unwrap();
+
+        let monitor = audio_instance
+            .start_convolution_monitor(vec![(InputChannel(2), vec![1.0])], 64)
+            .unwrap();
+        let mut heard = false;
+        for _ in 0..20 {
+            let block = monitor
+                .output()
+                .recv_timeout(std::time::Duration::from_secs(1))
+                .unwrap();
+            assert_eq!(block.len(), 1);
+            assert_eq!(block[0].len(), 64);
+            heard |= block[0].iter().any(|&sample| sample != 0.0);
+        }
+        assert!(heard);
+        assert_eq!(monitor.dropped_blocks(), 0);
+        assert!(audio_instance.input_tap.lock().unwrap().is_some());
+
+        drop(monitor);
+        assert!(audio_instance.input_tap.lock().unwrap().is_none());
+
+        assert!(audio_instance
+            .start_convolution_monitor(vec![(InputChannel(3), vec![1.0])], 64)
+            .is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::audio_class::AudioInstance;
+use crate::distortion::tone_level_dbfs;
+
+/// The level of a test tone on every input channel while each output channel plays it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrosstalkMatrix {
+    /// The level of the tone in dBFS, indexed by output channel index and then input channel
+    /// index. Negative infinity means the input was silent at the tone frequency.
+    pub levels_dbfs: Vec<Vec<f64>>,
+}
+
+impl CrosstalkMatrix {
+    /// The level on each input relative to the loudest input for the same output, in dB.
+    ///
+    /// The loudest input is taken to be the intended path, so it is 0 dB and every other input is
+    /// the crosstalk from that output. Rows of outputs no input heard are negative infinity.
+    pub fn crosstalk_db(&self) -> Vec<Vec<f64>> {
+        self.levels_dbfs
+            .iter()
+            .map(|row| {
+                let reference = row.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                row.iter()
+                    .map(|&level| {
+                        if reference == f64::NEG_INFINITY {
+                            f64::NEG_INFINITY
+                        } else {
+                            level - reference
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The worst crosstalk between any output and an input other than its loudest, in dB.
+    pub fn worst_crosstalk_db(&self) -> f64 {
+        self.crosstalk_db()
+            .iter()
+            .flat_map(|row| {
+                let mut row = row.clone();
+                row.sort_by(|a, b| b.total_cmp(a));
+                row.get(1).copied()
+            })
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+impl AudioInstance {
+    /// Measure the crosstalk between every output channel and every input channel.
+    ///
+    /// Plays a sine on each output channel in turn while recording every input, and measures the
+    /// level of the sine on each input with `tone_level_dbfs`, so noise at other frequencies
+    /// doesn't count as crosstalk.
+    ///
+    /// # Arguments
+    /// frequency: f64 - the frequency of the test tone in Hz
+    /// level_dbfs: f64 - the peak level of the test tone in dBFS
+    ///
+    /// # Returns
+    /// An output channels by input channels matrix of levels
+    ///
+    /// # Errors
+    /// Returns an error if the level is above 0 dBFS or the frequency is out of range
+    /// Returns an error if the tone is blocked by the safety interlock
+    pub fn measure_crosstalk(
+        &self,
+        frequency: f64,
+        level_dbfs: f64,
+    ) -> Result<CrosstalkMatrix, anyhow::Error> {
+        let mut levels_dbfs = Vec::with_capacity(self.number_of_output_channels as usize);
+        for output_index in 0..self.number_of_output_channels as usize {
+            let recorded_data = self.play_record_tone(output_index, frequency, level_dbfs)?;
+            levels_dbfs.push(
+                recorded_data
+                    .iter()
+                    .map(|channel| tone_level_dbfs(channel, self.sample_rate, frequency))
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+        }
+
+        Ok(CrosstalkMatrix { levels_dbfs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{ChannelModel, MockDevice};
+    use crate::builder::AudioInstanceBuilder;
+    use crate::channel::{InputChannel, OutputChannel};
+
+    #[test]
+    fn test_measure_crosstalk() {
+        // input 2 hears output 1 40 dB down, and nothing hears output 2
+        let device = MockDevice::new(2, 2).channel(
+            InputChannel(2),
+            ChannelModel::from_output(OutputChannel(1)).gain(0.01),
+        );
+        let audio_instance = AudioInstanceBuilder::new().mock(device).build().unwrap();
+
+        let matrix = audio_instance.measure_crosstalk(1000.0, -6.0).unwrap();
+        assert_eq!(matrix.levels_dbfs.len(), 2);
+        assert!((matrix.levels_dbfs[0][0] + 6.0).abs() < 0.05);
+        assert!((matrix.levels_dbfs[0][1] + 46.0).abs() < 0.05);
+        assert_eq!(matrix.levels_dbfs[1], vec![f64::NEG_INFINITY; 2]);
+
+        let crosstalk = matrix.crosstalk_db();
+        assert_eq!(crosstalk[0][0], 0.0);
+        assert!((crosstalk[0][1] + 40.0).abs() < 0.05);
+        assert!((matrix.worst_crosstalk_db() + 40.0).abs() < 0.05);
+
+        assert!(audio_instance.measure_crosstalk(1000.0, 1.0).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::missing_device_error::MissingDeviceError;
+
+#[cfg(feature = "device")]
+use super::methods::{DEVICE_NAME, HOST};
+#[cfg(feature = "device")]
+use cpal::traits::{DeviceTrait, HostTrait};
+
+/// Matches scoring below this are not considered the same device.
+const MINIMUM_MATCH_SCORE: f64 = 0.5;
+
+/// A stable identifier for an audio device that can be saved and resolved on another machine.
+///
+/// Device names differ between hosts and operating systems (e.g. `Focusrite USB ASIO` on Windows
+/// and `hw:CARD=USB,DEV=0` on Linux), so a saved identifier is resolved with fuzzy matching on the
+/// name and the channel signature rather than requiring an exact name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceId {
+    /// The name of the host the device was saved from, e.g. `ASIO` or `ALSA`
+    pub host: String,
+    pub name: String,
+    pub input_channels: u16,
+    pub output_channels: u16,
+}
+
+/// A device available on the current host, used as a candidate when resolving a `DeviceId`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCandidate {
+    pub name: String,
+    pub input_channels: u16,
+    pub output_channels: u16,
+}
+
+impl DeviceId {
+    /// The identifier of the currently selected device.
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized or the device is not found
+    #[cfg(feature = "device")]
+    pub fn current() -> Result<DeviceId, MissingDeviceError> {
+        let device_name = DEVICE_NAME.lock().unwrap().clone();
+        let binding = HOST.lock().unwrap();
+        let host = binding.as_ref().ok_or(MissingDeviceError::Error(
+            "Host not initialized".to_string(),
+        ))?;
+
+        let candidate = list_candidates(host)?
+            .into_iter()
+            .find(|candidate| candidate.name == device_name)
+            .ok_or(MissingDeviceError::Error("Device not found".to_string()))?;
+
+        Ok(DeviceId {
+            host: host.id().name().to_string(),
+            name: candidate.name,
+            input_channels: candidate.input_channels,
+            output_channels: candidate.output_channels,
+        })
+    }
+
+    /// Find the device on the current host that best matches this identifier.
+    ///
+    /// # Returns
+    /// The name of the matching device on the current host
+    ///
+    /// # Errors
+    /// Returns an error listing the available devices if none match closely enough
+    #[cfg(feature = "device")]
+    pub fn resolve(&self) -> Result<String, MissingDeviceError> {
+        let binding = HOST.lock().unwrap();
+        let host = binding.as_ref().ok_or(MissingDeviceError::Error(
+            "Host not initialized".to_string(),
+        ))?;
+
+        self.best_match(&list_candidates(host)?)
+    }
+
+    /// Resolve this identifier and select the device for audio I/O.
+    #[cfg(feature = "device")]
+    pub fn select(&self) -> Result<(), MissingDeviceError> {
+        let name = self.resolve()?;
+        *DEVICE_NAME.lock().unwrap() = name;
+        Ok(())
+    }
+
+    /// Find the candidate that best matches this identifier.
+    ///
+    /// An exact name match always wins. Otherwise candidates are scored on the overlap of the words
+    /// in their names and on whether their channel counts match.
+    pub fn best_match(&self, candidates: &[DeviceCandidate]) -> Result<String, MissingDeviceError> {
+        if let Some(candidate) = candidates.iter().find(|c| c.name == self.name) {
+            return Ok(candidate.name.clone());
+        }
+
+        let mut scored: Vec<(f64, &DeviceCandidate)> = candidates
+            .iter()
+            .map(|candidate| (self.score(candidate), candidate))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        match scored.first() {
+            Some(&(score, candidate)) if score >= MINIMUM_MATCH_SCORE => Ok(candidate.name.clone()),
+            _ => {
+                let available: Vec<String> = scored
+                    .iter()
+                    .map(|(score, candidate)| {
+                        format!(
+                            "\t{} ({} in, {} out, match {:.2})",
+                            candidate.name,
+                            candidate.input_channels,
+                            candidate.output_channels,
+                            score
+                        )
+                    })
+                    .collect();
+                Err(MissingDeviceError::Error(format!(
+                    "No device matches {}\nAvailable devices:\n{}",
+                    self,
+                    available.join("\n")
+                )))
+            }
+        }
+    }
+
+    fn score(&self, candidate: &DeviceCandidate) -> f64 {
+        let saved_words = name_words(&self.name);
+        let candidate_words = name_words(&candidate.name);
+
+        let shared = saved_words
+            .iter()
+            .filter(|word| candidate_words.contains(word))
+            .count();
+        let total = saved_words.len().max(candidate_words.len());
+        let name_score = if total == 0 {
+            0.0
+        } else {
+            shared as f64 / total as f64
+        };
+
+        let channel_score = if candidate.input_channels == self.input_channels
+            && candidate.output_channels == self.output_channels
+        {
+            1.0
+        } else {
+            0.0
+        };
+
+        0.7 * name_score + 0.3 * channel_score
+    }
+}
+
+/// Split a device name into lowercase words, ignoring words that only describe the host.
+fn name_words(name: &str) -> Vec<String> {
+    const HOST_WORDS: [&str; 6] = ["asio", "wasapi", "alsa", "hw", "dev", "card"];
+
+    name.split(|c: char| !c.is_alphanumeric())
+        .map(|word| word.to_lowercase())
+        .filter(|word| !word.is_empty() && !HOST_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+#[cfg(feature = "device")]
+fn list_candidates(host: &cpal::Host) -> Result<Vec<DeviceCandidate>, MissingDeviceError> {
+    let devices = host
+        .devices()
+        .map_err(|e| MissingDeviceError::Error(format!("Failed to get devices: {}", e)))?;
+
+    Ok(devices
+        .filter_map(|device| {
+            Some(DeviceCandidate {
+                name: device.name().ok()?,
+                input_channels: device
+                    .default_input_config()
+                    .map_or(0, |config| config.channels()),
+                output_channels: device
+                    .default_output_config()
+                    .map_or(0, |config| config.channels()),
+            })
+        })
+        .collect())
+}
+
+/// Formats as `host|name|input channels|output channels` for saving in config files.
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}|{}|{}|{}",
+            self.host, self.name, self.input_channels, self.output_channels
+        )
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = MissingDeviceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MissingDeviceError::Error(format!("Invalid device identifier: {}", s));
+
+        // the name may contain the separator, so split the channel counts off the end
+        let mut parts = s.rsplitn(3, '|');
+        let output_channels = parts.next().ok_or_else(invalid)?;
+        let input_channels = parts.next().ok_or_else(invalid)?;
+        let (host, name) = parts
+            .next()
+            .ok_or_else(invalid)?
+            .split_once('|')
+            .ok_or_else(invalid)?;
+
+        Ok(DeviceId {
+            host: host.to_string(),
+            name: name.to_string(),
+            input_channels: input_channels.parse().map_err(|_| invalid())?,
+            output_channels: output_channels.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn focusrite_id() -> DeviceId {
+        DeviceId {
+            host: "ASIO".to_string(),
+            name: "Focusrite USB ASIO".to_string(),
+            input_channels: 18,
+            output_channels: 20,
+        }
+    }
+
+    fn candidate(name: &str, input_channels: u16, output_channels: u16) -> DeviceCandidate {
+        DeviceCandidate {
+            name: name.to_string(),
+            input_channels,
+            output_channels,
+        }
+    }
+
+    #[test]
+    fn test_device_id_round_trip() {
+        let id = DeviceId {
+            name: "Odd|Name".to_string(),
+            ..focusrite_id()
+        };
+
+        assert_eq!(id.to_string().parse::<DeviceId>().unwrap(), id);
+    }
+
+    #[test]
+    fn test_invalid_device_id() {
+        assert!("ASIO|Focusrite".parse::<DeviceId>().is_err());
+        assert!("ASIO|Focusrite|x|2".parse::<DeviceId>().is_err());
+    }
+
+    #[test]
+    fn test_best_match_exact_name() {
+        let candidates = vec![
+            candidate("Speakers", 0, 2),
+            candidate("Focusrite USB ASIO", 2, 2),
+        ];
+
+        assert_eq!(
+            focusrite_id().best_match(&candidates).unwrap(),
+            "Focusrite USB ASIO"
+        );
+    }
+
+    #[test]
+    fn test_best_match_across_hosts() {
+        let candidates = vec![
+            candidate("HDA Intel PCH", 2, 2),
+            candidate("Focusrite USB", 18, 20),
+        ];
+
+        assert_eq!(
+            focusrite_id().best_match(&candidates).unwrap(),
+            "Focusrite USB"
+        );
+    }
+
+    #[test]
+    fn test_no_match_lists_devices() {
+        let candidates = vec![candidate("HDA Intel PCH", 2, 2)];
+        let error = focusrite_id().best_match(&candidates).unwrap_err();
+
+        assert!(error.to_string().contains("HDA Intel PCH"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{error::Error, fmt};
+
+use fs4::{FileExt, TryLockError};
+
+lazy_static::lazy_static! {
+    static ref LOCK_MODE: Mutex<DeviceLockMode> = Mutex::new(DeviceLockMode::Fail);
+}
+
+/// What to do when another process is using the device.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DeviceLockMode {
+    /// Return `DeviceLockError::DeviceLockedByOtherProcess` immediately
+    #[default]
+    Fail,
+    /// Wait for the other process to release the device, for up to the given time or forever.
+    /// Useful for queued lab jobs
+    Wait(Option<Duration>),
+}
+
+/// Error type for when the device can't be locked.
+#[derive(Debug)]
+pub enum DeviceLockError {
+    DeviceLockedByOtherProcess {
+        device: String,
+        /// The process holding the lock, if it could be read from the lock file
+        pid: Option<u32>,
+    },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for DeviceLockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeviceLockError::DeviceLockedByOtherProcess {
+                ref device,
+                pid: Some(pid),
+            } => write!(f, "Device {} is in use by process {}", device, pid),
+            DeviceLockError::DeviceLockedByOtherProcess {
+                ref device,
+                pid: None,
+            } => write!(f, "Device {} is in use by another process", device),
+            DeviceLockError::Io(ref error) => write!(f, "Failed to lock device: {}", error),
+        }
+    }
+}
+
+impl Error for DeviceLockError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DeviceLockError::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DeviceLockError {
+    fn from(error: std::io::Error) -> Self {
+        DeviceLockError::Io(error)
+    }
+}
+
+/// Set what audio instances do when another process is using the device.
+pub fn set_device_lock_mode(mode: DeviceLockMode) {
+    *LOCK_MODE.lock().unwrap() = mode;
+}
+
+pub fn device_lock_mode() -> DeviceLockMode {
+    *LOCK_MODE.lock().unwrap()
+}
+
+/// An exclusive lock on an audio device, shared between every process on the machine.
+///
+/// The lock is an OS file lock on a file in the temp directory named after the device, so it is
+/// released by the OS if the process exits without unlocking. The device is unlocked when this is
+/// dropped.
+#[derive(Debug)]
+pub struct DeviceLock {
+    file: File,
+    device: String,
+}
+
+impl DeviceLock {
+    /// Lock a device, returning an error straight away if another process has it locked.
+    ///
+    /// # Errors
+    /// Returns `DeviceLockError::DeviceLockedByOtherProcess` if the device is locked
+    pub fn acquire(device_name: &str) -> Result<Self, DeviceLockError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path(device_name))?;
+
+        // called through the trait, since newer versions of std have an inherent File::try_lock
+        match FileExt::try_lock(&file) {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(DeviceLockError::DeviceLockedByOtherProcess {
+                    device: device_name.to_string(),
+                    pid: read_pid(&mut file),
+                })
+            }
+            Err(TryLockError::Error(error)) => return Err(error.into()),
+        }
+
+        // record the owner so other processes can report who has the device
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        Ok(DeviceLock {
+            file,
+            device: device_name.to_string(),
+        })
+    }
+
+    /// Lock a device, waiting for another process to release it.
+    ///
+    /// # Arguments
+    /// device_name: &str - the name of the device to lock
+    /// timeout: Option<Duration> - the longest time to wait, or None to wait forever
+    ///
+    /// # Errors
+    /// Returns `DeviceLockError::DeviceLockedByOtherProcess` if the device is still locked after the timeout
+    pub fn acquire_wait(
+        device_name: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Self, DeviceLockError> {
+        let start = Instant::now();
+        loop {
+            match Self::acquire(device_name) {
+                Err(DeviceLockError::DeviceLockedByOtherProcess { .. })
+                    if timeout.map_or(true, |timeout| start.elapsed() < timeout) =>
+                {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Lock a device using the mode set with `set_device_lock_mode`.
+    pub fn acquire_with_mode(device_name: &str) -> Result<Self, DeviceLockError> {
+        match device_lock_mode() {
+            DeviceLockMode::Fail => Self::acquire(device_name),
+            DeviceLockMode::Wait(timeout) => Self::acquire_wait(device_name, timeout),
+        }
+    }
+
+    /// The name of the locked device.
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+}
+
+impl Drop for DeviceLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// The lock file for a device. Characters that aren't valid in file names are replaced.
+fn lock_path(device_name: &str) -> PathBuf {
+    let file_name: String = device_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    std::env::temp_dir().join(format!("multichannel_audio_{}.lock", file_name))
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_fails() {
+        let device = format!("test device {}", std::process::id());
+        let lock = DeviceLock::acquire(&device).unwrap();
+
+        match DeviceLock::acquire(&device) {
+            Err(DeviceLockError::DeviceLockedByOtherProcess { pid, .. }) => {
+                assert_eq!(pid, Some(std::process::id()));
+            }
+            other => panic!("expected the device to be locked, got {:?}", other),
+        }
+
+        drop(lock);
+        assert!(DeviceLock::acquire(&device).is_ok());
+    }
+
+    #[test]
+    fn test_wait_times_out() {
+        let device = format!("test wait device {}", std::process::id());
+        let _lock = DeviceLock::acquire(&device).unwrap();
+
+        let result = DeviceLock::acquire_wait(&device, Some(Duration::from_millis(200)));
+        assert!(matches!(
+            result,
+            Err(DeviceLockError::DeviceLockedByOtherProcess { .. })
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use super::methods::HOST;
+use crate::audio_class::AudioInstance;
+use crate::events::AudioEvent;
+
+/// A change to the audio devices connected to the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added(String),
+    Removed(String),
+}
+
+/// Polls the host for audio devices being connected and disconnected.
+///
+/// The polling thread stops when the monitor is dropped.
+pub struct DeviceMonitor {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DeviceMonitor {
+    /// Start polling the devices of the current host.
+    ///
+    /// # Arguments
+    /// interval: Duration - the time between polls
+    ///
+    /// # Returns
+    /// The monitor and a receiver for the device events
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    pub fn start(interval: Duration) -> Result<(Self, mpsc::Receiver<DeviceEvent>), anyhow::Error> {
+        let (sender, receiver) = mpsc::channel();
+        let monitor = Self::spawn(interval, move |event| {
+            // keep polling if the receiver has been dropped, other watchers may still need it
+            let _ = sender.send(event);
+        })?;
+        Ok((monitor, receiver))
+    }
+
+    /// Start polling and call `on_event` from the polling thread for every event.
+    fn spawn(
+        interval: Duration,
+        mut on_event: impl FnMut(DeviceEvent) + Send + 'static,
+    ) -> Result<Self, anyhow::Error> {
+        let mut known_devices = device_names()?;
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let stop_clone = Arc::clone(&stop);
+        let handle = std::thread::spawn(move || {
+            while !stop_clone.load(Ordering::Acquire) {
+                std::thread::sleep(interval);
+
+                // the host can fail to list devices while one is being plugged in, try again later
+                let Ok(current_devices) = device_names() else {
+                    continue;
+                };
+                for event in diff_devices(&known_devices, &current_devices) {
+                    on_event(event);
+                }
+                known_devices = current_devices;
+            }
+        });
+
+        Ok(DeviceMonitor {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for DeviceMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl AudioInstance {
+    /// Watch for the devices of this instance being disconnected.
+    ///
+    /// When a device is removed the instance is marked unhealthy, and playing or recording returns
+    /// an error instead of waiting forever for a stream that has stopped. The instance is marked
+    /// healthy again when `reconnect` succeeds. Calling this again replaces the previous monitor.
+    ///
+    /// # Arguments
+    /// interval: Duration - the time between polls of the host
+    ///
+    /// # Returns
+    /// A receiver for every device event on the host
+    pub fn monitor_devices(
+        &self,
+        interval: Duration,
+    ) -> Result<mpsc::Receiver<DeviceEvent>, anyhow::Error> {
+        let (sender, receiver) = mpsc::channel();
+        let healthy = Arc::clone(&self.healthy);
+        let events = Arc::clone(&self.events);
+        let watched_devices = self.device_names.clone();
+
+        let monitor = DeviceMonitor::spawn(interval, move |event| {
+            if let DeviceEvent::Removed(ref name) = event {
+                if watched_devices.contains(name) {
+                    healthy.store(false, Ordering::Release);
+                    events.send(AudioEvent::Error(format!("{name} was disconnected")));
+                }
+            }
+            let _ = sender.send(event);
+        })?;
+
+        *self.device_monitor.lock().unwrap() = Some(monitor);
+        Ok(receiver)
+    }
+
+    /// Whether the devices of this instance are still connected, as far as is known.
+    ///
+    /// Only updated while `monitor_devices` is running.
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    pub(super) fn check_healthy(&self) -> Result<(), anyhow::Error> {
+        if !self.is_healthy() {
+            return Err(disconnected_error());
+        }
+        Ok(())
+    }
+}
+
+/// The error returned when an operation can't finish because the device was unplugged.
+pub(crate) fn disconnected_error() -> anyhow::Error {
+    anyhow::Error::msg(
+        "The audio device has been disconnected. Call reconnect once it is plugged back in.",
+    )
+}
+
+fn device_names() -> Result<BTreeSet<String>, anyhow::Error> {
+    let binding = HOST.lock().unwrap();
+    let host = binding
+        .as_ref()
+        .ok_or_else(|| anyhow::Error::msg("Host not initialized"))?;
+
+    Ok(host
+        .devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+/// The events that turn one set of devices into another.
+fn diff_devices(previous: &BTreeSet<String>, current: &BTreeSet<String>) -> Vec<DeviceEvent> {
+    previous
+        .difference(current)
+        .map(|name| DeviceEvent::Removed(name.clone()))
+        .chain(
+            current
+                .difference(previous)
+                .map(|name| DeviceEvent::Added(name.clone())),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff_devices() {
+        let events = diff_devices(
+            &set(&["Speakers", "Focusrite"]),
+            &set(&["Speakers", "Headset"]),
+        );
+
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::Removed("Focusrite".to_string()),
+                DeviceEvent::Added("Headset".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_devices_unchanged() {
+        assert!(diff_devices(&set(&["Speakers"]), &set(&["Speakers"])).is_empty());
+    }
+}
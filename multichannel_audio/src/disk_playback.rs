@@ -0,0 +1,203 @@
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::audio_class::AudioInstance;
+use crate::channel::OutputSelector;
+use crate::methods::read_wave_file_channels;
+use crate::wav_source::WavSource;
+
+/// The length of each chunk read from disk, in seconds.
+const CHUNK_SECONDS: f64 = 0.5;
+
+impl AudioInstance {
+    /// Play a WAV file, streaming it from disk instead of loading it into memory.
+    ///
+    /// A reader thread reads the file in half-second chunks ahead of the output callback, and only
+    /// one chunk is queued behind the one playing, so memory use doesn't depend on the length of
+    /// the file. The chunks are played back-to-back through the output queue without gaps.
+    /// A file at a different sample rate is loaded into memory and resampled instead.
+    /// Blocks until the file has finished playing.
+    ///
+    /// # Arguments
+    /// path: &Path - the WAV file to play, with one channel per output channel of the device
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read
+    /// Returns an error if the number of channels does not match the device
+    /// Returns an error if the signal is blocked by the safety interlock
+    pub fn play_wav_file(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let reader = WavSource::open(path)?;
+        let spec = reader.spec();
+        if spec.channels != self.number_of_output_channels {
+            return Err(anyhow::anyhow!(
+                "The WAV file has {} channels but the device has {} output channels",
+                spec.channels,
+                self.number_of_output_channels
+            ));
+        }
+
+        self.stream_wav(path, reader, None)
+    }
+
+    /// Play a WAV file on some of the output channels, leaving the others silent.
+    ///
+    /// The file is streamed from disk as with `play_wav_file`. Blocks until the file has finished
+    /// playing.
+    ///
+    /// # Arguments
+    /// path: &Path - the WAV file to play
+    /// channel_map: &[impl OutputSelector] - the output channel to play each channel of the file on,
+    /// by number or label
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read
+    /// Returns an error if the map doesn't have one channel per channel of the file, or a channel
+    /// is out of range, repeated or an unknown label
+    /// Returns an error if the signal is blocked by the safety interlock
+    pub fn play_wav(
+        &self,
+        path: &Path,
+        channel_map: &[impl OutputSelector],
+    ) -> Result<(), anyhow::Error> {
+        let reader = WavSource::open(path)?;
+        let spec = reader.spec();
+        if channel_map.len() != spec.channels as usize {
+            return Err(anyhow::anyhow!(
+                "The WAV file has {} channels but the channel map has {}",
+                spec.channels,
+                channel_map.len()
+            ));
+        }
+
+        let mut indices = Vec::with_capacity(channel_map.len());
+        for channel in channel_map {
+            let channel = self.output_channel(channel)?;
+            let index = self.output_index(channel)?.get();
+            if indices.contains(&index) {
+                return Err(anyhow::anyhow!(
+                    "Channel {} has more than one channel of the file",
+                    channel
+                ));
+            }
+            indices.push(index);
+        }
+
+        self.stream_wav(path, reader, Some(indices))
+    }
+
+    /// Queue the chunks of a file for playback and block until they have played.
+    ///
+    /// # Arguments
+    /// path: &Path - the file to play
+    /// reader: WavSource - the file, opened for streaming
+    /// indices: Option<Vec<usize>> - the output channel index of each channel of the file, or None
+    /// if the file has one channel per output channel
+    fn stream_wav(
+        &self,
+        path: &Path,
+        mut reader: WavSource,
+        indices: Option<Vec<usize>>,
+    ) -> Result<(), anyhow::Error> {
+        // a file at another sample rate is resampled as a whole instead of streamed
+        if reader.spec().sample_rate != self.sample_rate {
+            let mut data = read_wave_file_channels(path, self.sample_rate)?;
+            if let Some(ref indices) = indices {
+                data = place_channels(data, indices, self.number_of_output_channels as usize);
+            }
+            self.enqueue(data)?;
+            return self.flush();
+        }
+
+        // the reader holds one chunk and the channel holds one more
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let chunk_frames = (self.sample_rate as f64 * CHUNK_SECONDS) as usize;
+        std::thread::spawn(move || loop {
+            let chunk = reader.read_chunk(chunk_frames);
+            let finished = !matches!(chunk, Ok(Some(_)));
+            // stop reading if playback has been abandoned
+            if sender.send(chunk).is_err() || finished {
+                break;
+            }
+        });
+
+        for chunk in receiver {
+            let Some(mut chunk) = chunk? else {
+                break;
+            };
+            if let Some(ref indices) = indices {
+                chunk = place_channels(chunk, indices, self.number_of_output_channels as usize);
+            }
+
+            // keep a single chunk queued behind the one playing
+            while self.queued() > 0 {
+                self.check_healthy()?;
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            if let Err(error) = self.enqueue(chunk) {
+                self.clear_queue();
+                return Err(error);
+            }
+        }
+
+        self.flush()
+    }
+}
+
+/// Put the channels of a chunk on the output channels at `indices`, with silence on the others.
+fn place_channels(
+    chunk: Vec<Vec<i32>>,
+    indices: &[usize],
+    output_channels: usize,
+) -> Vec<Vec<i32>> {
+    let length = chunk.first().map_or(0, |channel| channel.len());
+    let mut placed = vec![vec![0; length]; output_channels];
+    for (channel, &index) in chunk.into_iter().zip(indices) {
+        placed[index] = channel;
+    }
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+    use crate::channel::OutputChannel;
+    use crate::methods;
+
+    #[test]
+    fn test_place_channels() {
+        let placed = place_channels(vec![vec![1, 2], vec![3, 4]], &[2, 0], 4);
+        assert_eq!(placed, vec![vec![3, 4], vec![0, 0], vec![1, 2], vec![0, 0]]);
+    }
+
+    #[test]
+    fn test_play_wav_checks_the_file() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "multichannel_audio_play_wav_{}.wav",
+            std::process::id()
+        ));
+        methods::save_channels_to_wav(vec![vec![1000; 4800]], path.to_str().unwrap(), 48000)
+            .unwrap();
+
+        assert!(audio_instance.play_wav(&path, &[OutputChannel(2)]).is_ok());
+        assert!(audio_instance
+            .play_wav(&path, &[OutputChannel(1), OutputChannel(2)])
+            .is_err());
+        assert!(audio_instance.play_wav(&path, &[OutputChannel(3)]).is_err());
+        // the file has one channel, the device has two
+        assert!(audio_instance.play_wav_file(&path).is_err());
+
+        // files at another sample rate are resampled
+        methods::save_channels_to_wav(vec![vec![1000; 4410]], path.to_str().unwrap(), 44100)
+            .unwrap();
+        assert!(audio_instance.play_wav(&path, &[OutputChannel(1)]).is_ok());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::frame_queue::FrameRing;
+use crate::sample_formats::Sample;
+use crate::stream_controller::CaptureSink;
+
+/// The sample format of a WAV file written by `record_to_wav`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavSampleFormat {
+    Int16,
+    #[default]
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl WavSampleFormat {
+    fn spec(self, channels: u16, sample_rate: u32) -> hound::WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            WavSampleFormat::Int16 => (16, hound::SampleFormat::Int),
+            WavSampleFormat::Int24 => (24, hound::SampleFormat::Int),
+            WavSampleFormat::Int32 => (32, hound::SampleFormat::Int),
+            WavSampleFormat::Float32 => (32, hound::SampleFormat::Float),
+        };
+        hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+}
+
+/// Options for `record_to_wav`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WavRecordOptions {
+    pub sample_format: WavSampleFormat,
+}
+
+/// The largest RIFF chunk a WAV file can hold. Longer recordings are written as RF64.
+const MAX_RIFF_BYTES: u64 = u32::MAX as u64;
+/// The size of the chunk reserved after the WAVE tag, which becomes the `ds64` chunk of an RF64
+/// file.
+const DS64_CHUNK_BYTES: u32 = 28;
+/// The size of a `WAVE_FORMAT_EXTENSIBLE` fmt chunk.
+const FMT_CHUNK_BYTES: u32 = 40;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+const SUBTYPE_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+const SUBTYPE_IEEE_FLOAT: [u8; 16] = [
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Writes a WAV file that becomes an RF64 file (EBU Tech 3306) if it grows past 4 GB.
+///
+/// A `JUNK` chunk is reserved in the header while the data is written, and is replaced by the
+/// `ds64` chunk holding the 64-bit sizes when the file is finalized, so files under 4 GB are
+/// ordinary WAV files.
+struct Rf64Writer {
+    file: BufWriter<File>,
+    spec: hound::WavSpec,
+    data_bytes: u64,
+}
+
+impl Rf64Writer {
+    fn create(path: &Path, spec: hound::WavSpec) -> Result<Rf64Writer, anyhow::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&wav_header(&spec, 0))?;
+        Ok(Rf64Writer {
+            file,
+            spec,
+            data_bytes: 0,
+        })
+    }
+
+    /// Write a sample at the full scale of an `i32`, converted to the format of the file.
+    fn write_sample(&mut self, sample: i32) -> Result<(), anyhow::Error> {
+        let bytes_per_sample = self.spec.bits_per_sample as usize / 8;
+        match self.spec.sample_format {
+            hound::SampleFormat::Int => {
+                let bytes = (sample >> (32 - self.spec.bits_per_sample)).to_le_bytes();
+                self.file.write_all(&bytes[..bytes_per_sample])?
+            }
+            hound::SampleFormat::Float => {
+                self.file.write_all(&f32::from_i32(sample).to_le_bytes())?
+            }
+        }
+        self.data_bytes += bytes_per_sample as u64;
+        Ok(())
+    }
+
+    /// Pad the data chunk to an even length and write the final sizes to the header.
+    fn finalize(mut self) -> Result<(), anyhow::Error> {
+        if self.data_bytes % 2 == 1 {
+            self.file.write_all(&[0])?;
+        }
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file
+            .write_all(&wav_header(&self.spec, self.data_bytes))?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// The header of a WAV file with `data_bytes` bytes of samples, up to the start of the samples.
+/// It is an RF64 header if the file is too large for a WAV file, and is the same length either way.
+fn wav_header(spec: &hound::WavSpec, data_bytes: u64) -> Vec<u8> {
+    let header_bytes = 4 + 8 + DS64_CHUNK_BYTES + 8 + FMT_CHUNK_BYTES + 8;
+    let riff_bytes = header_bytes as u64 + data_bytes + data_bytes % 2;
+    let rf64 = riff_bytes > MAX_RIFF_BYTES;
+    let bytes_per_sample = spec.bits_per_sample / 8;
+    let block_align = spec.channels * bytes_per_sample;
+
+    let mut header = Vec::with_capacity(header_bytes as usize + 8);
+    if rf64 {
+        header.extend_from_slice(b"RF64");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+    } else {
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(riff_bytes as u32).to_le_bytes());
+    }
+    header.extend_from_slice(b"WAVE");
+
+    header.extend_from_slice(if rf64 { b"ds64" } else { b"JUNK" });
+    header.extend_from_slice(&DS64_CHUNK_BYTES.to_le_bytes());
+    if rf64 {
+        let frames = data_bytes / block_align as u64;
+        header.extend_from_slice(&riff_bytes.to_le_bytes());
+        header.extend_from_slice(&data_bytes.to_le_bytes());
+        header.extend_from_slice(&frames.to_le_bytes());
+        // no table of other chunk sizes
+        header.extend_from_slice(&0u32.to_le_bytes());
+    } else {
+        header.extend_from_slice(&[0; DS64_CHUNK_BYTES as usize]);
+    }
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&FMT_CHUNK_BYTES.to_le_bytes());
+    header.extend_from_slice(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&(spec.sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+    // the size of the extension
+    header.extend_from_slice(&22u16.to_le_bytes());
+    header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+    // no speaker positions
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(match spec.sample_format {
+        hound::SampleFormat::Int => &SUBTYPE_PCM,
+        hound::SampleFormat::Float => &SUBTYPE_IEEE_FLOAT,
+    });
+
+    header.extend_from_slice(b"data");
+    let data_size = if rf64 { u32::MAX } else { data_bytes as u32 };
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+/// The number of frames in each block handed to the writer thread.
+const WRITER_BLOCK_FRAMES: usize = 1024;
+/// How long the writer thread can fall behind the input callback before blocks are dropped, in
+/// seconds.
+const WRITER_BUFFER_SECONDS: f64 = 2.0;
+
+/// The blocks of a recording to disk, in order, as the writer thread receives them.
+///
+/// Waits for each block from the input callback's ring. The last block is cut to the frames that
+/// were recorded. Dropping it tells the input callback the writer has stopped.
+pub(crate) struct CaptureBlocks {
+    ring: Arc<FrameRing>,
+    frame_size: usize,
+    remaining_frames: usize,
+    /// Cleared once the input callback has finished the recording
+    recording: Arc<AtomicBool>,
+    writer_closed: Arc<AtomicBool>,
+    poll_interval: Duration,
+}
+
+impl Iterator for CaptureBlocks {
+    type Item = Vec<i32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut block = vec![0; self.ring.block_samples()];
+        while self.remaining_frames > 0 {
+            if self.ring.pop(&mut block) {
+                let frames = (block.len() / self.frame_size).min(self.remaining_frames);
+                self.remaining_frames -= frames;
+                block.truncate(frames * self.frame_size);
+                return Some(block);
+            }
+            // the recording ended early and every block has been read
+            if !self.recording.load(Ordering::Acquire) && self.ring.pending() == 0 {
+                return None;
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+        None
+    }
+}
+
+impl Drop for CaptureBlocks {
+    fn drop(&mut self) {
+        self.writer_closed.store(true, Ordering::Release);
+    }
+}
+
+impl AudioInstance {
+    /// Record straight to a multichannel WAV file.
+    ///
+    /// The input callback hands the samples to a writer thread in blocks, so only a few seconds
+    /// are held in memory however long the recording is. The enabled input channels are recorded, as with
+    /// `record`. This function blocks until the recording has finished and the file is written.
+    ///
+    /// Recordings too large for a WAV file's 4 GB limit are written as RF64 (EBU Tech 3306),
+    /// which most audio editors read. `methods::read_wave_file_channels` can only read files
+    /// under 4 GB.
+    ///
+    /// # Arguments
+    /// path: &Path - the file to write
+    /// duration: f64 - the duration of the recording in seconds
+    /// options: WavRecordOptions - the format of the file
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be written, or can't be written as fast as it is recorded
+    pub fn record_to_wav(
+        &self,
+        path: &Path,
+        duration: f64,
+        options: WavRecordOptions,
+    ) -> Result<(), anyhow::Error> {
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        let channels = self.recorded_channel_count();
+        let frames = (self.sample_rate as f64 * duration) as usize;
+        let spec = options
+            .sample_format
+            .spec(channels as u16, self.sample_rate);
+
+        let mut writer = Rf64Writer::create(path, spec)?;
+        self.record_with_writer(frames, move |blocks| {
+            for samples in blocks {
+                for sample in samples {
+                    writer.write_sample(sample)?;
+                }
+            }
+            writer.finalize()
+        })
+    }
+
+    /// Record `frames` frames, handing blocks of the enabled input channels to a writer thread.
+    /// Blocks until the recording has finished and the writer has returned.
+    ///
+    /// The recording ends early if the writer returns before it has received every block, e.g.
+    /// because the file or pipe it writes to has been closed.
+    ///
+    /// # Arguments
+    /// frames: usize - the number of frames to record
+    /// write: F - the body of the writer thread, which receives the interleaved blocks in order
+    ///
+    /// # Errors
+    /// Returns the error of the writer
+    /// Returns an error if the writer fell so far behind that blocks were dropped
+    pub(crate) fn record_with_writer<F>(&self, frames: usize, write: F) -> Result<(), anyhow::Error>
+    where
+        F: FnOnce(CaptureBlocks) -> Result<(), anyhow::Error> + Send + 'static,
+    {
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        let channels = self.capture_frames(0).channels;
+        let frame_size = self.recorded_channel_count();
+        let buffer_frames =
+            ((WRITER_BUFFER_SECONDS * self.sample_rate as f64) as usize).min(frames);
+        let ring = Arc::new(FrameRing::new(
+            frame_size,
+            WRITER_BLOCK_FRAMES,
+            buffer_frames.div_ceil(WRITER_BLOCK_FRAMES).max(1),
+        ));
+        let active = Arc::new(AtomicBool::new(true));
+        let writer_closed = Arc::new(AtomicBool::new(false));
+        let blocks = CaptureBlocks {
+            ring: Arc::clone(&ring),
+            frame_size,
+            remaining_frames: frames,
+            recording: Arc::clone(&active),
+            writer_closed: Arc::clone(&writer_closed),
+            poll_interval: Duration::from_secs_f64(
+                WRITER_BLOCK_FRAMES as f64 / self.sample_rate as f64 / 4.0,
+            ),
+        };
+        let writer_thread = std::thread::spawn(move || write(blocks));
+
+        let (lock, _) = &*self.record_wait_pair;
+        let mut recording = lock.lock().unwrap();
+        *self.capture_sink.lock().unwrap() = Some(CaptureSink {
+            ring: Arc::clone(&ring),
+            channels,
+            writer_closed,
+            remaining_frames: frames,
+            total_frames: frames,
+        });
+        // start recording audio
+        *recording = frames > 0;
+
+        let recorded = self.wait_for_recording(recording, |recording| !recording);
+        // nothing was recorded if the duration was 0 or the device was disconnected, so close
+        // the sink here
+        *self.capture_sink.lock().unwrap() = None;
+        active.store(false, Ordering::Release);
+
+        let written = writer_thread
+            .join()
+            .map_err(|_| anyhow::Error::msg("The writer thread panicked"))?;
+        recorded?;
+        written?;
+        match ring.dropped_blocks() {
+            0 => Ok(()),
+            dropped => Err(anyhow::anyhow!(
+                "The writer couldn't keep up with the recording and {} blocks of {} frames were lost",
+                dropped,
+                WRITER_BLOCK_FRAMES
+            )),
+        }
+    }
+
+    /// Record to a file, in the format given by the extension of the path.
+    ///
+    /// WAV files are written with the default `WavRecordOptions`, at the sample rate of the
+    /// instance. See `record_to_wav`.
+    ///
+    /// # Arguments
+    /// duration: f64 - the duration of the recording in seconds
+    /// path: &Path - the file to write
+    ///
+    /// # Errors
+    /// Returns an error if the extension is not a supported format
+    /// Returns an error if the file can't be written
+    pub fn record_to_path(&self, duration: f64, path: &Path) -> Result<(), anyhow::Error> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        if !extension.eq_ignore_ascii_case("wav") {
+            return Err(anyhow::anyhow!(
+                "Can't record to {}. Only WAV files are supported",
+                path.display()
+            ));
+        }
+        self.record_to_wav(path, duration, WavRecordOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+    use crate::methods;
+
+    #[test]
+    fn test_record_to_path() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .sample_rate(44100)
+            .mock(MockDevice::new(3, 1).noise(1 << 20, 7))
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "multichannel_audio_record_to_path_{}.wav",
+            std::process::id()
+        ));
+
+        audio_instance.record_to_path(0.1, &path).unwrap();
+        let recording = methods::read_wave_file_channels(&path, 44100).unwrap();
+        assert_eq!(recording.len(), 3);
+        assert_eq!(recording[0].len(), 4410);
+        assert!(recording[2].iter().any(|&sample| sample != 0));
+
+        assert!(audio_instance
+            .record_to_path(0.1, &path.with_extension("mp3"))
+            .is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_writer_ends_recording() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 1).noise(1 << 20, 3))
+            .build()
+            .unwrap();
+        // an open-ended recording stops once the writer returns
+        audio_instance
+            .record_with_writer(usize::MAX, |blocks| {
+                let blocks: Vec<Vec<i32>> = blocks.take(3).collect();
+                assert!(blocks.iter().all(|block| block.len() == 2 * 1024));
+                Ok(())
+            })
+            .unwrap();
+
+        // the last block holds only the recorded frames
+        audio_instance
+            .record_with_writer(1500, |blocks| {
+                let lengths: Vec<usize> = blocks.map(|block| block.len()).collect();
+                assert_eq!(lengths, [2 * 1024, 2 * 476]);
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_wav_header() {
+        let spec = WavSampleFormat::Int24.spec(3, 48000);
+        let header = wav_header(&spec, 9 * 1000);
+        assert_eq!(&header[..4], b"RIFF");
+        assert_eq!(header[4..8], (header.len() as u32 - 8 + 9000).to_le_bytes());
+        assert_eq!(&header[12..16], b"JUNK");
+        assert_eq!(header[header.len() - 4..], 9000u32.to_le_bytes());
+
+        // 5 GB doesn't fit in a WAV file
+        let data_bytes = 9 * 600_000_000u64;
+        let rf64 = wav_header(&spec, data_bytes);
+        assert_eq!(rf64.len(), header.len());
+        assert_eq!(&rf64[..4], b"RF64");
+        assert_eq!(rf64[4..8], u32::MAX.to_le_bytes());
+        assert_eq!(&rf64[12..16], b"ds64");
+        let riff_bytes = rf64.len() as u64 - 8 + data_bytes;
+        assert_eq!(rf64[20..28], riff_bytes.to_le_bytes());
+        assert_eq!(rf64[28..36], data_bytes.to_le_bytes());
+        assert_eq!(rf64[36..44], 600_000_000u64.to_le_bytes());
+        assert_eq!(rf64[rf64.len() - 4..], u32::MAX.to_le_bytes());
+        // the rest of the header is the same
+        assert_eq!(rf64[48..rf64.len() - 4], header[48..header.len() - 4]);
+    }
+
+    #[test]
+    fn test_record_to_wav_formats() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .sample_rate(44100)
+            .mock(MockDevice::new(1, 1).noise(1 << 20, 5))
+            .build()
+            .unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "multichannel_audio_record_to_wav_{}.wav",
+            std::process::id()
+        ));
+
+        // an odd number of 24-bit samples needs a pad byte
+        for sample_format in [
+            WavSampleFormat::Int16,
+            WavSampleFormat::Int24,
+            WavSampleFormat::Int32,
+            WavSampleFormat::Float32,
+        ] {
+            audio_instance
+                .record_to_wav(&path, 0.0101, WavRecordOptions { sample_format })
+                .unwrap();
+            let reader = hound::WavReader::open(&path).unwrap();
+            assert_eq!(reader.spec(), sample_format.spec(1, 44100));
+            assert_eq!(reader.len(), 445);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+use crate::analysis::{fft, Window};
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+#[cfg(feature = "device")]
+use crate::channel::{InputSelector, OutputChannel, OutputSelector};
+use crate::conversions::linear_to_db;
+use crate::sample_formats::Sample;
+#[cfg(feature = "device")]
+use crate::{conversions::db_to_linear, methods::format_signal_for_multichannel};
+
+/// The highest harmonic included in the THD, if it is below the Nyquist frequency.
+const HARMONICS: usize = 10;
+/// The number of bins either side of a tone that hold its energy. The main lobe of the
+/// Blackman-Harris window is 4 bins either side of its centre.
+const TONE_BINS: usize = 4;
+/// How far from the expected bin to search for the peak of a tone, for devices whose clocks
+/// are slightly off.
+const SEARCH_BINS: usize = 2;
+/// The length of the played sine in seconds.
+#[cfg(feature = "device")]
+const SINE_SECONDS: f64 = 1.5;
+/// The start and length of the analysed part of the recording in seconds, leaving time for the
+/// sine to arrive and settle.
+#[cfg(feature = "device")]
+const ANALYSIS_START: f64 = 0.5;
+#[cfg(feature = "device")]
+const ANALYSIS_SECONDS: f64 = 0.75;
+
+/// The distortion of a recorded sine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistortionResult {
+    /// The frequency of the peak of the fundamental in Hz
+    pub frequency: f64,
+    /// The level of the fundamental in dBFS, where 0 dBFS is a full-scale sine
+    pub level_dbfs: f64,
+    /// The total harmonic distortion as a ratio of the RMS of the harmonics to the fundamental
+    pub thd: f64,
+    /// The total harmonic distortion plus noise as a ratio of the RMS of everything except the
+    /// fundamental and DC to the fundamental
+    pub thd_n: f64,
+    /// The signal to noise and distortion ratio in dB
+    pub sinad_db: f64,
+    /// The level of each harmonic from the 2nd up in dBFS, stopping below the Nyquist frequency
+    pub harmonic_levels_dbfs: Vec<f64>,
+}
+
+impl DistortionResult {
+    /// The THD as a percentage.
+    pub fn thd_percent(&self) -> f64 {
+        self.thd * 100.0
+    }
+
+    /// The THD in dB relative to the fundamental.
+    pub fn thd_db(&self) -> f64 {
+        linear_to_db(self.thd)
+    }
+
+    /// The THD+N as a percentage.
+    pub fn thd_n_percent(&self) -> f64 {
+        self.thd_n * 100.0
+    }
+}
+
+/// Measure the THD, THD+N and SINAD of a recorded sine.
+///
+/// The signal is windowed with a Blackman-Harris window, and the energy of the fundamental and
+/// each harmonic is summed over the main lobe around its peak, so tones between bins are measured
+/// correctly. The recording should hold at least a few hundred periods of the sine, and must not
+/// include its start or end.
+///
+/// # Arguments
+/// signal: &[T] - the recorded sine
+/// fs: u32 - the sample rate of the signal
+/// frequency: f64 - the frequency of the sine in Hz
+///
+/// # Errors
+/// Returns an error if the frequency is not between 0 Hz and the Nyquist frequency
+/// Returns an error if the signal is too short to resolve the frequency or has no fundamental
+pub fn analyze_distortion<T: Sample>(
+    signal: &[T],
+    fs: u32,
+    frequency: f64,
+) -> Result<DistortionResult, anyhow::Error> {
+    check_frequency(signal.len(), fs, frequency)?;
+    let nyquist = fs as f64 / 2.0;
+
+    let spectrum = ToneSpectrum::new(signal, fs);
+    let (fundamental_bin, fundamental_power) = spectrum.tone(frequency);
+    if fundamental_power <= 0.0 {
+        return Err(anyhow::Error::msg("The signal has no fundamental"));
+    }
+
+    let mut harmonic_power = 0.0;
+    let mut harmonic_levels_dbfs = Vec::new();
+    for harmonic in 2..=HARMONICS {
+        let harmonic_frequency = frequency * harmonic as f64;
+        if harmonic_frequency + TONE_BINS as f64 * spectrum.resolution >= nyquist {
+            break;
+        }
+        let (_, power) = spectrum.tone(harmonic_frequency);
+        harmonic_power += power;
+        harmonic_levels_dbfs.push(spectrum.level_dbfs(power));
+    }
+
+    // everything except DC and the fundamental is noise and distortion
+    let total_power: f64 = spectrum.power[TONE_BINS + 1..].iter().sum();
+    let noise_and_distortion = (total_power - fundamental_power).max(0.0);
+
+    Ok(DistortionResult {
+        frequency: fundamental_bin as f64 * spectrum.resolution,
+        level_dbfs: spectrum.level_dbfs(fundamental_power),
+        thd: (harmonic_power / fundamental_power).sqrt(),
+        thd_n: (noise_and_distortion / fundamental_power).sqrt(),
+        sinad_db: 10.0 * (total_power / noise_and_distortion).log10(),
+        harmonic_levels_dbfs,
+    })
+}
+
+/// Measure the level of a sine in a signal, ignoring everything at other frequencies.
+///
+/// # Arguments
+/// signal: &[T] - the recorded signal
+/// fs: u32 - the sample rate of the signal
+/// frequency: f64 - the frequency of the sine in Hz
+///
+/// # Returns
+/// The peak level of the sine in dBFS, or negative infinity if the signal is silent
+///
+/// # Errors
+/// Returns an error if the frequency is not between 0 Hz and the Nyquist frequency
+/// Returns an error if the signal is too short to resolve the frequency
+pub fn tone_level_dbfs<T: Sample>(
+    signal: &[T],
+    fs: u32,
+    frequency: f64,
+) -> Result<f64, anyhow::Error> {
+    check_frequency(signal.len(), fs, frequency)?;
+    let spectrum = ToneSpectrum::new(signal, fs);
+    let (_, power) = spectrum.tone(frequency);
+    Ok(spectrum.level_dbfs(power))
+}
+
+fn check_frequency(length: usize, fs: u32, frequency: f64) -> Result<(), anyhow::Error> {
+    let nyquist = fs as f64 / 2.0;
+    if frequency.is_nan() || frequency <= 0.0 || frequency >= nyquist {
+        return Err(anyhow::anyhow!(
+            "The frequency must be between 0 Hz and {} Hz",
+            nyquist
+        ));
+    }
+    let resolution = fs as f64 / length.max(1) as f64;
+    if frequency < 2.0 * TONE_BINS as f64 * resolution {
+        return Err(anyhow::anyhow!(
+            "A signal of {} samples is too short to measure {} Hz",
+            length,
+            frequency
+        ));
+    }
+    Ok(())
+}
+
+/// The power in each bin of a Blackman-Harris windowed FFT, for measuring tones.
+struct ToneSpectrum {
+    power: Vec<f64>,
+    /// The width of each bin in Hz
+    resolution: f64,
+    /// Converts the energy of the bins of a tone to the square of its amplitude
+    noise_bandwidth: f64,
+}
+
+impl ToneSpectrum {
+    fn new<T: Sample>(signal: &[T], fs: u32) -> Self {
+        let window = Window::BlackmanHarris.coefficients(signal.len());
+        let window_sum: f64 = window.iter().sum();
+        let window_power: f64 = window.iter().map(|w| w * w).sum();
+
+        let spectrum = fft(signal, fs, Window::BlackmanHarris);
+        ToneSpectrum {
+            power: spectrum.bins.iter().map(|bin| bin.norm_sqr()).collect(),
+            resolution: fs as f64 / signal.len() as f64,
+            noise_bandwidth: signal.len() as f64 * window_power / (window_sum * window_sum),
+        }
+    }
+
+    /// The bin of the peak of a tone near a frequency, and the energy of its main lobe.
+    fn tone(&self, frequency: f64) -> (usize, f64) {
+        let last = self.power.len() - 1;
+        let expected = (frequency / self.resolution).round() as usize;
+        let search_start = expected.saturating_sub(SEARCH_BINS);
+        let search_end = (expected + SEARCH_BINS).min(last);
+        let peak = (search_start..=search_end)
+            .max_by(|&a, &b| self.power[a].total_cmp(&self.power[b]))
+            .unwrap_or(expected);
+        let lobe_start = peak.saturating_sub(TONE_BINS);
+        let lobe_end = (peak + TONE_BINS).min(last);
+        (peak, self.power[lobe_start..=lobe_end].iter().sum())
+    }
+
+    /// The peak level in dBFS of a tone with the energy `power`.
+    fn level_dbfs(&self, power: f64) -> f64 {
+        linear_to_db((power / self.noise_bandwidth).sqrt())
+    }
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Measure the distortion of a path from an output channel to an input channel.
+    ///
+    /// Plays a sine on the output channel while recording, then measures the THD, THD+N and SINAD
+    /// of the input channel with `analyze_distortion`. The start of the recording is skipped, so
+    /// the latency of the path doesn't need to be known.
+    ///
+    /// # Arguments
+    /// output_channel: impl OutputSelector - the channel to play the sine on, by number or label
+    /// input_channel: impl InputSelector - the channel to record it on, by number or label
+    /// frequency: f64 - the frequency of the sine in Hz
+    /// level_dbfs: f64 - the peak level of the sine in dBFS
+    ///
+    /// # Errors
+    /// Returns an error if a channel is out of range or an unknown label
+    /// Returns an error if the level is above 0 dBFS or the frequency is out of range
+    /// Returns an error if the sine is blocked by the safety interlock
+    pub fn measure_distortion(
+        &self,
+        output_channel: impl OutputSelector,
+        input_channel: impl InputSelector,
+        frequency: f64,
+        level_dbfs: f64,
+    ) -> Result<DistortionResult, anyhow::Error> {
+        let output_index = self.output_index(output_channel)?.get();
+        let input_index = self.input_index(input_channel)?.get();
+        let recorded_data = self.play_record_tone(output_index, frequency, level_dbfs)?;
+        let recording = &recorded_data[input_index];
+
+        analyze_distortion(recording, self.sample_rate, frequency)
+    }
+
+    /// Play a sine on one output channel while recording every input.
+    ///
+    /// # Arguments
+    /// output_index: usize - the index of the output channel to play the sine on
+    /// frequency: f64 - the frequency of the sine in Hz
+    /// level_dbfs: f64 - the peak level of the sine in dBFS
+    ///
+    /// # Returns
+    /// The settled part of the recording of every input channel, without its start or end
+    pub(crate) fn play_record_tone(
+        &self,
+        output_index: usize,
+        frequency: f64,
+        level_dbfs: f64,
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        if level_dbfs.is_nan() || level_dbfs > 0.0 {
+            return Err(anyhow::Error::msg("The level must not be above 0 dBFS"));
+        }
+        let fs = self.sample_rate as f64;
+        check_frequency(
+            (ANALYSIS_SECONDS * fs) as usize,
+            self.sample_rate,
+            frequency,
+        )?;
+
+        let amplitude = db_to_linear(level_dbfs) * i32::MAX as f64;
+        let sine: Vec<i32> = (0..(SINE_SECONDS * fs) as usize)
+            .map(|n| {
+                (amplitude * (std::f64::consts::TAU * frequency * n as f64 / fs).sin()).round()
+                    as i32
+            })
+            .collect();
+        let output_data = format_signal_for_multichannel(
+            sine,
+            OutputChannel::from_index(output_index),
+            self.number_of_output_channels as usize,
+        )?;
+
+        let recorded_data = self.play_record(output_data)?;
+        let start = (ANALYSIS_START * fs) as usize;
+        let end = start + (ANALYSIS_SECONDS * fs) as usize;
+        recorded_data
+            .into_iter()
+            .map(|channel| {
+                channel
+                    .get(start..end)
+                    .map(<[i32]>::to_vec)
+                    .ok_or(anyhow::Error::msg("The recording is too short to analyse"))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::TAU;
+
+    fn tones(fs: u32, tones: &[(f64, f64)], length: usize) -> Vec<f64> {
+        (0..length)
+            .map(|n| {
+                tones
+                    .iter()
+                    .map(|&(frequency, amplitude)| {
+                        amplitude * (TAU * frequency * n as f64 / fs as f64).sin()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_harmonics() {
+        // 997 Hz falls between bins
+        let signal = tones(
+            48000,
+            &[(997.0, 0.5), (1994.0, 0.005), (2991.0, 0.0005)],
+            36000,
+        );
+        let result = analyze_distortion(&signal, 48000, 997.0).unwrap();
+
+        assert!((result.level_dbfs + 6.02).abs() < 0.05);
+        assert!((result.frequency - 997.0).abs() < 2.0);
+        let expected_thd = (0.01f64.powi(2) + 0.001f64.powi(2)).sqrt();
+        assert!((result.thd / expected_thd - 1.0).abs() < 0.01);
+        assert!((result.thd_n / expected_thd - 1.0).abs() < 0.01);
+        assert!((result.sinad_db + linear_to_db(expected_thd)).abs() < 0.1);
+        assert!((result.harmonic_levels_dbfs[0] + 46.02).abs() < 0.05);
+        // harmonics up to the 10th
+        assert_eq!(result.harmonic_levels_dbfs.len(), 9);
+    }
+
+    #[test]
+    fn test_noise_counts_towards_thd_n() {
+        let mut signal = tones(48000, &[(1000.0, 0.5)], 48000);
+        let mut random = 1u64;
+        for sample in signal.iter_mut() {
+            random ^= random << 13;
+            random ^= random >> 7;
+            random ^= random << 17;
+            *sample += (random as f64 / u64::MAX as f64 - 0.5) * 0.01;
+        }
+
+        let result = analyze_distortion(&signal, 48000, 1000.0).unwrap();
+        assert!(result.thd < 1e-3);
+        assert!(result.thd_n > 5.0 * result.thd);
+        assert!((result.thd_n_percent() - 100.0 * result.thd_n).abs() < 1e-9);
+
+        assert!(analyze_distortion(&signal, 48000, 30000.0).is_err());
+        assert!(analyze_distortion(&signal[..100], 48000, 100.0).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_measure_distortion() {
+        use crate::backend::MockDevice;
+        use crate::builder::AudioInstanceBuilder;
+        use crate::channel::{InputChannel, OutputChannel};
+
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 1).delay(100))
+            .build()
+            .unwrap();
+        let result = audio_instance
+            .measure_distortion(OutputChannel(1), InputChannel(1), 1000.0, -12.0)
+            .unwrap();
+        assert!((result.level_dbfs + 12.0).abs() < 0.05);
+        assert!(result.thd_db() < -100.0);
+
+        assert!(audio_instance
+            .measure_distortion(OutputChannel(1), InputChannel(1), 1000.0, 3.0)
+            .is_err());
+        assert!(audio_instance
+            .measure_distortion(OutputChannel(2), InputChannel(1), 1000.0, -12.0)
+            .is_err());
+    }
+}
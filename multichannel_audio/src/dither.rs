@@ -0,0 +1,164 @@
+#[cfg(feature = "device")]
+use std::sync::atomic::Ordering;
+
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+use crate::sample_formats::Sample;
+
+/// The seed of the dither noise of `quantize_samples` and the output callback.
+pub(crate) const DITHER_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// How samples are rounded when they lose bits, e.g. when 32-bit samples are played on a 16-bit
+/// device. Float samples don't lose bits when converted to i32, and are always rounded to the
+/// nearest step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// Drop the low bits, rounding down. This is a bias of half a step, which is negligible for
+    /// full-scale signals
+    #[default]
+    Truncate,
+    /// Round to the nearest step
+    Nearest,
+}
+
+/// The rounding and dither used when samples lose bits.
+///
+/// Without dither, a tone only a few steps of the converter high is quantized into harmonic
+/// distortion. TPDF (triangular) dither adds ±1 step of noise first, which turns the distortion
+/// into a constant noise floor, so low-level calibration tones keep their level and spectrum.
+/// The noise is also added to silence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dither {
+    pub rounding: Rounding,
+    /// Add triangular dither before rounding
+    pub tpdf: bool,
+}
+
+impl Dither {
+    /// TPDF dither with rounding to the nearest step, the usual choice for playback.
+    pub fn tpdf() -> Self {
+        Dither {
+            rounding: Rounding::Nearest,
+            tpdf: true,
+        }
+    }
+
+    /// Pack the settings into a byte for sharing with the output callback.
+    #[cfg(feature = "device")]
+    pub(crate) fn to_bits(self) -> u8 {
+        (self.rounding == Rounding::Nearest) as u8 | (self.tpdf as u8) << 1
+    }
+
+    #[cfg(feature = "device")]
+    pub(crate) fn from_bits(bits: u8) -> Self {
+        Dither {
+            rounding: if bits & 1 == 1 {
+                Rounding::Nearest
+            } else {
+                Rounding::Truncate
+            },
+            tpdf: bits & 2 == 2,
+        }
+    }
+}
+
+/// Convert a full-scale i32 sample to a sample type, rounding and dithering as it loses bits.
+///
+/// Types with 32 bits of resolution are converted unchanged.
+///
+/// # Arguments
+/// value: i32 - the sample
+/// dither: Dither - the rounding and dither to use
+/// random: &mut u64 - the state of the dither noise. Must not be 0
+pub(crate) fn quantize<T: Sample>(value: i32, dither: Dither, random: &mut u64) -> T {
+    if T::BITS >= 32 {
+        return T::from_i32(value);
+    }
+    let step = 1i64 << (32 - T::BITS);
+    let mut value = value as i64;
+    if dither.tpdf {
+        // the difference of two uniform values is triangular from -1 to 1 step
+        value += next_random(random) % step - next_random(random) % step;
+    }
+    if dither.rounding == Rounding::Nearest {
+        value += step / 2;
+    }
+    T::from_i32(value.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+}
+
+/// Convert full-scale i32 samples to a sample type, rounding and dithering as they lose bits.
+///
+/// e.g. to write a calibration tone to a 16-bit WAV file without quantization distortion.
+/// The dither noise is the same every time.
+pub fn quantize_samples<T: Sample>(samples: &[i32], dither: Dither) -> Vec<T> {
+    let mut random = DITHER_SEED;
+    samples
+        .iter()
+        .map(|&sample| quantize(sample, dither, &mut random))
+        .collect()
+}
+
+/// The next value of a xorshift generator.
+fn next_random(random: &mut u64) -> i64 {
+    *random ^= *random << 13;
+    *random ^= *random >> 7;
+    *random ^= *random << 17;
+    (*random >> 1) as i64
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Set the rounding and dither of the output when the device has fewer than 32 bits, e.g. a
+    /// 16 or 24-bit converter. The default truncates without dither.
+    pub fn set_output_dither(&self, dither: Dither) {
+        self.output_dither
+            .store(dither.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn output_dither(&self) -> Dither {
+        Dither::from_bits(self.output_dither.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rounding() {
+        let half_step = 1 << 15;
+        let truncate = Dither::default();
+        let nearest = Dither {
+            rounding: Rounding::Nearest,
+            tpdf: false,
+        };
+        assert_eq!(quantize_samples::<i16>(&[half_step], truncate), vec![0]);
+        assert_eq!(quantize_samples::<i16>(&[half_step], nearest), vec![1]);
+        assert_eq!(
+            quantize_samples::<i16>(&[i32::MAX], nearest),
+            vec![i16::MAX]
+        );
+        assert_eq!(quantize_samples::<i32>(&[7], Dither::tpdf()), vec![7]);
+    }
+
+    #[test]
+    fn test_tpdf_keeps_the_level_of_small_signals() {
+        // a quarter of a 16-bit step is lost without dither
+        let samples = vec![1 << 14; 100_000];
+        let truncated: Vec<i16> = quantize_samples(&samples, Dither::default());
+        assert!(truncated.iter().all(|&sample| sample == 0));
+
+        let dithered: Vec<i16> = quantize_samples(&samples, Dither::tpdf());
+        let mean = dithered.iter().map(|&sample| sample as f64).sum::<f64>() / 100_000.0;
+        assert!((mean - 0.25).abs() < 0.01, "{}", mean);
+        assert!(dithered.iter().all(|&sample| (-1..=2).contains(&sample)));
+    }
+
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_dither_bits() {
+        for dither in [Dither::default(), Dither::tpdf()] {
+            assert_eq!(Dither::from_bits(dither.to_bits()), dither);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::audio_class::AudioInstance;
+
+/// A change in the state of playback or recording, sent from the audio callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioEvent {
+    /// The first sample of a signal given to `play`, `play_record` or `play_looped` has been
+    /// written to the output
+    PlaybackStarted,
+    /// The signal and every buffer queued after it have been played
+    PlaybackFinished,
+    /// A recording has captured every frame it asked for
+    RecordFinished {
+        /// The number of frames recorded
+        frames: usize,
+    },
+    /// The stream reported an error, or one of the devices was disconnected
+    Error(String),
+}
+
+/// Sends lifecycle events to the receiver from `AudioInstance::events`.
+///
+/// The callbacks only use `try_lock`, so an event is dropped rather than block the audio thread
+/// while `events` is replacing the receiver.
+#[derive(Default)]
+pub(crate) struct EventHub {
+    sender: Mutex<Option<mpsc::Sender<AudioEvent>>>,
+}
+
+impl EventHub {
+    /// Send an event to the receiver, if there is one. Called from the audio callbacks.
+    pub fn send(&self, event: AudioEvent) {
+        if let Ok(sender) = self.sender.try_lock() {
+            if let Some(ref sender) = *sender {
+                let _ = sender.send(event);
+            }
+        }
+    }
+}
+
+/// Build the error callback of a cpal stream, which logs the error and sends it as an event.
+pub(crate) fn stream_error_handler(events: Arc<EventHub>) -> impl FnMut(cpal::StreamError) {
+    move |err| {
+        tracing::error!(error = %err, "an error occurred on stream");
+        events.send(AudioEvent::Error(err.to_string()));
+    }
+}
+
+impl AudioInstance {
+    /// Receive an `AudioEvent` when playback starts or finishes, a recording finishes, or the
+    /// stream fails. Use this to track the state of the instance from a GUI or another thread
+    /// without polling or blocking a thread in `play` or `record`.
+    ///
+    /// Disconnected devices are only reported while `monitor_devices` is running.
+    ///
+    /// Only the most recently returned receiver gets events.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use multichannel_audio::audio_class::AudioInstance;
+    /// use multichannel_audio::events::AudioEvent;
+    ///
+    /// let audio_instance = AudioInstance::new(48000).unwrap();
+    /// let events = audio_instance.events();
+    /// std::thread::spawn(move || {
+    ///     for event in events {
+    ///         if let AudioEvent::Error(error) = event {
+    ///             eprintln!("audio error: {error}");
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn events(&self) -> mpsc::Receiver<AudioEvent> {
+        let (sender, receiver) = mpsc::channel();
+        *self.events.sender.lock().unwrap() = Some(sender);
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+    use std::time::Duration;
+
+    #[test]
+    fn test_events() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .build()
+            .unwrap();
+        let events = audio_instance.events();
+
+        audio_instance.play(vec![vec![1000; 960]; 2]).unwrap();
+        let recording = audio_instance.record_frames(480).unwrap();
+        assert_eq!(recording[0].len(), 480);
+
+        let received: Vec<AudioEvent> = events.iter().take(3).collect();
+        assert_eq!(
+            received,
+            vec![
+                AudioEvent::PlaybackStarted,
+                AudioEvent::PlaybackFinished,
+                AudioEvent::RecordFinished { frames: 480 },
+            ]
+        );
+
+        // stream errors are sent as events too
+        let hub = Arc::new(EventHub::default());
+        let (sender, receiver) = mpsc::channel();
+        *hub.sender.lock().unwrap() = Some(sender);
+        let mut handler = stream_error_handler(Arc::clone(&hub));
+        handler(cpal::StreamError::DeviceNotAvailable);
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap(),
+            AudioEvent::Error(_)
+        ));
+    }
+}
@@ -0,0 +1,622 @@
+use std::path::Path;
+
+use crate::methods::merge_channels;
+
+/// The number of frames in each FLAC frame.
+const FLAC_BLOCK_SIZE: usize = 4096;
+/// The most channels a FLAC file can hold.
+const FLAC_MAX_CHANNELS: usize = 8;
+/// The highest order of the fixed FLAC predictors.
+const MAX_FIXED_ORDER: usize = 4;
+/// The highest partition order tried for the residual of a subframe.
+const MAX_PARTITION_ORDER: u32 = 6;
+/// The largest Rice parameter, since 31 is the escape code of the 5-bit parameters.
+const MAX_RICE_PARAMETER: u32 = 30;
+
+/// The file format recordings are exported in.
+///
+/// Samples are scaled from the full i32 range to the bit depth of the file, so 24 bits keeps
+/// everything a 24-bit interface records. FLAC is lossless at the same bit depth, and usually
+/// much smaller than WAV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Integer PCM WAV, with 16, 24 or 32 bits per sample
+    Wav { bits_per_sample: u16 },
+    /// FLAC, with 16 or 24 bits per sample and up to 8 channels. 32-bit FLAC isn't written,
+    /// since `read_flac_channels` and many other decoders can't read it
+    Flac { bits_per_sample: u16 },
+}
+
+impl Default for ExportFormat {
+    /// 32-bit WAV, as saved by `save_channels_to_wav`.
+    fn default() -> Self {
+        ExportFormat::Wav {
+            bits_per_sample: 32,
+        }
+    }
+}
+
+impl ExportFormat {
+    /// The usual file extension of the format, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav { .. } => "wav",
+            ExportFormat::Flac { .. } => "flac",
+        }
+    }
+
+    fn bits_per_sample(&self) -> u16 {
+        match *self {
+            ExportFormat::Wav { bits_per_sample } | ExportFormat::Flac { bits_per_sample } => {
+                bits_per_sample
+            }
+        }
+    }
+}
+
+/// Save multiple channels to a file in an export format.
+///
+/// # Arguments
+/// data: &[Vec<i32>] - the channels to save, all the same length
+/// path: &Path - the file to write
+/// sample_rate: u32 - the sample rate of the data
+/// format: ExportFormat - the format of the file
+///
+/// # Errors
+/// Returns an error if the bit depth is not 16, 24 or 32, or is 32 for FLAC
+/// Returns an error if the format is FLAC and the channels are not all the same length
+/// Returns an error if there are more channels than the format can hold
+/// Returns an error if the file can't be written
+pub fn export_channels(
+    data: &[Vec<i32>],
+    path: &Path,
+    sample_rate: u32,
+    format: ExportFormat,
+) -> Result<(), anyhow::Error> {
+    std::fs::write(path, encode_channels(data, sample_rate, format)?)?;
+    Ok(())
+}
+
+/// Read a FLAC file, e.g. one saved with `export_channels`.
+///
+/// Samples are scaled from the bit depth of the file to the full i32 range.
+///
+/// # Returns
+/// The channels of the file and its sample rate
+///
+/// # Errors
+/// Returns an error if the file can't be read or is not a valid FLAC file
+/// Returns an error if the file has more than 24 bits per sample
+pub fn read_flac_channels(path: &Path) -> Result<(Vec<Vec<i32>>, u32), anyhow::Error> {
+    decode_flac(claxon::FlacReader::open(path)?)
+}
+
+fn decode_flac<R: std::io::Read>(
+    mut reader: claxon::FlacReader<R>,
+) -> Result<(Vec<Vec<i32>>, u32), anyhow::Error> {
+    let info = reader.streaminfo();
+    if info.bits_per_sample > 24 {
+        return Err(anyhow::anyhow!(
+            "FLAC files with {} bits per sample can't be read, only up to 24",
+            info.bits_per_sample
+        ));
+    }
+    let channels = info.channels as usize;
+    let shift = 32 - info.bits_per_sample;
+    let mut data = vec![Vec::with_capacity(info.samples.unwrap_or(0) as usize); channels];
+    for (index, sample) in reader.samples().enumerate() {
+        data[index % channels].push(sample? << shift);
+    }
+    Ok((data, info.sample_rate))
+}
+
+/// Encode multiple channels in an export format in memory.
+///
+/// See `export_channels`.
+pub fn encode_channels(
+    data: &[Vec<i32>],
+    sample_rate: u32,
+    format: ExportFormat,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let bits_per_sample = format.bits_per_sample();
+    if ![16, 24, 32].contains(&bits_per_sample) {
+        return Err(anyhow::anyhow!(
+            "Files can be exported with 16, 24 or 32 bits per sample, not {}",
+            bits_per_sample
+        ));
+    }
+    if matches!(format, ExportFormat::Flac { .. }) && bits_per_sample > 24 {
+        return Err(anyhow::anyhow!(
+            "FLAC files can be exported with 16 or 24 bits per sample, not {}",
+            bits_per_sample
+        ));
+    }
+    let shift = 32 - bits_per_sample as u32;
+    let data: Vec<Vec<i32>> = data
+        .iter()
+        .map(|channel| channel.iter().map(|&sample| sample >> shift).collect())
+        .collect();
+
+    match format {
+        ExportFormat::Wav { .. } => encode_wav(data, sample_rate, bits_per_sample),
+        ExportFormat::Flac { .. } => encode_flac(&data, sample_rate, bits_per_sample),
+    }
+}
+
+fn encode_wav(
+    data: Vec<Vec<i32>>,
+    sample_rate: u32,
+    bits_per_sample: u16,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let spec = hound::WavSpec {
+        channels: data.len() as u16,
+        sample_rate,
+        bits_per_sample,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut bytes, spec)?;
+    for sample in merge_channels(data) {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(bytes.into_inner())
+}
+
+/// Encode channels of samples already scaled to `bits_per_sample` as a FLAC stream.
+fn encode_flac(
+    data: &[Vec<i32>],
+    sample_rate: u32,
+    bits_per_sample: u16,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let channels = data.len();
+    if channels == 0 || channels > FLAC_MAX_CHANNELS {
+        return Err(anyhow::anyhow!(
+            "FLAC files hold 1 to {} channels, not {}",
+            FLAC_MAX_CHANNELS,
+            channels
+        ));
+    }
+    if sample_rate == 0 || sample_rate >= 1 << 20 {
+        return Err(anyhow::anyhow!(
+            "FLAC can't hold a sample rate of {} Hz",
+            sample_rate
+        ));
+    }
+    let frames = data[0].len();
+    if data.iter().any(|channel| channel.len() != frames) {
+        return Err(anyhow::Error::msg(
+            "All channels must be the same length to export them as FLAC",
+        ));
+    }
+
+    let mut writer = BitWriter::default();
+    writer.bytes.extend_from_slice(b"fLaC");
+
+    // the STREAMINFO block, which is the last metadata block
+    writer.write(1, 1);
+    writer.write(0, 7);
+    writer.write(34, 24);
+    writer.write(FLAC_BLOCK_SIZE as u64, 16);
+    writer.write(FLAC_BLOCK_SIZE as u64, 16);
+    // the frame sizes and the MD5 of the samples are left as unknown
+    writer.write(0, 24);
+    writer.write(0, 24);
+    writer.write(sample_rate as u64, 20);
+    writer.write(channels as u64 - 1, 3);
+    writer.write(bits_per_sample as u64 - 1, 5);
+    writer.write(frames as u64, 36);
+    writer.write(0, 64);
+    writer.write(0, 64);
+
+    for (frame_number, start) in (0..frames).step_by(FLAC_BLOCK_SIZE).enumerate() {
+        let end = (start + FLAC_BLOCK_SIZE).min(frames);
+        let frame_start = writer.bytes.len();
+
+        // the sample rate is taken from STREAMINFO. Not every decoder does that for the bit depth
+        writer.write(0b1111_1111_1111_1000, 16);
+        writer.write(0b0111, 4);
+        writer.write(0, 4);
+        writer.write(channels as u64 - 1, 4);
+        writer.write(sample_size_code(bits_per_sample), 3);
+        writer.write(0, 1);
+        writer.write_utf8(frame_number as u64);
+        writer.write((end - start) as u64 - 1, 16);
+        let crc = crc8(&writer.bytes[frame_start..]);
+        writer.write(crc as u64, 8);
+
+        for channel in data {
+            write_subframe(&mut writer, &channel[start..end], bits_per_sample as u32);
+        }
+        writer.align();
+        let crc = crc16(&writer.bytes[frame_start..]);
+        writer.write(crc as u64, 16);
+    }
+
+    Ok(writer.bytes)
+}
+
+/// The code of a bit depth in a frame header.
+fn sample_size_code(bits_per_sample: u16) -> u64 {
+    match bits_per_sample {
+        16 => 0b100,
+        24 => 0b110,
+        _ => 0b111,
+    }
+}
+
+/// Write the subframe of one channel of a frame, with whichever encoding is smallest.
+fn write_subframe(writer: &mut BitWriter, samples: &[i32], bits_per_sample: u32) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        writer.write(0, 1);
+        writer.write(0b000000, 6);
+        writer.write(0, 1);
+        writer.write_signed(samples[0] as i64, bits_per_sample);
+        return;
+    }
+
+    // e.g. 24-bit audio saved with 32 bits has 8 zero bits at the bottom of every sample
+    let wasted = samples
+        .iter()
+        .fold(0, |bits, &sample| bits | sample)
+        .trailing_zeros();
+    let bits = bits_per_sample - wasted;
+    let samples: Vec<i64> = samples
+        .iter()
+        .map(|&sample| (sample >> wasted) as i64)
+        .collect();
+
+    let verbatim_bits = samples.len() as u64 * bits as u64;
+    let best = (0..=MAX_FIXED_ORDER.min(samples.len() - 1))
+        .filter_map(|order| {
+            let residual = fixed_residual(&samples, order)?;
+            let (partition_order, parameters, size) = rice_partitions(&residual, order);
+            Some((order, residual, partition_order, parameters, size))
+        })
+        .min_by_key(|candidate| candidate.4 + candidate.0 as u64 * bits as u64);
+
+    writer.write(0, 1);
+    match best {
+        Some((order, residual, partition_order, parameters, size))
+            if size + (order as u64 * bits as u64) < verbatim_bits =>
+        {
+            writer.write(0b001000 | order as u64, 6);
+            writer.write_wasted_bits(wasted);
+            for &sample in &samples[..order] {
+                writer.write_signed(sample, bits);
+            }
+            write_residual(writer, &residual, order, partition_order, &parameters);
+        }
+        _ => {
+            writer.write(0b000001, 6);
+            writer.write_wasted_bits(wasted);
+            for &sample in &samples {
+                writer.write_signed(sample, bits);
+            }
+        }
+    }
+}
+
+/// The residual of a fixed predictor, or None if it doesn't fit in 32 bits as FLAC requires.
+fn fixed_residual(samples: &[i64], order: usize) -> Option<Vec<i64>> {
+    let residual: Vec<i64> = (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect();
+    residual
+        .iter()
+        .all(|&r| i32::try_from(r).is_ok())
+        .then_some(residual)
+}
+
+/// Choose the partition order and Rice parameters of a residual.
+///
+/// # Returns
+/// The partition order, the parameter of each partition and the size of the coded residual in bits
+fn rice_partitions(residual: &[i64], order: usize) -> (u32, Vec<u32>, u64) {
+    let block_size = residual.len() + order;
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1 << partition_order;
+        // every partition must hold a whole number of samples, and the first some residual
+        if block_size % partitions != 0 || block_size / partitions <= order {
+            break;
+        }
+        let mut parameters = Vec::with_capacity(partitions);
+        let mut size = 6;
+        for partition in partition_ranges(block_size, order, partition_order) {
+            let values = &residual[partition];
+            let sum: u64 = values.iter().map(|&r| zigzag(r)).sum();
+            let parameter = rice_parameter(sum, values.len());
+            size += 5 + rice_size(values, parameter);
+            parameters.push(parameter);
+        }
+        if best.as_ref().map_or(true, |best| size < best.2) {
+            best = Some((partition_order, parameters, size));
+        }
+    }
+    best.unwrap_or((0, vec![0], u64::MAX))
+}
+
+/// The ranges of the residual in each partition. The first partition is shorter by the
+/// predictor order, since the warm-up samples are not part of the residual.
+fn partition_ranges(
+    block_size: usize,
+    order: usize,
+    partition_order: u32,
+) -> impl Iterator<Item = std::ops::Range<usize>> {
+    let partition_length = block_size >> partition_order;
+    (0..1usize << partition_order).map(move |partition| {
+        let start = (partition * partition_length).saturating_sub(order);
+        let end = (partition + 1) * partition_length - order;
+        start..end
+    })
+}
+
+/// The Rice parameter for values with a mean of `sum / count`.
+fn rice_parameter(sum: u64, count: usize) -> u32 {
+    let mean = sum / count.max(1) as u64;
+    (u64::BITS - mean.leading_zeros()).min(MAX_RICE_PARAMETER)
+}
+
+fn rice_size(values: &[i64], parameter: u32) -> u64 {
+    values
+        .iter()
+        .map(|&r| (zigzag(r) >> parameter) + 1 + parameter as u64)
+        .sum()
+}
+
+fn write_residual(
+    writer: &mut BitWriter,
+    residual: &[i64],
+    order: usize,
+    partition_order: u32,
+    parameters: &[u32],
+) {
+    let block_size = residual.len() + order;
+    // 5-bit parameters are only needed for parameters above 14
+    let wide = parameters.iter().any(|&parameter| parameter > 14);
+    writer.write(wide as u64, 2);
+    writer.write(partition_order as u64, 4);
+    for (partition, &parameter) in
+        partition_ranges(block_size, order, partition_order).zip(parameters)
+    {
+        writer.write(parameter as u64, if wide { 5 } else { 4 });
+        for &r in &residual[partition] {
+            let value = zigzag(r);
+            writer.write_unary(value >> parameter);
+            writer.write(value & ((1 << parameter) - 1), parameter);
+        }
+    }
+}
+
+/// Fold a signed value into an unsigned one, as 0, -1, 1, -2, 2...
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Writes values of any number of bits, most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        // write in pieces of up to 32 bits, so the buffer never holds more than 39
+        if bits > 32 {
+            self.write(value >> 32, bits - 32);
+            self.write(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        if bits == 0 {
+            return;
+        }
+        self.buffer = (self.buffer << bits) | (value & ((1 << bits) - 1));
+        self.count += bits;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.bytes.push((self.buffer >> self.count) as u8);
+        }
+        self.buffer &= (1 << self.count) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Write `value` zeros followed by a one.
+    fn write_unary(&mut self, mut value: u64) {
+        while value >= 32 {
+            self.write(0, 32);
+            value -= 32;
+        }
+        self.write(1, value as u32 + 1);
+    }
+
+    /// Write the wasted bits flag of a subframe header, and the count if there are any.
+    fn write_wasted_bits(&mut self, wasted: u32) {
+        if wasted == 0 {
+            self.write(0, 1);
+        } else {
+            self.write(1, 1);
+            self.write_unary(wasted as u64 - 1);
+        }
+    }
+
+    /// Write a number in the UTF-8 style coding FLAC uses for frame numbers.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let mut continuation = 1;
+        while value >= 1 << (6 * continuation + 6 - continuation) {
+            continuation += 1;
+        }
+        let leading = (0xFF00u64 >> (continuation + 1)) & 0xFF;
+        self.write(leading | (value >> (6 * continuation)), 8);
+        for byte in (0..continuation).rev() {
+            self.write(0x80 | ((value >> (6 * byte)) & 0x3F), 8);
+        }
+    }
+
+    /// Pad with zeros to the next byte.
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flac_round_trip(data: &[Vec<i32>], bits_per_sample: u16) -> Vec<Vec<i32>> {
+        let flac = encode_channels(data, 44100, ExportFormat::Flac { bits_per_sample }).unwrap();
+        let reader = claxon::FlacReader::new(std::io::Cursor::new(flac)).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.bits_per_sample, bits_per_sample as u32);
+        assert_eq!(info.samples, Some(data[0].len() as u64));
+        decode_flac(reader).unwrap().0
+    }
+
+    #[test]
+    fn test_flac_round_trip() {
+        // a length that isn't a whole number of blocks, with a constant channel, a ramp that
+        // the fixed predictors code exactly and full scale square waves
+        let length = FLAC_BLOCK_SIZE * 2 + 123;
+        let data = vec![
+            vec![5 << 16; length],
+            (0..length).map(|i| (i as i32 - 4000) << 16).collect(),
+            (0..length)
+                .map(|i| if i / 7 % 2 == 0 { i32::MAX } else { i32::MIN })
+                .collect(),
+        ];
+        for bits_per_sample in [16, 24] {
+            let shift = 32 - bits_per_sample as u32;
+            let expected: Vec<Vec<i32>> = data
+                .iter()
+                .map(|channel| channel.iter().map(|&s| (s >> shift) << shift).collect())
+                .collect();
+            assert_eq!(flac_round_trip(&data, bits_per_sample), expected);
+        }
+
+        // a single frame shorter than the highest predictor order
+        let short = vec![vec![1 << 20, -3 << 20, 7 << 20]];
+        assert_eq!(flac_round_trip(&short, 24), short);
+    }
+
+    #[test]
+    fn test_bit_writer() {
+        let mut writer = BitWriter::default();
+        writer.write(0b101, 3);
+        writer.write_unary(2);
+        writer.write_signed(-1, 4);
+        writer.align();
+        assert_eq!(writer.bytes, vec![0b1010_0111, 0b1100_0000]);
+
+        let mut writer = BitWriter::default();
+        writer.write_utf8(0x7F);
+        writer.write_utf8(0x80);
+        writer.write_utf8(0x1000);
+        assert_eq!(writer.bytes, vec![0x7F, 0xC2, 0x80, 0xE1, 0x80, 0x80]);
+
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn test_export_flac() {
+        // 24-bit noise and a sine, in 32-bit samples
+        let mut random: u32 = 1;
+        let noise: Vec<i32> = (0..10000)
+            .map(|_| {
+                random ^= random << 13;
+                random ^= random >> 17;
+                random ^= random << 5;
+                ((random as i32) >> 12) << 8
+            })
+            .collect();
+        let sine: Vec<i32> = (0..10000)
+            .map(|i| ((i as f64 * 0.05).sin() * 1e9) as i32 & !0xFF)
+            .collect();
+        let data = vec![noise, sine];
+
+        let wav = encode_channels(
+            &data,
+            48000,
+            ExportFormat::Wav {
+                bits_per_sample: 24,
+            },
+        )
+        .unwrap();
+        let flac24 = ExportFormat::Flac {
+            bits_per_sample: 24,
+        };
+        let flac = encode_channels(&data, 48000, flac24).unwrap();
+        assert_eq!(&flac[..4], b"fLaC");
+        assert!(flac.len() < wav.len() * 3 / 4, "{}", flac.len());
+
+        let (decoded, sample_rate) =
+            decode_flac(claxon::FlacReader::new(std::io::Cursor::new(flac)).unwrap()).unwrap();
+        assert_eq!(sample_rate, 48000);
+        assert_eq!(decoded, data);
+
+        assert!(encode_channels(&vec![vec![0; 10]; 9], 48000, flac24).is_err());
+        assert!(encode_channels(
+            &data,
+            48000,
+            ExportFormat::Flac {
+                bits_per_sample: 32
+            }
+        )
+        .is_err());
+        // channels of different lengths aren't cut to the shortest
+        let uneven = vec![vec![0; 10], vec![0; 9]];
+        assert!(encode_channels(&uneven, 48000, flac24).is_err());
+        assert!(encode_channels(
+            &data,
+            48000,
+            ExportFormat::Wav {
+                bits_per_sample: 20
+            }
+        )
+        .is_err());
+    }
+}
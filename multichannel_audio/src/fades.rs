@@ -0,0 +1,106 @@
+use std::f64::consts::PI;
+
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+
+/// Raised-cosine ramps at the start and end of playback, to stop the clicks of a signal starting
+/// or stopping abruptly.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Fades {
+    /// The duration of the fade in, in seconds
+    pub fade_in: f64,
+    /// The duration of the fade out, in seconds
+    pub fade_out: f64,
+}
+
+impl Fades {
+    pub fn new(fade_in: f64, fade_out: f64) -> Self {
+        Fades { fade_in, fade_out }
+    }
+}
+
+/// Apply raised-cosine fades to interleaved audio in place.
+///
+/// If the fades are longer than the signal they overlap, and each frame gets the lower of the
+/// two gains.
+///
+/// # Arguments
+/// interleaved: &mut [i32] - the interleaved samples
+/// channels: usize - the number of channels in a frame
+/// fade_in_frames: usize - the length of the fade in
+/// fade_out_frames: usize - the length of the fade out
+pub fn apply_fades(
+    interleaved: &mut [i32],
+    channels: usize,
+    fade_in_frames: usize,
+    fade_out_frames: usize,
+) {
+    if channels == 0 {
+        return;
+    }
+    let frames = interleaved.len() / channels;
+    for (index, frame) in interleaved.chunks_exact_mut(channels).enumerate() {
+        let gain = ramp(index, fade_in_frames).min(ramp(frames - 1 - index, fade_out_frames));
+        if gain < 1.0 {
+            for sample in frame.iter_mut() {
+                *sample = (*sample as f64 * gain).round() as i32;
+            }
+        }
+    }
+}
+
+/// The gain of a raised-cosine ramp from 0 to 1 over `length` frames at a frame index.
+fn ramp(index: usize, length: usize) -> f64 {
+    if index >= length {
+        return 1.0;
+    }
+    0.5 * (1.0 - (PI * index as f64 / length as f64).cos())
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Fade the start and end of everything played with `play`, `play_on_channels` and
+    /// `play_record`, or None to turn the fades off. Fades are off by default.
+    ///
+    /// The fades are applied to the interleaved copy of the data, so the stimuli passed in are
+    /// not changed.
+    pub fn set_playback_fades(&self, fades: Option<Fades>) {
+        *self.fades.lock().unwrap() = fades;
+    }
+
+    /// Apply the playback fades, if any, to interleaved output data.
+    pub(super) fn fade_output(&self, interleaved: &mut [i32]) {
+        let Some(fades) = *self.fades.lock().unwrap() else {
+            return;
+        };
+        let frames = |seconds: f64| (seconds.max(0.0) * self.sample_rate as f64) as usize;
+        apply_fades(
+            interleaved,
+            self.number_of_output_channels as usize,
+            frames(fades.fade_in),
+            frames(fades.fade_out),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_fades() {
+        let mut interleaved = vec![1000; 20];
+        apply_fades(&mut interleaved, 2, 4, 2);
+
+        let left: Vec<i32> = interleaved.iter().step_by(2).copied().collect();
+        assert_eq!(left, vec![0, 146, 500, 854, 1000, 1000, 1000, 1000, 500, 0]);
+        assert_eq!(interleaved[1], interleaved[0]);
+    }
+
+    #[test]
+    fn test_overlapping_fades() {
+        let mut interleaved = vec![1000; 3];
+        apply_fades(&mut interleaved, 1, 10, 10);
+        assert!(interleaved.iter().all(|&sample| sample < 1000));
+    }
+}
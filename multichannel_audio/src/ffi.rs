@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::audio_class::AudioInstance;
+use crate::methods::set_host_and_audio_device;
+
+// C can't pass nested vectors, so multichannel data is passed as one flat buffer with the channels
+// one after another, the same as the wasm bindings. Every function returns one of the MCA_ codes
+// and the message of the last error can be read with `mca_last_error`.
+
+/// The call succeeded.
+pub const MCA_OK: c_int = 0;
+/// A pointer argument was null.
+pub const MCA_ERR_NULL_POINTER: c_int = -1;
+/// The output buffer is too small for the result. The required length is still written.
+pub const MCA_ERR_BUFFER_TOO_SMALL: c_int = -2;
+/// The operation failed. See `mca_last_error`.
+pub const MCA_ERR_FAILED: c_int = -3;
+/// The library panicked. See `mca_last_error`.
+pub const MCA_ERR_PANIC: c_int = -4;
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+fn set_last_error(message: String) {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+/// Run a function, turning errors and panics into error codes.
+fn guard(function: impl FnOnce() -> Result<c_int, anyhow::Error>) -> c_int {
+    match catch_unwind(AssertUnwindSafe(function)) {
+        Ok(Ok(code)) => code,
+        Ok(Err(err)) => {
+            set_last_error(err.to_string());
+            MCA_ERR_FAILED
+        }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            set_last_error(format!("panic: {}", message));
+            MCA_ERR_PANIC
+        }
+    }
+}
+
+/// Split channels that are one after another into a vector per channel.
+///
+/// # Safety
+/// `data` must point to `channels * frames` readable samples
+unsafe fn read_channels(data: *const i32, channels: usize, frames: usize) -> Vec<Vec<i32>> {
+    let samples = std::slice::from_raw_parts(data, channels * frames);
+    samples
+        .chunks_exact(frames.max(1))
+        .map(|channel| channel.to_vec())
+        .collect()
+}
+
+/// Copy a recording into a buffer with the channels one after another.
+///
+/// # Safety
+/// `out` must point to `capacity` writable samples and `written` must be valid to write
+unsafe fn write_channels(
+    recording: &[Vec<i32>],
+    out: *mut i32,
+    capacity: usize,
+    written: *mut usize,
+) -> c_int {
+    let length = recording.iter().map(|channel| channel.len()).sum();
+    *written = length;
+    if length > capacity {
+        return MCA_ERR_BUFFER_TOO_SMALL;
+    }
+    let out = std::slice::from_raw_parts_mut(out, length);
+    for (destination, &sample) in out.iter_mut().zip(recording.iter().flatten()) {
+        *destination = sample;
+    }
+    MCA_OK
+}
+
+/// Set up the audio host and device. Call once before creating an instance.
+#[no_mangle]
+pub extern "C" fn mca_init() -> c_int {
+    guard(|| {
+        set_host_and_audio_device()?;
+        Ok(MCA_OK)
+    })
+}
+
+/// Create an audio instance.
+///
+/// # Safety
+/// `instance` must be valid to write. The instance must be freed with `mca_instance_free`
+#[no_mangle]
+pub unsafe extern "C" fn mca_instance_new(
+    sample_rate: u32,
+    instance: *mut *mut AudioInstance,
+) -> c_int {
+    if instance.is_null() {
+        return MCA_ERR_NULL_POINTER;
+    }
+    guard(|| {
+        let created = AudioInstance::new(sample_rate)?;
+        *instance = Box::into_raw(Box::new(created));
+        Ok(MCA_OK)
+    })
+}
+
+/// Free an audio instance created with `mca_instance_new`. Null is ignored.
+///
+/// # Safety
+/// `instance` must come from `mca_instance_new` and must not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn mca_instance_free(instance: *mut AudioInstance) {
+    if !instance.is_null() {
+        drop(Box::from_raw(instance));
+    }
+}
+
+/// The number of output channels of an instance, or 0 if it is null.
+///
+/// # Safety
+/// `instance` must come from `mca_instance_new`
+#[no_mangle]
+pub unsafe extern "C" fn mca_output_channels(instance: *const AudioInstance) -> u32 {
+    instance
+        .as_ref()
+        .map_or(0, |instance| instance.channels_out() as u32)
+}
+
+/// The number of input channels of an instance, or 0 if it is null.
+///
+/// # Safety
+/// `instance` must come from `mca_instance_new`
+#[no_mangle]
+pub unsafe extern "C" fn mca_input_channels(instance: *const AudioInstance) -> u32 {
+    instance
+        .as_ref()
+        .map_or(0, |instance| instance.channels_in() as u32)
+}
+
+/// Play audio on every output channel and block until it has finished.
+///
+/// # Safety
+/// `instance` must come from `mca_instance_new` and `data` must point to
+/// `mca_output_channels(instance) * frames` samples
+#[no_mangle]
+pub unsafe extern "C" fn mca_play(
+    instance: *const AudioInstance,
+    data: *const i32,
+    frames: usize,
+) -> c_int {
+    let Some(instance) = instance.as_ref() else {
+        return MCA_ERR_NULL_POINTER;
+    };
+    if data.is_null() {
+        return MCA_ERR_NULL_POINTER;
+    }
+    guard(|| {
+        instance.play(read_channels(
+            data,
+            instance.channels_out() as usize,
+            frames,
+        ))?;
+        Ok(MCA_OK)
+    })
+}
+
+/// Record every input channel and block until the recording is complete.
+///
+/// # Arguments
+/// out: *mut i32 - the buffer for the recording, with the channels one after another
+/// capacity: usize - the number of samples `out` can hold
+/// written: *mut usize - set to the number of samples in the recording
+///
+/// # Safety
+/// `instance` must come from `mca_instance_new`, `out` must point to `capacity` writable samples
+/// and `written` must be valid to write
+#[no_mangle]
+pub unsafe extern "C" fn mca_record(
+    instance: *const AudioInstance,
+    duration: f64,
+    out: *mut i32,
+    capacity: usize,
+    written: *mut usize,
+) -> c_int {
+    let Some(instance) = instance.as_ref() else {
+        return MCA_ERR_NULL_POINTER;
+    };
+    if out.is_null() || written.is_null() {
+        return MCA_ERR_NULL_POINTER;
+    }
+    guard(|| {
+        let recording = instance.record(duration)?;
+        Ok(write_channels(&recording, out, capacity, written))
+    })
+}
+
+/// Play audio on every output channel while recording every input channel, and block until
+/// both are complete. See `mca_play` and `mca_record` for the buffers.
+///
+/// # Safety
+/// See `mca_play` and `mca_record`
+#[no_mangle]
+pub unsafe extern "C" fn mca_play_record(
+    instance: *const AudioInstance,
+    data: *const i32,
+    frames: usize,
+    out: *mut i32,
+    capacity: usize,
+    written: *mut usize,
+) -> c_int {
+    let Some(instance) = instance.as_ref() else {
+        return MCA_ERR_NULL_POINTER;
+    };
+    if data.is_null() || out.is_null() || written.is_null() {
+        return MCA_ERR_NULL_POINTER;
+    }
+    guard(|| {
+        let output_data = read_channels(data, instance.channels_out() as usize, frames);
+        let recording = instance.play_record(output_data)?;
+        Ok(write_channels(&recording, out, capacity, written))
+    })
+}
+
+/// Copy the message of the last error on this thread into a buffer as a null-terminated string.
+///
+/// # Returns
+/// The length of the message in bytes, not including the terminator. The message is cut short
+/// if it is longer than the buffer
+///
+/// # Safety
+/// `buffer` must point to `length` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn mca_last_error(buffer: *mut c_char, length: usize) -> usize {
+    LAST_ERROR.with(|last_error| {
+        let message = last_error.borrow();
+        if !buffer.is_null() && length > 0 {
+            let copied = message.len().min(length - 1);
+            let buffer = std::slice::from_raw_parts_mut(buffer as *mut u8, length);
+            buffer[..copied].copy_from_slice(&message.as_bytes()[..copied]);
+            buffer[copied] = 0;
+        }
+        message.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_buffers() {
+        let data = [1, 2, 3, 4, 5, 6];
+        let channels = unsafe { read_channels(data.as_ptr(), 2, 3) };
+        assert_eq!(channels, vec![vec![1, 2, 3], vec![4, 5, 6]]);
+
+        let mut out = [0; 6];
+        let mut written = 0;
+        let code = unsafe { write_channels(&channels, out.as_mut_ptr(), 6, &mut written) };
+        assert_eq!(code, MCA_OK);
+        assert_eq!(out, data);
+
+        let code = unsafe { write_channels(&channels, out.as_mut_ptr(), 5, &mut written) };
+        assert_eq!(code, MCA_ERR_BUFFER_TOO_SMALL);
+        assert_eq!(written, 6);
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        assert_eq!(
+            guard(|| Err(anyhow::Error::msg("no device"))),
+            MCA_ERR_FAILED
+        );
+        let mut buffer = [0 as c_char; 4];
+        let length = unsafe { mca_last_error(buffer.as_mut_ptr(), buffer.len()) };
+        assert_eq!(length, 9);
+        assert_eq!(buffer.map(|c| c as u8), *b"no \0");
+
+        assert_eq!(guard(|| panic!("bad state")), MCA_ERR_PANIC);
+        assert_eq!(
+            unsafe { mca_play(std::ptr::null(), std::ptr::null(), 0) },
+            MCA_ERR_NULL_POINTER
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::sample_formats::Sample;
+
+/// Where the input callback finds the ring of the running `FrameQueue`, if there is one.
+pub(crate) type FrameQueueSlot = Mutex<Option<Arc<FrameRing>>>;
+
+/// A bounded single-producer, single-consumer ring of fixed-size blocks of interleaved input.
+///
+/// The samples are atomics, so neither side ever locks or allocates: the input callback writes a
+/// block and publishes it by advancing `written`, and the consumer copies it out and frees the slot
+/// by advancing `read`. A block that arrives while the ring is full is dropped and counted, so a
+/// slow consumer costs blocks rather than latency.
+///
+/// Besides the `FrameQueue`, recordings to disk use a ring to hand their samples to the writer
+/// thread.
+pub(crate) struct FrameRing {
+    samples: Box<[AtomicI32]>,
+    channels: usize,
+    /// The number of samples in a block, `block_frames * channels`
+    block_samples: usize,
+    capacity_blocks: usize,
+    /// The number of blocks published by the input callback
+    written: AtomicUsize,
+    /// The number of blocks taken by the consumer
+    read: AtomicUsize,
+    /// The number of samples of the block being written. Only the input callback uses it
+    fill: AtomicUsize,
+    /// Whether the block being written is dropped because the ring was full when it started
+    dropping: AtomicBool,
+    dropped_blocks: AtomicUsize,
+}
+
+impl FrameRing {
+    pub fn new(channels: usize, block_frames: usize, capacity_blocks: usize) -> Self {
+        let block_samples = channels * block_frames;
+        FrameRing {
+            samples: (0..block_samples * capacity_blocks)
+                .map(|_| AtomicI32::new(0))
+                .collect(),
+            channels,
+            block_samples,
+            capacity_blocks,
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            fill: AtomicUsize::new(0),
+            dropping: AtomicBool::new(false),
+            dropped_blocks: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a buffer of interleaved input, publishing each block as it fills. Called from the
+    /// input callback.
+    pub fn push<T: Sample>(&self, data: &[T], channels: usize) {
+        if channels != self.channels {
+            return;
+        }
+        self.push_samples(data.iter().map(|&sample| sample.to_i32()));
+    }
+
+    /// Add interleaved samples of the ring's channels, publishing each block as it fills. Called
+    /// from the input callback.
+    pub fn push_samples(&self, samples: impl IntoIterator<Item = i32>) {
+        let mut fill = self.fill.load(Ordering::Relaxed);
+        let mut dropping = self.dropping.load(Ordering::Relaxed);
+        let mut start =
+            (self.written.load(Ordering::Relaxed) % self.capacity_blocks) * self.block_samples;
+
+        for sample in samples {
+            if fill == 0 {
+                let written = self.written.load(Ordering::Relaxed);
+                dropping = written - self.read.load(Ordering::Acquire) >= self.capacity_blocks;
+                start = (written % self.capacity_blocks) * self.block_samples;
+            }
+            if !dropping {
+                self.samples[start + fill].store(sample, Ordering::Relaxed);
+            }
+            fill += 1;
+            if fill == self.block_samples {
+                fill = 0;
+                if dropping {
+                    self.dropped_blocks.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.written.fetch_add(1, Ordering::Release);
+                }
+            }
+        }
+
+        self.fill.store(fill, Ordering::Relaxed);
+        self.dropping.store(dropping, Ordering::Relaxed);
+    }
+
+    /// Publish the block being written before it is full, at the end of a recording. The rest of
+    /// the block holds stale samples, so the consumer must know how many frames to expect. Called
+    /// from the input callback.
+    pub fn flush(&self) {
+        if self.fill.swap(0, Ordering::Relaxed) == 0 {
+            return;
+        }
+        if self.dropping.load(Ordering::Relaxed) {
+            self.dropped_blocks.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.written.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    /// Copy the oldest block into `block` and free its slot. Called from the consumer.
+    ///
+    /// # Returns
+    /// Whether there was a block
+    pub fn pop(&self, block: &mut [i32]) -> bool {
+        let read = self.read.load(Ordering::Relaxed);
+        if self.written.load(Ordering::Acquire) == read {
+            return false;
+        }
+        let start = (read % self.capacity_blocks) * self.block_samples;
+        for (sample, stored) in block
+            .iter_mut()
+            .zip(&self.samples[start..start + self.block_samples])
+        {
+            *sample = stored.load(Ordering::Relaxed);
+        }
+        self.read.store(read + 1, Ordering::Release);
+        true
+    }
+
+    pub fn pending(&self) -> usize {
+        self.written.load(Ordering::Acquire) - self.read.load(Ordering::Relaxed)
+    }
+
+    /// The number of samples in a block.
+    pub fn block_samples(&self) -> usize {
+        self.block_samples
+    }
+
+    /// The number of blocks dropped because the ring was full.
+    pub fn dropped_blocks(&self) -> usize {
+        self.dropped_blocks.load(Ordering::Relaxed)
+    }
+}
+
+/// Fixed-size blocks of every input channel, delivered from the input callback as they fill, e.g.
+/// to feed real-time beamforming or monitoring.
+///
+/// The queue holds at most `capacity_blocks` blocks, so the audio is never more than
+/// `max_latency` behind the input callback. A block that arrives while the queue is full is
+/// dropped and counted in `overflows`. Stop the queue by dropping it.
+pub struct FrameQueue {
+    ring: Arc<FrameRing>,
+    slot: Arc<FrameQueueSlot>,
+    block_frames: usize,
+    sample_rate: u32,
+}
+
+impl FrameQueue {
+    /// The number of frames in each block.
+    pub fn block_frames(&self) -> usize {
+        self.block_frames
+    }
+
+    /// The number of channels in each block, which is every input channel of the device.
+    pub fn channels(&self) -> usize {
+        self.ring.channels
+    }
+
+    /// Take the oldest block without waiting, copying it into a buffer of interleaved samples.
+    /// Nothing is allocated, so this can be called from a real-time thread.
+    ///
+    /// # Arguments
+    /// block: &mut [i32] - the buffer for the block, `block_frames * channels` samples long
+    ///
+    /// # Returns
+    /// Whether a block was waiting
+    ///
+    /// # Panics
+    /// Panics if the buffer is the wrong length
+    pub fn try_recv_into(&mut self, block: &mut [i32]) -> bool {
+        assert_eq!(
+            block.len(),
+            self.ring.block_samples,
+            "the buffer must hold block_frames * channels samples"
+        );
+        self.ring.pop(block)
+    }
+
+    /// Wait for the next block, up to a timeout.
+    ///
+    /// # Returns
+    /// The block as a vector per channel, or None if no block arrived in time
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Vec<Vec<i32>>> {
+        let deadline = Instant::now() + timeout;
+        // poll a few times per block, since the input callback can't wake a waiting thread
+        // without taking a lock
+        let interval = self.block_duration() / 4;
+        let mut block = vec![0; self.ring.block_samples];
+        while !self.ring.pop(&mut block) {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            std::thread::sleep(interval.min(deadline - now));
+        }
+        Some(
+            (0..self.channels())
+                .map(|channel| {
+                    block
+                        .iter()
+                        .skip(channel)
+                        .step_by(self.channels())
+                        .copied()
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// The number of blocks waiting to be taken.
+    pub fn pending(&self) -> usize {
+        self.ring.pending()
+    }
+
+    /// The number of blocks dropped because the queue was full.
+    pub fn overflows(&self) -> usize {
+        self.ring.dropped_blocks()
+    }
+
+    /// The longest a block can wait in a full queue before it is taken, not counting the latency
+    /// of the driver and the block itself.
+    pub fn max_latency(&self) -> Duration {
+        Duration::from_secs_f64(
+            (self.block_frames * self.ring.capacity_blocks) as f64 / self.sample_rate as f64,
+        )
+    }
+
+    fn block_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.block_frames as f64 / self.sample_rate as f64)
+    }
+}
+
+impl Drop for FrameQueue {
+    fn drop(&mut self) {
+        // only detach the ring if a newer queue hasn't replaced it
+        let mut slot = self.slot.lock().unwrap();
+        if slot
+            .as_ref()
+            .is_some_and(|ring| Arc::ptr_eq(ring, &self.ring))
+        {
+            *slot = None;
+        }
+    }
+}
+
+impl AudioInstance {
+    /// Deliver every input channel in fixed-size blocks as the audio arrives, for real-time
+    /// processing outside the audio thread.
+    ///
+    /// The input callback copies each buffer from the driver into a lock-free queue and publishes
+    /// each block as soon as it is full, whether or not anything is being recorded. Only one queue
+    /// runs at a time, and starting another stops the first. This function returns immediately.
+    ///
+    /// # Arguments
+    /// block_frames: usize - the number of frames in each block, e.g. 128
+    /// capacity_blocks: usize - the number of blocks the queue holds before dropping new ones
+    ///
+    /// # Errors
+    /// Returns an error if either size is 0 or the input stream can't be started
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use multichannel_audio::audio_class::AudioInstance;
+    ///
+    /// let audio_instance = AudioInstance::new(48000).unwrap();
+    /// let mut queue = audio_instance.start_frame_queue(128, 8).unwrap();
+    /// while let Some(block) = queue.recv_timeout(Duration::from_secs(1)) {
+    ///     // beamform block, one vector of 128 samples per input channel
+    /// }
+    /// ```
+    pub fn start_frame_queue(
+        &self,
+        block_frames: usize,
+        capacity_blocks: usize,
+    ) -> Result<FrameQueue, anyhow::Error> {
+        if block_frames == 0 || capacity_blocks == 0 {
+            return Err(anyhow::Error::msg(
+                "The block size and capacity of a frame queue must not be 0",
+            ));
+        }
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        let ring = Arc::new(FrameRing::new(
+            self.number_of_input_channels as usize,
+            block_frames,
+            capacity_blocks,
+        ));
+        *self.frame_queue.lock().unwrap() = Some(Arc::clone(&ring));
+        Ok(FrameQueue {
+            ring,
+            slot: Arc::clone(&self.frame_queue),
+            block_frames,
+            sample_rate: self.sample_rate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+
+    #[test]
+    fn test_frame_ring() {
+        // 2 channels, blocks of 3 frames, room for 2 blocks
+        let ring = FrameRing::new(2, 3, 2);
+        let samples: Vec<i32> = (0..30).collect();
+        // uneven buffers, like audio callbacks
+        for buffer in samples.chunks(4) {
+            ring.push(buffer, 2);
+        }
+
+        // 5 blocks arrived, the first 2 fit and the other 3 were dropped
+        assert_eq!(ring.pending(), 2);
+        assert_eq!(ring.dropped_blocks.load(Ordering::Relaxed), 3);
+        let mut block = [0; 6];
+        assert!(ring.pop(&mut block));
+        assert_eq!(block, [0, 1, 2, 3, 4, 5]);
+        assert!(ring.pop(&mut block));
+        assert_eq!(block, [6, 7, 8, 9, 10, 11]);
+        assert!(!ring.pop(&mut block));
+
+        // the slots are reused once they have been read
+        ring.push(&samples[..6], 2);
+        assert!(ring.pop(&mut block));
+        assert_eq!(block, [0, 1, 2, 3, 4, 5]);
+
+        // buffers with a different channel count are ignored
+        ring.push(&samples[..6], 3);
+        assert_eq!(ring.pending(), 0);
+
+        // a partial block is only published by a flush
+        ring.push(&samples[..4], 2);
+        assert_eq!(ring.pending(), 0);
+        ring.flush();
+        assert!(ring.pop(&mut block));
+        assert_eq!(block[..4], [0, 1, 2, 3]);
+        ring.flush();
+        assert_eq!(ring.pending(), 0);
+    }
+
+    #[test]
+    fn test_frame_queue() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(3, 2).noise(1 << 20, 7))
+            .build()
+            .unwrap();
+        assert!(audio_instance.start_frame_queue(0, 8).is_err());
+
+        let mut queue = audio_instance.start_frame_queue(128, 64).unwrap();
+        assert_eq!(queue.channels(), 3);
+        assert_eq!(
+            queue.max_latency(),
+            Duration::from_secs_f64(128.0 * 64.0 / 48000.0)
+        );
+        let block = queue.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(block.len(), 3);
+        assert!(block.iter().all(|channel| channel.len() == 128));
+        assert!(block[0].iter().any(|&sample| sample != 0));
+
+        let mut interleaved = vec![0; 128 * 3];
+        while queue.pending() == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(queue.try_recv_into(&mut interleaved));
+
+        // dropping the queue detaches it from the input callback
+        drop(queue);
+        assert!(audio_instance.frame_queue.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::audio_class::AudioInstance;
+use crate::biquad::Biquad;
+use crate::channel::InputChannel;
+use crate::stream_controller::InputCapture;
+
+/// The -3 dB frequency of the DC removal filter in Hz.
+const DC_REMOVAL_CUTOFF: f64 = 5.0;
+
+/// The cutoff of the anti-aliasing filter as a fraction of the decimated sample rate.
+const ANTI_ALIAS_CUTOFF: f64 = 0.4;
+
+/// The Qs of the two sections of a 4th order Butterworth filter.
+const BUTTERWORTH_Q: [f64; 2] = [0.5412, 1.3066];
+
+/// The filters and decimation applied by the input callback as each frame arrives.
+#[derive(Debug, Clone)]
+pub(crate) struct InputChain {
+    /// The filters of each input channel, by 0-based channel index
+    filters: BTreeMap<usize, Vec<Biquad>>,
+    /// Keep one frame in this many
+    decimation: usize,
+    /// The anti-aliasing filters of every input channel, when decimating
+    anti_alias: Vec<[Biquad; 2]>,
+    /// The position of the next frame in the decimation period
+    phase: usize,
+}
+
+impl Default for InputChain {
+    fn default() -> Self {
+        InputChain {
+            filters: BTreeMap::new(),
+            decimation: 1,
+            anti_alias: Vec::new(),
+            phase: 0,
+        }
+    }
+}
+
+impl InputChain {
+    /// Whether the callback needs to process the frames at all.
+    pub fn is_active(&self) -> bool {
+        !self.filters.is_empty() || self.decimation > 1
+    }
+
+    /// Clear the filter states at the start of a recording.
+    pub fn reset(&mut self) {
+        for filter in self.filters.values_mut().flatten() {
+            filter.reset();
+        }
+        for filter in self.anti_alias.iter_mut().flatten() {
+            filter.reset();
+        }
+        self.phase = 0;
+    }
+
+    /// Filter one frame in place. Called from the input callback.
+    ///
+    /// # Arguments
+    /// frame: &mut [i32] - the recorded samples of the frame
+    /// enabled_channels: &[usize] - the channel index of each sample, or empty if every channel is recorded
+    ///
+    /// # Returns
+    /// Whether the frame is kept after decimation
+    pub fn process_frame(&mut self, frame: &mut [i32], enabled_channels: &[usize]) -> bool {
+        for (position, sample) in frame.iter_mut().enumerate() {
+            let channel = enabled_channels.get(position).copied().unwrap_or(position);
+            let mut value = *sample as f64;
+            if let Some(filters) = self.filters.get_mut(&channel) {
+                for filter in filters.iter_mut() {
+                    value = filter.process_sample(value);
+                }
+            }
+            if let Some(filters) = self.anti_alias.get_mut(channel) {
+                for filter in filters.iter_mut() {
+                    value = filter.process_sample(value);
+                }
+            }
+            *sample = value.round() as i32;
+        }
+
+        let keep = self.phase == 0;
+        self.phase = (self.phase + 1) % self.decimation;
+        keep
+    }
+}
+
+/// The input chain shared with the input callback.
+///
+/// The user thread edits its own copy and hands the callback a clone of it. The callback only picks
+/// the clone up with `try_lock` and runs a chain it owns, so it never waits on the user thread or
+/// copies the chain itself.
+#[derive(Debug, Default)]
+pub(crate) struct InputChainLane {
+    /// The chain as the user thread last set it
+    settings: Mutex<InputChain>,
+    /// The next chain for the callback to run
+    next: Mutex<Option<InputChain>>,
+    changed: AtomicBool,
+}
+
+impl InputChainLane {
+    /// Change the chain. Called from the user thread.
+    fn update<R>(&self, change: impl FnOnce(&mut InputChain) -> R) -> R {
+        let mut settings = self.settings.lock().unwrap();
+        let result = change(&mut settings);
+        *self.next.lock().unwrap() = Some(settings.clone());
+        self.changed.store(true, Ordering::Release);
+        result
+    }
+
+    fn decimation(&self) -> usize {
+        self.settings.lock().unwrap().decimation
+    }
+
+    /// Take the new chain if it has been changed. Called from the input callback.
+    pub fn take_chain(&self) -> Option<InputChain> {
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        match self.next.try_lock() {
+            Ok(mut next) => next.take(),
+            Err(_) => {
+                // try again at the next callback
+                self.changed.store(true, Ordering::Release);
+                None
+            }
+        }
+    }
+}
+
+impl AudioInstance {
+    /// Add a filter to the end of the chain of an input channel.
+    ///
+    /// The filters are applied by the input callback as the data arrives, to recordings made with
+    /// `record`, `play_record` and the other functions that record into memory. Recordings to
+    /// disk are not filtered.
+    ///
+    /// # Errors
+    /// Returns an error if the channel is out of range
+    pub fn add_input_filter(
+        &self,
+        channel: InputChannel,
+        filter: Biquad,
+    ) -> Result<(), anyhow::Error> {
+        let index = self.input_index(channel)?.get();
+        self.input_chain
+            .update(|chain| chain.filters.entry(index).or_default().push(filter));
+        Ok(())
+    }
+
+    /// Remove the DC offset of an input channel with a high-pass filter at 5 Hz.
+    ///
+    /// # Errors
+    /// Returns an error if the channel is out of range
+    pub fn add_input_dc_removal(&self, channel: InputChannel) -> Result<(), anyhow::Error> {
+        let pole = 1.0 - 2.0 * PI * DC_REMOVAL_CUTOFF / self.sample_rate as f64;
+        self.add_input_filter(channel, Biquad::new([1.0, -1.0, 0.0], [1.0, -pole, 0.0])?)
+    }
+
+    /// Keep only one in every `factor` frames of recordings, after a low-pass filter to stop
+    /// aliasing. A factor of 1 turns decimation off.
+    ///
+    /// The recordings are made at `recorded_sample_rate`, so they take `factor` times less memory.
+    /// Functions that align recordings to the played signal expect the full sample rate, so don't
+    /// use them while decimating.
+    ///
+    /// # Errors
+    /// Returns an error if the factor is 0 or doesn't divide the sample rate
+    pub fn set_input_decimation(&self, factor: usize) -> Result<(), anyhow::Error> {
+        if factor == 0 || self.sample_rate as usize % factor != 0 {
+            return Err(anyhow::anyhow!(
+                "The decimation factor must divide the sample rate of {} Hz, got {}",
+                self.sample_rate,
+                factor
+            ));
+        }
+
+        let anti_alias = if factor == 1 {
+            Vec::new()
+        } else {
+            let cutoff = ANTI_ALIAS_CUTOFF * self.sample_rate as f64 / factor as f64;
+            let sections = BUTTERWORTH_Q.map(|q| Biquad::low_pass(self.sample_rate, cutoff, q));
+            vec![sections; self.number_of_input_channels as usize]
+        };
+        self.input_chain.update(|chain| {
+            chain.decimation = factor;
+            chain.phase = 0;
+            chain.anti_alias = anti_alias;
+        });
+        Ok(())
+    }
+
+    /// Remove the input filters and turn decimation off.
+    pub fn clear_input_processors(&self) {
+        self.input_chain
+            .update(|chain| *chain = InputChain::default());
+    }
+
+    /// The sample rate of recordings, after any decimation set with `set_input_decimation`.
+    pub fn recorded_sample_rate(&self) -> u32 {
+        self.sample_rate / self.input_chain.decimation() as u32
+    }
+
+    /// The number of frames in a recording of a duration, rounded to the nearest frame so
+    /// durations like 0.29 s don't lose a frame to floating point error.
+    pub(super) fn recorded_frames(&self, duration: f64) -> usize {
+        (self.recorded_sample_rate() as f64 * duration.max(0.0)).round() as usize
+    }
+
+    /// An empty recording of the enabled input channels with room for a duration.
+    pub(super) fn input_capture(&self, duration: f64) -> InputCapture {
+        self.capture_frames(self.recorded_frames(duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimation_keeps_one_in_n() {
+        let mut chain = InputChain {
+            decimation: 4,
+            ..InputChain::default()
+        };
+        let kept = (0..16)
+            .filter(|_| chain.process_frame(&mut [1, 2], &[]))
+            .count();
+        assert_eq!(kept, 4);
+        assert!(chain.is_active());
+        assert!(!InputChain::default().is_active());
+    }
+
+    #[test]
+    fn test_filters_apply_to_enabled_channel() {
+        let pole = 1.0 - 2.0 * PI * DC_REMOVAL_CUTOFF / 48000.0;
+        let mut chain = InputChain::default();
+        chain.filters.insert(
+            3,
+            vec![Biquad::new([1.0, -1.0, 0.0], [1.0, -pole, 0.0]).unwrap()],
+        );
+
+        // only the recorded channel 3 (the second in the frame) has the DC removed
+        let mut frame = [0, 0];
+        for _ in 0..48000 {
+            frame = [1_000_000, 1_000_000];
+            chain.process_frame(&mut frame, &[0, 3]);
+        }
+        assert_eq!(frame[0], 1_000_000);
+        assert!(frame[1].abs() < 1000);
+    }
+
+    #[test]
+    fn test_decimated_recording() {
+        use crate::backend::MockDevice;
+        use crate::builder::AudioInstanceBuilder;
+
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2).noise(1 << 20, 7))
+            .build()
+            .unwrap();
+        // the callback picks the chain up at its next buffer
+        audio_instance.set_input_decimation(4).unwrap();
+        audio_instance
+            .add_input_dc_removal(InputChannel(2))
+            .unwrap();
+        assert_eq!(audio_instance.recorded_sample_rate(), 12000);
+        let recording = audio_instance.record(0.1).unwrap();
+        assert_eq!(recording.len(), 2);
+        assert!(recording.iter().all(|channel| channel.len() == 1200));
+
+        // the lane hands each change over once
+        assert!(audio_instance.input_chain.take_chain().is_none());
+        audio_instance.clear_input_processors();
+        assert!(audio_instance.input_chain.take_chain().is_some());
+        assert!(audio_instance.input_chain.take_chain().is_none());
+        assert_eq!(audio_instance.recorded_sample_rate(), 48000);
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+
+use crate::conversions::dbfs_to_i32;
+
+/// Safety interlock that blocks loud playback unless it has been armed.
+///
+/// When enabled, any playback with a peak above `max_unarmed_level` requires a prior call to
+/// `arm`. The interlock disarms itself once the arm duration has passed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interlock {
+    /// The highest peak sample that can be played without arming. None if the interlock is off
+    max_unarmed_level: Option<i32>,
+    armed_until: Option<Instant>,
+}
+
+impl Interlock {
+    pub fn enable(&mut self, max_unarmed_level_dbfs: f64) {
+        self.max_unarmed_level = Some(dbfs_to_i32(max_unarmed_level_dbfs));
+    }
+
+    pub fn disable(&mut self) {
+        self.max_unarmed_level = None;
+        self.armed_until = None;
+    }
+
+    pub fn arm(&mut self, duration: Duration, now: Instant) {
+        self.armed_until = Some(now + duration);
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed_until = None;
+    }
+
+    pub fn is_armed(&self, now: Instant) -> bool {
+        self.armed_until
+            .is_some_and(|armed_until| now < armed_until)
+    }
+
+    /// Check that a signal with the given peak is allowed to play.
+    pub fn check(&self, peak: i32, now: Instant) -> Result<(), anyhow::Error> {
+        match self.max_unarmed_level {
+            Some(max_level) if peak > max_level && !self.is_armed(now) => {
+                Err(anyhow::anyhow!(
+                    "Playback peak of {:.1} dBFS is above the interlock level of {:.1} dBFS. Call arm() before playing.",
+                    to_dbfs(peak),
+                    to_dbfs(max_level)
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The peak absolute sample of multichannel data.
+pub(crate) fn peak(data: &[Vec<i32>]) -> i32 {
+    data.iter()
+        .flatten()
+        .map(|&sample| sample.saturating_abs())
+        .max()
+        .unwrap_or(0)
+}
+
+fn to_dbfs(level: i32) -> f64 {
+    20.0 * (level as f64 / i32::MAX as f64).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_interlock_allows_everything() {
+        let interlock = Interlock::default();
+
+        assert!(interlock.check(i32::MAX, Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_interlock_blocks_loud_signals_until_armed() {
+        let mut interlock = Interlock::default();
+        interlock.enable(-20.0);
+        let now = Instant::now();
+
+        assert!(interlock.check(i32::MAX / 100, now).is_ok());
+        assert!(interlock.check(i32::MAX / 2, now).is_err());
+
+        interlock.arm(Duration::from_secs(10), now);
+        assert!(interlock.check(i32::MAX / 2, now).is_ok());
+    }
+
+    #[test]
+    fn test_interlock_disarms_after_timeout() {
+        let mut interlock = Interlock::default();
+        interlock.enable(-20.0);
+        let now = Instant::now();
+
+        interlock.arm(Duration::from_secs(10), now);
+        assert!(interlock
+            .check(i32::MAX, now + Duration::from_secs(11))
+            .is_err());
+    }
+
+    #[test]
+    fn test_peak() {
+        assert_eq!(peak(&[vec![1, -5, 3], vec![4, 0, i32::MIN]]), i32::MAX);
+        assert_eq!(peak(&[vec![1, -5, 3]]), 5);
+        assert_eq!(peak(&[]), 0);
+    }
+}
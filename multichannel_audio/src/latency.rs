@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use crate::audio_class::AudioInstance;
+use crate::callback_load::StreamDirection;
+use crate::channel::{InputSelector, OutputSelector};
+use crate::time_align::{find_start, read_chirp};
+
+/// The measured round-trip latency of the audio device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyInfo {
+    /// The round-trip latency in samples
+    pub samples: usize,
+    /// The round-trip latency in seconds
+    pub seconds: f64,
+}
+
+/// The timing of the streams as reported by the backend.
+///
+/// The latencies are the ones the driver reports with each buffer. They leave out anything the
+/// driver doesn't know about, such as the converters, so use `measure_latency` for the real
+/// round-trip latency.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimingInfo {
+    /// The time from the output callback to its first sample leaving the device, or None if the
+    /// backend doesn't report it
+    pub output_latency: Option<Duration>,
+    /// The time from the first sample of an input buffer being captured to the input callback, or
+    /// None if the backend doesn't report it
+    pub input_latency: Option<Duration>,
+    /// The frames per callback granted by the driver, or None before the first callback
+    pub buffer_size: Option<usize>,
+    /// The time between callbacks, or None before the first callback
+    pub callback_period: Option<Duration>,
+}
+
+impl TimingInfo {
+    /// The expected round-trip delay from the output callback to the input callback, or None
+    /// unless both latencies are known.
+    pub fn round_trip(&self) -> Option<Duration> {
+        Some(self.output_latency? + self.input_latency?)
+    }
+
+    /// A bound on the round-trip latency in seconds, for `AlignmentConfig::max_latency`.
+    ///
+    /// Twice the expected round trip plus two callback periods, to allow for delays the backend
+    /// doesn't report. None unless the round trip and callback period are known.
+    pub fn max_latency(&self) -> Option<f64> {
+        Some(2.0 * self.round_trip()?.as_secs_f64() + 2.0 * self.callback_period?.as_secs_f64())
+    }
+}
+
+impl AudioInstance {
+    /// The latency, buffer size and callback period reported by the backend.
+    ///
+    /// The values are taken from the latest callbacks, so they are None until the streams have run
+    /// and on backends that don't report them.
+    pub fn timing_info(&self) -> TimingInfo {
+        let output = self.callback_monitor.timing(StreamDirection::Output);
+        let input = self.callback_monitor.timing(StreamDirection::Input);
+        let buffer_size = output.frames().or(input.frames());
+        TimingInfo {
+            output_latency: output.latency(),
+            input_latency: input.latency(),
+            buffer_size,
+            callback_period: buffer_size
+                .map(|frames| Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)),
+        }
+    }
+
+    /// Measure the round-trip latency of the device and cache it on this instance.
+    ///
+    /// Plays the timing chirp on `timing_channel_out` and finds it on `timing_channel_in`, which must
+    /// be connected with a physical loopback cable. The channels can be given by number or label.
+    ///
+    /// # Errors
+    /// Returns an error if the chirp is not found in the recording, or is found before it was
+    /// played
+    pub fn measure_latency(
+        &self,
+        timing_channel_out: impl OutputSelector,
+        timing_channel_in: impl InputSelector,
+    ) -> Result<LatencyInfo, anyhow::Error> {
+        let timing_index = self.output_index(timing_channel_out)?.get();
+        let timing_channel_in = self.input_channel(timing_channel_in)?.index()?;
+
+        let fs = self.sample_rate as usize;
+        let chirp = read_chirp(self.sample_rate)?;
+
+        // half a second of silence so find_start can skip the noise when the stream starts,
+        // then the chirp, then a second of silence for the chirp to arrive in
+        let chirp_start = fs / 2;
+        let chirp_end = chirp_start + chirp.len();
+        let mut output_data =
+            vec![vec![0i32; chirp_end + fs]; self.number_of_output_channels as usize];
+        output_data[timing_index][chirp_start..chirp_end].copy_from_slice(&chirp);
+
+        let mut recorded_data = self.play_record(output_data)?;
+        let loopback = recorded_data
+            .get_mut(timing_channel_in)
+            .ok_or(anyhow::anyhow!("timing_channel_in is out of range"))?;
+
+        // find_start returns the end of the chirp in the recording
+        let samples = find_start(loopback, self.sample_rate)?
+            .checked_sub(chirp_end)
+            .ok_or(anyhow::anyhow!(
+                "The timing chirp was found before it was played. Check the timing channels"
+            ))?;
+        let latency = LatencyInfo {
+            samples,
+            seconds: samples as f64 / self.sample_rate as f64,
+        };
+
+        *self.latency.lock().unwrap() = Some(latency);
+        Ok(latency)
+    }
+
+    /// The latency measured by `measure_latency`, if it has been measured.
+    pub fn latency(&self) -> Option<LatencyInfo> {
+        *self.latency.lock().unwrap()
+    }
+
+    /// Play and record, removing the measured round-trip latency from the recording.
+    ///
+    /// Playback is padded with silence for the length of the latency, and the first latency
+    /// samples are removed from the recording. The returned channels are the same length as the
+    /// output data and start when playback started, without needing a loopback channel.
+    ///
+    /// # Errors
+    /// Returns an error if the latency has not been measured with `measure_latency`
+    pub fn play_record_latency_compensated(
+        &self,
+        mut output_data: Vec<Vec<i32>>,
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        let latency = self.latency().ok_or(anyhow::anyhow!(
+            "Latency has not been measured. Call measure_latency first."
+        ))?;
+
+        for channel in output_data.iter_mut() {
+            channel.resize(channel.len() + latency.samples, 0);
+        }
+
+        let mut recorded_data = self.play_record(output_data)?;
+        for channel in recorded_data.iter_mut() {
+            channel.drain(..latency.samples.min(channel.len()));
+        }
+
+        Ok(recorded_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+    use crate::channel::{InputChannel, OutputChannel};
+
+    #[test]
+    fn test_timing_info() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 1).buffer_frames(480))
+            .build()
+            .unwrap();
+        audio_instance.record(0.05).unwrap();
+
+        // the mock device has no driver to report latencies
+        let timing = audio_instance.timing_info();
+        assert_eq!(timing.buffer_size, Some(480));
+        assert_eq!(timing.callback_period, Some(Duration::from_millis(10)));
+        assert_eq!(timing.round_trip(), None);
+
+        let reported = TimingInfo {
+            output_latency: Some(Duration::from_millis(5)),
+            input_latency: Some(Duration::from_millis(3)),
+            ..timing
+        };
+        assert_eq!(reported.round_trip(), Some(Duration::from_millis(8)));
+        assert!((reported.max_latency().unwrap() - 0.036).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_measure_latency() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2).delay(64))
+            .duplex(true)
+            .build()
+            .unwrap();
+        assert!(audio_instance
+            .play_record_latency_compensated(vec![vec![0; 10]; 2])
+            .is_err());
+
+        let latency = audio_instance
+            .measure_latency(OutputChannel(2), InputChannel(2))
+            .unwrap();
+        assert_eq!(latency.samples, 64);
+        assert_eq!(audio_instance.latency(), Some(latency));
+
+        // the compensated recording starts when playback started
+        let mut output = vec![vec![0; 4800]; 2];
+        output[0][0] = 100_000_000;
+        output[0][1000] = 50_000_000;
+        let recording = audio_instance
+            .play_record_latency_compensated(output.clone())
+            .unwrap();
+        assert_eq!(recording[0], output[0]);
+    }
+}
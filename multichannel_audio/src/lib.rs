@@ -1,5 +1,110 @@
+pub mod analysis;
+#[cfg(feature = "device")]
 pub mod audio_class;
+#[cfg(feature = "device")]
+pub mod backend;
+#[cfg(feature = "device")]
+pub mod background;
+pub mod biquad;
+#[cfg(feature = "device")]
+pub mod builder;
+pub mod bwf;
+pub mod calibration;
+#[cfg(feature = "device")]
+pub mod callback_load;
+pub mod channel;
+pub mod coherence;
+pub mod config;
+#[cfg(feature = "device")]
+pub mod context;
+pub mod conversions;
+pub mod convolution;
+#[cfg(feature = "device")]
+pub mod crosstalk;
+pub mod device_id;
+#[cfg(feature = "device")]
+pub mod device_lock;
+#[cfg(feature = "device")]
+pub mod device_monitor;
+#[cfg(feature = "device")]
+pub mod disk_playback;
+#[cfg(feature = "device")]
+pub mod disk_recording;
+pub mod distortion;
+pub mod dither;
+#[cfg(feature = "device")]
+pub mod events;
+pub mod export;
+pub mod fades;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "device")]
+pub mod frame_queue;
+#[cfg(feature = "device")]
+pub mod input_processing;
+#[cfg(feature = "device")]
+pub(crate) mod interlock;
+#[cfg(feature = "device")]
+pub mod latency;
+#[cfg(feature = "device")]
+pub mod limiter;
+#[cfg(feature = "device")]
+pub mod loop_playback;
+#[cfg(feature = "device")]
+pub mod loopback;
 pub mod methods;
 pub mod missing_device_error;
+#[cfg(feature = "device")]
+pub mod mixer;
+pub mod mls;
+#[cfg(feature = "device")]
+pub mod multi_device;
+pub mod multitone;
+pub mod orthogonal;
+#[cfg(feature = "device")]
+pub mod output_processing;
+#[cfg(feature = "device")]
+pub mod passthrough;
+pub mod pcm;
+#[cfg(feature = "device")]
+pub mod pre_record;
+#[cfg(feature = "device")]
+pub mod preflight;
+pub mod profile;
+#[cfg(feature = "device")]
+pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "device")]
+pub mod queue_playback;
+#[cfg(feature = "device")]
+pub mod record_result;
+#[cfg(feature = "device")]
+pub mod recording_guard;
+pub mod resample;
+pub mod sample_formats;
+#[cfg(feature = "device")]
+pub mod scheduled_playback;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod session;
+pub mod signal;
+#[cfg(feature = "device")]
+pub mod silence_watchdog;
+pub mod source;
+pub mod stepped_sine;
+pub mod stimulus_bank;
+#[cfg(feature = "device")]
 pub(crate) mod stream_controller;
-pub(crate) mod time_align;
+#[cfg(feature = "device")]
+pub mod stream_stats;
+pub mod time_align;
+#[cfg(feature = "device")]
+pub mod timestamp_map;
+#[cfg(feature = "device")]
+pub mod trigger;
+#[cfg(feature = "device")]
+pub mod warm_up;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod wav_source;
@@ -0,0 +1,376 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::audio_class::AudioInstance;
+use crate::conversions::db_to_linear;
+use crate::resample::taps;
+
+/// The number of samples either side of a position the true peak limiter interpolates from. The
+/// output is delayed by this many frames so the limiter can see the samples after a peak.
+const LOOKAHEAD_FRAMES: usize = 8;
+
+/// The positions between two samples where the true peak limiter estimates the waveform, which is
+/// 4x oversampling.
+const OVERSAMPLED_POSITIONS: [f64; 3] = [0.25, 0.5, 0.75];
+
+/// How the output limiter keeps the output under its ceiling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimiterMode {
+    /// Clip samples at the ceiling
+    HardClip,
+    /// Leave samples up to 6 dB below the ceiling untouched and bend louder samples smoothly
+    /// towards the ceiling, which distorts less than clipping
+    SoftClip,
+    /// Turn the gain down as soon as the peak between samples, estimated at 4x oversampling, would
+    /// go over the ceiling, and back up over the release time. Delays the output by 8 frames
+    TruePeak {
+        /// The time for the gain to recover by 63% after a peak, in seconds
+        release: f64,
+    },
+}
+
+/// The settings of the output limiter. See `AudioInstance::set_output_limiter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterSettings {
+    pub mode: LimiterMode,
+    /// The highest level of the output, in dBFS
+    pub ceiling_dbfs: f64,
+}
+
+impl LimiterSettings {
+    /// Check the settings are usable.
+    ///
+    /// # Errors
+    /// Returns an error if the ceiling is above 0 dBFS or the release time is negative
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.ceiling_dbfs.is_nan() || self.ceiling_dbfs > 0.0 {
+            return Err(anyhow::anyhow!(
+                "The limiter ceiling must be at most 0 dBFS, got {}",
+                self.ceiling_dbfs
+            ));
+        }
+        if let LimiterMode::TruePeak { release } = self.mode {
+            if release.is_nan() || release < 0.0 {
+                return Err(anyhow::anyhow!(
+                    "The limiter release time must be positive, got {}",
+                    release
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The limiter settings shared with the output callback, and the number of clips it has counted.
+#[derive(Debug, Default)]
+pub(crate) struct LimiterLane {
+    /// The next settings, or None to turn the limiter off
+    settings: Mutex<Option<LimiterSettings>>,
+    changed: AtomicBool,
+    clips: AtomicUsize,
+}
+
+impl LimiterLane {
+    /// Replace the settings. Called from the user thread.
+    fn set_settings(&self, settings: Option<LimiterSettings>) {
+        *self.settings.lock().unwrap() = settings;
+        self.changed.store(true, Ordering::Release);
+    }
+
+    /// Take new settings if they have been changed. Called from the output callback.
+    pub fn take_settings(&self) -> Option<Option<LimiterSettings>> {
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        match self.settings.try_lock() {
+            Ok(settings) => Some(*settings),
+            Err(_) => {
+                // try again at the next callback
+                self.changed.store(true, Ordering::Release);
+                None
+            }
+        }
+    }
+
+    pub fn add_clips(&self, clips: usize) {
+        if clips > 0 {
+            self.clips.fetch_add(clips, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The state of the output limiter in the output callback.
+pub(crate) struct Limiter {
+    mode: LimiterMode,
+    /// The ceiling as an i32 sample value
+    ceiling: f64,
+    channels: usize,
+    /// The gain of the true peak limiter
+    gain: f64,
+    /// How much of the gain reduction is left after each frame
+    release: f64,
+    /// The last frames of the true peak limiter, oldest first
+    history: Vec<f64>,
+    /// The interpolation filter for each of `OVERSAMPLED_POSITIONS`
+    phases: Vec<Vec<f64>>,
+}
+
+impl Limiter {
+    pub fn new(settings: LimiterSettings, channels: usize, sample_rate: u32) -> Self {
+        let release = match settings.mode {
+            LimiterMode::TruePeak { release } if release > 0.0 => {
+                (-1.0 / (release * sample_rate as f64)).exp()
+            }
+            _ => 0.0,
+        };
+        Limiter {
+            mode: settings.mode,
+            ceiling: db_to_linear(settings.ceiling_dbfs) * i32::MAX as f64,
+            channels,
+            gain: 1.0,
+            release,
+            history: vec![0.0; (2 * LOOKAHEAD_FRAMES + 1) * channels],
+            phases: OVERSAMPLED_POSITIONS
+                .iter()
+                .map(|&position| taps(position, 1.0, LOOKAHEAD_FRAMES))
+                .collect(),
+        }
+    }
+
+    /// Limit a buffer of interleaved samples in place.
+    ///
+    /// # Returns
+    /// The number of samples that reached the ceiling, or for the true peak limiter the number of
+    /// frames whose peak went over it
+    pub fn process(&mut self, data: &mut [i32]) -> usize {
+        match self.mode {
+            LimiterMode::HardClip => {
+                let ceiling = self.ceiling as i32;
+                let mut clips = 0;
+                for sample in data.iter_mut() {
+                    if sample.unsigned_abs() >= ceiling as u32 {
+                        clips += 1;
+                        *sample = (*sample).clamp(-ceiling, ceiling);
+                    }
+                }
+                clips
+            }
+            LimiterMode::SoftClip => {
+                let mut clips = 0;
+                for sample in data.iter_mut() {
+                    let value = *sample as f64;
+                    if value.abs() >= self.ceiling {
+                        clips += 1;
+                    }
+                    *sample = soft_clip(value, self.ceiling).round() as i32;
+                }
+                clips
+            }
+            LimiterMode::TruePeak { .. } => {
+                let mut clips = 0;
+                for frame in data.chunks_exact_mut(self.channels) {
+                    clips += self.process_frame(frame) as usize;
+                }
+                clips
+            }
+        }
+    }
+
+    /// Push a frame into the lookahead and replace it with the frame from `LOOKAHEAD_FRAMES`
+    /// frames ago, limited.
+    fn process_frame(&mut self, frame: &mut [i32]) -> bool {
+        let channels = self.channels;
+        self.history.copy_within(channels.., 0);
+        let newest = self.history.len() - channels;
+        for (slot, &sample) in self.history[newest..].iter_mut().zip(frame.iter()) {
+            *slot = sample as f64;
+        }
+
+        // the peak of the frame being output, in the middle of the history, and of the waveform
+        // either side of it. Each filter spans the frames from `start` on
+        let mut peak: f64 = 0.0;
+        for channel in 0..channels {
+            let sample = |frame: usize| self.history[frame * channels + channel];
+            peak = peak.max(sample(LOOKAHEAD_FRAMES).abs());
+            for phase in &self.phases {
+                for start in [0, 1] {
+                    let value: f64 = phase
+                        .iter()
+                        .enumerate()
+                        .map(|(j, tap)| tap * sample(start + j))
+                        .sum();
+                    peak = peak.max(value.abs());
+                }
+            }
+        }
+
+        let target = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+        self.gain = target.min(1.0 - (1.0 - self.gain) * self.release);
+
+        let output = LOOKAHEAD_FRAMES * channels;
+        for (sample, &value) in frame
+            .iter_mut()
+            .zip(&self.history[output..output + channels])
+        {
+            *sample = (value * self.gain)
+                .round()
+                .clamp(i32::MIN as f64, i32::MAX as f64) as i32;
+        }
+        peak > self.ceiling
+    }
+}
+
+/// Bend a sample smoothly towards the ceiling above 6 dB below it.
+fn soft_clip(value: f64, ceiling: f64) -> f64 {
+    let knee = ceiling / 2.0;
+    if value.abs() <= knee {
+        return value;
+    }
+    let excess = (value.abs() - knee) / (ceiling - knee);
+    value.signum() * (knee + (ceiling - knee) * excess.tanh())
+}
+
+impl AudioInstance {
+    /// Add a limiter as the last stage of the output, so an accidentally loud stimulus can't
+    /// damage speakers or amplifiers during unattended test runs.
+    ///
+    /// The limiter runs in the output callback on everything that is played, including the
+    /// background. The change takes effect at the next audio callback.
+    ///
+    /// # Arguments
+    /// settings: Option<LimiterSettings> - the limiter to use, or None to turn it off
+    ///
+    /// # Errors
+    /// Returns an error if the settings are invalid. See `LimiterSettings::validate`
+    pub fn set_output_limiter(
+        &self,
+        settings: Option<LimiterSettings>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(ref settings) = settings {
+            settings.validate()?;
+        }
+        self.limiter.set_settings(settings);
+        Ok(())
+    }
+
+    /// The number of samples the output limiter has caught at its ceiling, or for the true peak
+    /// limiter the number of frames it has turned down, since the instance was created or
+    /// `reset_clip_count` was called.
+    pub fn clip_count(&self) -> usize {
+        self.limiter.clips.load(Ordering::Relaxed)
+    }
+
+    /// Set the count of `clip_count` back to 0.
+    pub fn reset_clip_count(&self) {
+        self.limiter.clips.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+    use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+    fn limiter(mode: LimiterMode) -> Limiter {
+        let settings = LimiterSettings {
+            mode,
+            ceiling_dbfs: -6.0,
+        };
+        Limiter::new(settings, 1, 48000)
+    }
+
+    #[test]
+    fn test_hard_clip() {
+        let mut limiter = limiter(LimiterMode::HardClip);
+        let ceiling = limiter.ceiling as i32;
+        let mut data = vec![ceiling / 2, ceiling + 1000, -i32::MAX];
+        assert_eq!(limiter.process(&mut data), 2);
+        assert_eq!(data, vec![ceiling / 2, ceiling, -ceiling]);
+    }
+
+    #[test]
+    fn test_soft_clip() {
+        let mut limiter = limiter(LimiterMode::SoftClip);
+        let ceiling = limiter.ceiling;
+        let mut data = vec![(ceiling / 4.0) as i32, (ceiling * 0.75) as i32, i32::MAX];
+        assert_eq!(limiter.process(&mut data), 1);
+        // quiet samples are untouched, loud ones approach the ceiling without passing it
+        assert_eq!(data[0], (ceiling / 4.0) as i32);
+        assert!(data[1] < (ceiling * 0.75) as i32 && data[1] > (ceiling * 0.5) as i32);
+        assert!((data[2] as f64) < ceiling && (data[2] as f64) > ceiling * 0.95);
+    }
+
+    #[test]
+    fn test_true_peak() {
+        let mut limiter = limiter(LimiterMode::TruePeak { release: 0.01 });
+        let ceiling = limiter.ceiling;
+
+        // a full-scale tone at a quarter of the sample rate, sampled 45 degrees from its peaks
+        let mut data: Vec<i32> = (0..4800)
+            .map(|i| ((i as f64 + 0.5) * 0.5 * PI).sin() * i32::MAX as f64)
+            .map(|sample| sample as i32)
+            .collect();
+        assert!(limiter.process(&mut data) > 0);
+
+        // delayed by the lookahead, and the peaks between the samples are under the ceiling too
+        assert_eq!(data[0], 0);
+        let sample_peak = data[100..]
+            .iter()
+            .map(|sample| sample.unsigned_abs())
+            .max()
+            .unwrap() as f64;
+        assert!(
+            sample_peak / FRAC_1_SQRT_2 <= ceiling * 1.01,
+            "{}",
+            sample_peak
+        );
+        assert!(
+            sample_peak / FRAC_1_SQRT_2 > ceiling * 0.9,
+            "{}",
+            sample_peak
+        );
+
+        assert!(LimiterSettings {
+            mode: LimiterMode::HardClip,
+            ceiling_dbfs: 1.0,
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_output_limiter() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 1))
+            .duplex(true)
+            .build()
+            .unwrap();
+        let settings = LimiterSettings {
+            mode: LimiterMode::HardClip,
+            ceiling_dbfs: -6.0,
+        };
+        audio_instance.set_output_limiter(Some(settings)).unwrap();
+
+        let recording = audio_instance
+            .play_record(vec![vec![i32::MAX; 4800]])
+            .unwrap();
+        let ceiling = db_to_linear(-6.0) * i32::MAX as f64;
+        assert!(recording[0]
+            .iter()
+            .all(|&sample| (sample as f64) <= ceiling));
+        assert!(audio_instance.clip_count() >= 4800);
+
+        audio_instance.reset_clip_count();
+        audio_instance.set_output_limiter(None).unwrap();
+        audio_instance
+            .play_record(vec![vec![i32::MAX; 4800]])
+            .unwrap();
+        assert_eq!(audio_instance.clip_count(), 0);
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::stream_controller::{PlayGate, LOOP_ON, LOOP_STOP};
+
+/// Handle to a looped playback started with `AudioInstance::play_looped`.
+///
+/// Playback stops when `stop` is called or the handle is dropped.
+pub struct LoopHandle {
+    loop_state: Arc<AtomicU8>,
+    play_gate: Arc<PlayGate>,
+    healthy: Arc<AtomicBool>,
+}
+
+impl LoopHandle {
+    /// Stop the looped playback. Blocks until the output callback has stopped playing it, or the
+    /// device is disconnected.
+    pub fn stop(&self) {
+        if self
+            .loop_state
+            .compare_exchange(LOOP_ON, LOOP_STOP, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            // a disconnected device has stopped playing already
+            let _ = self.play_gate.wait(&self.healthy);
+        }
+    }
+
+    /// Whether the signal is still looping.
+    pub fn is_playing(&self) -> bool {
+        self.loop_state.load(Ordering::Acquire) == LOOP_ON
+    }
+}
+
+impl Drop for LoopHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl AudioInstance {
+    /// Play multiple channels of audio data in a continuous loop.
+    ///
+    /// The output callback wraps around to the start of the data without a gap, which is useful
+    /// for background masking noise during long measurement sessions. This function returns
+    /// immediately. Stop other playback before calling it, and stop the loop before playing
+    /// anything else.
+    ///
+    /// # Arguments
+    /// output_data: Vec<Vec<i32>> - the audio data to loop. The outer vector represents the channels and the inner vector represents the samples.
+    ///
+    /// # Returns
+    /// A handle that stops the playback when `stop` is called or it is dropped
+    pub fn play_looped(&self, output_data: Vec<Vec<i32>>) -> Result<LoopHandle, anyhow::Error> {
+        if self.number_of_output_channels != output_data.len() as u16 {
+            return Err(anyhow::Error::msg("Number of channels does not match"));
+        }
+        if output_data[0].is_empty() {
+            return Err(anyhow::Error::msg("Cannot loop an empty signal"));
+        }
+        self.check_interlock(&output_data)?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        let flattened_output_data = self.flatten_output_data(output_data);
+
+        // set the loop flag before the callback picks up the new buffer
+        self.loop_state.store(LOOP_ON, Ordering::Release);
+        *self.output_buffer.lock().unwrap() = self.output_signal(flattened_output_data)?;
+        self.play_gate.start();
+
+        Ok(LoopHandle {
+            loop_state: Arc::clone(&self.loop_state),
+            play_gate: Arc::clone(&self.play_gate),
+            healthy: Arc::clone(&self.healthy),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+
+    #[test]
+    fn test_play_looped() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 1))
+            .duplex(true)
+            .build()
+            .unwrap();
+        assert!(audio_instance.play_looped(vec![vec![]]).is_err());
+        assert!(audio_instance.play_looped(vec![vec![1000]; 2]).is_err());
+
+        let signal: Vec<i32> = (1..=100).map(|sample| sample * 1000).collect();
+        let handle = audio_instance.play_looped(vec![signal.clone()]).unwrap();
+        assert!(handle.is_playing());
+
+        // the signal wraps around without a gap, so every sample follows the one before it
+        let recording = audio_instance.record(0.5).unwrap();
+        let position = |sample: i32| signal.iter().position(|&x| x == sample).unwrap();
+        for pair in recording[0].windows(2) {
+            assert_eq!((position(pair[0]) + 1) % signal.len(), position(pair[1]));
+        }
+
+        handle.stop();
+        assert!(!handle.is_playing());
+        let recording = audio_instance.record(0.1).unwrap();
+        assert!(recording[0].iter().all(|&sample| sample == 0));
+
+        // dropping the handle stops the loop too
+        drop(audio_instance.play_looped(vec![signal]).unwrap());
+        let recording = audio_instance.record(0.1).unwrap();
+        assert!(recording[0].iter().all(|&sample| sample == 0));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::audio_class::AudioInstance;
+use crate::channel::{InputChannel, OutputChannel};
+use crate::latency::LatencyInfo;
+use crate::orthogonal::deconvolve;
+use crate::time_align::read_chirp;
+
+/// The peak-to-RMS ratio of the deconvolved recording above which the chirp counts as received.
+const DETECTION_THRESHOLD_DB: f64 = 30.0;
+
+/// The expected latency and level of a loopback connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopbackLimits {
+    /// The longest acceptable round-trip latency in seconds
+    pub max_latency: f64,
+    /// The lowest acceptable gain from the output to the input in dB
+    pub min_gain_db: f64,
+    /// The highest acceptable gain from the output to the input in dB
+    pub max_gain_db: f64,
+}
+
+impl Default for LoopbackLimits {
+    fn default() -> Self {
+        LoopbackLimits {
+            max_latency: 0.5,
+            min_gain_db: -30.0,
+            max_gain_db: 6.0,
+        }
+    }
+}
+
+/// A problem found by `verify_loopback`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopbackProblem {
+    /// The chirp was not found on the input. The channels are probably not connected
+    NotReceived,
+    LatencyTooLong {
+        seconds: f64,
+    },
+    LevelTooLow {
+        gain_db: f64,
+    },
+    LevelTooHigh {
+        gain_db: f64,
+    },
+    /// The input clipped while receiving the chirp
+    Clipping,
+}
+
+/// The result of checking the wiring of a loopback connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopbackReport {
+    pub output: OutputChannel,
+    pub input: InputChannel,
+    /// The round-trip latency, if the chirp was received
+    pub latency: Option<LatencyInfo>,
+    /// The gain from the output to the input in dB, if the chirp was received
+    pub gain_db: Option<f64>,
+    /// How far the received chirp is above the noise, in dB
+    pub snr_db: f64,
+    pub problems: Vec<LoopbackProblem>,
+}
+
+impl LoopbackReport {
+    /// Whether the loopback was received within the limits.
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl AudioInstance {
+    /// Check an output channel is connected to an input channel before a long test run.
+    ///
+    /// Plays the timing chirp on `output` and checks it is received on `input` within the
+    /// latency and level limits. Problems are returned in the report rather than as errors, so
+    /// every connection can be checked before deciding what to do.
+    ///
+    /// # Errors
+    /// Returns an error if either channel is out of range or playing and recording fails
+    pub fn verify_loopback(
+        &self,
+        output: OutputChannel,
+        input: InputChannel,
+        limits: LoopbackLimits,
+    ) -> Result<LoopbackReport, anyhow::Error> {
+        let fs = self.sample_rate as usize;
+        let chirp = read_chirp(self.sample_rate)?;
+
+        // half a second of silence for the stream to settle, then the chirp, then time for it to arrive
+        let chirp_start = fs / 2;
+        let chirp_end = chirp_start + chirp.len();
+        let latency_padding = (limits.max_latency.max(0.0) * fs as f64) as usize;
+        let mut output_data = vec![
+            vec![0i32; chirp_end + latency_padding + fs / 10];
+            self.number_of_output_channels as usize
+        ];
+        output_data[self.output_index(output)?.get()][chirp_start..chirp_end]
+            .copy_from_slice(&chirp);
+
+        let recorded_data = self.play_record(output_data)?;
+        let recording = recorded_data
+            .get(input.index()?)
+            .ok_or(anyhow::anyhow!("Channel {} is out of range", input))?;
+
+        Ok(analyze_loopback(
+            recording,
+            &chirp,
+            chirp_start,
+            self.sample_rate,
+            limits,
+            output,
+            input,
+        ))
+    }
+}
+
+/// Find the chirp in a loopback recording and check it against the limits.
+fn analyze_loopback(
+    recording: &[i32],
+    chirp: &[i32],
+    chirp_start: usize,
+    fs: u32,
+    limits: LoopbackLimits,
+    output: OutputChannel,
+    input: InputChannel,
+) -> LoopbackReport {
+    let mut report = LoopbackReport {
+        output,
+        input,
+        latency: None,
+        gain_db: None,
+        snr_db: 0.0,
+        problems: Vec::new(),
+    };
+
+    let impulse_response = deconvolve(recording, chirp);
+    let (peak_index, peak) = impulse_response.iter().enumerate().skip(chirp_start).fold(
+        (chirp_start, 0.0f64),
+        |(index, peak), (i, &value)| {
+            if value.abs() > peak {
+                (i, value.abs())
+            } else {
+                (index, peak)
+            }
+        },
+    );
+    let rms = (impulse_response.iter().map(|x| x * x).sum::<f64>()
+        / impulse_response.len().max(1) as f64)
+        .sqrt();
+    report.snr_db = 20.0 * (peak / rms).log10();
+    // a silent recording gives NaN
+    if report.snr_db.is_nan() || report.snr_db < DETECTION_THRESHOLD_DB {
+        report.problems.push(LoopbackProblem::NotReceived);
+        return report;
+    }
+
+    let samples = peak_index - chirp_start;
+    let seconds = samples as f64 / fs as f64;
+
+    // the deconvolved peak is spread over the band of the chirp, so fit the chirp to the recording
+    // to get the gain
+    let received = &recording[peak_index.min(recording.len())..];
+    let (correlation, energy) =
+        received
+            .iter()
+            .zip(chirp)
+            .fold((0.0, 0.0), |(correlation, energy), (&y, &x)| {
+                (
+                    correlation + y as f64 * x as f64,
+                    energy + x as f64 * x as f64,
+                )
+            });
+    let gain_db = 20.0 * (correlation / energy).abs().log10();
+    report.latency = Some(LatencyInfo { samples, seconds });
+    report.gain_db = Some(gain_db);
+
+    if seconds > limits.max_latency {
+        report
+            .problems
+            .push(LoopbackProblem::LatencyTooLong { seconds });
+    }
+    if gain_db < limits.min_gain_db {
+        report
+            .problems
+            .push(LoopbackProblem::LevelTooLow { gain_db });
+    }
+    if gain_db > limits.max_gain_db {
+        report
+            .problems
+            .push(LoopbackProblem::LevelTooHigh { gain_db });
+    }
+    if received
+        .iter()
+        .take(chirp.len())
+        .any(|&sample| sample.unsigned_abs() >= i32::MAX as u32 - 1)
+    {
+        report.problems.push(LoopbackProblem::Clipping);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording_with_chirp(chirp: &[i32], start: usize, divisor: i32) -> Vec<i32> {
+        // a little deterministic noise so the recording is never perfectly clean
+        let mut recording: Vec<i32> = (0..72000).map(|n| (n * 7919 % 201 - 100) * 1000).collect();
+        for (sample, &value) in recording[start..].iter_mut().zip(chirp) {
+            *sample += value / divisor;
+        }
+        recording
+    }
+
+    #[test]
+    fn test_analyze_loopback_received() {
+        let chirp = read_chirp(48000).unwrap();
+        let recording = recording_with_chirp(&chirp, 24000 + 480, 2);
+
+        let report = analyze_loopback(
+            &recording,
+            &chirp,
+            24000,
+            48000,
+            LoopbackLimits::default(),
+            OutputChannel(1),
+            InputChannel(2),
+        );
+
+        assert!(report.passed(), "{:?}", report.problems);
+        assert_eq!(report.latency.unwrap().samples, 480);
+        assert!((report.gain_db.unwrap() + 6.02).abs() < 0.5);
+
+        let strict = LoopbackLimits {
+            max_latency: 0.005,
+            min_gain_db: -3.0,
+            ..LoopbackLimits::default()
+        };
+        let report = analyze_loopback(
+            &recording,
+            &chirp,
+            24000,
+            48000,
+            strict,
+            OutputChannel(1),
+            InputChannel(2),
+        );
+        assert!(matches!(
+            report.problems[..],
+            [
+                LoopbackProblem::LatencyTooLong { .. },
+                LoopbackProblem::LevelTooLow { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_analyze_loopback_not_received() {
+        let chirp = read_chirp(48000).unwrap();
+        let recording = recording_with_chirp(&[], 0, 1);
+
+        let report = analyze_loopback(
+            &recording,
+            &chirp,
+            24000,
+            48000,
+            LoopbackLimits::default(),
+            OutputChannel(1),
+            InputChannel(2),
+        );
+        assert_eq!(report.problems, vec![LoopbackProblem::NotReceived]);
+        assert!(report.latency.is_none());
+    }
+}
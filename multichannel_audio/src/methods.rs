@@ -1,15 +1,26 @@
 #![allow(dead_code)]
 
+#[cfg(feature = "device")]
 use cpal::traits::{DeviceTrait, HostTrait};
 use hound::{self, SampleFormat};
+#[cfg(feature = "device")]
 use lazy_static::lazy_static;
 use std::f32::consts::PI;
 use std::io::Cursor;
 use std::path::Path;
+#[cfg(feature = "device")]
 use std::sync::Mutex;
 
+#[cfg(feature = "device")]
 use crate::missing_device_error::MissingDeviceError;
 
+use crate::channel::{ChannelIndex, OutputChannel};
+use crate::conversions::f32_to_i32;
+use crate::export::{export_channels, ExportFormat};
+use crate::resample::{resample, resample_channels, ResampleQuality};
+use crate::sample_formats::Sample;
+
+#[cfg(feature = "device")]
 lazy_static! {
     /// The audio host to use for audio I/O
     ///
@@ -26,6 +37,10 @@ lazy_static! {
     /// Focusrite USB ASIO
     /// ```
     pub static ref DEVICE_NAME: Mutex<String> = Mutex::new(String::new());
+    /// The name of the audio device to record from, if it is not `DEVICE_NAME`.
+    ///
+    /// CoreAudio on macOS lists the input and output of built-in audio as separate devices.
+    pub static ref INPUT_DEVICE_NAME: Mutex<Option<String>> = Mutex::new(None);
 }
 
 /// Set the host and audio device to use for audio I/O
@@ -34,6 +49,9 @@ lazy_static! {
 /// This will be updated in the future to allow the user to select the audio device.
 ///
 /// On Windows, defaults to ASIO and on Linux the default host is used.
+/// On macOS, the default CoreAudio input and output devices are used. If they are different
+/// devices, the input device is stored in `INPUT_DEVICE_NAME`.
+#[cfg(feature = "device")]
 pub fn set_host_and_audio_device() -> Result<(), MissingDeviceError> {
     #[cfg(target_os = "windows")]
     {
@@ -48,6 +66,22 @@ pub fn set_host_and_audio_device() -> Result<(), MissingDeviceError> {
         *HOST.lock().unwrap() = Some(host);
         *DEVICE_NAME.lock().unwrap() = "hw:CARD=USB,DEV=0".to_string();
     }
+    #[cfg(target_os = "macos")]
+    {
+        let host = cpal::default_host();
+        let output_name = host
+            .default_output_device()
+            .and_then(|d| d.name().ok())
+            .ok_or(MissingDeviceError::Error(
+                "No default output device".to_string(),
+            ))?;
+        let input_name = host.default_input_device().and_then(|d| d.name().ok());
+
+        // an aggregate device has both inputs and outputs, so only set the input when it differs
+        *INPUT_DEVICE_NAME.lock().unwrap() = input_name.filter(|name| *name != output_name);
+        *HOST.lock().unwrap() = Some(host);
+        *DEVICE_NAME.lock().unwrap() = output_name;
+    }
 
     let device_exists = match (*HOST)
         .lock()
@@ -71,18 +105,178 @@ pub fn set_host_and_audio_device() -> Result<(), MissingDeviceError> {
     Ok(())
 }
 
+/// Record from a different device than the one used for playback.
+///
+/// Pass None to record from `DEVICE_NAME`. Takes effect for audio instances created afterwards.
+/// Duplex instances need input and output on the same device.
+#[cfg(feature = "device")]
+pub fn set_input_device(name: Option<&str>) {
+    *INPUT_DEVICE_NAME.lock().unwrap() = name.map(|name| name.to_string());
+}
+
+/// Sample rates checked against the ranges reported by the device.
+#[cfg(feature = "device")]
+const STANDARD_SAMPLE_RATES: [u32; 13] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000, 352800, 384000,
+];
+
+/// The configurations supported by an audio device.
+#[cfg(feature = "device")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCaps {
+    pub name: String,
+    /// None if the device has no inputs
+    pub input: Option<DirectionCaps>,
+    /// None if the device has no outputs
+    pub output: Option<DirectionCaps>,
+}
+
+/// The configurations supported in one direction (input or output) of an audio device.
+#[cfg(feature = "device")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectionCaps {
+    pub min_channels: u16,
+    pub max_channels: u16,
+    /// Every channel count the device can open, from fewest to most
+    pub channel_counts: Vec<u16>,
+    /// The standard sample rates that fall within the ranges supported by the device
+    pub sample_rates: Vec<u32>,
+    pub sample_formats: Vec<cpal::SampleFormat>,
+    /// The minimum and maximum buffer size in frames, if the driver reports it
+    pub buffer_size: Option<(u32, u32)>,
+}
+
+/// List the supported sample rates, sample formats, channel counts and buffer sizes of a device.
+///
+/// This can be used to present valid options to users before creating an `AudioInstance`.
+///
+/// # Errors
+/// Returns an error if the host has not been initialized
+/// Returns an error if the device is not found
+#[cfg(feature = "device")]
+pub fn device_capabilities(name: &str) -> Result<DeviceCaps, anyhow::Error> {
+    let binding = HOST.lock().unwrap();
+    let host = binding
+        .as_ref()
+        .ok_or_else(|| anyhow::Error::msg("Host not initialized"))?;
+
+    let device = host
+        .devices()?
+        .find(|d| d.name().is_ok_and(|device_name| device_name == name))
+        .ok_or(anyhow::Error::msg("Device not found"))?;
+
+    Ok(DeviceCaps {
+        name: name.to_string(),
+        input: device
+            .supported_input_configs()
+            .ok()
+            .and_then(summarize_configs),
+        output: device
+            .supported_output_configs()
+            .ok()
+            .and_then(summarize_configs),
+    })
+}
+
+#[cfg(feature = "device")]
+fn summarize_configs(
+    configs: impl Iterator<Item = cpal::SupportedStreamConfigRange>,
+) -> Option<DirectionCaps> {
+    let configs: Vec<cpal::SupportedStreamConfigRange> = configs.collect();
+    if configs.is_empty() {
+        return None;
+    }
+
+    let sample_rates = STANDARD_SAMPLE_RATES
+        .into_iter()
+        .filter(|&rate| {
+            configs.iter().any(|c| {
+                c.min_sample_rate() <= cpal::SampleRate(rate)
+                    && cpal::SampleRate(rate) <= c.max_sample_rate()
+            })
+        })
+        .collect();
+
+    let mut sample_formats: Vec<cpal::SampleFormat> = Vec::new();
+    for config in configs.iter() {
+        if !sample_formats.contains(&config.sample_format()) {
+            sample_formats.push(config.sample_format());
+        }
+    }
+
+    let buffer_size = configs
+        .iter()
+        .filter_map(|c| match *c.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => Some((min, max)),
+            cpal::SupportedBufferSize::Unknown => None,
+        })
+        .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)));
+
+    let mut channel_counts: Vec<u16> = configs.iter().map(|c| c.channels()).collect();
+    channel_counts.sort_unstable();
+    channel_counts.dedup();
+
+    Some(DirectionCaps {
+        min_channels: *channel_counts.first()?,
+        max_channels: *channel_counts.last()?,
+        channel_counts,
+        sample_rates,
+        sample_formats,
+        buffer_size,
+    })
+}
+
 /// Generate a sine wave signal.
 pub fn generate_sine_wave(frequency: u32, duration: f32, fs: u32) -> Vec<i32> {
     let signal: Vec<f32> = (0..(fs as f32 * duration) as usize)
         .map(|i| ((i as u32 * frequency * 2) as f32 * PI / fs as f32).sin() as f32)
         .collect();
 
-    let signal: Vec<i32> = signal
-        .iter()
-        .map(|&x| (x * i32::MAX as f32) as i32)
-        .collect(); // Convert the signal to i32
-                    //return the signal
-    signal
+    // Convert the signal to i32
+    f32_to_i32(&signal)
+}
+
+/// Generate an exponential sine sweep.
+///
+/// The frequency rises exponentially from `start_frequency` to `end_frequency`, so every octave
+/// gets the same time.
+///
+/// # Arguments
+/// start_frequency: f64 - the frequency at the start of the sweep in Hz
+/// end_frequency: f64 - the frequency at the end of the sweep in Hz
+/// duration: f32 - the length of the sweep in seconds
+/// fs: u32 - the sample rate
+pub fn generate_exponential_sweep(
+    start_frequency: f64,
+    end_frequency: f64,
+    duration: f32,
+    fs: u32,
+) -> Vec<i32> {
+    let length = (fs as f32 * duration) as usize;
+    let rate = (end_frequency / start_frequency).ln();
+    let scale = 2.0 * std::f64::consts::PI * start_frequency * duration as f64 / rate;
+
+    (0..length)
+        .map(|i| {
+            let t = i as f64 / fs as f64;
+            let phase = scale * ((t * rate / duration as f64).exp() - 1.0);
+            (phase.sin() * i32::MAX as f64) as i32
+        })
+        .collect()
+}
+
+/// Generate one period of a maximum length sequence at full scale.
+///
+/// The sequence is `2^order - 1` samples long. Play it periodically and use `MlsAnalyzer` to
+/// extract the impulse response from the recording.
+///
+/// # Errors
+/// Returns an error if the order is not from 2 to 24
+pub fn generate_mls(order: u32) -> Result<Vec<i32>, anyhow::Error> {
+    Ok(crate::mls::mls_bits(order)?
+        .into_iter()
+        .map(|bit| if bit { -i32::MAX } else { i32::MAX })
+        .collect())
 }
 
 /// Generate a white noise signal.
@@ -106,6 +300,7 @@ pub fn generate_gaussian_white_noise(
     white_noise
 }
 
+#[cfg(feature = "device")]
 pub(crate) fn print_devices() -> Result<(), Box<dyn std::error::Error>> {
     let binding = HOST.lock().unwrap();
     let host = binding.as_ref().ok_or("Host not initialized")?;
@@ -139,19 +334,130 @@ pub(crate) fn print_devices() -> Result<(), Box<dyn std::error::Error>> {
 ///
 /// This is useful for playing a single channel signal on a multi-channel audio interface.
 ///
-/// Puts the signal in specified playback_index channel and nothing in all other channels.
+/// Puts the signal on one channel and nothing in all other channels. See
+/// `format_signals_for_multichannel` to place more than one signal.
+///
+/// # Arguments
+/// signal: Vec<i32> - the signal to play
+/// channel: OutputChannel - the channel to play it on
+/// output_channels: usize - the number of output channels of the device
+///
+/// # Errors
+/// Returns an error if the channel is out of range
 pub fn format_signal_for_multichannel(
     signal: Vec<i32>,
-    playback_index: usize,
+    channel: OutputChannel,
     output_channels: usize,
-) -> Vec<Vec<i32>> {
-    if playback_index >= output_channels {
-        return vec![];
+) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    format_signals_for_multichannel(vec![(signal, channel)], output_channels)
+}
+
+/// Place several single channel signals on the channels of a multi-channel signal.
+///
+/// Channels without a signal are silent. Signals shorter than the longest one are padded with
+/// silence so every channel is the same length.
+///
+/// # Arguments
+/// signals: Vec<(Vec<i32>, OutputChannel)> - each signal and the channel to play it on
+/// output_channels: usize - the number of output channels of the device
+///
+/// # Errors
+/// Returns an error if a channel is out of range or has more than one signal
+pub fn format_signals_for_multichannel(
+    signals: Vec<(Vec<i32>, OutputChannel)>,
+    output_channels: usize,
+) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    let length = signals
+        .iter()
+        .map(|(signal, _)| signal.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut multi_channel_data: Vec<Option<Vec<i32>>> = vec![None; output_channels];
+    for (mut signal, channel) in signals {
+        let slot = &mut multi_channel_data[ChannelIndex::output(channel, output_channels)?.get()];
+        if slot.is_some() {
+            return Err(anyhow::anyhow!(
+                "Channel {} has more than one signal",
+                channel
+            ));
+        }
+
+        signal.resize(length, 0);
+        *slot = Some(signal);
+    }
+
+    Ok(multi_channel_data
+        .into_iter()
+        .map(|channel| channel.unwrap_or_else(|| vec![0; length]))
+        .collect())
+}
+
+/// Place several single channel signals on the channels of a multi-channel f32 signal.
+///
+/// The same as `format_signals_for_multichannel`, with samples scaled to -1.0 to 1.0.
+pub fn format_signals_for_multichannel_f32(
+    signals: Vec<(Vec<i32>, OutputChannel)>,
+    output_channels: usize,
+) -> Result<Vec<Vec<f32>>, anyhow::Error> {
+    Ok(format_signals_for_multichannel(signals, output_channels)?
+        .into_iter()
+        .map(|channel| channel.into_iter().map(f32::from_i32).collect())
+        .collect())
+}
+
+/// Interleave signals onto some of the channels of a device, leaving the other channels silent.
+///
+/// The untouched channels are zero-filled while interleaving, so there is no need to allocate
+/// silent channels for devices that expose many more channels than are used.
+///
+/// # Arguments
+/// data: &[Vec<i32>] - one signal per channel in `channels`. The signals must be the same length
+/// channels: &[OutputChannel] - the device channel to play each signal on
+/// output_channels: usize - the number of output channels of the device
+///
+/// # Returns
+/// The interleaved samples for all `output_channels` channels
+///
+/// # Errors
+/// Returns an error if the number of signals and channels don't match, the signals are different
+/// lengths, or a channel is out of range or repeated
+pub fn interleave_on_channels(
+    data: &[Vec<i32>],
+    channels: &[OutputChannel],
+    output_channels: usize,
+) -> Result<Vec<i32>, anyhow::Error> {
+    if data.len() != channels.len() {
+        return Err(anyhow::anyhow!(
+            "Got {} signals for {} channels",
+            data.len(),
+            channels.len()
+        ));
+    }
+    let length = data.first().map_or(0, |signal| signal.len());
+    if data.iter().any(|signal| signal.len() != length) {
+        return Err(anyhow::Error::msg("Signals must be the same length"));
     }
 
-    let mut multi_channel_data = vec![vec![0; signal.len()]; output_channels];
-    multi_channel_data[playback_index] = signal;
-    multi_channel_data
+    let mut indices = Vec::with_capacity(channels.len());
+    for &channel in channels {
+        let index = ChannelIndex::output(channel, output_channels)?.get();
+        if indices.contains(&index) {
+            return Err(anyhow::anyhow!(
+                "Channel {} has more than one signal",
+                channel
+            ));
+        }
+        indices.push(index);
+    }
+
+    let mut interleaved = vec![0; length * output_channels];
+    for (signal, index) in data.iter().zip(indices) {
+        for (frame, &sample) in interleaved.chunks_exact_mut(output_channels).zip(signal) {
+            frame[index] = sample;
+        }
+    }
+    Ok(interleaved)
 }
 
 /// Save a signal to a WAV file.
@@ -176,6 +482,8 @@ pub fn save_to_wav(data: &Vec<i32>, filename: &str, sample_rate: u32) -> Result<
 }
 
 /// Read a WAV file from a byte array.
+///
+/// A single channel file at a different sample rate is resampled to `fs`.
 pub fn read_wave_file_dart(byte_data: Vec<u8>, fs: u32) -> Result<Vec<i32>, hound::Error> {
     let cursor = Cursor::new(byte_data);
     read_wave_file_data(cursor, fs)
@@ -188,7 +496,11 @@ fn read_wave_file_data<R: std::io::Read + std::io::Seek>(
     let mut reader = hound::WavReader::new(reader)?;
     let spec = reader.spec();
 
-    assert_eq!(spec.sample_rate, fs, "Sample rate of WAV file does not match the sample rate of the audio interface.\n\tWAV file sample rate: {}\n\tAudio interface sample rate: {}", spec.sample_rate, fs);
+    if spec.sample_rate != fs && spec.channels != 1 {
+        return Err(hound::Error::FormatError(
+            "Only single channel WAV files can be resampled. Use read_wave_file_channels",
+        ));
+    }
 
     let samples: Vec<i32> = match (spec.sample_format, spec.bits_per_sample) {
         // get int samples (any int format with 32 bits or less)
@@ -197,40 +509,122 @@ fn read_wave_file_data<R: std::io::Read + std::io::Seek>(
         // get float samples and convert them to int32
         (SampleFormat::Float, 32) => {
             let float_samples: Vec<f32> = reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?;
-            float_samples
-                .into_iter()
-                .map(|sample| (sample * std::i32::MAX as f32) as i32)
-                .collect()
+            f32_to_i32(&float_samples)
         }
         _ => return Err(hound::Error::Unsupported),
     };
 
-    Ok(samples)
+    // files at another sample rate are converted to the rate of the device
+    resample(&samples, spec.sample_rate, fs, ResampleQuality::default())
+        .map_err(|_| hound::Error::FormatError("The sample rate must be more than 0 Hz"))
 }
 
 /// Read a WAV file from a file path.
+///
+/// A single channel file at a different sample rate is resampled to `fs`.
 pub fn read_wave_file(filepath: &Path, fs: u32) -> Result<Vec<i32>, hound::Error> {
-    let mut reader = hound::WavReader::open(filepath)?;
-    let spec = reader.spec();
+    let file = std::io::BufReader::new(std::fs::File::open(filepath)?);
+    read_wave_file_data(file, fs)
+}
+
+/// Read every channel of a WAV file from a byte array.
+///
+/// Handles any number of channels. Integer samples of any bit depth are scaled to the full i32
+/// range, so a 16-bit and a 24-bit file play at the same level. Files at a different sample rate
+/// are resampled to `fs`.
+///
+/// # Returns
+/// A vector of channels where each channel is a vector of samples
+pub fn split_interleaved_wav(byte_data: Vec<u8>, fs: u32) -> Result<Vec<Vec<i32>>, hound::Error> {
+    read_wave_file_channels_data(Cursor::new(byte_data), fs)
+}
+
+/// Read every channel of a WAV file from a file path.
+///
+/// See `split_interleaved_wav` for details.
+pub fn read_wave_file_channels(filepath: &Path, fs: u32) -> Result<Vec<Vec<i32>>, hound::Error> {
+    let file = std::io::BufReader::new(std::fs::File::open(filepath)?);
+    read_wave_file_channels_data(file, fs)
+}
 
-    assert_eq!(spec.sample_rate, fs, "Sample rate of WAV file does not match the sample rate of the audio interface.\n\tWAV file sample rate: {}\n\tAudio interface sample rate: {}", spec.sample_rate, fs);
+fn read_wave_file_channels_data<R: std::io::Read + std::io::Seek>(
+    reader: R,
+    fs: u32,
+) -> Result<Vec<Vec<i32>>, hound::Error> {
+    let mut reader = hound::WavReader::new(reader)?;
+    let spec = reader.spec();
 
     let samples: Vec<i32> = match (spec.sample_format, spec.bits_per_sample) {
-        // get int samples (any int format with 32 bits or less)
-        (SampleFormat::Int, _) => reader.samples::<i32>().collect::<Result<Vec<_>, _>>()?,
+        // scale int samples up to 32 bits
+        (SampleFormat::Int, bits) if bits <= 32 => {
+            let shift = 32 - bits as u32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|s| s << shift))
+                .collect::<Result<Vec<_>, _>>()?
+        }
 
         // get float samples and convert them to int32
-        (SampleFormat::Float, 32) => {
-            let float_samples: Vec<f32> = reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?;
-            float_samples
-                .into_iter()
-                .map(|sample| (sample * std::i32::MAX as f32) as i32)
-                .collect()
-        }
+        (SampleFormat::Float, 32) => reader
+            .samples::<f32>()
+            .map(|sample| sample.map(|s| (s * i32::MAX as f32) as i32))
+            .collect::<Result<Vec<_>, _>>()?,
         _ => return Err(hound::Error::Unsupported),
     };
 
-    Ok(samples)
+    let channels = split_channels(&samples, spec.channels as usize);
+    resample_channels(&channels, spec.sample_rate, fs, ResampleQuality::default())
+        .map_err(|_| hound::Error::FormatError("The sample rate must be more than 0 Hz"))
+}
+
+/// Split interleaved samples into a vector of channels.
+///
+/// Any incomplete frame at the end of the samples is dropped.
+pub fn split_channels(interleaved: &[i32], number_of_channels: usize) -> Vec<Vec<i32>> {
+    if number_of_channels == 0 {
+        return vec![];
+    }
+
+    let mut channels =
+        vec![Vec::with_capacity(interleaved.len() / number_of_channels); number_of_channels];
+    for frame in interleaved.chunks_exact(number_of_channels) {
+        for (channel, &sample) in channels.iter_mut().zip(frame) {
+            channel.push(sample);
+        }
+    }
+    channels
+}
+
+/// Merge a vector of channels into interleaved samples.
+///
+/// All channels must be the same length. Longer channels are truncated to the shortest one.
+pub fn merge_channels(channels: Vec<Vec<i32>>) -> Vec<i32> {
+    let length = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+
+    let mut interleaved = Vec::with_capacity(length * channels.len());
+    for sample_index in 0..length {
+        for channel in channels.iter() {
+            interleaved.push(channel[sample_index]);
+        }
+    }
+    interleaved
+}
+
+/// Save multiple channels to a single multichannel 32-bit WAV file.
+///
+/// Use `export::export_channels` for other bit depths or FLAC, and `bwf::save_channels_to_bwf`
+/// to add a timestamp and other Broadcast Wave metadata.
+pub fn save_channels_to_wav(
+    data: Vec<Vec<i32>>,
+    filename: &str,
+    sample_rate: u32,
+) -> Result<(), anyhow::Error> {
+    export_channels(
+        &data,
+        Path::new(filename),
+        sample_rate,
+        ExportFormat::default(),
+    )
 }
 
 #[cfg(test)]
@@ -238,6 +632,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[cfg(feature = "device")]
     fn test_set_host_and_audio_device() {
         let _ = set_host_and_audio_device();
     }
@@ -321,23 +716,176 @@ mod tests {
     #[test]
     fn test_format_signal_for_multichannel() {
         let signal = vec![1, 2, 3, 4, 5];
-        let playback_index = 1;
         let output_channels = 3;
         let formatted_signal =
-            format_signal_for_multichannel(signal.clone(), playback_index, output_channels);
+            format_signal_for_multichannel(signal.clone(), OutputChannel(2), output_channels)
+                .unwrap();
 
         assert_eq!(formatted_signal.len(), output_channels);
-        assert_eq!(formatted_signal[playback_index], signal);
+        assert_eq!(formatted_signal[1], signal);
+        assert_eq!(formatted_signal[0], vec![0; 5]);
     }
 
     #[test]
-    fn test_higher_playback_index_than_number_of_channels() {
+    fn test_higher_playback_channel_than_number_of_channels() {
         let signal = vec![1, 2, 3, 4, 5];
-        let playback_index = 4;
         let output_channels = 3;
+
+        assert!(format_signal_for_multichannel(signal, OutputChannel(5), output_channels).is_err());
+    }
+
+    #[test]
+    fn test_format_signals_for_multichannel() {
+        let formatted_signal = format_signals_for_multichannel(
+            vec![
+                (vec![1, 2, 3], OutputChannel(1)),
+                (vec![4], OutputChannel(3)),
+            ],
+            4,
+        )
+        .unwrap();
+
+        assert_eq!(
+            formatted_signal,
+            vec![vec![1, 2, 3], vec![0; 3], vec![4, 0, 0], vec![0; 3]]
+        );
+    }
+
+    #[test]
+    fn test_format_signals_for_multichannel_invalid_channels() {
+        assert!(format_signals_for_multichannel(vec![(vec![1], OutputChannel(3))], 2).is_err());
+        assert!(format_signals_for_multichannel(
+            vec![(vec![1], OutputChannel(1)), (vec![2], OutputChannel(1))],
+            2
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_format_signals_for_multichannel_f32() {
         let formatted_signal =
-            format_signal_for_multichannel(signal, playback_index as usize, output_channels);
+            format_signals_for_multichannel_f32(vec![(vec![i32::MAX], OutputChannel(2))], 2)
+                .unwrap();
+
+        assert_eq!(formatted_signal, vec![vec![0.0], vec![1.0]]);
+    }
+
+    #[test]
+    fn test_merge_and_split_channels() {
+        let channels = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+        let interleaved = merge_channels(channels.clone());
+
+        assert_eq!(interleaved, vec![1, 4, 7, 2, 5, 8, 3, 6, 9]);
+        assert_eq!(split_channels(&interleaved, 3), channels);
+    }
 
-        assert_eq!(formatted_signal.len(), 0);
+    #[test]
+    fn test_split_interleaved_wav() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for sample in [1i16, -1, 2, -2, 3, -3] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let channels = split_interleaved_wav(bytes.into_inner(), 48000).unwrap();
+
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0], vec![1 << 16, 2 << 16, 3 << 16]);
+        assert_eq!(channels[1], vec![-1 << 16, -2 << 16, -3 << 16]);
+    }
+
+    #[test]
+    fn test_split_interleaved_wav_resamples() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Int,
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for _ in 0..441 {
+            writer.write_sample(1000).unwrap();
+            writer.write_sample(-1000).unwrap();
+        }
+        writer.finalize().unwrap();
+        let bytes = bytes.into_inner();
+
+        let channels = split_interleaved_wav(bytes.clone(), 48000).unwrap();
+        assert_eq!(channels[0].len(), 480);
+        assert_eq!(channels[1][240], -1000);
+        // the single channel reader can't resample interleaved channels
+        assert!(read_wave_file_dart(bytes, 48000).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_summarize_configs() {
+        let configs = vec![
+            cpal::SupportedStreamConfigRange::new(
+                2,
+                cpal::SampleRate(44100),
+                cpal::SampleRate(48000),
+                cpal::SupportedBufferSize::Range { min: 64, max: 1024 },
+                cpal::SampleFormat::I32,
+            ),
+            cpal::SupportedStreamConfigRange::new(
+                18,
+                cpal::SampleRate(96000),
+                cpal::SampleRate(96000),
+                cpal::SupportedBufferSize::Range { min: 32, max: 512 },
+                cpal::SampleFormat::F32,
+            ),
+        ];
+
+        let caps = summarize_configs(configs.into_iter()).unwrap();
+
+        assert_eq!(caps.min_channels, 2);
+        assert_eq!(caps.max_channels, 18);
+        assert_eq!(caps.channel_counts, vec![2, 18]);
+        assert_eq!(caps.sample_rates, vec![44100, 48000, 96000]);
+        assert_eq!(
+            caps.sample_formats,
+            vec![cpal::SampleFormat::I32, cpal::SampleFormat::F32]
+        );
+        assert_eq!(caps.buffer_size, Some((32, 1024)));
+    }
+
+    #[test]
+    fn test_interleave_on_channels() {
+        let interleaved = interleave_on_channels(
+            &[vec![1, 2], vec![3, 4]],
+            &[OutputChannel(4), OutputChannel(2)],
+            4,
+        )
+        .unwrap();
+        assert_eq!(interleaved, vec![0, 3, 0, 1, 0, 4, 0, 2]);
+
+        assert!(interleave_on_channels(&[vec![1]], &[OutputChannel(5)], 4).is_err());
+        assert!(interleave_on_channels(
+            &[vec![1], vec![2]],
+            &[OutputChannel(1), OutputChannel(1)],
+            4
+        )
+        .is_err());
+        assert!(interleave_on_channels(
+            &[vec![1], vec![2, 3]],
+            &[OutputChannel(1), OutputChannel(2)],
+            4
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_summarize_no_configs() {
+        assert!(summarize_configs(std::iter::empty()).is_none());
     }
 }
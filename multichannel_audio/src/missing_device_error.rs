@@ -1,5 +1,6 @@
 use std::{error::Error, fmt};
 
+#[cfg(feature = "device")]
 use cpal::HostUnavailable;
 
 /// Error type for when the audio device is missing.
@@ -16,6 +17,7 @@ impl fmt::Display for MissingDeviceError {
     }
 }
 
+#[cfg(feature = "device")]
 impl From<HostUnavailable> for MissingDeviceError {
     fn from(_error: HostUnavailable) -> Self {
         MissingDeviceError::Error("Failed to connect to Focusrite Host".to_string())
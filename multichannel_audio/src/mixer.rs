@@ -0,0 +1,283 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::device_monitor::disconnected_error;
+use crate::signal::Signal;
+
+/// The voice is playing, or waiting for the output callback to start it.
+const VOICE_PLAYING: u8 = 0;
+/// The voice stops at the next callback.
+const VOICE_STOPPING: u8 = 1;
+/// The voice has played to the end or been stopped.
+const VOICE_FINISHED: u8 = 2;
+
+/// A signal started with `AudioInstance::play_mixed` or `loop_mixed`.
+///
+/// Dropping the handle doesn't stop the signal.
+#[derive(Debug, Clone)]
+pub struct PlayHandle {
+    state: Arc<AtomicU8>,
+    healthy: Arc<AtomicBool>,
+}
+
+impl PlayHandle {
+    /// Stop the signal at the next audio callback. Does nothing if it has already finished.
+    pub fn stop(&self) {
+        let _ = self.state.compare_exchange(
+            VOICE_PLAYING,
+            VOICE_STOPPING,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+
+    /// Whether the signal is still playing.
+    pub fn is_playing(&self) -> bool {
+        self.state.load(Ordering::Acquire) != VOICE_FINISHED
+    }
+
+    /// Block until the signal has played to the end or been stopped. A looped signal only ends
+    /// when it is stopped.
+    ///
+    /// # Errors
+    /// Returns an error if the device is disconnected before the signal ends
+    pub fn wait(&self) -> Result<(), anyhow::Error> {
+        while self.is_playing() {
+            if !self.healthy.load(Ordering::Acquire) {
+                return Err(disconnected_error());
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        Ok(())
+    }
+}
+
+/// A signal mixed into the output by the output callback.
+struct Voice {
+    signal: Signal,
+    position: usize,
+    looping: bool,
+    state: Arc<AtomicU8>,
+}
+
+impl Drop for Voice {
+    // a voice is finished however it leaves the mixer, including when the stream is closed
+    fn drop(&mut self) {
+        self.state.store(VOICE_FINISHED, Ordering::Release);
+    }
+}
+
+/// The voices started on the user thread, waiting for the output callback to take them.
+///
+/// The callback only uses `try_lock`, and takes new voices at the next callback rather than wait,
+/// as with the background.
+#[derive(Default)]
+pub(crate) struct MixerLane {
+    pending: Mutex<Vec<Voice>>,
+    added: AtomicBool,
+    stop_all: AtomicBool,
+}
+
+impl MixerLane {
+    /// Hand a voice to the output callback. Called from the user thread.
+    fn add(&self, voice: Voice) {
+        self.pending.lock().unwrap().push(voice);
+        self.added.store(true, Ordering::Release);
+    }
+
+    /// Stop every voice, including the ones the callback hasn't taken yet. Called from the user
+    /// thread.
+    fn stop_all(&self) {
+        self.stop_all.store(true, Ordering::Release);
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+/// The voices playing in the output callback.
+#[derive(Default)]
+pub(crate) struct Mixer {
+    voices: Vec<Voice>,
+}
+
+impl Mixer {
+    /// Add the playing voices to a buffer of interleaved output. Called from the output callback.
+    ///
+    /// The voices are summed with the buffer, saturating at full scale.
+    pub fn mix(&mut self, lane: &MixerLane, buffer: &mut [i32]) {
+        if lane.stop_all.swap(false, Ordering::AcqRel) {
+            self.voices.clear();
+        }
+        if lane.added.swap(false, Ordering::AcqRel) {
+            match lane.pending.try_lock() {
+                Ok(mut pending) => self.voices.append(&mut pending),
+                // try again at the next callback
+                Err(_) => lane.added.store(true, Ordering::Release),
+            }
+        }
+        self.voices
+            .retain(|voice| voice.state.load(Ordering::Acquire) == VOICE_PLAYING);
+
+        for voice in self.voices.iter_mut() {
+            for sample in buffer.iter_mut() {
+                if voice.position >= voice.signal.len() {
+                    if !voice.looping {
+                        break;
+                    }
+                    voice.position = 0;
+                }
+                *sample = sample.saturating_add(voice.signal[voice.position]);
+                voice.position += 1;
+            }
+        }
+        self.voices
+            .retain(|voice| voice.looping || voice.position < voice.signal.len());
+    }
+}
+
+impl AudioInstance {
+    /// Start playing a signal mixed with everything else that is playing, and return immediately.
+    ///
+    /// Any number of signals can play at once, e.g. masking noise and a probe tone, on top of
+    /// `play` and the background. They are summed, saturating at full scale, so set an output
+    /// limiter with `set_output_limiter` if the sum can clip. Each signal starts at the next audio
+    /// callback and can be stopped on its own with the returned handle.
+    ///
+    /// # Arguments
+    /// output_data: Vec<Vec<i32>> - one vector of samples per output channel of the device. All
+    /// channels must be the same length.
+    ///
+    /// # Errors
+    /// Returns an error if the number of channels does not match the device, or the channels are
+    /// empty or have different lengths
+    /// Returns an error if the signal is blocked by the safety interlock
+    pub fn play_mixed(&self, output_data: Vec<Vec<i32>>) -> Result<PlayHandle, anyhow::Error> {
+        self.start_voice(output_data, false)
+    }
+
+    /// Start looping a signal mixed with everything else that is playing, until it is stopped.
+    ///
+    /// See `play_mixed`.
+    ///
+    /// # Errors
+    /// Returns an error if the number of channels does not match the device, or the channels are
+    /// empty or have different lengths
+    /// Returns an error if the signal is blocked by the safety interlock
+    pub fn loop_mixed(&self, output_data: Vec<Vec<i32>>) -> Result<PlayHandle, anyhow::Error> {
+        self.start_voice(output_data, true)
+    }
+
+    /// Stop every signal started with `play_mixed` and `loop_mixed`.
+    pub fn stop_mixed(&self) {
+        self.mixer.stop_all();
+    }
+
+    fn start_voice(
+        &self,
+        mut output_data: Vec<Vec<i32>>,
+        looping: bool,
+    ) -> Result<PlayHandle, anyhow::Error> {
+        if self.number_of_output_channels != output_data.len() as u16 {
+            return Err(anyhow::Error::msg("Number of channels does not match"));
+        }
+        let length = output_data.first().map_or(0, |channel| channel.len());
+        if length == 0 {
+            return Err(anyhow::Error::msg("The signal is empty"));
+        }
+        if output_data.iter().any(|channel| channel.len() != length) {
+            return Err(anyhow::Error::msg("All channels must be the same length"));
+        }
+        self.process_output(&mut output_data, 0..self.number_of_output_channels as usize);
+        self.check_interlock(&output_data)?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        let mut flattened_output_data = self.flatten_output_data(output_data);
+        self.fade_output(&mut flattened_output_data);
+        let state = Arc::new(AtomicU8::new(VOICE_PLAYING));
+        self.mixer.add(Voice {
+            signal: self.output_signal(flattened_output_data)?,
+            position: 0,
+            looping,
+            state: Arc::clone(&state),
+        });
+
+        Ok(PlayHandle {
+            state,
+            healthy: Arc::clone(&self.healthy),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+
+    fn voice(samples: Vec<i32>, looping: bool) -> (Voice, Arc<AtomicU8>) {
+        let state = Arc::new(AtomicU8::new(VOICE_PLAYING));
+        let voice = Voice {
+            signal: Signal::from_interleaved(samples, 1).unwrap(),
+            position: 0,
+            looping,
+            state: Arc::clone(&state),
+        };
+        (voice, state)
+    }
+
+    #[test]
+    fn test_mix() {
+        let lane = MixerLane::default();
+        let mut mixer = Mixer::default();
+        let (tone, tone_state) = voice(vec![1, 2, 3], false);
+        let (noise, noise_state) = voice(vec![10, 20], true);
+        let (loud, _) = voice(vec![i32::MAX; 4], false);
+        lane.add(tone);
+        lane.add(noise);
+
+        let mut buffer = vec![100; 4];
+        mixer.mix(&lane, &mut buffer);
+        assert_eq!(buffer, vec![111, 122, 113, 120]);
+        assert_eq!(tone_state.load(Ordering::Acquire), VOICE_FINISHED);
+
+        // the sum saturates instead of wrapping
+        lane.add(loud);
+        let mut buffer = vec![0; 2];
+        mixer.mix(&lane, &mut buffer);
+        assert_eq!(buffer, vec![i32::MAX, i32::MAX]);
+
+        noise_state.store(VOICE_STOPPING, Ordering::Release);
+        let mut buffer = vec![0; 2];
+        mixer.mix(&lane, &mut buffer);
+        assert_eq!(buffer, vec![i32::MAX, i32::MAX]);
+        assert_eq!(noise_state.load(Ordering::Acquire), VOICE_FINISHED);
+        assert!(mixer.voices.is_empty());
+    }
+
+    #[test]
+    fn test_play_mixed() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 2))
+            .build()
+            .unwrap();
+        let noise = audio_instance.loop_mixed(vec![vec![10; 100]; 2]).unwrap();
+        let tone = audio_instance.play_mixed(vec![vec![20; 480]; 2]).unwrap();
+        tone.wait().unwrap();
+        assert!(!tone.is_playing());
+        assert!(noise.is_playing());
+
+        noise.stop();
+        noise.wait().unwrap();
+        assert!(audio_instance.play_mixed(vec![vec![0; 10]]).is_err());
+        assert!(audio_instance
+            .play_mixed(vec![vec![0; 10], vec![0; 5]])
+            .is_err());
+
+        let noise = audio_instance.loop_mixed(vec![vec![10; 100]; 2]).unwrap();
+        audio_instance.stop_mixed();
+        noise.wait().unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+/// The feedback taps of a maximal length shift register for each order, starting at order 2.
+const TAPS: [&[u32]; 23] = [
+    &[2, 1],
+    &[3, 2],
+    &[4, 3],
+    &[5, 3],
+    &[6, 5],
+    &[7, 6],
+    &[8, 6, 5, 4],
+    &[9, 5],
+    &[10, 7],
+    &[11, 9],
+    &[12, 11, 10, 4],
+    &[13, 12, 11, 8],
+    &[14, 13, 12, 2],
+    &[15, 14],
+    &[16, 15, 13, 4],
+    &[17, 14],
+    &[18, 11],
+    &[19, 18, 17, 14],
+    &[20, 17],
+    &[21, 19],
+    &[22, 21],
+    &[23, 18],
+    &[24, 23, 22, 17],
+];
+
+/// The supported orders of maximum length sequences.
+pub const ORDERS: std::ops::RangeInclusive<u32> = 2..=24;
+
+/// Generate one period of a maximum length sequence as bits.
+///
+/// # Errors
+/// Returns an error if the order is not in `ORDERS`
+pub(crate) fn mls_bits(order: u32) -> Result<Vec<bool>, anyhow::Error> {
+    if !ORDERS.contains(&order) {
+        return Err(anyhow::anyhow!(
+            "MLS order must be from {} to {}, got {}",
+            ORDERS.start(),
+            ORDERS.end(),
+            order
+        ));
+    }
+    let taps = TAPS[order as usize - 2];
+    let length = (1usize << order) - 1;
+
+    let mut register: u32 = 1;
+    Ok((0..length)
+        .map(|_| {
+            let output = register & 1 == 1;
+            let feedback = taps
+                .iter()
+                .fold(0, |bit, &tap| bit ^ (register >> (order - tap)))
+                & 1;
+            register = (register >> 1) | (feedback << (order - 1));
+            output
+        })
+        .collect())
+}
+
+/// Extracts impulse responses from recordings of a maximum length sequence.
+///
+/// The recording is cross-correlated with the sequence using the fast Hadamard transform, which
+/// is exact for MLS because its circular autocorrelation is an impulse. The sequence must be
+/// played periodically, so play it at least twice and analyse the periods after the first, once
+/// the system has settled. The impulse response must be shorter than one period.
+#[derive(Debug, Clone)]
+pub struct MlsAnalyzer {
+    order: u32,
+    bits: Vec<bool>,
+    /// The Hadamard index of each sample of a period
+    signal_permutation: Vec<usize>,
+    /// The Hadamard index of each sample of the impulse response
+    response_permutation: Vec<usize>,
+}
+
+impl MlsAnalyzer {
+    /// # Errors
+    /// Returns an error if the order is not in `ORDERS`
+    pub fn new(order: u32) -> Result<Self, anyhow::Error> {
+        let bits = mls_bits(order)?;
+        let length = bits.len();
+        let order_bits = order as usize;
+
+        // each sample maps to the register state at that point, read from the next `order` bits
+        let signal_permutation: Vec<usize> = (0..length)
+            .map(|n| {
+                (0..order_bits)
+                    .filter(|&j| bits[(n + j) % length])
+                    .fold(0, |state, j| state | (1 << j))
+            })
+            .collect();
+
+        // the samples where the register state is a single bit form the basis for the lags
+        let mut basis = vec![0; order_bits];
+        for (n, &state) in signal_permutation.iter().enumerate() {
+            if state.is_power_of_two() {
+                basis[state.trailing_zeros() as usize] = n;
+            }
+        }
+        let response_permutation = (0..length)
+            .map(|lag| {
+                (0..order_bits)
+                    .filter(|&j| bits[(basis[j] + length - lag) % length])
+                    .fold(0, |state, j| state | (1 << j))
+            })
+            .collect();
+
+        Ok(MlsAnalyzer {
+            order,
+            bits,
+            signal_permutation,
+            response_permutation,
+        })
+    }
+
+    pub fn order(&self) -> u32 {
+        self.order
+    }
+
+    /// The length of one period of the sequence in samples.
+    pub fn period(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// One period of the sequence to play, at full scale.
+    pub fn sequence(&self) -> Vec<i32> {
+        self.bits
+            .iter()
+            .map(|&bit| if bit { -i32::MAX } else { i32::MAX })
+            .collect()
+    }
+
+    /// Calculate the impulse response from a recording of the periodic sequence.
+    ///
+    /// Every whole period in the recording is averaged, which lowers the noise by 3 dB for every
+    /// doubling of the number of periods.
+    ///
+    /// # Arguments
+    /// recording: &[i32] - the recording, starting at the start of a period
+    ///
+    /// # Returns
+    /// The impulse response, one period long, as a ratio of the recorded to the played level
+    ///
+    /// # Errors
+    /// Returns an error if the recording is shorter than one period
+    pub fn impulse_response(&self, recording: &[i32]) -> Result<Vec<f64>, anyhow::Error> {
+        let length = self.period();
+        let periods = recording.len() / length;
+        if periods == 0 {
+            return Err(anyhow::anyhow!(
+                "The recording is {} samples long, shorter than one MLS period of {} samples",
+                recording.len(),
+                length
+            ));
+        }
+
+        let mut average = vec![0.0; length];
+        for period in recording.chunks_exact(length) {
+            for (sum, &sample) in average.iter_mut().zip(period) {
+                *sum += sample as f64 / i32::MAX as f64;
+            }
+        }
+
+        let mut permuted = vec![0.0; length + 1];
+        permuted[0] = -average.iter().sum::<f64>();
+        for (&index, &sample) in self.signal_permutation.iter().zip(&average) {
+            permuted[index] = sample;
+        }
+        fast_hadamard_transform(&mut permuted);
+
+        let scale = 1.0 / ((length + 1) * periods) as f64;
+        Ok(self
+            .response_permutation
+            .iter()
+            .map(|&index| permuted[index] * scale)
+            .collect())
+    }
+}
+
+/// In-place Walsh-Hadamard transform in natural order. The length must be a power of two.
+fn fast_hadamard_transform(data: &mut [f64]) {
+    let mut width = 1;
+    while width < data.len() {
+        for block in data.chunks_exact_mut(width * 2) {
+            let (low, high) = block.split_at_mut(width);
+            for (a, b) in low.iter_mut().zip(high) {
+                let sum = *a + *b;
+                *b = *a - *b;
+                *a = sum;
+            }
+        }
+        width *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequences_are_maximal_length() {
+        for order in 2..=16 {
+            let bits = mls_bits(order).unwrap();
+            let ones = bits.iter().filter(|&&bit| bit).count();
+            assert_eq!(ones, 1 << (order - 1), "order {}", order);
+
+            // every non-zero state appears exactly once per period
+            let analyzer = MlsAnalyzer::new(order).unwrap();
+            let mut states = analyzer.signal_permutation.clone();
+            states.sort_unstable();
+            states.dedup();
+            assert_eq!(states.len(), bits.len(), "order {}", order);
+        }
+        assert!(mls_bits(1).is_err());
+        assert!(mls_bits(25).is_err());
+    }
+
+    #[test]
+    fn test_impulse_response() {
+        let analyzer = MlsAnalyzer::new(10).unwrap();
+        let sequence = analyzer.sequence();
+        let period = analyzer.period();
+        let h = [(0, 0.5), (3, -0.25), (40, 0.125)];
+
+        // two periods of the circular convolution of the sequence with h
+        let recording: Vec<i32> = (0..period * 2)
+            .map(|n| {
+                h.iter()
+                    .map(|&(delay, gain)| {
+                        gain * sequence[(n % period + period - delay) % period] as f64
+                    })
+                    .sum::<f64>() as i32
+            })
+            .collect();
+
+        let response = analyzer.impulse_response(&recording).unwrap();
+        assert_eq!(response.len(), period);
+        for (n, &value) in response.iter().enumerate() {
+            let expected = h
+                .iter()
+                .find(|&&(delay, _)| delay == n)
+                .map_or(0.0, |&(_, gain)| gain);
+            assert!((value - expected).abs() < 1e-6, "sample {}: {}", n, value);
+        }
+        assert!(analyzer.impulse_response(&recording[..10]).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{InputCallbackInfo, Stream, StreamInstant};
+
+use super::methods::HOST;
+use crate::channel::InputChannel;
+use crate::device_lock::DeviceLock;
+use crate::sample_formats::Sample;
+use crate::stream_controller::with_sample_type;
+use crate::time_align::find_start;
+
+/// The recording from one device of a `MultiDeviceRecorder`.
+#[derive(Debug, Clone)]
+pub struct DeviceRecording {
+    pub device: String,
+    /// A vector of channels where each channel is a vector of samples
+    pub data: Vec<Vec<i32>>,
+    /// The time the first sample was captured, on the clock of this device's stream.
+    /// Stream clocks of different devices can't be compared
+    pub capture_start: StreamInstant,
+    /// The time the first sample was delivered, on the system clock shared by every device
+    pub started_at: Instant,
+}
+
+/// The time of the first callback of a capture, on the stream and system clocks.
+type CaptureStart = Arc<Mutex<Option<(StreamInstant, Instant)>>>;
+
+/// Records from several input devices at the same time.
+///
+/// For setups where one array of microphones spans more than one audio interface. Every device
+/// records the same number of samples, and recordings can be aligned with a timing chirp recorded
+/// by every device.
+pub struct MultiDeviceRecorder {
+    devices: Vec<String>,
+    sample_rate: u32,
+}
+
+impl MultiDeviceRecorder {
+    /// Create a recorder for the named devices on the current host.
+    ///
+    /// # Arguments
+    /// device_names: &[&str] - the names of the input devices to record from
+    /// fs: u32 - the sample rate to record at on every device
+    ///
+    /// # Errors
+    /// Returns an error if the host has not been initialized
+    /// Returns an error if a device is not found or does not support the sample rate
+    pub fn new(device_names: &[&str], fs: u32) -> Result<Self, anyhow::Error> {
+        if device_names.is_empty() {
+            return Err(anyhow::Error::msg("At least one device is required"));
+        }
+
+        for &name in device_names {
+            let device = find_input_device(name)?;
+            let supported = device
+                .supported_input_configs()?
+                .any(|config| config.min_sample_rate().0 <= fs && fs <= config.max_sample_rate().0);
+            if !supported {
+                return Err(anyhow::anyhow!(
+                    "Device {} does not support a sample rate of {} Hz",
+                    name,
+                    fs
+                ));
+            }
+        }
+
+        Ok(MultiDeviceRecorder {
+            devices: device_names.iter().map(|name| name.to_string()).collect(),
+            sample_rate: fs,
+        })
+    }
+
+    /// Record from every device at the same time.
+    ///
+    /// All streams are built before any are started, so the devices start recording within a few
+    /// milliseconds of each other. Use `started_at` on each recording or `record_aligned` to line
+    /// them up exactly.
+    ///
+    /// # Arguments
+    /// duration: f64 - the duration of the recording in seconds
+    ///
+    /// # Returns
+    /// One recording per device, in the order the devices were given
+    pub fn record(&self, duration: f64) -> Result<Vec<DeviceRecording>, anyhow::Error> {
+        let frames = (self.sample_rate as f64 * duration) as usize;
+
+        // make sure no other process is using the devices
+        let _locks = self
+            .devices
+            .iter()
+            .map(|name| DeviceLock::acquire_with_mode(name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut captures = Vec::with_capacity(self.devices.len());
+        for name in self.devices.iter() {
+            let device = find_input_device(name)?;
+            let default_config = device.default_input_config()?;
+            let mut config = default_config.config();
+            config.sample_rate = cpal::SampleRate(self.sample_rate);
+
+            let buffer = Arc::new(Mutex::new(Vec::with_capacity(
+                frames * config.channels as usize,
+            )));
+            let capture_start: CaptureStart = Arc::new(Mutex::new(None));
+            let stream = with_sample_type!(
+                default_config.sample_format(),
+                create_capture_stream(
+                    &device,
+                    &config,
+                    Arc::clone(&buffer),
+                    Arc::clone(&capture_start),
+                )
+            )?;
+            captures.push((stream, buffer, capture_start, config.channels as usize));
+        }
+
+        for (stream, ..) in captures.iter() {
+            stream.play()?;
+        }
+
+        // allow for the streams taking a while to start
+        let timeout = Instant::now() + Duration::from_secs_f64(duration) + Duration::from_secs(2);
+        while captures.iter().any(|(_, buffer, ..)| {
+            let buffer = buffer.lock().unwrap();
+            buffer.len() < buffer.capacity()
+        }) {
+            if Instant::now() > timeout {
+                return Err(anyhow::Error::msg(
+                    "Timed out waiting for every device to finish recording",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut recordings = Vec::with_capacity(captures.len());
+        for ((stream, buffer, capture_start, channels), name) in
+            captures.into_iter().zip(self.devices.iter())
+        {
+            drop(stream);
+            let (capture_start, started_at) = capture_start.lock().unwrap().ok_or(
+                anyhow::anyhow!("Device {} did not report a timestamp", name),
+            )?;
+            let interleaved = std::mem::take(&mut *buffer.lock().unwrap());
+
+            recordings.push(DeviceRecording {
+                device: name.clone(),
+                data: super::methods::split_channels(&interleaved, channels),
+                capture_start,
+                started_at,
+            });
+        }
+
+        Ok(recordings)
+    }
+
+    /// Record from every device and align the recordings using a timing chirp.
+    ///
+    /// The timing chirp must be played into one input of every device, e.g. with
+    /// `AudioInstance::aligned_play_record` on another device or a splitter cable. Everything up to
+    /// the end of the chirp is removed, and every recording is trimmed to the length of the shortest.
+    ///
+    /// # Arguments
+    /// duration: f64 - the duration of the recording in seconds
+    /// timing_channels: &[InputChannel] - the channel the chirp is recorded on for each device
+    pub fn record_aligned(
+        &self,
+        duration: f64,
+        timing_channels: &[InputChannel],
+    ) -> Result<Vec<DeviceRecording>, anyhow::Error> {
+        if timing_channels.len() != self.devices.len() {
+            return Err(anyhow::anyhow!(
+                "Expected a timing channel for each of the {} devices, got {}",
+                self.devices.len(),
+                timing_channels.len()
+            ));
+        }
+
+        let mut recordings = self.record(duration)?;
+        for (recording, &timing_channel) in recordings.iter_mut().zip(timing_channels) {
+            let loopback =
+                recording
+                    .data
+                    .get_mut(timing_channel.index()?)
+                    .ok_or(anyhow::anyhow!(
+                        "Timing channel {} is out of range for device {}",
+                        timing_channel,
+                        recording.device
+                    ))?;
+            let start_sample = find_start(loopback, self.sample_rate)?;
+            for channel in recording.data.iter_mut() {
+                channel.drain(..start_sample.min(channel.len()));
+            }
+        }
+
+        trim_to_shortest(&mut recordings);
+        Ok(recordings)
+    }
+}
+
+fn find_input_device(name: &str) -> Result<cpal::Device, anyhow::Error> {
+    let binding = HOST.lock().unwrap();
+    let host = binding
+        .as_ref()
+        .ok_or_else(|| anyhow::Error::msg("Host not initialized"))?;
+
+    host.input_devices()?
+        .find(|d| d.name().is_ok_and(|device_name| device_name == name))
+        .ok_or(anyhow::anyhow!("Device not found: {}", name))
+}
+
+/// Trim every channel of every recording to the length of the shortest one.
+fn trim_to_shortest(recordings: &mut [DeviceRecording]) {
+    let length = recordings
+        .iter()
+        .flat_map(|recording| recording.data.iter())
+        .map(|channel| channel.len())
+        .min()
+        .unwrap_or(0);
+
+    for channel in recordings
+        .iter_mut()
+        .flat_map(|recording| recording.data.iter_mut())
+    {
+        channel.truncate(length);
+    }
+}
+
+fn create_capture_stream<T: Sample + cpal::SizedSample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    buffer: Arc<Mutex<Vec<i32>>>,
+    capture_start: CaptureStart,
+) -> Result<Stream, anyhow::Error> {
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], info: &InputCallbackInfo| {
+            let mut buffer = buffer.lock().unwrap();
+            if buffer.is_empty() {
+                *capture_start.lock().unwrap() = Some((info.timestamp().capture, Instant::now()));
+            }
+
+            let remaining_capacity = buffer.capacity() - buffer.len();
+            buffer.extend(
+                data.iter()
+                    .take(remaining_capacity)
+                    .map(|&sample| sample.to_i32()),
+            );
+        },
+        |err| tracing::error!(error = %err, "an error occurred on stream"),
+        None,
+    )?;
+    Ok(stream)
+}
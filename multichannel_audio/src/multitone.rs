@@ -0,0 +1,291 @@
+use std::f64::consts::TAU;
+
+use crate::conversions::{db_to_linear, linear_to_db};
+
+/// The length of signal the crest factor is optimized over in seconds. Tones at whole numbers of
+/// Hz repeat every second, so the crest factor is the same over any longer signal.
+const OPTIMIZE_SECONDS: f64 = 1.0;
+/// How far below its peak the signal is clipped in each iteration of the crest factor
+/// optimization.
+const CLIP_RATIO: f64 = 0.85;
+
+/// One tone of a `Multitone`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneComponent {
+    /// The frequency in Hz
+    pub frequency: f64,
+    /// The level relative to the other tones in dB
+    pub level_db: f64,
+    /// The phase of a cosine in radians
+    pub phase: f64,
+}
+
+/// A sum of tones with chosen frequencies, levels and phases, e.g. to measure a frequency response
+/// or intermodulation at many frequencies at once.
+///
+/// The phases decide the peak of the sum, so for a given peak level the right phases give the
+/// tones more energy. `schroeder_phases` and `optimize_crest_factor` choose them.
+///
+/// # Example
+/// ```
+/// use multichannel_audio::multitone::Multitone;
+///
+/// let multitone = Multitone::new()
+///     .tone(100.0, 0.0, 0.0)
+///     .tone(1000.0, -6.0, 0.0)
+///     .tone(5000.0, -6.0, 0.0)
+///     .optimize_crest_factor(48000, 50);
+/// let signal = multitone.generate(2.0, 48000, -3.0).unwrap();
+/// assert_eq!(signal.len(), 96000);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Multitone {
+    components: Vec<ToneComponent>,
+}
+
+impl Multitone {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tone.
+    ///
+    /// # Arguments
+    /// frequency: f64 - the frequency in Hz
+    /// level_db: f64 - the level relative to the other tones in dB
+    /// phase: f64 - the phase of a cosine in radians
+    pub fn tone(mut self, frequency: f64, level_db: f64, phase: f64) -> Self {
+        self.components.push(ToneComponent {
+            frequency,
+            level_db,
+            phase,
+        });
+        self
+    }
+
+    pub fn components(&self) -> &[ToneComponent] {
+        &self.components
+    }
+
+    /// Replace the phases with Schroeder's, which spread the energy of the tones over time so the
+    /// sum has a low crest factor. Tones with more power get more of the phase progression.
+    pub fn schroeder_phases(mut self) -> Self {
+        let powers: Vec<f64> = self
+            .components
+            .iter()
+            .map(|component| db_to_linear(component.level_db).powi(2))
+            .collect();
+        let total: f64 = powers.iter().sum();
+        if total <= 0.0 || !total.is_finite() {
+            return self;
+        }
+        for k in 0..self.components.len() {
+            let phase: f64 = (0..k)
+                .map(|l| (k - l) as f64 * powers[l] / total)
+                .sum::<f64>();
+            self.components[k].phase = (-TAU * phase).rem_euclid(TAU);
+        }
+        self
+    }
+
+    /// Lower the crest factor of the sum by iterating from the Schroeder phases.
+    ///
+    /// Each iteration clips the peaks of the signal and takes the phase of each tone from the
+    /// clipped signal, which moves energy away from the peaks. The phases with the lowest crest
+    /// factor found are kept, so more iterations never make it worse. Frequencies at whole
+    /// numbers of Hz give the best results, since the signal then repeats every second.
+    ///
+    /// # Arguments
+    /// fs: u32 - the sample rate the signal will be generated at
+    /// iterations: usize - the number of iterations, e.g. 50
+    pub fn optimize_crest_factor(self, fs: u32, iterations: usize) -> Self {
+        let mut best = self.schroeder_phases();
+        let length = (OPTIMIZE_SECONDS * fs as f64) as usize;
+        if best.components.is_empty() || length == 0 {
+            return best;
+        }
+        let mut best_crest_factor = crest_factor_db_f64(&best.synthesize(length, fs));
+
+        let mut current = best.clone();
+        for _ in 0..iterations {
+            let signal = current.synthesize(length, fs);
+            let limit = CLIP_RATIO * signal.iter().fold(0.0, |peak, x| f64::max(peak, x.abs()));
+            let clipped: Vec<f64> = signal.iter().map(|x| x.clamp(-limit, limit)).collect();
+            for component in current.components.iter_mut() {
+                component.phase = cosine_phase(&clipped, fs, component.frequency);
+            }
+
+            let crest_factor = crest_factor_db_f64(&current.synthesize(length, fs));
+            if crest_factor < best_crest_factor {
+                best_crest_factor = crest_factor;
+                best = current.clone();
+            }
+        }
+        best
+    }
+
+    /// Generate the sum of the tones, scaled so its peak is at a level.
+    ///
+    /// # Arguments
+    /// duration: f64 - the length of the signal in seconds
+    /// fs: u32 - the sample rate
+    /// peak_dbfs: f64 - the level of the peak of the signal in dBFS
+    ///
+    /// # Errors
+    /// Returns an error if there are no tones, a frequency is not between 0 Hz and the Nyquist
+    /// frequency, or a level is not a number
+    /// Returns an error if the peak is above 0 dBFS
+    pub fn generate(
+        &self,
+        duration: f64,
+        fs: u32,
+        peak_dbfs: f64,
+    ) -> Result<Vec<i32>, anyhow::Error> {
+        if self.components.is_empty() {
+            return Err(anyhow::Error::msg("A multitone needs at least one tone"));
+        }
+        let nyquist = fs as f64 / 2.0;
+        for component in &self.components {
+            if component.frequency.is_nan()
+                || component.frequency <= 0.0
+                || component.frequency >= nyquist
+            {
+                return Err(anyhow::anyhow!(
+                    "The frequency {} Hz is not between 0 Hz and {} Hz",
+                    component.frequency,
+                    nyquist
+                ));
+            }
+            if !component.level_db.is_finite() || !component.phase.is_finite() {
+                return Err(anyhow::anyhow!(
+                    "The level and phase of the {} Hz tone must be numbers",
+                    component.frequency
+                ));
+            }
+        }
+        if peak_dbfs.is_nan() || peak_dbfs > 0.0 {
+            return Err(anyhow::Error::msg("The peak must not be above 0 dBFS"));
+        }
+
+        let signal = self.synthesize((duration.max(0.0) * fs as f64) as usize, fs);
+        let peak = signal.iter().fold(0.0, |peak, x| f64::max(peak, x.abs()));
+        if peak == 0.0 {
+            return Ok(vec![0; signal.len()]);
+        }
+        let gain = db_to_linear(peak_dbfs) * i32::MAX as f64 / peak;
+        Ok(signal.iter().map(|x| (x * gain).round() as i32).collect())
+    }
+
+    /// The sum of the tones at their relative levels.
+    fn synthesize(&self, length: usize, fs: u32) -> Vec<f64> {
+        let mut signal = vec![0.0; length];
+        for component in &self.components {
+            let amplitude = db_to_linear(component.level_db);
+            let omega = TAU * component.frequency / fs as f64;
+            for (n, sample) in signal.iter_mut().enumerate() {
+                *sample += amplitude * (omega * n as f64 + component.phase).cos();
+            }
+        }
+        signal
+    }
+}
+
+/// The phase of a cosine at a frequency in a signal, in radians.
+fn cosine_phase(signal: &[f64], fs: u32, frequency: f64) -> f64 {
+    let omega = TAU * frequency / fs as f64;
+    let (real, imaginary) =
+        signal
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(real, imaginary), (n, &x)| {
+                let angle = omega * n as f64;
+                (real + x * angle.cos(), imaginary + x * angle.sin())
+            });
+    // x[n] = cos(omega * n + phase) correlates with cos as cos(phase) and with sin as -sin(phase)
+    (-imaginary).atan2(real).rem_euclid(TAU)
+}
+
+fn crest_factor_db_f64(signal: &[f64]) -> f64 {
+    let peak = signal.iter().fold(0.0, |peak, x| f64::max(peak, x.abs()));
+    let rms = (signal.iter().map(|x| x * x).sum::<f64>() / signal.len().max(1) as f64).sqrt();
+    linear_to_db(peak / rms)
+}
+
+/// The crest factor of a signal, the ratio of its peak to its RMS level, in dB. A sine is 3 dB.
+pub fn crest_factor_db(signal: &[i32]) -> f64 {
+    let signal: Vec<f64> = signal.iter().map(|&sample| sample as f64).collect();
+    crest_factor_db_f64(&signal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distortion::tone_level_dbfs;
+
+    fn equal_tones() -> Multitone {
+        (1..=20).fold(Multitone::new(), |multitone, k| {
+            multitone.tone(100.0 * k as f64, 0.0, 0.0)
+        })
+    }
+
+    #[test]
+    fn test_multitone_levels() {
+        let signal = Multitone::new()
+            .tone(500.0, 0.0, 0.0)
+            .tone(3000.0, -12.0, 1.0)
+            .generate(1.0, 48000, -1.0)
+            .unwrap();
+        assert_eq!(signal.len(), 48000);
+        let peak = signal
+            .iter()
+            .map(|sample| sample.unsigned_abs())
+            .max()
+            .unwrap();
+        assert!((linear_to_db(peak as f64 / i32::MAX as f64) + 1.0).abs() < 0.01);
+
+        let difference = tone_level_dbfs(&signal, 48000, 500.0).unwrap()
+            - tone_level_dbfs(&signal, 48000, 3000.0).unwrap();
+        assert!((difference - 12.0).abs() < 0.1);
+
+        assert!(Multitone::new().generate(1.0, 48000, -1.0).is_err());
+        assert!(Multitone::new()
+            .tone(30000.0, 0.0, 0.0)
+            .generate(1.0, 48000, -1.0)
+            .is_err());
+        assert!(equal_tones().generate(1.0, 48000, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_crest_factor_optimization() {
+        let sine = Multitone::new()
+            .tone(1000.0, 0.0, 0.0)
+            .generate(1.0, 48000, 0.0)
+            .unwrap();
+        assert!((crest_factor_db(&sine) - 3.01).abs() < 0.01);
+
+        // in phase, the tones all peak together
+        let zero_phase = crest_factor_db(&equal_tones().generate(1.0, 48000, 0.0).unwrap());
+        assert!((zero_phase - linear_to_db(40f64.sqrt())).abs() < 0.1);
+
+        let schroeder = equal_tones().schroeder_phases();
+        let schroeder_crest = crest_factor_db(&schroeder.generate(1.0, 48000, 0.0).unwrap());
+        assert!(schroeder_crest < zero_phase - 8.0);
+
+        let optimized = equal_tones().optimize_crest_factor(48000, 30);
+        let optimized_crest = crest_factor_db(&optimized.generate(1.0, 48000, 0.0).unwrap());
+        assert!(optimized_crest <= schroeder_crest + 1e-9);
+        // the frequencies and levels are unchanged
+        assert_eq!(
+            optimized
+                .components()
+                .iter()
+                .map(|component| component.frequency)
+                .collect::<Vec<_>>(),
+            schroeder
+                .components()
+                .iter()
+                .map(|component| component.frequency)
+                .collect::<Vec<_>>()
+        );
+    }
+}
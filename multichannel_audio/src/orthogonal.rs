@@ -0,0 +1,279 @@
+use rustfft::{num_complex::Complex, FftPlanner};
+
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+use crate::channel::OutputChannel;
+#[cfg(feature = "device")]
+use crate::channel::{ChannelIndex, InputChannel};
+#[cfg(feature = "device")]
+use crate::time_align::{align_with_layout, assemble_signal_with_config};
+
+/// Stimuli for measuring several output channels at once with time-shifted copies of one sweep.
+///
+/// Every channel plays the same exponential sweep, each starting `shift` samples after the
+/// previous channel. Deconvolving a recording by the sweep gives the impulse responses of all the
+/// channels one after the other, so they can be cut apart. `shift` must be longer than the
+/// impulse responses being measured, otherwise they overlap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShiftedSweeps {
+    sweep: Vec<i32>,
+    shift: usize,
+    outputs: Vec<OutputChannel>,
+}
+
+/// The impulse responses from every measured output channel to every input channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrthogonalResponse {
+    /// The output channels, in the order of the second index of `impulse_responses`
+    pub outputs: Vec<OutputChannel>,
+    /// The impulse responses indexed by input channel, then output channel
+    pub impulse_responses: Vec<Vec<Vec<f64>>>,
+}
+
+impl OrthogonalResponse {
+    /// The impulse response from an output channel to a recorded channel.
+    ///
+    /// # Arguments
+    /// output: OutputChannel - the output channel that played
+    /// input_index: usize - the 0-based index of the channel in the recording
+    pub fn get(&self, output: OutputChannel, input_index: usize) -> Option<&[f64]> {
+        let output_index = self.outputs.iter().position(|&o| o == output)?;
+        self.impulse_responses
+            .get(input_index)
+            .map(|responses| responses[output_index].as_slice())
+    }
+}
+
+impl ShiftedSweeps {
+    /// # Arguments
+    /// sweep: Vec<i32> - the sweep to play, e.g. from `generate_exponential_sweep`
+    /// shift: usize - the delay between channels in samples, longer than the impulse responses
+    /// outputs: &[OutputChannel] - the output channels to measure
+    ///
+    /// # Errors
+    /// Returns an error if there are no output channels or an output channel is repeated
+    pub fn new(
+        sweep: Vec<i32>,
+        shift: usize,
+        outputs: &[OutputChannel],
+    ) -> Result<Self, anyhow::Error> {
+        if outputs.is_empty() || sweep.is_empty() || shift == 0 {
+            return Err(anyhow::Error::msg(
+                "Shifted sweeps need a sweep, a shift and at least one output channel",
+            ));
+        }
+        for (i, output) in outputs.iter().enumerate() {
+            output.index()?;
+            if outputs[..i].contains(output) {
+                return Err(anyhow::anyhow!("Channel {} is used more than once", output));
+            }
+        }
+
+        Ok(ShiftedSweeps {
+            sweep,
+            shift,
+            outputs: outputs.to_vec(),
+        })
+    }
+
+    pub fn outputs(&self) -> &[OutputChannel] {
+        &self.outputs
+    }
+
+    /// The length of each stimulus in samples, including the time for the last impulse response.
+    pub fn stimulus_length(&self) -> usize {
+        self.sweep.len() + self.shift * self.outputs.len()
+    }
+
+    /// The signal to play on each output channel, in the order of `outputs`.
+    pub fn stimuli(&self) -> Vec<Vec<i32>> {
+        (0..self.outputs.len())
+            .map(|i| {
+                let mut stimulus = vec![0; self.stimulus_length()];
+                let start = i * self.shift;
+                stimulus[start..start + self.sweep.len()].copy_from_slice(&self.sweep);
+                stimulus
+            })
+            .collect()
+    }
+
+    /// Split a recording of the stimuli into the impulse response from each output channel.
+    ///
+    /// # Arguments
+    /// response: &[i32] - one recorded channel, aligned so sample 0 is the start of the stimuli
+    ///
+    /// # Returns
+    /// One impulse response of `shift` samples per output channel, in the order of `outputs`
+    pub fn demultiplex(&self, response: &[i32]) -> Vec<Vec<f64>> {
+        let impulse_response = deconvolve(response, &self.sweep);
+
+        (0..self.outputs.len())
+            .map(|i| {
+                let start = (i * self.shift).min(impulse_response.len());
+                let end = (start + self.shift).min(impulse_response.len());
+                let mut channel = impulse_response[start..end].to_vec();
+                channel.resize(self.shift, 0.0);
+                channel
+            })
+            .collect()
+    }
+}
+
+/// Deconvolve a signal by a broadband excitation, giving the impulse response of the system it
+/// was played through. Frequencies the excitation doesn't cover are suppressed.
+pub(crate) fn deconvolve(signal: &[i32], excitation: &[i32]) -> Vec<f64> {
+    let length = (signal.len() + excitation.len()).next_power_of_two();
+    let mut planner = FftPlanner::new();
+    let fft = planner.plan_fft_forward(length);
+    let ifft = planner.plan_fft_inverse(length);
+
+    let spectrum = |samples: &[i32]| {
+        let mut buffer: Vec<Complex<f64>> = samples
+            .iter()
+            .map(|&sample| Complex::new(sample as f64 / i32::MAX as f64, 0.0))
+            .chain(std::iter::repeat(Complex::new(0.0, 0.0)))
+            .take(length)
+            .collect();
+        fft.process(&mut buffer);
+        buffer
+    };
+    let mut output = spectrum(signal);
+    let excitation = spectrum(excitation);
+
+    // regularize so bins outside the excitation band don't blow up
+    let max_power = excitation.iter().map(|x| x.norm_sqr()).fold(0.0, f64::max);
+    let regularization = max_power * 1e-6;
+    for (y, x) in output.iter_mut().zip(&excitation) {
+        *y = *y * x.conj() / (x.norm_sqr() + regularization);
+    }
+
+    ifft.process(&mut output);
+    output
+        .iter()
+        .take(signal.len())
+        .map(|y| y.re / length as f64)
+        .collect()
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Measure the impulse responses of several output channels in a single run.
+    ///
+    /// Every channel in `sweeps` plays a time-shifted copy of the same sweep with the loopback
+    /// timing chirp, then the aligned recording on each input channel is split into the response
+    /// from each output.
+    ///
+    /// # Arguments
+    /// sweeps: &ShiftedSweeps - the stimuli and the channels to play them on
+    /// timing_channel_out: OutputChannel - the output channel looped back for timing
+    /// timing_channel_in: InputChannel - the input channel the timing signal is recorded on
+    /// number_of_output_channels: usize - the number of output channels of the device
+    /// max_latency: f64 - the longest round-trip latency to allow for, in seconds
+    ///
+    /// # Errors
+    /// Returns an error if a channel is out of range
+    /// Returns an error if the timing signal can't be found in the recording
+    pub fn aligned_play_record_orthogonal(
+        &self,
+        sweeps: &ShiftedSweeps,
+        timing_channel_out: OutputChannel,
+        timing_channel_in: InputChannel,
+        number_of_output_channels: usize,
+        max_latency: f64,
+    ) -> Result<OrthogonalResponse, anyhow::Error> {
+        let fs = self.sample_rate;
+        let duration = sweeps.stimulus_length().div_ceil(fs as usize);
+        let config = self.alignment_config();
+        let mut output_data = assemble_signal_with_config(
+            &vec![0; duration * fs as usize],
+            duration,
+            sweeps.outputs[0],
+            timing_channel_out,
+            fs,
+            number_of_output_channels,
+            &config,
+        )?;
+
+        let stimulus_start = config.signal_start(fs)?;
+        for (output, stimulus) in sweeps.outputs.iter().zip(sweeps.stimuli()) {
+            let channel =
+                &mut output_data[ChannelIndex::output(*output, number_of_output_channels)?.get()];
+            channel[stimulus_start..stimulus_start + stimulus.len()].copy_from_slice(&stimulus);
+        }
+
+        // keep recording long enough to capture the last response after the latency
+        let padding = (max_latency.max(0.0) * fs as f64) as usize;
+        for channel in output_data.iter_mut() {
+            channel.resize(channel.len() + padding, 0);
+        }
+
+        let mut recorded_data = self.play_record(output_data)?;
+        let aligned_data = align_with_layout(
+            &mut recorded_data,
+            timing_channel_in,
+            &config,
+            fs,
+            duration * fs as usize,
+        )?;
+
+        Ok(OrthogonalResponse {
+            outputs: sweeps.outputs.clone(),
+            impulse_responses: aligned_data
+                .iter()
+                .map(|response| sweeps.demultiplex(response))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods::generate_exponential_sweep;
+
+    #[test]
+    fn test_stimuli_are_shifted() {
+        let sweeps =
+            ShiftedSweeps::new(vec![1, 2, 3], 4, &[OutputChannel(2), OutputChannel(5)]).unwrap();
+
+        assert_eq!(
+            sweeps.stimuli(),
+            vec![
+                vec![1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0],
+                vec![0, 0, 0, 0, 1, 2, 3, 0, 0, 0, 0],
+            ]
+        );
+        assert!(ShiftedSweeps::new(vec![1], 4, &[OutputChannel(1), OutputChannel(1)]).is_err());
+    }
+
+    #[test]
+    fn test_demultiplex_separates_channels() {
+        let sweep = generate_exponential_sweep(50.0, 20000.0, 0.5, 48000);
+        let shift = 2000;
+        let outputs = [OutputChannel(1), OutputChannel(2)];
+        let sweeps = ShiftedSweeps::new(sweep, shift, &outputs).unwrap();
+
+        // output 1 arrives 10 samples late at half level, output 2 arrives 100 samples late
+        let stimuli = sweeps.stimuli();
+        let mut response = vec![0i32; sweeps.stimulus_length()];
+        for (n, sample) in response.iter_mut().enumerate() {
+            let first = if n >= 10 { stimuli[0][n - 10] / 4 } else { 0 };
+            let second = if n >= 100 { stimuli[1][n - 100] / 2 } else { 0 };
+            *sample = first + second;
+        }
+
+        let impulse_responses = sweeps.demultiplex(&response);
+        let peak = |ir: &[f64]| {
+            ir.iter()
+                .copied()
+                .enumerate()
+                .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+                .unwrap()
+        };
+        let (first_index, first_peak) = peak(&impulse_responses[0]);
+        let (second_index, second_peak) = peak(&impulse_responses[1]);
+        assert_eq!(first_index, 10);
+        assert_eq!(second_index, 100);
+        assert!((second_peak / first_peak - 2.0).abs() < 0.1);
+    }
+}
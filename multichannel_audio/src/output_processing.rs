@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use crate::audio_class::AudioInstance;
+use crate::biquad::Biquad;
+use crate::channel::OutputChannel;
+
+/// A function applied to a whole channel of output data before it is played.
+pub type OutputProcessor = Box<dyn FnMut(&mut [i32]) + Send>;
+
+/// The processing chain of each output channel, by 0-based channel index.
+pub(crate) type OutputProcessors = BTreeMap<usize, Vec<OutputProcessor>>;
+
+impl AudioInstance {
+    /// Add a processor to the end of the chain of an output channel.
+    ///
+    /// The chain is applied to the data of the channel in `play`, `play_on_channels` and
+    /// `play_record` before it is interleaved, so EQ or protection filters can be applied without
+    /// changing the stimulus files. The interlock checks the processed data.
+    ///
+    /// # Arguments
+    /// channel: OutputChannel - the channel to process
+    /// processor: impl FnMut(&mut [i32]) - called with the whole channel before each playback
+    ///
+    /// # Errors
+    /// Returns an error if the channel is out of range
+    pub fn add_output_processor(
+        &self,
+        channel: OutputChannel,
+        processor: impl FnMut(&mut [i32]) + Send + 'static,
+    ) -> Result<(), anyhow::Error> {
+        let index = self.output_index(channel)?.get();
+        self.output_processors
+            .lock()
+            .unwrap()
+            .entry(index)
+            .or_default()
+            .push(Box::new(processor));
+        Ok(())
+    }
+
+    /// Add a biquad filter to the end of the chain of an output channel.
+    ///
+    /// The filter starts from silence for each playback.
+    ///
+    /// # Errors
+    /// Returns an error if the channel is out of range
+    pub fn add_output_filter(
+        &self,
+        channel: OutputChannel,
+        filter: Biquad,
+    ) -> Result<(), anyhow::Error> {
+        self.add_output_processor(channel, move |samples| {
+            let mut filter = filter;
+            filter.process(samples);
+        })
+    }
+
+    /// Remove the processing chain of an output channel, or of every channel if None.
+    pub fn clear_output_processors(&self, channel: Option<OutputChannel>) {
+        let mut processors = self.output_processors.lock().unwrap();
+        match channel.and_then(|channel| channel.index().ok()) {
+            Some(index) => {
+                processors.remove(&index);
+            }
+            None => processors.clear(),
+        }
+    }
+
+    /// Run the processing chains over output data.
+    ///
+    /// # Arguments
+    /// output_data: &mut [Vec<i32>] - the channels to process
+    /// channels: impl Iterator<Item = usize> - the 0-based output channel index of each vector in `output_data`
+    pub(super) fn process_output(
+        &self,
+        output_data: &mut [Vec<i32>],
+        channels: impl Iterator<Item = usize>,
+    ) {
+        let mut processors = self.output_processors.lock().unwrap();
+        if processors.is_empty() {
+            return;
+        }
+        for (data, index) in output_data.iter_mut().zip(channels) {
+            if let Some(chain) = processors.get_mut(&index) {
+                for processor in chain.iter_mut() {
+                    processor(data);
+                }
+            }
+        }
+    }
+}
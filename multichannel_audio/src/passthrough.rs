@@ -0,0 +1,328 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::channel::{InputChannel, OutputChannel};
+use crate::conversions::db_to_linear;
+use crate::sample_formats::Sample;
+
+/// The most input frames held for the output callback. Older frames are dropped, so the
+/// monitoring latency stays low when the input and output callbacks drift apart.
+const MAX_QUEUED_FRAMES: usize = 2048;
+
+/// A path from an input channel to an output channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PassthroughRoute {
+    pub input: InputChannel,
+    pub output: OutputChannel,
+    /// The gain of the path in dB
+    pub gain_db: f64,
+}
+
+/// The input channels to monitor and the output channels to hear them on.
+///
+/// # Example
+/// ```
+/// use multichannel_audio::channel::{InputChannel, OutputChannel};
+/// use multichannel_audio::passthrough::PassthroughMatrix;
+///
+/// // the measurement mic on input 1 in both headphone channels
+/// let matrix = PassthroughMatrix::new()
+///     .route(InputChannel(1), OutputChannel(3), -6.0)
+///     .route(InputChannel(1), OutputChannel(4), -6.0);
+/// assert_eq!(matrix.routes().len(), 2);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PassthroughMatrix {
+    routes: Vec<PassthroughRoute>,
+}
+
+impl PassthroughMatrix {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a path from an input channel to an output channel. An output can mix several inputs.
+    ///
+    /// # Arguments
+    /// input: InputChannel - the input channel to monitor
+    /// output: OutputChannel - the output channel to play it on
+    /// gain_db: f64 - the gain of the path in dB
+    pub fn route(mut self, input: InputChannel, output: OutputChannel, gain_db: f64) -> Self {
+        self.routes.push(PassthroughRoute {
+            input,
+            output,
+            gain_db,
+        });
+        self
+    }
+
+    pub fn routes(&self) -> &[PassthroughRoute] {
+        &self.routes
+    }
+}
+
+/// A path as the callbacks use it: the input index, output index and linear gain.
+type Path = (usize, usize, f32);
+
+/// Input frames handed from the input callback of a duplex stream to its output callback.
+///
+/// The user thread hands over new routes and sets `changed`, as with the background. The
+/// callbacks only lock the frames with `try_lock`, and skip a buffer rather than wait.
+#[derive(Default)]
+pub(crate) struct PassthroughLane {
+    routes: Mutex<Option<Vec<Path>>>,
+    changed: AtomicBool,
+    active: AtomicBool,
+    /// Interleaved input frames waiting to be played
+    frames: Mutex<VecDeque<i32>>,
+    input_channels: AtomicUsize,
+}
+
+impl PassthroughLane {
+    /// Replace the routes. No routes stops the passthrough. Called from the user thread.
+    fn set_routes(&self, routes: Vec<Path>) {
+        let active = !routes.is_empty();
+        *self.routes.lock().unwrap() = Some(routes);
+        self.changed.store(true, Ordering::Release);
+        self.active.store(active, Ordering::Release);
+        if !active {
+            self.frames.lock().unwrap().clear();
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Take new routes if they have been set. Called from the output callback.
+    fn take_routes(&self) -> Option<Vec<Path>> {
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        match self.routes.try_lock() {
+            Ok(mut routes) => routes.take(),
+            Err(_) => {
+                // try again at the next callback
+                self.changed.store(true, Ordering::Release);
+                None
+            }
+        }
+    }
+
+    /// Keep a buffer of input for the output callback. Called from the input callback.
+    pub fn push<T: Sample>(&self, data: &[T], channels: usize) {
+        if !self.is_active() {
+            return;
+        }
+        let Ok(mut frames) = self.frames.try_lock() else {
+            return;
+        };
+        self.input_channels.store(channels, Ordering::Relaxed);
+        frames.extend(data.iter().map(|&sample| sample.to_i32()));
+
+        let excess = frames.len().saturating_sub(MAX_QUEUED_FRAMES * channels);
+        frames.drain(..excess);
+    }
+}
+
+/// Mixes the input frames of a `PassthroughLane` into the output. Owned by the output callback.
+pub(crate) struct Passthrough {
+    lane: Arc<PassthroughLane>,
+    routes: Vec<Path>,
+    frame: Vec<i32>,
+}
+
+impl Passthrough {
+    pub fn new(lane: Arc<PassthroughLane>) -> Self {
+        Passthrough {
+            lane,
+            routes: Vec::new(),
+            frame: Vec::new(),
+        }
+    }
+
+    /// Mix the waiting input into a buffer of interleaved output.
+    pub fn mix(&mut self, buffer: &mut [i32], channels: usize) {
+        if let Some(routes) = self.lane.take_routes() {
+            self.routes = routes;
+        }
+        let input_channels = self.lane.input_channels.load(Ordering::Relaxed);
+        if self.routes.is_empty() || input_channels == 0 {
+            return;
+        }
+        let Ok(mut frames) = self.lane.frames.try_lock() else {
+            return;
+        };
+
+        for output_frame in buffer.chunks_exact_mut(channels) {
+            if frames.len() < input_channels {
+                break;
+            }
+            self.frame.clear();
+            self.frame.extend(frames.drain(..input_channels));
+
+            for &(input, output, gain) in self.routes.iter() {
+                if let (Some(&sample), Some(mixed)) =
+                    (self.frame.get(input), output_frame.get_mut(output))
+                {
+                    *mixed = mixed.saturating_add((sample as f32 * gain) as i32);
+                }
+            }
+        }
+    }
+}
+
+/// The highest peak the routes can add to any output channel, if every input is at full scale.
+fn worst_case_peak(routes: &[(usize, usize, f32)]) -> i32 {
+    let mut gains: Vec<(usize, f64)> = Vec::new();
+    for &(_, output, gain) in routes {
+        match gains.iter_mut().find(|(channel, _)| *channel == output) {
+            Some((_, total)) => *total += gain.abs() as f64,
+            None => gains.push((output, gain.abs() as f64)),
+        }
+    }
+    let total = gains
+        .iter()
+        .fold(0.0, |peak, &(_, gain)| f64::max(peak, gain));
+    (total * i32::MAX as f64).min(i32::MAX as f64) as i32
+}
+
+impl AudioInstance {
+    /// Route input channels live to output channels, e.g. to listen to a measurement mic.
+    ///
+    /// The input is mixed into the output in the callbacks of the duplex stream, on top of anything
+    /// being played, and keeps running while the instance is otherwise idle. Calling this again
+    /// replaces the routes. This function returns immediately.
+    ///
+    /// The input can't be checked in advance like a signal, so the safety interlock assumes every
+    /// input reaches full scale. Routes that could then play above the interlock level need it
+    /// to be armed when passthrough starts.
+    ///
+    /// # Arguments
+    /// matrix: &PassthroughMatrix - the paths from input to output channels and their gains
+    ///
+    /// # Errors
+    /// Returns an error if the instance is not duplex
+    /// Returns an error if a channel is out of range or a gain is not a number
+    /// Returns an error if the routes are blocked by the safety interlock
+    pub fn start_passthrough(&self, matrix: &PassthroughMatrix) -> Result<(), anyhow::Error> {
+        if !self.duplex {
+            return Err(anyhow::Error::msg(
+                "Passthrough needs a duplex instance, since it runs in the duplex callbacks",
+            ));
+        }
+
+        let mut routes = Vec::with_capacity(matrix.routes().len());
+        for route in matrix.routes() {
+            if route.gain_db.is_nan() {
+                return Err(anyhow::anyhow!(
+                    "The gain from {} is not a number",
+                    route.input
+                ));
+            }
+            routes.push((
+                self.input_index(route.input)?.get(),
+                self.output_index(route.output)?.get(),
+                db_to_linear(route.gain_db) as f32,
+            ));
+        }
+        self.check_interlock_level(worst_case_peak(&routes))?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        self.passthrough.set_routes(routes);
+        Ok(())
+    }
+
+    /// Stop routing input channels to output channels.
+    pub fn stop_passthrough(&self) {
+        self.passthrough.set_routes(Vec::new());
+    }
+
+    /// Whether input channels are being routed to output channels.
+    pub fn is_passthrough_active(&self) -> bool {
+        self.passthrough.is_active()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{ChannelModel, MockDevice};
+    use crate::builder::AudioInstanceBuilder;
+
+    #[test]
+    fn test_passthrough_mix() {
+        let lane = Arc::new(PassthroughLane::default());
+        let mut passthrough = Passthrough::new(Arc::clone(&lane));
+        lane.set_routes(vec![(1, 0, 0.5)]);
+        lane.push(&[10, 1000, 20, 2000], 2);
+
+        let mut buffer = vec![1, 0, 1, 0, 1, 0];
+        passthrough.mix(&mut buffer, 2);
+        // the third frame has no input yet
+        assert_eq!(buffer, vec![501, 0, 1001, 0, 1, 0]);
+
+        lane.set_routes(Vec::new());
+        lane.push(&[10, 1000], 2);
+        let mut buffer = vec![0; 4];
+        passthrough.mix(&mut buffer, 2);
+        assert_eq!(buffer, vec![0; 4]);
+    }
+
+    #[test]
+    fn test_start_passthrough() {
+        // a mic on input 1, and input 2 hears output 2
+        let device = MockDevice::new(2, 2)
+            .channel(InputChannel(1), ChannelModel::unconnected().noise(100_000));
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(device.clone())
+            .duplex(true)
+            .build()
+            .unwrap();
+
+        let recording = audio_instance.record(0.05).unwrap();
+        assert!(recording[1].iter().all(|&sample| sample == 0));
+
+        let matrix = PassthroughMatrix::new().route(InputChannel(1), OutputChannel(2), 0.0);
+        audio_instance.start_passthrough(&matrix).unwrap();
+        assert!(audio_instance.is_passthrough_active());
+        let recording = audio_instance.record(0.05).unwrap();
+        assert!(recording[1].iter().any(|&sample| sample != 0));
+
+        audio_instance.stop_passthrough();
+        assert!(!audio_instance.is_passthrough_active());
+
+        let bad_route = PassthroughMatrix::new().route(InputChannel(3), OutputChannel(1), 0.0);
+        assert!(audio_instance.start_passthrough(&bad_route).is_err());
+
+        let separate = AudioInstanceBuilder::new().mock(device).build().unwrap();
+        assert!(separate.start_passthrough(&matrix).is_err());
+    }
+
+    #[test]
+    fn test_passthrough_interlock() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .duplex(true)
+            .build()
+            .unwrap();
+        audio_instance.enable_interlock(-20.0);
+
+        let quiet = PassthroughMatrix::new().route(InputChannel(1), OutputChannel(1), -30.0);
+        audio_instance.start_passthrough(&quiet).unwrap();
+
+        // two quiet routes to the same output can add up to more than the interlock level
+        let summed = quiet.route(InputChannel(2), OutputChannel(1), -12.0);
+        assert!(audio_instance.start_passthrough(&summed).is_err());
+        let loud = PassthroughMatrix::new().route(InputChannel(1), OutputChannel(2), 0.0);
+        assert!(audio_instance.start_passthrough(&loud).is_err());
+
+        audio_instance.arm(std::time::Duration::from_secs(10));
+        audio_instance.start_passthrough(&loud).unwrap();
+        audio_instance.stop_passthrough();
+    }
+}
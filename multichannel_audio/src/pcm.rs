@@ -0,0 +1,309 @@
+use std::io::Read;
+#[cfg(feature = "device")]
+use std::io::Write;
+use std::str::FromStr;
+
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+use crate::sample_formats::Sample;
+use crate::source::SignalSource;
+
+/// The sample format of a raw stream of interleaved little-endian PCM, with the names `sox` and
+/// `ffmpeg` use, e.g. `s16le`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PcmFormat {
+    #[default]
+    S16Le,
+    /// 24-bit samples packed into 3 bytes
+    S24Le,
+    S32Le,
+    /// 32-bit floats from -1.0 to 1.0
+    F32Le,
+}
+
+impl PcmFormat {
+    /// The number of bytes in one sample.
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            PcmFormat::S16Le => 2,
+            PcmFormat::S24Le => 3,
+            PcmFormat::S32Le | PcmFormat::F32Le => 4,
+        }
+    }
+
+    /// Append a full-scale i32 sample to a buffer in this format.
+    pub fn encode(self, sample: i32, out: &mut Vec<u8>) {
+        match self {
+            PcmFormat::S16Le => out.extend_from_slice(&((sample >> 16) as i16).to_le_bytes()),
+            PcmFormat::S24Le => out.extend_from_slice(&(sample >> 8).to_le_bytes()[..3]),
+            PcmFormat::S32Le => out.extend_from_slice(&sample.to_le_bytes()),
+            PcmFormat::F32Le => out.extend_from_slice(&f32::from_i32(sample).to_le_bytes()),
+        }
+    }
+
+    /// Read one sample in this format as a full-scale i32.
+    ///
+    /// # Arguments
+    /// bytes: &[u8] - exactly `bytes_per_sample` bytes
+    pub fn decode(self, bytes: &[u8]) -> i32 {
+        match self {
+            PcmFormat::S16Le => (i16::from_le_bytes([bytes[0], bytes[1]]) as i32) << 16,
+            PcmFormat::S24Le => i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]),
+            PcmFormat::S32Le => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            PcmFormat::F32Le => {
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_i32()
+            }
+        }
+    }
+}
+
+impl FromStr for PcmFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "s16le" => Ok(PcmFormat::S16Le),
+            "s24le" => Ok(PcmFormat::S24Le),
+            "s32le" => Ok(PcmFormat::S32Le),
+            "f32le" => Ok(PcmFormat::F32Le),
+            _ => Err(anyhow::anyhow!(
+                "Unknown PCM format {}. Use s16le, s24le, s32le or f32le",
+                s
+            )),
+        }
+    }
+}
+
+/// A stream of raw interleaved PCM read a frame at a time, e.g. from stdin.
+///
+/// As with `WavSource`, the channels of the stream play on the first output channels. The next
+/// frame is read ahead, so the source finishes as soon as the stream ends.
+///
+/// # Example
+/// ```no_run
+/// # #[cfg(feature = "device")]
+/// # {
+/// use multichannel_audio::audio_class::AudioInstance;
+/// use multichannel_audio::pcm::{PcmFormat, PcmSource};
+///
+/// // sox sweep.wav -t raw -e signed -b 16 -L - | my_program
+/// let audio_instance = AudioInstance::new(48000).unwrap();
+/// let mut source = PcmSource::new(std::io::stdin().lock(), PcmFormat::S16Le, 2);
+/// audio_instance.play_source(&mut source, None).unwrap();
+/// # }
+/// ```
+pub struct PcmSource<R: Read> {
+    reader: R,
+    format: PcmFormat,
+    channels: usize,
+    /// The bytes of the next frame, or None at the end of the stream
+    next: Option<Vec<u8>>,
+}
+
+impl<R: Read> PcmSource<R> {
+    /// Start reading a stream. Blocks until the first frame has arrived.
+    ///
+    /// # Arguments
+    /// reader: R - the stream of interleaved samples
+    /// format: PcmFormat - the sample format of the stream
+    /// channels: usize - the number of channels in each frame of the stream
+    pub fn new(reader: R, format: PcmFormat, channels: usize) -> Self {
+        let mut source = PcmSource {
+            reader,
+            format,
+            channels,
+            next: None,
+        };
+        source.read_ahead();
+        source
+    }
+
+    /// Read the next frame. A partial frame at the end of the stream is dropped.
+    fn read_ahead(&mut self) {
+        let mut frame = vec![0u8; self.channels * self.format.bytes_per_sample()];
+        self.next = match self.reader.read_exact(&mut frame) {
+            Ok(()) if !frame.is_empty() => Some(frame),
+            Ok(()) => None,
+            Err(error) => {
+                // a source can't fail, so end it where the stream can't be read
+                if error.kind() != std::io::ErrorKind::UnexpectedEof {
+                    tracing::warn!(%error, "failed to read the PCM stream, stopping it");
+                }
+                None
+            }
+        };
+    }
+}
+
+impl<R: Read> SignalSource for PcmSource<R> {
+    fn next_frame(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        let Some(frame) = self.next.take() else {
+            return;
+        };
+        for (out, bytes) in out
+            .iter_mut()
+            .zip(frame.chunks_exact(self.format.bytes_per_sample()))
+        {
+            *out = f32::from_i32(self.format.decode(bytes));
+        }
+        self.read_ahead();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.next.is_none()
+    }
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Record raw interleaved PCM to a writer, e.g. stdout, to feed `sox` or `ffmpeg`.
+    ///
+    /// The enabled input channels are recorded, as with `record`. The input callback hands each
+    /// buffer to a writer thread, so only a few buffers are held in memory however long the
+    /// recording is. This function blocks until the recording has finished.
+    ///
+    /// # Arguments
+    /// writer: W - where to write the samples
+    /// duration: Option<f64> - the duration of the recording in seconds, or None to record until
+    /// the writer fails, e.g. because the other end of a pipe has been closed
+    /// format: PcmFormat - the sample format to write
+    ///
+    /// # Errors
+    /// Returns an error if the duration is negative
+    /// Returns an error if writing fails. A closed pipe ends a recording without a duration
+    /// without an error
+    pub fn record_pcm<W: Write + Send + 'static>(
+        &self,
+        mut writer: W,
+        duration: Option<f64>,
+        format: PcmFormat,
+    ) -> Result<(), anyhow::Error> {
+        let frames = match duration {
+            Some(duration) if duration.is_nan() || duration < 0.0 => {
+                return Err(anyhow::anyhow!("The duration must not be negative"));
+            }
+            Some(duration) => (self.sample_rate as f64 * duration) as usize,
+            None => usize::MAX,
+        };
+
+        self.record_with_writer(frames, move |blocks| {
+            let mut bytes = Vec::new();
+            for samples in blocks {
+                bytes.clear();
+                for sample in samples {
+                    format.encode(sample, &mut bytes);
+                }
+                match writer.write_all(&bytes).and_then(|()| writer.flush()) {
+                    Ok(()) => {}
+                    Err(error)
+                        if duration.is_none() && error.kind() == std::io::ErrorKind::BrokenPipe =>
+                    {
+                        return Ok(());
+                    }
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::render_source;
+
+    #[test]
+    fn test_pcm_formats() {
+        for (format, tolerance) in [
+            (PcmFormat::S16Le, 1 << 16),
+            (PcmFormat::S24Le, 1 << 8),
+            (PcmFormat::S32Le, 0),
+            (PcmFormat::F32Le, 1 << 8),
+        ] {
+            for sample in [0, 1 << 20, -(1 << 30), i32::MAX, i32::MIN + 1] {
+                let mut bytes = Vec::new();
+                format.encode(sample, &mut bytes);
+                assert_eq!(bytes.len(), format.bytes_per_sample());
+                let decoded = format.decode(&bytes);
+                assert!(
+                    (decoded as i64 - sample as i64).abs() <= tolerance,
+                    "{format:?} {sample} {decoded}"
+                );
+            }
+        }
+        assert_eq!("F32LE".parse::<PcmFormat>().unwrap(), PcmFormat::F32Le);
+        assert!("u8".parse::<PcmFormat>().is_err());
+    }
+
+    #[test]
+    fn test_pcm_source() {
+        let mut bytes = Vec::new();
+        for sample in [1 << 16, 2 << 16, 3 << 16, 4 << 16, 5 << 16] {
+            PcmFormat::S16Le.encode(sample, &mut bytes);
+        }
+        // the fifth sample is half a frame, so it is dropped
+        let mut source = PcmSource::new(bytes.as_slice(), PcmFormat::S16Le, 2);
+        let rendered = render_source(&mut source, 3, 10);
+        assert!(source.is_finished());
+        assert_eq!(rendered[0], vec![1 << 16, 3 << 16]);
+        assert_eq!(rendered[1], vec![2 << 16, 4 << 16]);
+        assert_eq!(rendered[2], vec![0, 0]);
+
+        assert!(PcmSource::new(&[][..], PcmFormat::F32Le, 2).is_finished());
+    }
+
+    #[cfg(feature = "device")]
+    #[test]
+    fn test_record_pcm() {
+        use crate::backend::MockDevice;
+        use crate::builder::AudioInstanceBuilder;
+        use std::sync::{Arc, Mutex};
+
+        /// A writer that keeps what is written, shared with the test.
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        /// A pipe whose reader has gone away.
+        struct ClosedPipe;
+
+        impl Write for ClosedPipe {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 1).noise(1 << 20, 3))
+            .build()
+            .unwrap();
+        let buffer = SharedBuffer::default();
+        audio_instance
+            .record_pcm(buffer.clone(), Some(0.1), PcmFormat::S24Le)
+            .unwrap();
+        assert_eq!(buffer.0.lock().unwrap().len(), 4800 * 2 * 3);
+
+        // recording until the pipe closes
+        audio_instance
+            .record_pcm(ClosedPipe, None, PcmFormat::S16Le)
+            .unwrap();
+        assert!(audio_instance
+            .record_pcm(ClosedPipe, Some(0.1), PcmFormat::S16Le)
+            .is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::channel::InputChannel;
+use crate::sample_formats::Sample;
+
+/// A circular buffer of the most recent input, kept while nothing is being recorded.
+///
+/// The input callback pushes to the buffer while holding the record wait lock, so taking the
+/// buffer and starting a recording under the same lock loses no frames in between.
+#[derive(Default)]
+pub(crate) struct PreRecordBuffer {
+    /// Interleaved samples of every input channel
+    samples: Mutex<VecDeque<i32>>,
+    /// The number of samples to keep. 0 turns the buffer off
+    capacity: AtomicUsize,
+}
+
+impl PreRecordBuffer {
+    /// Add the samples of a callback, dropping the oldest. Called from the input callback.
+    pub fn push<T: Sample>(&self, data: &[T]) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return;
+        }
+        let mut samples = self.samples.lock().unwrap();
+        samples.extend(data.iter().map(|&sample| sample.to_i32()));
+        let excess = samples.len().saturating_sub(capacity);
+        samples.drain(..excess);
+    }
+
+    fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+        let mut samples = self.samples.lock().unwrap();
+        let excess = samples.len().saturating_sub(capacity);
+        samples.drain(..excess);
+        let length = samples.len();
+        samples.reserve(capacity - length);
+    }
+
+    fn take(&self) -> Vec<i32> {
+        // drain rather than take, so the buffer keeps its allocation
+        self.samples.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl AudioInstance {
+    /// Keep the last `seconds` of input while nothing is being recorded, so `record_with_pre_roll`
+    /// can return audio from before it was called. None turns the buffer off and frees it.
+    ///
+    /// Every input channel is kept, so the buffer takes `seconds` x sample rate x input channels
+    /// samples of memory.
+    pub fn set_pre_record(&self, seconds: Option<f64>) {
+        let capacity = seconds.map_or(0, |seconds| {
+            (seconds.max(0.0) * self.sample_rate as f64) as usize
+                * self.number_of_input_channels as usize
+        });
+        self.pre_record.set_capacity(capacity);
+        if capacity == 0 {
+            *self.pre_record.samples.lock().unwrap() = VecDeque::new();
+        }
+    }
+
+    /// Record, starting with the audio kept by the pre-record buffer from before the call.
+    ///
+    /// Use this to capture impulsive events that happen before the software trigger. The
+    /// pre-roll is as long as the buffer set with `set_pre_record`, or shorter if the stream has
+    /// not been running that long or another recording ended recently.
+    ///
+    /// # Arguments
+    /// duration: f64 - the duration to record after the call, in seconds
+    ///
+    /// # Returns
+    /// A tuple of the recording, with the pre-roll first, and the number of pre-roll samples in each channel
+    ///
+    /// # Errors
+    /// Returns an error if the pre-record buffer is off or decimation is set
+    pub fn record_with_pre_roll(
+        &self,
+        duration: f64,
+    ) -> Result<(Vec<Vec<i32>>, usize), anyhow::Error> {
+        if self.pre_record.capacity.load(Ordering::Relaxed) == 0 {
+            return Err(anyhow::Error::msg(
+                "The pre-record buffer is off. Turn it on with set_pre_record",
+            ));
+        }
+        if self.recorded_sample_rate() != self.sample_rate {
+            return Err(anyhow::Error::msg(
+                "The pre-record buffer can't be used while decimating",
+            ));
+        }
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        self.start_capture(self.input_capture(duration));
+
+        let (lock, _) = &*self.record_wait_pair;
+        let mut recording = lock.lock().unwrap();
+        // take the pre-roll and start recording together so no frames are missed
+        let pre_roll = self.pre_record.take();
+        *recording = true;
+        self.wait_for_recording(recording, |recording| !recording)?;
+
+        let capture = self.take_capture();
+        let pre_roll = select_channels(
+            &pre_roll,
+            self.number_of_input_channels as usize,
+            &capture.input_channels(),
+        );
+        let mut channel_recordings = capture.into_channels();
+        let pre_roll_length = pre_roll.first().map_or(0, |channel| channel.len());
+        for (channel, mut pre_roll) in channel_recordings.iter_mut().zip(pre_roll) {
+            pre_roll.append(channel);
+            *channel = pre_roll;
+        }
+
+        Ok((channel_recordings, pre_roll_length))
+    }
+}
+
+/// Split interleaved samples of every channel into the recorded channels.
+pub(crate) fn select_channels(
+    interleaved: &[i32],
+    channels: usize,
+    recorded: &[InputChannel],
+) -> Vec<Vec<i32>> {
+    recorded
+        .iter()
+        .filter_map(|channel| channel.index().ok())
+        .map(|index| {
+            interleaved
+                .chunks_exact(channels)
+                .map(|frame| frame[index])
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pre_record_keeps_latest() {
+        let buffer = PreRecordBuffer::default();
+        buffer.push(&[1i32, 2]);
+
+        buffer.set_capacity(4);
+        buffer.push(&[1i32, 2, 3, 4]);
+        buffer.push(&[5i32, 6]);
+        assert_eq!(buffer.take(), vec![3, 4, 5, 6]);
+        assert!(buffer.take().is_empty());
+    }
+
+    #[test]
+    fn test_select_channels() {
+        let selected = select_channels(&[1, 2, 3, 4, 5, 6], 3, &[InputChannel(3), InputChannel(1)]);
+        assert_eq!(selected, vec![vec![3, 6], vec![1, 4]]);
+    }
+}
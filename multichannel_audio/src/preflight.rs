@@ -0,0 +1,474 @@
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use crate::audio_class::AudioInstance;
+use crate::calibration::Calibration;
+use crate::channel::{InputChannel, OutputChannel};
+use crate::device_monitor::disconnected_error;
+use crate::interlock;
+
+use cpal::traits::DeviceTrait;
+
+/// The bytes per sample of a saved session, which is 32-bit WAV. See `Session::save`.
+const SESSION_BYTES_PER_SAMPLE: u64 = 4;
+
+/// The operation to validate before any audio is emitted.
+#[derive(Debug)]
+pub enum PreflightCall<'a> {
+    Play {
+        output_data: &'a [Vec<i32>],
+    },
+    Record {
+        duration: f64,
+    },
+    PlayRecord {
+        output_data: &'a [Vec<i32>],
+    },
+    AlignedPlayRecord {
+        training_signal: &'a [i32],
+        training_channel: OutputChannel,
+        timing_channel_out: OutputChannel,
+        timing_channel_in: InputChannel,
+        number_of_output_channels: usize,
+    },
+}
+
+/// A problem found by a preflight check.
+///
+/// Errors mean the operation will fail. Warnings mean the operation will run but the result is
+/// likely not what was intended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreflightIssue {
+    Error(String),
+    Warning(String),
+}
+
+impl PreflightIssue {
+    pub fn is_error(&self) -> bool {
+        matches!(self, PreflightIssue::Error(_))
+    }
+}
+
+/// What an operation needs besides the device, for `preflight_with`. Checks are skipped for
+/// anything that isn't set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreflightNeeds<'a> {
+    /// The calibration the recording will be converted with
+    pub calibration: Option<&'a Calibration>,
+    /// The input channels that must be calibrated, or empty for every recorded channel
+    pub calibrated_channels: &'a [InputChannel],
+    /// The directory the session will be saved to, which must have room for the recording and
+    /// the stimulus
+    pub session_dir: Option<&'a Path>,
+}
+
+impl AudioInstance {
+    /// Run all cheap validations for an operation without playing or recording anything.
+    ///
+    /// Checks that the device is present, the sample rate is supported, and that the channels and
+    /// stimulus are valid for the call and allowed by the safety interlock. This is intended for
+    /// GUIs that need to disable a "Start" button and show the reasons. Use `preflight_with` to
+    /// also check the calibration and the disk space for the session.
+    ///
+    /// # Returns
+    /// A list of errors and warnings. The list is empty if everything passed.
+    pub fn preflight(&self, call: &PreflightCall) -> Vec<PreflightIssue> {
+        self.preflight_with(call, &PreflightNeeds::default())
+    }
+
+    /// Run the validations of `preflight`, and check the calibration and the free disk space
+    /// the operation needs.
+    ///
+    /// # Returns
+    /// A list of errors and warnings. The list is empty if everything passed.
+    pub fn preflight_with(
+        &self,
+        call: &PreflightCall,
+        needs: &PreflightNeeds,
+    ) -> Vec<PreflightIssue> {
+        let mut issues = self.check_device();
+
+        let (plays, records) = match call {
+            PreflightCall::Play { .. } => (true, false),
+            PreflightCall::Record { .. } => (false, true),
+            _ => (true, true),
+        };
+        if plays && self.number_of_output_channels == 0 {
+            issues.push(PreflightIssue::Error(
+                "The instance has no outputs to play on".to_string(),
+            ));
+        }
+        if records && self.number_of_input_channels == 0 {
+            issues.push(PreflightIssue::Error(
+                "The instance has no inputs to record from".to_string(),
+            ));
+        }
+
+        match *call {
+            PreflightCall::Play { output_data } | PreflightCall::PlayRecord { output_data } => {
+                issues.extend(check_output_data(
+                    output_data,
+                    self.number_of_output_channels as usize,
+                ));
+                if let Err(err) = self.check_interlock(output_data) {
+                    issues.push(PreflightIssue::Error(err.to_string()));
+                }
+            }
+            PreflightCall::Record { duration } => {
+                if duration <= 0.0 || (self.sample_rate as f64 * duration) < 1.0 {
+                    issues.push(PreflightIssue::Error(format!(
+                        "Recording duration of {} seconds is too short",
+                        duration
+                    )));
+                }
+            }
+            PreflightCall::AlignedPlayRecord {
+                training_signal,
+                training_channel,
+                timing_channel_out,
+                timing_channel_in,
+                number_of_output_channels,
+            } => {
+                for channel in [training_channel, timing_channel_out] {
+                    if channel.0 == 0 || channel.0 > number_of_output_channels {
+                        issues.push(channel_range_error(channel, number_of_output_channels));
+                    }
+                }
+                let input_channels = self.recorded_channel_count();
+                if timing_channel_in.0 == 0 || timing_channel_in.0 > input_channels {
+                    issues.push(channel_range_error(timing_channel_in, input_channels));
+                }
+
+                if number_of_output_channels != self.number_of_output_channels as usize {
+                    issues.push(PreflightIssue::Error(format!(
+                        "Number of channels does not match\n\tExpected: {}, Actual: {}",
+                        self.number_of_output_channels, number_of_output_channels
+                    )));
+                }
+
+                if training_channel == timing_channel_out {
+                    issues.push(PreflightIssue::Warning(
+                        "The training signal and timing chirp are on the same output channel"
+                            .to_string(),
+                    ));
+                }
+
+                if training_signal.len() < self.sample_rate as usize {
+                    issues.push(PreflightIssue::Error(
+                        "Training signal must be at least 1 second long".to_string(),
+                    ));
+                }
+                issues.extend(check_clipping(training_signal, training_channel));
+
+                // the chirp is played too
+                match self.alignment_config().chirp(self.sample_rate) {
+                    Ok(chirp) => {
+                        let peak = interlock::peak(&[chirp])
+                            .max(interlock::peak(&[training_signal.to_vec()]));
+                        if let Err(err) = self.check_interlock_level(peak) {
+                            issues.push(PreflightIssue::Error(err.to_string()));
+                        }
+                    }
+                    Err(err) => issues.push(PreflightIssue::Error(err.to_string())),
+                }
+            }
+        }
+
+        if let Some(calibration) = needs.calibration {
+            let channels: Vec<InputChannel> = match needs.calibrated_channels {
+                [] => (0..self.recorded_channel_count())
+                    .map(InputChannel::from_index)
+                    .collect(),
+                channels => channels.to_vec(),
+            };
+            issues.extend(check_calibration(calibration, &channels));
+        }
+        if let Some(dir) = needs.session_dir {
+            issues.extend(check_disk_space(dir, self.session_bytes(call)));
+        }
+
+        issues
+    }
+
+    /// Check the device is still connected and supports the sample rate of this instance in the
+    /// directions it has streams for.
+    fn check_device(&self) -> Vec<PreflightIssue> {
+        if !self.healthy.load(Ordering::Acquire) {
+            return vec![PreflightIssue::Error(disconnected_error().to_string())];
+        }
+        // a mock device is always present and runs at any rate
+        if self.is_mock() {
+            return vec![];
+        }
+        let (device, input_device) = match self.find_devices() {
+            Ok(devices) => devices,
+            Err(err) => return vec![PreflightIssue::Error(err.to_string())],
+        };
+
+        let fs = cpal::SampleRate(self.sample_rate);
+        let mut issues = Vec::new();
+        if self.number_of_output_channels > 0
+            && !device.supported_output_configs().is_ok_and(|mut configs| {
+                configs.any(|c| c.min_sample_rate() <= fs && fs <= c.max_sample_rate())
+            })
+        {
+            issues.push(PreflightIssue::Error(format!(
+                "Sample rate of {} Hz is not supported by the output device",
+                self.sample_rate
+            )));
+        }
+        if self.number_of_input_channels > 0
+            && !input_device
+                .supported_input_configs()
+                .is_ok_and(|mut configs| {
+                    configs.any(|c| c.min_sample_rate() <= fs && fs <= c.max_sample_rate())
+                })
+        {
+            issues.push(PreflightIssue::Error(format!(
+                "Sample rate of {} Hz is not supported by the input device",
+                self.sample_rate
+            )));
+        }
+        issues
+    }
+
+    /// The bytes a session needs to save the stimulus and recording of a call.
+    fn session_bytes(&self, call: &PreflightCall) -> u64 {
+        let (played_frames, recorded_frames) = match *call {
+            PreflightCall::Play { output_data } => (output_data.first().map_or(0, Vec::len), 0),
+            PreflightCall::Record { duration } => {
+                (0, (duration.max(0.0) * self.sample_rate as f64) as usize)
+            }
+            PreflightCall::PlayRecord { output_data } => {
+                let frames = output_data.first().map_or(0, Vec::len);
+                (frames, frames)
+            }
+            PreflightCall::AlignedPlayRecord {
+                training_signal, ..
+            } => (training_signal.len(), training_signal.len()),
+        };
+        let samples = played_frames as u64 * self.number_of_output_channels as u64
+            + recorded_frames as u64 * self.recorded_channel_count() as u64;
+        samples.saturating_mul(SESSION_BYTES_PER_SAMPLE)
+    }
+}
+
+/// Check every channel has a sensitivity in a calibration.
+fn check_calibration(calibration: &Calibration, channels: &[InputChannel]) -> Vec<PreflightIssue> {
+    channels
+        .iter()
+        .filter(|&&channel| calibration.sensitivity(channel).is_none())
+        .map(|channel| PreflightIssue::Error(format!("{} has not been calibrated", channel)))
+        .collect()
+}
+
+/// Check the disk a directory is on has room for a number of bytes. The directory doesn't need to
+/// exist yet.
+fn check_disk_space(dir: &Path, bytes: u64) -> Option<PreflightIssue> {
+    // the space is that of the nearest directory that exists
+    let existing = dir
+        .ancestors()
+        .find(|path| path.exists())
+        .unwrap_or(Path::new("."));
+    match fs4::available_space(existing) {
+        Ok(available) if available < bytes => Some(PreflightIssue::Error(format!(
+            "The session needs {} MB but only {} MB is free in {}",
+            bytes.div_ceil(1_000_000),
+            available / 1_000_000,
+            dir.display()
+        ))),
+        Ok(_) => None,
+        Err(err) => Some(PreflightIssue::Warning(format!(
+            "Could not check the free space in {}: {}",
+            dir.display(),
+            err
+        ))),
+    }
+}
+
+/// Check multichannel output data against the number of output channels of the device.
+fn check_output_data(output_data: &[Vec<i32>], output_channels: usize) -> Vec<PreflightIssue> {
+    let mut issues = Vec::new();
+
+    if output_data.len() != output_channels {
+        issues.push(PreflightIssue::Error(format!(
+            "Number of channels does not match\n\tExpected: {}, Actual: {}",
+            output_channels,
+            output_data.len()
+        )));
+    }
+
+    match output_data.first() {
+        None => return issues,
+        Some(channel) if channel.is_empty() => {
+            issues.push(PreflightIssue::Error("Output data is empty".to_string()));
+        }
+        Some(channel) => {
+            if output_data.iter().any(|c| c.len() != channel.len()) {
+                issues.push(PreflightIssue::Error(
+                    "All channels must be the same length".to_string(),
+                ));
+            }
+        }
+    }
+
+    for (channel_index, channel) in output_data.iter().enumerate() {
+        issues.extend(check_clipping(
+            channel,
+            OutputChannel::from_index(channel_index),
+        ));
+    }
+
+    issues
+}
+
+fn channel_range_error(channel: impl std::fmt::Display, channel_count: usize) -> PreflightIssue {
+    PreflightIssue::Error(format!(
+        "Channel {} is out of range. Channels are numbered from 1 to {}.",
+        channel, channel_count
+    ))
+}
+
+/// Warn if a signal reaches full scale, since it has most likely been clipped.
+fn check_clipping(signal: &[i32], channel: OutputChannel) -> Option<PreflightIssue> {
+    if signal.iter().any(|&x| x == i32::MAX || x <= -i32::MAX) {
+        return Some(PreflightIssue::Warning(format!(
+            "Signal on {} reaches full scale and may be clipped",
+            channel
+        )));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+
+    #[test]
+    fn test_preflight_mock() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .build()
+            .unwrap();
+        // about -67 dBFS
+        let output_data = vec![vec![1_000_000; 4800]; 2];
+        let call = PreflightCall::PlayRecord {
+            output_data: &output_data,
+        };
+        assert_eq!(audio_instance.preflight(&call), vec![]);
+
+        // the stimulus is above the interlock level
+        audio_instance.enable_interlock(-80.0);
+        let issues = audio_instance.preflight(&call);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+        audio_instance.disable_interlock();
+
+        // input 2 has no sensitivity
+        let mut calibration = Calibration::new();
+        calibration.set_sensitivity(InputChannel(1), 1.0).unwrap();
+        let needs = PreflightNeeds {
+            calibration: Some(&calibration),
+            ..Default::default()
+        };
+        assert_eq!(
+            audio_instance.preflight_with(&call, &needs),
+            vec![PreflightIssue::Error(
+                "input 2 has not been calibrated".to_string()
+            )]
+        );
+        let needs = PreflightNeeds {
+            calibrated_channels: &[InputChannel(1)],
+            ..needs
+        };
+        assert_eq!(audio_instance.preflight_with(&call, &needs), vec![]);
+
+        // no disk has room for a recording this long
+        let dir = std::env::temp_dir()
+            .join("multichannel_audio_preflight")
+            .join("session");
+        let needs = PreflightNeeds {
+            session_dir: Some(&dir),
+            ..Default::default()
+        };
+        assert_eq!(audio_instance.preflight_with(&call, &needs), vec![]);
+        let issues =
+            audio_instance.preflight_with(&PreflightCall::Record { duration: 1e12 }, &needs);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+
+        audio_instance.healthy.store(false, Ordering::Release);
+        assert_eq!(
+            audio_instance.preflight(&call),
+            vec![PreflightIssue::Error(disconnected_error().to_string())]
+        );
+    }
+
+    #[test]
+    fn test_preflight_one_direction() {
+        let output_only = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .output_only()
+            .build()
+            .unwrap();
+        let output_data = vec![vec![1000; 4800]; 2];
+        let play = PreflightCall::Play {
+            output_data: &output_data,
+        };
+        assert_eq!(output_only.preflight(&play), vec![]);
+        let issues = output_only.preflight(&PreflightCall::Record { duration: 1.0 });
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+
+        let input_only = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .input_only()
+            .build()
+            .unwrap();
+        assert_eq!(
+            input_only.preflight(&PreflightCall::Record { duration: 1.0 }),
+            vec![]
+        );
+        assert!(input_only
+            .preflight(&play)
+            .iter()
+            .any(PreflightIssue::is_error));
+    }
+
+    #[test]
+    fn test_check_output_data() {
+        let output_data = vec![vec![1, 2, 3], vec![0, 0, 0]];
+        let issues = check_output_data(&output_data, 2);
+
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn test_check_output_data_wrong_channels() {
+        let output_data = vec![vec![1, 2, 3]];
+        let issues = check_output_data(&output_data, 2);
+
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+    }
+
+    #[test]
+    fn test_check_output_data_uneven_channels() {
+        let output_data = vec![vec![1, 2, 3], vec![0, 0]];
+        let issues = check_output_data(&output_data, 2);
+
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+    }
+
+    #[test]
+    fn test_check_output_data_clipping() {
+        let output_data = vec![vec![1, i32::MAX, 3], vec![0, 0, 0]];
+        let issues = check_output_data(&output_data, 2);
+
+        assert_eq!(issues.len(), 1);
+        assert!(!issues[0].is_error());
+    }
+}
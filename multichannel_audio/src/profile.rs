@@ -0,0 +1,246 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::Calibration;
+use crate::channel::{InputChannel, OutputChannel};
+use crate::config::AudioConfig;
+#[cfg(feature = "device")]
+use crate::{audio_class::AudioInstance, builder::AudioInstanceBuilder};
+
+/// An output channel wired straight back to an input channel, e.g. for timing alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopbackWiring {
+    pub output: OutputChannel,
+    pub input: InputChannel,
+}
+
+/// The measured sensitivity of an input channel, as stored in a profile.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChannelSensitivity {
+    pub channel: InputChannel,
+    /// The pressure in pascals that gives a full-scale sample
+    pub pascals_per_full_scale: f64,
+}
+
+/// Everything about one test bench that measurement code would otherwise hard-code: the device,
+/// the default sample rate, the channel names, how the loopbacks are wired and the calibration of
+/// the microphones. Each bench of a lab keeps a profile in a TOML file, and the same code runs on
+/// any bench by loading its profile.
+///
+/// The device settings are the same as an `AudioConfig`, at the top level of the file.
+///
+/// # Example
+/// ```toml
+/// name = "bench 2"
+/// device = "Focusrite USB ASIO"
+/// sample_rate = 96000
+///
+/// [input_channels]
+/// reference_mic = 1
+///
+/// [[loopbacks]]
+/// output = 8
+/// input = 8
+///
+/// [[calibration]]
+/// channel = 1
+/// pascals_per_full_scale = 6.32
+/// ```
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HardwareProfile {
+    /// The name of the bench, e.g. to show which profile is loaded
+    pub name: String,
+    #[serde(flatten)]
+    pub config: AudioConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub loopbacks: Vec<LoopbackWiring>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calibration: Vec<ChannelSensitivity>,
+}
+
+impl HardwareProfile {
+    pub fn new(name: &str) -> Self {
+        HardwareProfile {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Load a profile saved with `save`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read, is not a valid profile, or has an invalid
+    /// calibration
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        let profile: HardwareProfile = toml::from_str(&contents)
+            .map_err(|err| anyhow::anyhow!("{} is not a valid profile: {}", path.display(), err))?;
+        profile.calibration()?;
+        Ok(profile)
+    }
+
+    /// Save the profile to a TOML file.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The calibration of the input channels of the bench.
+    ///
+    /// # Errors
+    /// Returns an error if a sensitivity is not a positive number
+    pub fn calibration(&self) -> Result<Calibration, anyhow::Error> {
+        let mut calibration = Calibration::new();
+        for sensitivity in &self.calibration {
+            calibration.set_sensitivity(sensitivity.channel, sensitivity.pascals_per_full_scale)?;
+        }
+        Ok(calibration)
+    }
+
+    /// Replace the calibration of the profile, e.g. after recalibrating the microphones.
+    pub fn set_calibration(&mut self, calibration: &Calibration) {
+        self.calibration = calibration
+            .channels()
+            .filter_map(|channel| {
+                Some(ChannelSensitivity {
+                    channel,
+                    pascals_per_full_scale: calibration.sensitivity(channel)?,
+                })
+            })
+            .collect();
+    }
+
+    /// The input channel an output channel is looped back to, if it is.
+    pub fn loopback_input(&self, output: OutputChannel) -> Option<InputChannel> {
+        self.loopbacks
+            .iter()
+            .find(|loopback| loopback.output == output)
+            .map(|loopback| loopback.input)
+    }
+}
+
+#[cfg(feature = "device")]
+impl HardwareProfile {
+    /// Start configuring an instance for the bench, e.g. to change the buffer size before
+    /// building it. See `AudioConfig::builder`.
+    ///
+    /// # Errors
+    /// Returns an error if the host is not available on this platform
+    pub fn builder(&self) -> Result<AudioInstanceBuilder, anyhow::Error> {
+        self.config.builder()
+    }
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Open the device of a test bench at its default sample rate, with its channel names.
+    ///
+    /// The loopbacks and calibration of the profile are used by passing them to the measurement
+    /// functions, e.g. `verify_loopback` and `record_calibrated`.
+    ///
+    /// # Errors
+    /// Returns an error if the host is not available, the calibration of the profile is invalid,
+    /// or the device can't be opened
+    pub fn from_profile(profile: &HardwareProfile) -> Result<Self, anyhow::Error> {
+        profile.calibration()?;
+        profile.builder()?.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!(
+            "multichannel_audio_profile_{}.toml",
+            std::process::id()
+        ));
+        let mut profile = HardwareProfile::new("bench 2");
+        profile.config.device = Some("Focusrite USB ASIO".to_string());
+        profile.config.sample_rate = Some(96000);
+        profile
+            .config
+            .input_channels
+            .insert("reference_mic".to_string(), 1);
+        profile.loopbacks.push(LoopbackWiring {
+            output: OutputChannel(8),
+            input: InputChannel(8),
+        });
+        let mut calibration = Calibration::new();
+        calibration.set_sensitivity(InputChannel(1), 6.32).unwrap();
+        profile.set_calibration(&calibration);
+        profile.save(&path).unwrap();
+
+        let loaded = HardwareProfile::load(&path).unwrap();
+        assert_eq!(loaded, profile);
+        assert_eq!(loaded.calibration().unwrap(), calibration);
+        assert_eq!(
+            loaded.loopback_input(OutputChannel(8)),
+            Some(InputChannel(8))
+        );
+        assert_eq!(loaded.loopback_input(OutputChannel(1)), None);
+        assert_eq!(
+            loaded
+                .config
+                .channel_labels()
+                .input(&"reference_mic")
+                .unwrap(),
+            InputChannel(1)
+        );
+
+        std::fs::write(
+            &path,
+            "[[calibration]]\nchannel = 1\npascals_per_full_scale = -1.0\n",
+        )
+        .unwrap();
+        assert!(HardwareProfile::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let profile: HardwareProfile = toml::from_str(
+            "name = \"bench 1\"\nsample_rate = 44100\n\n[[loopbacks]]\noutput = 2\ninput = 4\n",
+        )
+        .unwrap();
+        assert_eq!(profile.name, "bench 1");
+        assert_eq!(profile.config.sample_rate, Some(44100));
+        assert_eq!(profile.config.device, None);
+        assert_eq!(
+            profile.loopbacks,
+            vec![LoopbackWiring {
+                output: OutputChannel(2),
+                input: InputChannel(4),
+            }]
+        );
+        assert!(profile.calibration.is_empty());
+    }
+
+    #[cfg(feature = "device")]
+    #[test]
+    fn test_profile_builder() {
+        use crate::backend::MockDevice;
+
+        let mut profile = HardwareProfile::new("bench 3");
+        profile.config.sample_rate = Some(44100);
+        profile
+            .config
+            .output_channels
+            .insert("speaker".to_string(), 2);
+        let audio_instance = profile
+            .builder()
+            .unwrap()
+            .mock(MockDevice::new(2, 2))
+            .build()
+            .unwrap();
+        assert_eq!(audio_instance.sample_rate(), 44100);
+        assert_eq!(
+            audio_instance.output_channel("speaker").unwrap(),
+            OutputChannel(2)
+        );
+    }
+}
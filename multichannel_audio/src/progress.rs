@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+
+use crate::audio_class::AudioInstance;
+use crate::callback_load::StreamDirection;
+
+/// The shortest time between two updates sent to the receiver from `progress_updates`, in seconds.
+const UPDATE_INTERVAL: f64 = 0.1;
+
+/// How far through playing or recording a buffer the streams are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub direction: StreamDirection,
+    /// The number of frames played or recorded so far
+    pub frames: usize,
+    /// The number of frames in the buffer being played or recorded
+    pub total_frames: usize,
+}
+
+impl Progress {
+    /// The fraction of the buffer that has been played or recorded, from 0.0 to 1.0.
+    pub fn fraction(&self) -> f64 {
+        if self.total_frames == 0 {
+            return 0.0;
+        }
+        self.frames as f64 / self.total_frames as f64
+    }
+
+    /// Whether the whole buffer has been played or recorded.
+    pub fn is_complete(&self) -> bool {
+        self.total_frames > 0 && self.frames >= self.total_frames
+    }
+}
+
+/// The progress of one stream direction, written by its callback.
+#[derive(Default)]
+struct DirectionProgress {
+    frames: AtomicUsize,
+    total_frames: AtomicUsize,
+    /// The frame count of the last update sent to the receiver
+    last_update: AtomicUsize,
+}
+
+/// Tracks how far the callbacks are through the current playback and recording.
+///
+/// The callbacks only update atomics and use `try_lock` for the update receiver, so reporting
+/// progress never blocks the audio thread.
+#[derive(Default)]
+pub(crate) struct ProgressMonitor {
+    input: DirectionProgress,
+    output: DirectionProgress,
+    updates: Mutex<Option<mpsc::Sender<Progress>>>,
+}
+
+impl ProgressMonitor {
+    /// Report the progress of a stream. Called from the audio callbacks.
+    ///
+    /// # Arguments
+    /// direction: StreamDirection - the stream the progress is for
+    /// frames: usize - the number of frames played or recorded so far
+    /// total_frames: usize - the number of frames in the buffer
+    /// sample_rate: u32 - the sample rate of the stream, used to limit how often updates are sent
+    pub fn update(
+        &self,
+        direction: StreamDirection,
+        frames: usize,
+        total_frames: usize,
+        sample_rate: u32,
+    ) {
+        let progress = self.direction(direction);
+        progress.frames.store(frames, Ordering::Relaxed);
+        let previous_total = progress.total_frames.swap(total_frames, Ordering::Relaxed);
+
+        let last_update = progress.last_update.load(Ordering::Relaxed);
+        let interval = (sample_rate as f64 * UPDATE_INTERVAL) as usize;
+        let send = total_frames != previous_total
+            || frames < last_update
+            || frames - last_update >= interval
+            || (frames >= total_frames && last_update < total_frames);
+        if !send {
+            return;
+        }
+        progress.last_update.store(frames, Ordering::Relaxed);
+
+        if let Ok(updates) = self.updates.try_lock() {
+            if let Some(ref sender) = *updates {
+                let _ = sender.send(Progress {
+                    direction,
+                    frames,
+                    total_frames,
+                });
+            }
+        }
+    }
+
+    fn progress(&self, direction: StreamDirection) -> Progress {
+        let progress = self.direction(direction);
+        Progress {
+            direction,
+            frames: progress.frames.load(Ordering::Relaxed),
+            total_frames: progress.total_frames.load(Ordering::Relaxed),
+        }
+    }
+
+    fn direction(&self, direction: StreamDirection) -> &DirectionProgress {
+        match direction {
+            StreamDirection::Input => &self.input,
+            StreamDirection::Output => &self.output,
+        }
+    }
+}
+
+impl AudioInstance {
+    /// How far through the current or most recent playback or recording a stream is.
+    ///
+    /// Playback progress is for the buffer currently being played, so buffers added with
+    /// `enqueue` each count from zero. Looped playback starts again from zero at each repeat.
+    pub fn progress(&self, direction: StreamDirection) -> Progress {
+        self.progress_monitor.progress(direction)
+    }
+
+    /// Receive a `Progress` update from the callbacks about ten times a second while playing or
+    /// recording, and when a buffer is complete. Use this to show progress bars for long
+    /// measurements without polling.
+    ///
+    /// Only the most recently returned receiver gets updates.
+    pub fn progress_updates(&self) -> mpsc::Receiver<Progress> {
+        let (sender, receiver) = mpsc::channel();
+        *self.progress_monitor.updates.lock().unwrap() = Some(sender);
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_updates() {
+        let monitor = ProgressMonitor::default();
+        let (sender, receiver) = mpsc::channel();
+        *monitor.updates.lock().unwrap() = Some(sender);
+
+        // 480 frames at 48 kHz is under the update interval
+        monitor.update(StreamDirection::Output, 0, 9600, 48000);
+        monitor.update(StreamDirection::Output, 480, 9600, 48000);
+        monitor.update(StreamDirection::Output, 4800, 9600, 48000);
+        monitor.update(StreamDirection::Output, 9600, 9600, 48000);
+
+        let updates: Vec<usize> = receiver
+            .try_iter()
+            .map(|progress| progress.frames)
+            .collect();
+        assert_eq!(updates, vec![0, 4800, 9600]);
+
+        let progress = monitor.progress(StreamDirection::Output);
+        assert!(progress.is_complete());
+        assert_eq!(progress.fraction(), 1.0);
+        assert_eq!(monitor.progress(StreamDirection::Input).fraction(), 0.0);
+    }
+}
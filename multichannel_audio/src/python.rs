@@ -0,0 +1,180 @@
+use numpy::ndarray::{Array2, ArrayView2};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+use crate::audio_class::AudioInstance;
+use crate::config::AudioConfig;
+use crate::methods;
+
+// Channel data is a 2-dimensional int32 array of shape (channels, frames) with full-scale samples,
+// the same layout as the vector per channel used by the rest of the crate. Playing and recording
+// release the GIL, so other Python threads keep running while they block.
+
+/// Copy each row of an array into a channel.
+fn channels_from_array(array: ArrayView2<i32>) -> Vec<Vec<i32>> {
+    array.outer_iter().map(|channel| channel.to_vec()).collect()
+}
+
+/// Copy channels into an array of shape (channels, frames).
+///
+/// # Errors
+/// Returns an error if the channels are not all the same length
+fn array_from_channels(channels: Vec<Vec<i32>>) -> Result<Array2<i32>, anyhow::Error> {
+    let frames = channels.first().map_or(0, |channel| channel.len());
+    let shape = (channels.len(), frames);
+    Array2::from_shape_vec(shape, channels.concat())
+        .map_err(|_| anyhow::anyhow!("The channels are not all the same length"))
+}
+
+/// An audio device opened for playing and recording, see `AudioInstance`.
+#[pyclass(name = "AudioInstance", module = "multichannel_audio")]
+pub struct PyAudioInstance {
+    inner: AudioInstance,
+}
+
+#[pymethods]
+impl PyAudioInstance {
+    /// Open a device. The defaults are the default host and device at 48 kHz.
+    ///
+    /// # Arguments
+    /// sample_rate: u32 - the sample rate of the stream
+    /// device: Option<String> - the output device, or None for the default device
+    /// input_device: Option<String> - the input device, if it is different to the output device
+    /// host: Option<String> - the name of the host, e.g. "ASIO", or None for the default host
+    #[new]
+    #[pyo3(signature = (sample_rate = 48000, device = None, input_device = None, host = None))]
+    fn new(
+        sample_rate: u32,
+        device: Option<String>,
+        input_device: Option<String>,
+        host: Option<String>,
+    ) -> Result<Self, anyhow::Error> {
+        let config = AudioConfig {
+            host,
+            device,
+            input_device,
+            sample_rate: Some(sample_rate),
+            ..Default::default()
+        };
+        Ok(PyAudioInstance {
+            inner: config.builder()?.build()?,
+        })
+    }
+
+    #[getter]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[getter]
+    fn channels_in(&self) -> u16 {
+        self.inner.channels_in()
+    }
+
+    #[getter]
+    fn channels_out(&self) -> u16 {
+        self.inner.channels_out()
+    }
+
+    /// Play an array of shape (channels_out, frames) and block until it has finished.
+    fn play(&self, py: Python<'_>, data: PyReadonlyArray2<'_, i32>) -> Result<(), anyhow::Error> {
+        let channels = channels_from_array(data.as_array());
+        py.detach(|| self.inner.play(channels))
+    }
+
+    /// Record every input channel for a duration in seconds.
+    ///
+    /// # Returns
+    /// An array of shape (channels_in, frames)
+    fn record<'py>(
+        &self,
+        py: Python<'py>,
+        duration: f64,
+    ) -> Result<Bound<'py, PyArray2<i32>>, anyhow::Error> {
+        let recording = py.detach(|| self.inner.record(duration))?;
+        Ok(array_from_channels(recording)?.into_pyarray(py))
+    }
+
+    /// Play an array of shape (channels_out, frames) and record every input channel at the same
+    /// time.
+    ///
+    /// # Returns
+    /// An array of shape (channels_in, frames)
+    fn play_record<'py>(
+        &self,
+        py: Python<'py>,
+        data: PyReadonlyArray2<'_, i32>,
+    ) -> Result<Bound<'py, PyArray2<i32>>, anyhow::Error> {
+        let channels = channels_from_array(data.as_array());
+        let recording = py.detach(|| self.inner.play_record(channels))?;
+        Ok(array_from_channels(recording)?.into_pyarray(py))
+    }
+}
+
+/// Generate a sine wave signal.
+#[pyfunction]
+fn generate_sine_wave(
+    py: Python<'_>,
+    frequency: u32,
+    duration: f32,
+    fs: u32,
+) -> Bound<'_, PyArray1<i32>> {
+    methods::generate_sine_wave(frequency, duration, fs).into_pyarray(py)
+}
+
+/// Generate an exponential sine sweep.
+#[pyfunction]
+fn generate_exponential_sweep(
+    py: Python<'_>,
+    start_frequency: f64,
+    end_frequency: f64,
+    duration: f32,
+    fs: u32,
+) -> Bound<'_, PyArray1<i32>> {
+    methods::generate_exponential_sweep(start_frequency, end_frequency, duration, fs)
+        .into_pyarray(py)
+}
+
+/// Generate a white noise signal.
+#[pyfunction]
+fn generate_gaussian_white_noise(
+    py: Python<'_>,
+    duration_seconds: f32,
+    fs: u32,
+) -> Bound<'_, PyArray1<i32>> {
+    methods::generate_gaussian_white_noise(duration_seconds, fs, None).into_pyarray(py)
+}
+
+/// Generate one period of a maximum length sequence at full scale.
+#[pyfunction]
+fn generate_mls(py: Python<'_>, order: u32) -> Result<Bound<'_, PyArray1<i32>>, anyhow::Error> {
+    Ok(methods::generate_mls(order)?.into_pyarray(py))
+}
+
+/// The `multichannel_audio` Python module.
+#[pymodule]
+fn multichannel_audio(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyAudioInstance>()?;
+    module.add_function(wrap_pyfunction!(generate_sine_wave, module)?)?;
+    module.add_function(wrap_pyfunction!(generate_exponential_sweep, module)?)?;
+    module.add_function(wrap_pyfunction!(generate_gaussian_white_noise, module)?)?;
+    module.add_function(wrap_pyfunction!(generate_mls, module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_array_conversions() {
+        let channels = vec![vec![1, 2, 3], vec![4, 5, 6]];
+        let array = array_from_channels(channels.clone()).unwrap();
+        assert_eq!(array.shape(), &[2, 3]);
+        assert_eq!(array[[1, 0]], 4);
+        assert_eq!(channels_from_array(array.view()), channels);
+
+        assert_eq!(array_from_channels(Vec::new()).unwrap().shape(), &[0, 0]);
+        assert!(array_from_channels(vec![vec![1, 2], vec![3]]).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use crate::audio_class::{AudioInstance, StreamControllerType};
+
+impl AudioInstance {
+    /// Add multiple channels of audio data to the end of the output queue.
+    ///
+    /// Queued buffers are played back-to-back by the output callback without a gap between them.
+    /// Playback starts as soon as the first buffer is queued. This function returns immediately;
+    /// call `flush` to wait for the queue to finish playing.
+    ///
+    /// # Arguments
+    /// output_data: Vec<Vec<i32>> - the audio data to queue. The outer vector represents the channels and the inner vector represents the samples.
+    ///
+    /// # Errors
+    /// Returns an error if the number of channels does not match the device
+    /// Returns an error if the signal is blocked by the safety interlock
+    pub fn enqueue(&self, output_data: Vec<Vec<i32>>) -> Result<(), anyhow::Error> {
+        if self.number_of_output_channels != output_data.len() as u16 {
+            return Err(anyhow::Error::msg("Number of channels does not match"));
+        }
+        self.check_interlock(&output_data)?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        let flattened_output_data = self.flatten_output_data(output_data);
+        if flattened_output_data.is_empty() {
+            return Ok(());
+        }
+
+        // hold the queue lock while starting, so the callback can't finish in between
+        let mut queue = self.output_queue.lock().unwrap();
+        queue.push_back(self.output_signal(flattened_output_data)?);
+        self.play_gate.start();
+
+        Ok(())
+    }
+
+    /// Block until every queued buffer has finished playing.
+    ///
+    /// # Errors
+    /// Returns an error if the device is disconnected first
+    pub fn flush(&self) -> Result<(), anyhow::Error> {
+        self.play_gate.wait(&self.healthy)
+    }
+
+    /// The number of buffers waiting in the output queue, not including the one playing.
+    pub fn queued(&self) -> usize {
+        self.output_queue.lock().unwrap().len()
+    }
+
+    /// Remove all buffers from the output queue that have not started playing.
+    pub fn clear_queue(&self) {
+        self.output_queue.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::audio_class::AudioInstance;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+    use crate::frame_queue::FrameQueue;
+
+    fn mock_instance() -> AudioInstance {
+        AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 1))
+            .duplex(true)
+            .build()
+            .unwrap()
+    }
+
+    /// Everything the loopback input has heard since the queue started, up to the first silence
+    /// after `last`.
+    fn heard_until_silence_after(queue: &mut FrameQueue, last: i32) -> Vec<i32> {
+        let mut heard: Vec<i32> = Vec::new();
+        while let Some(block) = queue.recv_timeout(Duration::from_secs(5)) {
+            heard.extend(&block[0]);
+            if let Some(position) = heard.iter().position(|&sample| sample == last) {
+                if heard[position..].contains(&0) {
+                    break;
+                }
+            }
+        }
+        assert_eq!(queue.overflows(), 0);
+        heard
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let audio_instance = mock_instance();
+        assert!(audio_instance.enqueue(vec![vec![0; 10]; 2]).is_err());
+
+        // the frame queue hears every buffer, however long the enqueue calls take
+        let mut queue = audio_instance.start_frame_queue(256, 4096).unwrap();
+        audio_instance.enqueue(vec![vec![1000; 48000]]).unwrap();
+        audio_instance.enqueue(vec![vec![2000; 24000]]).unwrap();
+        audio_instance.flush().unwrap();
+        assert_eq!(audio_instance.queued(), 0);
+
+        let heard = heard_until_silence_after(&mut queue, 2000);
+        let mut values = heard.clone();
+        values.dedup();
+        assert_eq!(values.last(), Some(&0));
+        assert_eq!(
+            values.iter().skip_while(|&&x| x == 0).collect::<Vec<_>>(),
+            [&1000, &2000, &0]
+        );
+        // both buffers play in full, without a gap between them
+        assert_eq!(
+            heard.iter().filter(|&&sample| sample == 1000).count(),
+            48000
+        );
+        assert_eq!(
+            heard.iter().filter(|&&sample| sample == 2000).count(),
+            24000
+        );
+    }
+
+    #[test]
+    fn test_clear_queue() {
+        let audio_instance = mock_instance();
+        // nothing is queued, so there is nothing to wait for
+        audio_instance.flush().unwrap();
+
+        let mut queue = audio_instance.start_frame_queue(256, 4096).unwrap();
+        audio_instance.enqueue(vec![vec![1000; 4800]]).unwrap();
+        audio_instance.enqueue(vec![vec![2000; 4800]]).unwrap();
+        // whether or not the first buffer has started, the second one hasn't
+        audio_instance.clear_queue();
+        assert_eq!(audio_instance.queued(), 0);
+        audio_instance.flush().unwrap();
+
+        // the cleared buffer never plays
+        audio_instance.enqueue(vec![vec![3000; 480]]).unwrap();
+        audio_instance.flush().unwrap();
+        let heard = heard_until_silence_after(&mut queue, 3000);
+        assert!(!heard.contains(&2000));
+    }
+}
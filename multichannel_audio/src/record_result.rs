@@ -0,0 +1,120 @@
+use cpal::StreamInstant;
+
+use crate::audio_class::AudioInstance;
+use crate::timestamp_map::TimestampMap;
+
+/// A recording and the stream time its first sample was captured.
+#[derive(Debug, Clone)]
+pub struct RecordResult {
+    /// A vector of channels where each channel is a vector of samples
+    pub data: Vec<Vec<i32>>,
+    /// The time the first sample was captured, on the clock of the input stream
+    pub capture_start: StreamInstant,
+    /// The capture time of every buffer of the recording
+    pub timestamps: TimestampMap,
+    /// For `play_record_with_timestamp`, the index of the sample in the recording that was
+    /// captured when the first output sample was played, measured from the callback timestamps.
+    /// Negative if playback started before the recording. None for recordings without playback
+    pub playback_offset: Option<i64>,
+}
+
+impl RecordResult {
+    /// The recording shifted so that it starts when the playback started, to correct the skew
+    /// between playback and recording without a loopback channel.
+    ///
+    /// The start is trimmed, or padded with zeros if the playback started before the recording.
+    /// The accuracy depends on the timestamps reported by the driver, and doesn't include the
+    /// latency of the converters.
+    ///
+    /// # Returns
+    /// The shifted recording, or the recording as it is if there was no playback
+    pub fn aligned_to_playback(&self) -> Vec<Vec<i32>> {
+        shift(&self.data, self.playback_offset.unwrap_or(0))
+    }
+}
+
+/// Drop the first `offset` samples of each channel, or add zeros to the start if it is negative.
+fn shift(data: &[Vec<i32>], offset: i64) -> Vec<Vec<i32>> {
+    data.iter()
+        .map(|channel| {
+            if offset >= 0 {
+                channel.iter().skip(offset as usize).copied().collect()
+            } else {
+                std::iter::repeat(0)
+                    .take(offset.unsigned_abs() as usize)
+                    .chain(channel.iter().copied())
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+impl AudioInstance {
+    /// Record multiple channels of audio data along with the time the recording started.
+    ///
+    /// The timestamp comes from the driver, so it can be correlated with playback timestamps and
+    /// external clocks. See `record` for details.
+    ///
+    /// # Arguments
+    /// duration: f64 - the duration of the recording in seconds
+    pub fn record_with_timestamp(&self, duration: f64) -> Result<RecordResult, anyhow::Error> {
+        let data = self.record(duration)?;
+        self.record_result(data)
+    }
+
+    /// Play and record multiple channels of audio data along with the time the recording started
+    /// and the offset between the start of playback and the start of the recording.
+    ///
+    /// See `play_record` for details.
+    pub fn play_record_with_timestamp(
+        &self,
+        output_data: Vec<Vec<i32>>,
+    ) -> Result<RecordResult, anyhow::Error> {
+        self.schedule.clear_started();
+        let data = self.play_record(output_data)?;
+        let mut result = self.record_result(data)?;
+
+        result.playback_offset = self.schedule.started().map(|started| {
+            let capture_start = result.timestamps.buffers()[0].host_time;
+            let seconds = match started.checked_duration_since(capture_start) {
+                Some(after) => after.as_secs_f64(),
+                None => -(capture_start - started).as_secs_f64(),
+            };
+            (seconds * self.recorded_sample_rate() as f64).round() as i64
+        });
+        Ok(result)
+    }
+
+    fn record_result(&self, data: Vec<Vec<i32>>) -> Result<RecordResult, anyhow::Error> {
+        let buffers = self.capture_timestamps.lock().unwrap().clone();
+        let capture_start = buffers
+            .first()
+            .ok_or(anyhow::Error::msg(
+                "The input stream did not report a timestamp",
+            ))?
+            .capture;
+
+        Ok(RecordResult {
+            data,
+            capture_start,
+            timestamps: TimestampMap::new(self.sample_rate, buffers),
+            playback_offset: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift() {
+        let data = vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]];
+        assert_eq!(shift(&data, 1), vec![vec![2, 3, 4], vec![6, 7, 8]]);
+        assert_eq!(
+            shift(&data, -2),
+            vec![vec![0, 0, 1, 2, 3, 4], vec![0, 0, 5, 6, 7, 8]]
+        );
+        assert_eq!(shift(&data, 0), data);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::audio_class::{AudioInstance, StreamControllerType};
+
+/// A recording in progress, started with `AudioInstance::begin_record`.
+///
+/// Dropping the guard cancels the recording and throws the data away, so an abandoned recording
+/// can't leave the input stream capturing into the next operation.
+pub struct RecordingGuard<'a> {
+    audio_instance: &'a AudioInstance,
+    finished: bool,
+}
+
+impl AudioInstance {
+    /// Start recording without blocking.
+    ///
+    /// The recording stops by itself after `max_duration` seconds. Call `finish` on the returned
+    /// guard to stop early and get the data recorded so far, or `wait` to wait for the whole
+    /// duration.
+    ///
+    /// # Arguments
+    /// max_duration: f64 - the longest the recording can run for, in seconds
+    pub fn begin_record(&self, max_duration: f64) -> Result<RecordingGuard<'_>, anyhow::Error> {
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        self.start_capture(self.input_capture(max_duration));
+        let (lock, _) = &*self.record_wait_pair;
+        *lock.lock().unwrap() = true;
+
+        Ok(RecordingGuard {
+            audio_instance: self,
+            finished: false,
+        })
+    }
+}
+
+impl RecordingGuard<'_> {
+    /// Whether the recording has reached its maximum duration.
+    pub fn is_complete(&self) -> bool {
+        let (lock, _) = &*self.audio_instance.record_wait_pair;
+        !*lock.lock().unwrap()
+    }
+
+    /// Stop recording and return the data recorded so far.
+    ///
+    /// # Returns
+    /// A vector of channels where each channel is a vector of samples
+    pub fn finish(mut self) -> Vec<Vec<i32>> {
+        self.stop();
+        self.finished = true;
+
+        self.audio_instance.take_capture().into_channels()
+    }
+
+    /// Wait for the recording to reach its maximum duration and return it.
+    ///
+    /// # Errors
+    /// Returns an error if the device is disconnected first. The recording is thrown away
+    pub fn wait(self) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        let (lock, _) = &*self.audio_instance.record_wait_pair;
+        self.audio_instance
+            .wait_for_recording(lock.lock().unwrap(), |recording| !recording)?;
+        Ok(self.finish())
+    }
+
+    /// Stop the input callback from capturing and wake anything waiting on the recording.
+    fn stop(&self) {
+        let (lock, cvar) = &*self.audio_instance.record_wait_pair;
+        *lock.lock().unwrap() = false;
+        cvar.notify_all();
+    }
+}
+
+impl Drop for RecordingGuard<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.stop();
+            // free the partial recording
+            self.audio_instance.take_capture();
+        }
+    }
+}
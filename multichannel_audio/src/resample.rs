@@ -0,0 +1,244 @@
+use std::f64::consts::PI;
+
+/// The number of zero crossings on each side of the polyphase filter.
+const POLYPHASE_ZERO_CROSSINGS: usize = 16;
+
+/// The number of zero crossings on each side of the high quality sinc filter.
+const SINC_ZERO_CROSSINGS: usize = 64;
+
+/// The most filter phases to precompute. Ratios that need more compute the taps for each sample.
+const MAX_PHASES: usize = 1024;
+
+/// The trade-off between speed and accuracy of `resample`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+    /// Linear interpolation. Fast, but aliases and rolls off the high frequencies. Good enough
+    /// for timing signals and previews
+    Linear,
+    /// A windowed sinc filter with precomputed phases. Flat to about 90% of the Nyquist frequency
+    #[default]
+    Polyphase,
+    /// A long windowed sinc filter computed for every sample. Slow, but flat to about 98% of the
+    /// Nyquist frequency, for measurement stimuli
+    Sinc,
+}
+
+/// Resample a signal to a different sample rate.
+///
+/// # Arguments
+/// signal: &[i32] - the samples of the signal
+/// from_fs: u32 - the sample rate of the signal
+/// to_fs: u32 - the sample rate to convert to
+/// quality: ResampleQuality - the filter to use
+///
+/// # Returns
+/// The resampled signal, `signal.len() * to_fs / from_fs` samples long, rounded up
+///
+/// # Errors
+/// Returns an error if either sample rate is 0
+pub fn resample(
+    signal: &[i32],
+    from_fs: u32,
+    to_fs: u32,
+    quality: ResampleQuality,
+) -> Result<Vec<i32>, anyhow::Error> {
+    if from_fs == 0 || to_fs == 0 {
+        return Err(anyhow::anyhow!(
+            "Can't resample from {} Hz to {} Hz",
+            from_fs,
+            to_fs
+        ));
+    }
+    if from_fs == to_fs || signal.is_empty() {
+        return Ok(signal.to_vec());
+    }
+
+    // reduce the ratio so output sample k is at input position k * step / up
+    let divisor = gcd(from_fs as u64, to_fs as u64);
+    let up = to_fs as u64 / divisor;
+    let step = from_fs as u64 / divisor;
+    let length = (signal.len() as u64 * up).div_ceil(step) as usize;
+    // lower the cutoff below the new Nyquist frequency when downsampling
+    let cutoff = (to_fs as f64 / from_fs as f64).min(1.0);
+
+    let resampled = match quality {
+        ResampleQuality::Linear => (0..length)
+            .map(|k| {
+                let position = k as u64 * step;
+                let index = (position / up) as usize;
+                let fraction = (position % up) as f64 / up as f64;
+                let next = signal.get(index + 1).unwrap_or(&signal[index]);
+                signal[index] as f64 * (1.0 - fraction) + *next as f64 * fraction
+            })
+            .map(to_sample)
+            .collect(),
+        ResampleQuality::Polyphase if (up as usize) <= MAX_PHASES => {
+            let phases: Vec<Vec<f64>> = (0..up)
+                .map(|phase| taps(phase as f64 / up as f64, cutoff, POLYPHASE_ZERO_CROSSINGS))
+                .collect();
+            (0..length)
+                .map(|k| {
+                    let position = k as u64 * step;
+                    let index = (position / up) as usize;
+                    convolve(signal, index, &phases[(position % up) as usize])
+                })
+                .map(to_sample)
+                .collect()
+        }
+        ResampleQuality::Polyphase | ResampleQuality::Sinc => {
+            let zero_crossings = match quality {
+                ResampleQuality::Sinc => SINC_ZERO_CROSSINGS,
+                _ => POLYPHASE_ZERO_CROSSINGS,
+            };
+            (0..length)
+                .map(|k| {
+                    let position = k as u64 * step;
+                    let index = (position / up) as usize;
+                    let fraction = (position % up) as f64 / up as f64;
+                    convolve(signal, index, &taps(fraction, cutoff, zero_crossings))
+                })
+                .map(to_sample)
+                .collect()
+        }
+    };
+    Ok(resampled)
+}
+
+/// Resample every channel of a multichannel signal. See `resample`.
+///
+/// # Errors
+/// Returns an error if either sample rate is 0
+pub fn resample_channels(
+    channels: &[Vec<i32>],
+    from_fs: u32,
+    to_fs: u32,
+    quality: ResampleQuality,
+) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    channels
+        .iter()
+        .map(|channel| resample(channel, from_fs, to_fs, quality))
+        .collect()
+}
+
+/// The filter taps for an output sample `fraction` of the way from input sample `index` to the next.
+///
+/// Tap `j` is the weight of input sample `index + j + 1 - half`, where `half` is half the number
+/// of taps.
+pub(crate) fn taps(fraction: f64, cutoff: f64, zero_crossings: usize) -> Vec<f64> {
+    // the filter is stretched when the cutoff is lowered
+    let half = (zero_crossings as f64 / cutoff).ceil() as usize;
+    let width = zero_crossings as f64 / cutoff;
+    (0..2 * half)
+        .map(|j| {
+            let distance = j as f64 + 1.0 - half as f64 - fraction;
+            if distance.abs() >= width {
+                return 0.0;
+            }
+            cutoff * sinc(cutoff * distance) * blackman(distance / width)
+        })
+        .collect()
+}
+
+/// Apply filter taps around an input sample. Samples before the start and after the end are 0.
+fn convolve(signal: &[i32], index: usize, taps: &[f64]) -> f64 {
+    let half = taps.len() / 2;
+    taps.iter()
+        .enumerate()
+        .filter_map(|(j, &tap)| {
+            let input = (index + j + 1).checked_sub(half)?;
+            signal.get(input).map(|&sample| sample as f64 * tap)
+        })
+        .sum()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The Blackman window, for positions from -1 to 1.
+fn blackman(position: f64) -> f64 {
+    0.42 + 0.5 * (PI * position).cos() + 0.08 * (2.0 * PI * position).cos()
+}
+
+fn to_sample(value: f64) -> i32 {
+    value.round().clamp(i32::MIN as f64, i32::MAX as f64) as i32
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods::generate_sine_wave;
+
+    /// The RMS error between a resampled sine and the ideal one, ignoring the filter edges.
+    fn sine_error(quality: ResampleQuality, from_fs: u32, to_fs: u32) -> f64 {
+        let resampled = resample(
+            &generate_sine_wave(1000, 0.1, from_fs),
+            from_fs,
+            to_fs,
+            quality,
+        )
+        .unwrap();
+        let expected = generate_sine_wave(1000, 0.1, to_fs);
+        let edge = to_fs as usize / 100;
+        let squared: f64 = resampled[edge..expected.len() - edge]
+            .iter()
+            .zip(&expected[edge..])
+            .map(|(&a, &b)| ((a as f64 - b as f64) / i32::MAX as f64).powi(2))
+            .sum();
+        (squared / (expected.len() - 2 * edge) as f64).sqrt()
+    }
+
+    #[test]
+    fn test_resample_length() {
+        let signal = vec![1; 48000];
+        for quality in [
+            ResampleQuality::Linear,
+            ResampleQuality::Polyphase,
+            ResampleQuality::Sinc,
+        ] {
+            assert_eq!(
+                resample(&signal, 48000, 44100, quality).unwrap().len(),
+                44100
+            );
+            assert_eq!(
+                resample(&signal[..3], 48000, 96000, quality).unwrap().len(),
+                6
+            );
+        }
+        assert_eq!(
+            resample(&[1, 2, 3], 48000, 48000, ResampleQuality::Sinc).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(resample(&signal, 0, 48000, ResampleQuality::Linear).is_err());
+    }
+
+    #[test]
+    fn test_resample_keeps_the_original_samples() {
+        let signal: Vec<i32> = (0..100).map(|i| (i * i) % 1000 * 1000).collect();
+        let upsampled = resample(&signal, 24000, 48000, ResampleQuality::Linear).unwrap();
+        assert_eq!(upsampled[10], signal[5]);
+        assert_eq!(upsampled[11], (signal[5] + signal[6]) / 2);
+    }
+
+    #[test]
+    fn test_resample_quality() {
+        let linear = sine_error(ResampleQuality::Linear, 44100, 48000);
+        let polyphase = sine_error(ResampleQuality::Polyphase, 44100, 48000);
+        let sinc = sine_error(ResampleQuality::Sinc, 44100, 48000);
+        assert!(polyphase < linear, "{} {}", polyphase, linear);
+        assert!(sinc < 1e-3, "{}", sinc);
+        assert!(sine_error(ResampleQuality::Polyphase, 96000, 44100) < 1e-3);
+    }
+}
@@ -0,0 +1,172 @@
+/// A sample type that can be converted to and from the full-scale i32 samples used by this crate.
+///
+/// Integer types are scaled so that their full range maps onto the full i32 range. Unsigned types
+/// are offset so that their midpoint is silence. Floating point types use -1.0 to 1.0.
+pub trait Sample: Copy + Send + 'static {
+    /// The number of bits of resolution. `from_i32` drops the low bits of samples with fewer than 32.
+    const BITS: u32 = 32;
+
+    fn to_i32(self) -> i32;
+    fn from_i32(value: i32) -> Self;
+}
+
+/// A 24-bit signed sample stored in the low 24 bits of an i32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct I24(i32);
+
+impl I24 {
+    pub const MIN: i32 = -(1 << 23);
+    pub const MAX: i32 = (1 << 23) - 1;
+
+    /// Create a 24-bit sample, clamping the value to the 24-bit range.
+    pub fn new(value: i32) -> Self {
+        I24(value.clamp(Self::MIN, Self::MAX))
+    }
+
+    pub fn inner(self) -> i32 {
+        self.0
+    }
+}
+
+impl Sample for i32 {
+    fn to_i32(self) -> i32 {
+        self
+    }
+
+    fn from_i32(value: i32) -> Self {
+        value
+    }
+}
+
+impl Sample for I24 {
+    const BITS: u32 = 24;
+
+    fn to_i32(self) -> i32 {
+        self.0 << 8
+    }
+
+    fn from_i32(value: i32) -> Self {
+        I24(value >> 8)
+    }
+}
+
+impl Sample for i16 {
+    const BITS: u32 = 16;
+
+    fn to_i32(self) -> i32 {
+        (self as i32) << 16
+    }
+
+    fn from_i32(value: i32) -> Self {
+        (value >> 16) as i16
+    }
+}
+
+impl Sample for u16 {
+    const BITS: u32 = 16;
+
+    fn to_i32(self) -> i32 {
+        (self as i32 - 32768) << 16
+    }
+
+    fn from_i32(value: i32) -> Self {
+        ((value >> 16) + 32768) as u16
+    }
+}
+
+impl Sample for i8 {
+    const BITS: u32 = 8;
+
+    fn to_i32(self) -> i32 {
+        (self as i32) << 24
+    }
+
+    fn from_i32(value: i32) -> Self {
+        (value >> 24) as i8
+    }
+}
+
+impl Sample for u8 {
+    const BITS: u32 = 8;
+
+    fn to_i32(self) -> i32 {
+        (self as i32 - 128) << 24
+    }
+
+    fn from_i32(value: i32) -> Self {
+        ((value >> 24) + 128) as u8
+    }
+}
+
+// Converting floats to i32 gains resolution rather than losing it, so `Dither` doesn't apply: a
+// step of an i32 is -187 dBFS, far below the 24 bits of an f32 at full scale. Samples are rounded
+// to the nearest step so quiet samples aren't biased towards zero.
+impl Sample for f32 {
+    fn to_i32(self) -> i32 {
+        // float to int casts saturate, so out of range values are clipped
+        (self * i32::MAX as f32).round() as i32
+    }
+
+    fn from_i32(value: i32) -> Self {
+        value as f32 / i32::MAX as f32
+    }
+}
+
+impl Sample for f64 {
+    fn to_i32(self) -> i32 {
+        (self * i32::MAX as f64).round() as i32
+    }
+
+    fn from_i32(value: i32) -> Self {
+        value as f64 / i32::MAX as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_i16_round_trip() {
+        for sample in [i16::MIN, -1, 0, 1, i16::MAX] {
+            assert_eq!(i16::from_i32(sample.to_i32()), sample);
+        }
+        assert_eq!(i16::MAX.to_i32(), i32::MAX - 0xFFFF);
+    }
+
+    #[test]
+    fn test_unsigned_midpoint_is_silence() {
+        assert_eq!(32768u16.to_i32(), 0);
+        assert_eq!(128u8.to_i32(), 0);
+        assert_eq!(u16::from_i32(0), 32768);
+        assert_eq!(u8::from_i32(0), 128);
+        assert_eq!(u16::from_i32(i32::MIN), 0);
+        assert_eq!(u8::from_i32(i32::MAX), u8::MAX);
+    }
+
+    #[test]
+    fn test_i24_scaling() {
+        assert_eq!(I24::new(I24::MAX).to_i32(), I24::MAX << 8);
+        assert_eq!(I24::new(i32::MAX).inner(), I24::MAX);
+        assert_eq!(I24::from_i32(i32::MIN).inner(), I24::MIN);
+        assert_eq!(I24::from_i32(I24::new(-5).to_i32()), I24::new(-5));
+    }
+
+    #[test]
+    fn test_f32_scaling() {
+        assert_eq!(1.0f32.to_i32(), i32::MAX);
+        assert_eq!(2.0f32.to_i32(), i32::MAX);
+        assert_eq!(0.0f32.to_i32(), 0);
+        assert!((f32::from_i32(i32::MIN) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_float_rounding() {
+        let step = 1.0 / i32::MAX as f64;
+        assert_eq!((0.6 * step).to_i32(), 1);
+        assert_eq!((-0.6 * step).to_i32(), -1);
+        assert_eq!((0.4 * step).to_i32(), 0);
+        assert_eq!(((2.6 * step) as f32).to_i32(), 3);
+        assert_eq!((-1.0f64).to_i32(), -i32::MAX);
+    }
+}
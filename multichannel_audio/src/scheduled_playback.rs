@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use cpal::StreamInstant;
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+
+/// When scheduled playback should start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackStart {
+    /// A time on the system clock, e.g. when an external trigger fired
+    Instant(Instant),
+    /// A time on the clock of the output stream
+    StreamInstant(StreamInstant),
+}
+
+impl From<Instant> for PlaybackStart {
+    fn from(instant: Instant) -> Self {
+        PlaybackStart::Instant(instant)
+    }
+}
+
+impl From<StreamInstant> for PlaybackStart {
+    fn from(instant: StreamInstant) -> Self {
+        PlaybackStart::StreamInstant(instant)
+    }
+}
+
+impl AudioInstance {
+    /// Play multiple channels of audio data starting at a precise time.
+    ///
+    /// The output callback writes silence until the first sample of the data is due to leave the
+    /// device at `start`, using the playback timestamps reported by the driver. If `start` has
+    /// already passed, playback starts immediately. This function blocks until the audio has
+    /// finished playing.
+    ///
+    /// # Arguments
+    /// output_data: Vec<Vec<i32>> - the audio data to play. The outer vector represents the channels and the inner vector represents the samples.
+    /// start: impl Into<PlaybackStart> - an `Instant` or a `StreamInstant` to start playing at
+    ///
+    /// # Errors
+    /// Returns an error if the number of channels does not match the device
+    /// Returns an error if the output stream has not reported a timestamp yet
+    pub fn play_at(
+        &self,
+        output_data: Vec<Vec<i32>>,
+        start: impl Into<PlaybackStart>,
+    ) -> Result<(), anyhow::Error> {
+        if self.number_of_output_channels != output_data.len() as u16 {
+            return Err(anyhow::Error::msg("Number of channels does not match"));
+        }
+        self.check_interlock(&output_data)?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        let start = match start.into() {
+            PlaybackStart::StreamInstant(start) => start,
+            PlaybackStart::Instant(start) => self.to_stream_instant(start)?,
+        };
+
+        let flattened_output_data = self.flatten_output_data(output_data);
+
+        // schedule before handing over the buffer, so the callback sees both together
+        self.schedule.schedule(start);
+        *self.output_buffer.lock().unwrap() = self.output_signal(flattened_output_data)?;
+
+        self.play_gate.start();
+        self.play_gate.wait(&self.healthy)
+    }
+
+    /// The current time on the clock of the output stream.
+    ///
+    /// Estimated from the timestamp of the most recent output callback. Returns None if the output
+    /// stream has not run yet.
+    pub fn stream_time(&self) -> Option<StreamInstant> {
+        let (reference_instant, reference) = self.schedule.reference()?;
+        reference.add(reference_instant.elapsed())
+    }
+
+    /// Convert a time on the system clock to a time on the clock of the output stream.
+    fn to_stream_instant(&self, instant: Instant) -> Result<StreamInstant, anyhow::Error> {
+        // the output callback records the reference once the stream is running
+        let timeout = Instant::now() + Duration::from_secs(1);
+        let (reference_instant, reference) = loop {
+            if let Some(reference) = self.schedule.reference() {
+                break reference;
+            }
+            if Instant::now() > timeout {
+                return Err(anyhow::Error::msg(
+                    "The output stream has not reported a timestamp",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+
+        let converted = if instant >= reference_instant {
+            reference.add(instant - reference_instant)
+        } else {
+            reference.sub(reference_instant - instant)
+        };
+        converted.ok_or(anyhow::Error::msg(
+            "Start time is out of range of the stream clock",
+        ))
+    }
+}
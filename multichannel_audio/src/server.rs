@@ -0,0 +1,376 @@
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tungstenite::{Message, WebSocket};
+
+use crate::audio_class::AudioInstance;
+use crate::channel::{InputChannel, OutputChannel};
+
+// The protocol is one JSON request per text message and one JSON response to each, e.g.
+// {"op": "record", "duration": 1.5} -> {"result": "recording", "channels": [[...], [...]]}
+// Samples are full-scale i32 with a vector per channel, the same as the rest of the crate.
+
+/// How long `serve` waits for the next request from a client before dropping it, so an idle or
+/// stalled client doesn't keep the device from everyone else.
+const CLIENT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The default longest recording or signal a client can ask for, in seconds.
+const MAX_DURATION: f64 = 600.0;
+
+/// An operation requested by a remote client, tagged by `op`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// The sample rate and channel counts of the device
+    Info,
+    /// Play a signal on every output channel, see `AudioInstance::play`
+    Play { channels: Vec<Vec<i32>> },
+    /// Record every input channel, see `AudioInstance::record`
+    Record {
+        /// The duration in seconds
+        duration: f64,
+    },
+    /// Play and record at the same time, see `AudioInstance::play_record`
+    PlayRecord { channels: Vec<Vec<i32>> },
+    /// Play with a loopback timing signal and return the aligned recording, see
+    /// `AudioInstance::aligned_play_record`
+    AlignedPlayRecord {
+        signal: Vec<i32>,
+        channel: OutputChannel,
+        timing_out: OutputChannel,
+        timing_in: InputChannel,
+        /// The number of output channels to play on
+        outputs: usize,
+    },
+}
+
+/// The reply to a `Request`, tagged by `result`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Info {
+        sample_rate: u32,
+        channels_in: u16,
+        channels_out: u16,
+    },
+    /// A playback has finished
+    Done,
+    /// A recording, one vector per input channel
+    Recording { channels: Vec<Vec<i32>> },
+    /// The request was invalid or the operation failed
+    Error { message: String },
+}
+
+/// The largest requests the server runs, so a client can't hang it or exhaust its memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerLimits {
+    /// The longest recording or signal in seconds, 10 minutes by default
+    pub max_duration: f64,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        ServerLimits {
+            max_duration: MAX_DURATION,
+        }
+    }
+}
+
+impl Request {
+    /// Check the duration of a recording and the length of a signal against limits.
+    ///
+    /// # Arguments
+    /// limits: &ServerLimits - the largest request to allow
+    /// fs: u32 - the sample rate of the device
+    ///
+    /// # Errors
+    /// Returns an error if a duration is negative or not a number
+    /// Returns an error if a recording or signal is longer than the limits allow
+    pub fn check(&self, limits: &ServerLimits, fs: u32) -> Result<(), anyhow::Error> {
+        let max_samples = (limits.max_duration.max(0.0) * fs as f64) as usize;
+        let samples = match self {
+            Request::Info => 0,
+            Request::Record { duration } => {
+                if !(duration.is_finite() && *duration >= 0.0) {
+                    return Err(anyhow::anyhow!(
+                        "The duration must be 0 seconds or more, got {}",
+                        duration
+                    ));
+                }
+                if *duration > limits.max_duration {
+                    return Err(anyhow::anyhow!(
+                        "The duration must be at most {} seconds, got {}",
+                        limits.max_duration,
+                        duration
+                    ));
+                }
+                0
+            }
+            Request::Play { channels } | Request::PlayRecord { channels } => {
+                channels.iter().map(Vec::len).max().unwrap_or(0)
+            }
+            Request::AlignedPlayRecord { signal, .. } => signal.len(),
+        };
+        if samples > max_samples {
+            return Err(anyhow::anyhow!(
+                "The signal must be at most {} samples long, got {}",
+                max_samples,
+                samples
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AudioInstance {
+    /// Run one request from a remote client. Requests are checked against `limits` first, and
+    /// errors are returned as `Response::Error`.
+    pub fn handle_request(&self, request: Request, limits: &ServerLimits) -> Response {
+        if let Err(err) = request.check(limits, self.sample_rate()) {
+            return Response::Error {
+                message: err.to_string(),
+            };
+        }
+        let result = match request {
+            Request::Info => Ok(Response::Info {
+                sample_rate: self.sample_rate(),
+                channels_in: self.channels_in(),
+                channels_out: self.channels_out(),
+            }),
+            Request::Play { channels } => self.play(channels).map(|()| Response::Done),
+            Request::Record { duration } => self
+                .record(duration)
+                .map(|channels| Response::Recording { channels }),
+            Request::PlayRecord { channels } => self
+                .play_record(channels)
+                .map(|channels| Response::Recording { channels }),
+            Request::AlignedPlayRecord {
+                signal,
+                channel,
+                timing_out,
+                timing_in,
+                outputs,
+            } => self
+                .aligned_play_record(signal, channel, timing_out, timing_in, outputs)
+                .map(|channels| Response::Recording { channels }),
+        };
+        result.unwrap_or_else(|err| Response::Error {
+            message: err.to_string(),
+        })
+    }
+
+    /// Serve requests over WebSocket so the instance can be driven from another computer, e.g.
+    /// an analysis workstation driving the measurement PC the audio interface is connected to.
+    ///
+    /// Clients are served one at a time, since they share the device. A client that fails, or
+    /// sends nothing for a minute, is logged and dropped, as is a connection that
+    /// can't be accepted. This function blocks forever.
+    ///
+    /// # Arguments
+    /// address: impl ToSocketAddrs - the address to listen on, e.g. `"127.0.0.1:9001"`, or
+    /// `"0.0.0.0:9001"` to accept clients from other computers
+    /// limits: ServerLimits - the largest request to run
+    ///
+    /// # Errors
+    /// Returns an error if the address can't be bound
+    ///
+    /// # Example
+    /// ```no_run
+    /// use multichannel_audio::audio_class::AudioInstance;
+    /// use multichannel_audio::server::ServerLimits;
+    ///
+    /// let audio_instance = AudioInstance::new(48000).unwrap();
+    /// // e.g. send {"op": "record", "duration": 2.0} from a client
+    /// audio_instance
+    ///     .serve("127.0.0.1:9001", ServerLimits::default())
+    ///     .unwrap();
+    /// ```
+    pub fn serve(
+        &self,
+        address: impl ToSocketAddrs,
+        limits: ServerLimits,
+    ) -> Result<(), anyhow::Error> {
+        let listener = TcpListener::bind(address)?;
+        tracing::info!(address = ?listener.local_addr()?, "serving audio requests");
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    tracing::warn!(%error, "failed to accept a remote client");
+                    continue;
+                }
+            };
+            // a panic while serving one client must not stop the server
+            let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                stream
+                    .set_read_timeout(Some(CLIENT_READ_TIMEOUT))
+                    .map_err(anyhow::Error::from)
+                    .and_then(|()| self.serve_connection(stream, &limits))
+            }));
+            match served {
+                Ok(Ok(())) => {}
+                Ok(Err(error)) => tracing::warn!(%error, "remote client failed"),
+                Err(_) => tracing::error!("serving a remote client panicked"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Serve one WebSocket client until it disconnects, running requests up to `limits`.
+    ///
+    /// # Errors
+    /// Returns an error if the handshake fails or the connection is lost
+    pub fn serve_connection(
+        &self,
+        stream: TcpStream,
+        limits: &ServerLimits,
+    ) -> Result<(), anyhow::Error> {
+        let peer = stream.peer_addr()?;
+        let mut websocket = tungstenite::accept(stream)
+            .map_err(|err| anyhow::anyhow!("WebSocket handshake with {} failed: {}", peer, err))?;
+        tracing::info!(%peer, "remote client connected");
+
+        loop {
+            let response = match websocket.read() {
+                Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                    Ok(request) => self.handle_request(request, limits),
+                    Err(err) => Response::Error {
+                        message: format!("Invalid request: {}", err),
+                    },
+                },
+                Ok(Message::Close(_))
+                | Err(tungstenite::Error::ConnectionClosed)
+                | Err(tungstenite::Error::AlreadyClosed) => break,
+                // pings are answered by tungstenite
+                Ok(_) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            send(&mut websocket, &response)?;
+        }
+        tracing::info!(%peer, "remote client disconnected");
+        Ok(())
+    }
+}
+
+fn send(websocket: &mut WebSocket<TcpStream>, response: &Response) -> Result<(), anyhow::Error> {
+    websocket.send(Message::Text(serde_json::to_string(response)?))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockDevice;
+    use crate::builder::AudioInstanceBuilder;
+
+    #[test]
+    fn test_parse_requests() {
+        let request: Request = serde_json::from_str(
+            r#"{"op": "aligned_play_record", "signal": [1, 2], "channel": 1, "timing_out": 2,
+                "timing_in": 2, "outputs": 2}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            Request::AlignedPlayRecord {
+                signal: vec![1, 2],
+                channel: OutputChannel(1),
+                timing_out: OutputChannel(2),
+                timing_in: InputChannel(2),
+                outputs: 2,
+            }
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"op": "explode"}"#).is_err());
+        assert_eq!(
+            serde_json::to_string(&Response::Done).unwrap(),
+            r#"{"result":"done"}"#
+        );
+    }
+
+    #[test]
+    fn test_serve_connection() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2))
+            .build()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = std::thread::spawn(move || {
+            let (mut websocket, _) = tungstenite::connect(format!("ws://{}", address)).unwrap();
+            let mut request = |text: &str| {
+                websocket.send(Message::Text(text.to_string())).unwrap();
+                let reply = websocket.read().unwrap();
+                serde_json::from_str::<Response>(reply.to_text().unwrap()).unwrap()
+            };
+            let replies = vec![
+                request(r#"{"op": "info"}"#),
+                request(r#"{"op": "record", "duration": 0.01}"#),
+                request(r#"{"op": "play", "channels": [[1, 2]]}"#),
+                request("not json"),
+                request(r#"{"op": "record", "duration": 0}"#),
+                request(r#"{"op": "record", "duration": -1}"#),
+                request(r#"{"op": "record", "duration": 1e300}"#),
+            ];
+            websocket.close(None).unwrap();
+            replies
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let limits = ServerLimits { max_duration: 1.0 };
+        audio_instance.serve_connection(stream, &limits).unwrap();
+        let replies = client.join().unwrap();
+
+        assert_eq!(
+            replies[0],
+            Response::Info {
+                sample_rate: 48000,
+                channels_in: 2,
+                channels_out: 2,
+            }
+        );
+        let Response::Recording { ref channels } = replies[1] else {
+            panic!("expected a recording, got {:?}", replies[1]);
+        };
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].len(), 480);
+        // one channel is the wrong shape for a stereo device
+        assert!(matches!(replies[2], Response::Error { .. }));
+        assert!(matches!(replies[3], Response::Error { .. }));
+        assert_eq!(
+            replies[4],
+            Response::Recording {
+                channels: vec![vec![]; 2]
+            }
+        );
+        assert!(matches!(replies[5], Response::Error { .. }));
+        assert!(matches!(replies[6], Response::Error { .. }));
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let limits = ServerLimits { max_duration: 1.0 };
+        assert!(Request::Record { duration: 1.0 }
+            .check(&limits, 48000)
+            .is_ok());
+        assert!(Request::Record { duration: f64::NAN }
+            .check(&limits, 48000)
+            .is_err());
+        let play = |samples| Request::PlayRecord {
+            channels: vec![vec![0; 10], vec![0; samples]],
+        };
+        assert!(play(48000).check(&limits, 48000).is_ok());
+        assert!(play(48001).check(&limits, 48000).is_err());
+        let aligned = Request::AlignedPlayRecord {
+            signal: vec![0; 48001],
+            channel: OutputChannel(1),
+            timing_out: OutputChannel(2),
+            timing_in: InputChannel(2),
+            outputs: 2,
+        };
+        assert!(aligned.check(&limits, 48000).is_err());
+        assert!(Request::Info.check(&limits, 48000).is_ok());
+    }
+}
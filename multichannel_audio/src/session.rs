@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::export::{self, ExportFormat};
+use crate::methods;
+
+const MANIFEST_FILE: &str = "session.toml";
+const STIMULUS_DIR: &str = "stimuli";
+const TAKE_DIR: &str = "takes";
+
+/// One recording in a session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Take {
+    /// The name of the stimulus played for this take, if any
+    pub stimulus: Option<String>,
+    /// The recording, one vector per input channel
+    pub recording: Vec<Vec<i32>>,
+    /// The number of samples removed from the start of the recording when it was aligned
+    pub alignment_offset: Option<usize>,
+    /// When the take was added to the session
+    pub timestamp: SystemTime,
+}
+
+/// A sequence of measurements, e.g. the takes of a repeated play and record loop, with the stimuli
+/// they played.
+///
+/// A session is saved to a directory with a `session.toml` manifest, the stimuli in `stimuli/`
+/// and each take as a multichannel WAV or FLAC file in `takes/`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    sample_rate: u32,
+    stimuli: BTreeMap<String, Vec<Vec<i32>>>,
+    takes: Vec<Take>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    sample_rate: u32,
+    #[serde(default)]
+    stimuli: Vec<StimulusEntry>,
+    #[serde(default)]
+    takes: Vec<TakeEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StimulusEntry {
+    name: String,
+    file: String,
+    channels: usize,
+    length: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TakeEntry {
+    file: String,
+    stimulus: Option<String>,
+    alignment_offset: Option<usize>,
+    /// Seconds since the Unix epoch
+    timestamp: f64,
+}
+
+impl Session {
+    pub fn new(sample_rate: u32) -> Self {
+        Session {
+            sample_rate,
+            stimuli: BTreeMap::new(),
+            takes: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Add a stimulus that takes can refer to by name.
+    ///
+    /// # Arguments
+    /// name: &str - the name of the stimulus, which is also its file name when saved
+    /// channels: Vec<Vec<i32>> - the stimulus, one vector per output channel
+    ///
+    /// # Errors
+    /// Returns an error if the session already has a stimulus with this name, the name can't be
+    /// used as a file name or the stimulus has no channels
+    pub fn add_stimulus(
+        &mut self,
+        name: &str,
+        channels: Vec<Vec<i32>>,
+    ) -> Result<(), anyhow::Error> {
+        if name.is_empty() || name.contains(['/', '\\']) {
+            anyhow::bail!("\"{}\" can't be used as a stimulus name.", name);
+        }
+        if channels.is_empty() {
+            anyhow::bail!("Stimulus \"{}\" has no channels.", name);
+        }
+        if self.stimuli.contains_key(name) {
+            anyhow::bail!("The session already has a stimulus named \"{}\".", name);
+        }
+
+        self.stimuli.insert(name.to_string(), channels);
+        Ok(())
+    }
+
+    pub fn stimulus(&self, name: &str) -> Option<&Vec<Vec<i32>>> {
+        self.stimuli.get(name)
+    }
+
+    /// The names of every stimulus in the session, in alphabetical order.
+    pub fn stimulus_names(&self) -> impl Iterator<Item = &str> {
+        self.stimuli.keys().map(|name| name.as_str())
+    }
+
+    /// Add a take, timestamped with the current time.
+    ///
+    /// # Arguments
+    /// stimulus: Option<&str> - the name of the stimulus played for the take
+    /// recording: Vec<Vec<i32>> - the recording, one vector per input channel
+    /// alignment_offset: Option<usize> - the number of samples removed when the recording was aligned
+    ///
+    /// # Returns
+    /// The index of the take
+    ///
+    /// # Errors
+    /// Returns an error if the stimulus is not in the session or the recording has no channels
+    pub fn add_take(
+        &mut self,
+        stimulus: Option<&str>,
+        recording: Vec<Vec<i32>>,
+        alignment_offset: Option<usize>,
+    ) -> Result<usize, anyhow::Error> {
+        if let Some(name) = stimulus {
+            if !self.stimuli.contains_key(name) {
+                anyhow::bail!("The session has no stimulus named \"{}\".", name);
+            }
+        }
+        if recording.is_empty() {
+            anyhow::bail!("The recording has no channels.");
+        }
+
+        self.takes.push(Take {
+            stimulus: stimulus.map(str::to_string),
+            recording,
+            alignment_offset,
+            timestamp: SystemTime::now(),
+        });
+        Ok(self.takes.len() - 1)
+    }
+
+    pub fn takes(&self) -> &[Take] {
+        &self.takes
+    }
+
+    pub fn take(&self, index: usize) -> Option<&Take> {
+        self.takes.get(index)
+    }
+
+    /// Every take of a stimulus, in the order they were added.
+    pub fn takes_of<'a>(&'a self, stimulus: &'a str) -> impl Iterator<Item = &'a Take> {
+        self.takes
+            .iter()
+            .filter(move |take| take.stimulus.as_deref() == Some(stimulus))
+    }
+
+    pub fn len(&self) -> usize {
+        self.takes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.takes.is_empty()
+    }
+
+    /// Save the session to a directory, creating it if it doesn't exist.
+    ///
+    /// Samples are saved as 32-bit WAV files, so a loaded session is identical to the saved one.
+    /// See `save_as` for smaller files.
+    ///
+    /// # Errors
+    /// Returns an error if the directory or any file can't be written
+    pub fn save(&self, dir: &Path) -> Result<(), anyhow::Error> {
+        self.save_as(dir, ExportFormat::default())
+    }
+
+    /// Save the session to a directory in an export format, creating it if it doesn't exist.
+    ///
+    /// Samples are scaled to the bit depth of the format, so a session saved with fewer than 32
+    /// bits loads with the lowest bits of each sample cleared.
+    ///
+    /// # Errors
+    /// Returns an error if the bit depth is not 16, 24 or 32, or is 32 for FLAC
+    /// Returns an error if a stimulus or take has more channels than the format can hold
+    /// Returns an error if the directory or any file can't be written
+    pub fn save_as(&self, dir: &Path, format: ExportFormat) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(dir.join(STIMULUS_DIR))?;
+        std::fs::create_dir_all(dir.join(TAKE_DIR))?;
+
+        let mut stimuli = Vec::with_capacity(self.stimuli.len());
+        for (name, channels) in self.stimuli.iter() {
+            let file = format!("{}/{}.{}", STIMULUS_DIR, name, format.extension());
+            export::export_channels(channels, &dir.join(&file), self.sample_rate, format)?;
+            stimuli.push(StimulusEntry {
+                name: name.clone(),
+                file,
+                channels: channels.len(),
+                length: channels.iter().map(|c| c.len()).min().unwrap_or(0),
+            });
+        }
+
+        let mut takes = Vec::with_capacity(self.takes.len());
+        for (index, take) in self.takes.iter().enumerate() {
+            let file = format!("{}/take_{:04}.{}", TAKE_DIR, index + 1, format.extension());
+            export::export_channels(&take.recording, &dir.join(&file), self.sample_rate, format)?;
+            let timestamp = take
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            takes.push(TakeEntry {
+                file,
+                stimulus: take.stimulus.clone(),
+                alignment_offset: take.alignment_offset,
+                timestamp,
+            });
+        }
+
+        let manifest = Manifest {
+            sample_rate: self.sample_rate,
+            stimuli,
+            takes,
+        };
+        std::fs::write(dir.join(MANIFEST_FILE), toml::to_string(&manifest)?)?;
+        Ok(())
+    }
+
+    /// Load a session saved with `save` or `save_as`.
+    ///
+    /// # Errors
+    /// Returns an error if the manifest or any WAV or FLAC file it lists can't be read
+    pub fn load(dir: &Path) -> Result<Self, anyhow::Error> {
+        let manifest_path = dir.join(MANIFEST_FILE);
+        let contents = std::fs::read_to_string(&manifest_path)?;
+        let manifest: Manifest = toml::from_str(&contents).map_err(|err| {
+            anyhow::anyhow!(
+                "{} is not a valid session: {}",
+                manifest_path.display(),
+                err
+            )
+        })?;
+
+        let mut session = Session::new(manifest.sample_rate);
+        for entry in manifest.stimuli {
+            let channels = read_channels(&dir.join(&entry.file), manifest.sample_rate)?;
+            session.add_stimulus(&entry.name, channels)?;
+        }
+
+        for entry in manifest.takes {
+            if let Some(name) = &entry.stimulus {
+                if !session.stimuli.contains_key(name) {
+                    anyhow::bail!("{} refers to a missing stimulus \"{}\".", entry.file, name);
+                }
+            }
+            let recording = read_channels(&dir.join(&entry.file), manifest.sample_rate)?;
+            let timestamp = UNIX_EPOCH + Duration::from_secs_f64(entry.timestamp.max(0.0));
+            session.takes.push(Take {
+                stimulus: entry.stimulus,
+                recording,
+                alignment_offset: entry.alignment_offset,
+                timestamp,
+            });
+        }
+
+        Ok(session)
+    }
+}
+
+fn read_channels(path: &Path, sample_rate: u32) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    let is_flac = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("flac"));
+    let channels = if is_flac {
+        export::read_flac_channels(path).and_then(|(channels, file_rate)| {
+            if file_rate != sample_rate {
+                anyhow::bail!("the file is at {} Hz, not {} Hz", file_rate, sample_rate);
+            }
+            Ok(channels)
+        })
+    } else {
+        methods::read_wave_file_channels(path, sample_rate).map_err(anyhow::Error::from)
+    };
+    channels.map_err(|err| anyhow::anyhow!("Could not read {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir =
+            std::env::temp_dir().join(format!("multichannel_audio_session_{}", std::process::id()));
+        let mut session = Session::new(48000);
+        session
+            .add_stimulus("sweep", vec![(0..100).map(|x| x << 20).collect()])
+            .unwrap();
+        for take in 0..3 {
+            let recording = vec![vec![take; 50], vec![-take; 50]];
+            session
+                .add_take(Some("sweep"), recording, Some(12))
+                .unwrap();
+        }
+        session.add_take(None, vec![vec![7; 10]], None).unwrap();
+        session.save(&dir).unwrap();
+
+        let loaded = Session::load(&dir).unwrap();
+        assert_eq!(loaded.sample_rate(), 48000);
+        assert_eq!(loaded.stimulus("sweep"), session.stimulus("sweep"));
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(loaded.takes_of("sweep").count(), 3);
+        for (loaded_take, take) in loaded.takes().iter().zip(session.takes()) {
+            assert_eq!(loaded_take.stimulus, take.stimulus);
+            assert_eq!(loaded_take.recording, take.recording);
+            assert_eq!(loaded_take.alignment_offset, take.alignment_offset);
+            let drift = loaded_take
+                .timestamp
+                .duration_since(take.timestamp)
+                .or_else(|err| Ok::<_, ()>(err.duration()))
+                .unwrap();
+            assert!(drift < Duration::from_millis(1));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_as_flac() {
+        let dir = std::env::temp_dir().join(format!(
+            "multichannel_audio_session_flac_{}",
+            std::process::id()
+        ));
+        let mut session = Session::new(44100);
+        session
+            .add_stimulus("tone", vec![(0..1000).map(|x| (x % 50) << 24).collect()])
+            .unwrap();
+        session
+            .add_take(
+                Some("tone"),
+                vec![vec![3 << 8; 500], vec![-5 << 8; 500]],
+                None,
+            )
+            .unwrap();
+        let format = ExportFormat::Flac {
+            bits_per_sample: 24,
+        };
+        session.save_as(&dir, format).unwrap();
+        assert!(dir.join("takes/take_0001.flac").exists());
+
+        let loaded = Session::load(&dir).unwrap();
+        assert_eq!(loaded.stimulus("tone"), session.stimulus("tone"));
+        assert_eq!(loaded.takes()[0].recording, session.takes()[0].recording);
+
+        assert!(session
+            .save_as(
+                &dir,
+                ExportFormat::Flac {
+                    bits_per_sample: 32
+                }
+            )
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_takes() {
+        let mut session = Session::new(44100);
+        assert!(session
+            .add_take(Some("missing"), vec![vec![0; 10]], None)
+            .is_err());
+        assert!(session.add_take(None, vec![], None).is_err());
+        assert!(session.add_stimulus("a/b", vec![vec![0]]).is_err());
+        session.add_stimulus("tone", vec![vec![0]]).unwrap();
+        assert!(session.add_stimulus("tone", vec![vec![0]]).is_err());
+        assert!(session.is_empty());
+    }
+}
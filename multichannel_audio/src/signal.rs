@@ -0,0 +1,173 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+#[cfg(feature = "device")]
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::conversions::interleave;
+
+/// Interleaved audio shared between threads without copying.
+///
+/// Cloning a signal only clones a reference to the samples, so the same signal can be played any
+/// number of times, and the output callback reads it where it is. Use this for large stimuli
+/// that would otherwise be copied each time they are played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signal {
+    samples: Arc<[i32]>,
+    channels: usize,
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Signal {
+            samples: Arc::from(Vec::new()),
+            channels: 0,
+        }
+    }
+}
+
+impl Signal {
+    /// Interleave the channels of a signal.
+    ///
+    /// # Arguments
+    /// channels: &[Vec<i32>] - the samples of each channel
+    ///
+    /// # Errors
+    /// Returns an error if there are no channels or they are not all the same length
+    pub fn from_channels(channels: &[Vec<i32>]) -> Result<Self, anyhow::Error> {
+        if channels.is_empty() {
+            return Err(anyhow::Error::msg("A signal needs at least one channel"));
+        }
+        Ok(Signal {
+            samples: interleave(channels)?.into(),
+            channels: channels.len(),
+        })
+    }
+
+    /// Wrap samples that are already interleaved.
+    ///
+    /// # Arguments
+    /// samples: impl Into<Arc<[i32]>> - the interleaved samples
+    /// channels: usize - the number of channels in a frame
+    ///
+    /// # Errors
+    /// Returns an error if the number of samples isn't a whole number of frames
+    pub fn from_interleaved(
+        samples: impl Into<Arc<[i32]>>,
+        channels: usize,
+    ) -> Result<Self, anyhow::Error> {
+        let samples = samples.into();
+        if channels == 0 || samples.len() % channels != 0 {
+            return Err(anyhow::anyhow!(
+                "{} samples is not a whole number of {}-channel frames",
+                samples.len(),
+                channels
+            ));
+        }
+        Ok(Signal { samples, channels })
+    }
+
+    /// The number of channels in a frame.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// The number of frames in the signal.
+    pub fn frames(&self) -> usize {
+        match self.channels {
+            0 => 0,
+            channels => self.samples.len() / channels,
+        }
+    }
+}
+
+impl Deref for Signal {
+    type Target = [i32];
+
+    fn deref(&self) -> &[i32] {
+        &self.samples
+    }
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Play a signal without copying it.
+    ///
+    /// The output callback reads the samples straight from the signal. The output processors and
+    /// playback fades are not applied, since they would need a copy. This function blocks until
+    /// the signal has finished playing.
+    ///
+    /// # Errors
+    /// Returns an error if the number of channels does not match the device
+    /// Returns an error if the signal is blocked by the safety interlock
+    pub fn play_signal(&self, signal: &Signal) -> Result<(), anyhow::Error> {
+        self.check_signal(signal)?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        *self.output_buffer.lock().unwrap() = signal.clone();
+
+        self.play_gate.start();
+        self.play_gate.wait(&self.healthy)
+    }
+
+    /// Add a signal to the end of the output queue without copying it. See `enqueue`.
+    ///
+    /// # Errors
+    /// Returns an error if the number of channels does not match the device
+    /// Returns an error if the signal is blocked by the safety interlock
+    pub fn enqueue_signal(&self, signal: &Signal) -> Result<(), anyhow::Error> {
+        self.check_signal(signal)?;
+
+        // ensure the stream is running
+        self.ensure_stream_running(StreamControllerType::Output)?;
+
+        if signal.is_empty() {
+            return Ok(());
+        }
+
+        // hold the queue lock while starting, so the callback can't finish in between
+        let mut queue = self.output_queue.lock().unwrap();
+        queue.push_back(signal.clone());
+        self.play_gate.start();
+
+        Ok(())
+    }
+
+    fn check_signal(&self, signal: &Signal) -> Result<(), anyhow::Error> {
+        if signal.channels() != self.number_of_output_channels as usize {
+            return Err(anyhow::Error::msg("Number of channels does not match"));
+        }
+        let peak = signal
+            .iter()
+            .map(|&sample| sample.saturating_abs())
+            .max()
+            .unwrap_or(0);
+        self.check_interlock_level(peak)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clone_shares_samples() {
+        let signal = Signal::from_channels(&[vec![1, 2, 3], vec![4, 5, 6]]).unwrap();
+        assert_eq!(&signal[..], &[1, 4, 2, 5, 3, 6]);
+        assert_eq!(signal.frames(), 3);
+
+        let clone = signal.clone();
+        assert!(std::ptr::eq(signal.as_ptr(), clone.as_ptr()));
+    }
+
+    #[test]
+    fn test_from_interleaved() {
+        let signal = Signal::from_interleaved(vec![1, 2, 3, 4], 2).unwrap();
+        assert_eq!(signal.channels(), 2);
+        assert!(Signal::from_interleaved(vec![1, 2, 3], 2).is_err());
+        assert!(Signal::from_interleaved(vec![1, 2], 0).is_err());
+        assert!(Signal::from_channels(&[]).is_err());
+        assert_eq!(Signal::default().frames(), 0);
+    }
+}
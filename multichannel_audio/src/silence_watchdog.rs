@@ -0,0 +1,186 @@
+use std::sync::{mpsc, Mutex};
+use std::{error::Error, fmt};
+
+use crate::audio_class::AudioInstance;
+use crate::channel::InputChannel;
+
+/// What to do when a channel of a recording is silent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WatchdogAction {
+    /// Send a `SilentChannels` warning to the receiver from `silence_warnings` and return the
+    /// recording as usual
+    Warn,
+    /// Return a `SilentChannels` error instead of the recording
+    #[default]
+    Error,
+}
+
+/// Checks every channel of a `play_record` recording is above the noise floor.
+///
+/// A recording that is silent on a channel usually means an amplifier is off or a cable is
+/// broken, which otherwise isn't noticed until the analysis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SilenceWatchdog {
+    /// Channels with an RMS level below this are silent
+    pub threshold_dbfs: f64,
+    pub action: WatchdogAction,
+}
+
+impl Default for SilenceWatchdog {
+    fn default() -> Self {
+        SilenceWatchdog {
+            threshold_dbfs: -80.0,
+            action: WatchdogAction::Error,
+        }
+    }
+}
+
+/// The channels of a recording that stayed below the silence threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SilentChannels {
+    /// Each silent input channel and its RMS level in dBFS
+    pub channels: Vec<(InputChannel, f64)>,
+    pub threshold_dbfs: f64,
+}
+
+impl fmt::Display for SilentChannels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Recording is silent on")?;
+        for (i, (channel, level)) in self.channels.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{} {} ({:.1} dBFS)", separator, channel, level)?;
+        }
+        write!(
+            f,
+            ". Check the amplifiers and cables, or lower the threshold of {} dBFS.",
+            self.threshold_dbfs
+        )
+    }
+}
+
+impl Error for SilentChannels {}
+
+/// The watchdog settings of an audio instance.
+#[derive(Default)]
+pub(crate) struct SilenceMonitor {
+    watchdog: Mutex<Option<SilenceWatchdog>>,
+    warnings: Mutex<Option<mpsc::Sender<SilentChannels>>>,
+}
+
+impl AudioInstance {
+    /// Check the level of every recorded channel after `play_record`, or None to turn the check
+    /// off. The check is off by default.
+    pub fn set_silence_watchdog(&self, watchdog: Option<SilenceWatchdog>) {
+        *self.silence_monitor.watchdog.lock().unwrap() = watchdog;
+    }
+
+    /// Receive a `SilentChannels` warning whenever the watchdog finds silent channels and its
+    /// action is `WatchdogAction::Warn`.
+    ///
+    /// Only the most recently returned receiver gets warnings.
+    pub fn silence_warnings(&self) -> mpsc::Receiver<SilentChannels> {
+        let (sender, receiver) = mpsc::channel();
+        *self.silence_monitor.warnings.lock().unwrap() = Some(sender);
+        receiver
+    }
+
+    /// Run the silence watchdog on a recording, if it is turned on.
+    ///
+    /// # Errors
+    /// Returns `SilentChannels` if a channel is silent and the watchdog action is `WatchdogAction::Error`
+    pub(super) fn check_silence(
+        &self,
+        recording: &[Vec<i32>],
+        channels: &[InputChannel],
+    ) -> Result<(), anyhow::Error> {
+        let Some(watchdog) = *self.silence_monitor.watchdog.lock().unwrap() else {
+            return Ok(());
+        };
+
+        let channels = silent_channels(recording, channels, watchdog.threshold_dbfs);
+        if channels.is_empty() {
+            return Ok(());
+        }
+
+        let silent = SilentChannels {
+            channels,
+            threshold_dbfs: watchdog.threshold_dbfs,
+        };
+        match watchdog.action {
+            WatchdogAction::Error => Err(silent.into()),
+            WatchdogAction::Warn => {
+                if let Some(ref sender) = *self.silence_monitor.warnings.lock().unwrap() {
+                    let _ = sender.send(silent);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Find the channels of a recording with an RMS level below the threshold.
+///
+/// # Arguments
+/// recording: &[Vec<i32>] - the recorded channels
+/// channels: &[InputChannel] - the input channel each recorded channel came from
+/// threshold_dbfs: f64 - the level below which a channel is silent
+fn silent_channels(
+    recording: &[Vec<i32>],
+    channels: &[InputChannel],
+    threshold_dbfs: f64,
+) -> Vec<(InputChannel, f64)> {
+    recording
+        .iter()
+        .zip(channels)
+        .filter_map(|(samples, &channel)| {
+            let level = rms_dbfs(samples);
+            (level < threshold_dbfs).then_some((channel, level))
+        })
+        .collect()
+}
+
+fn rms_dbfs(samples: &[i32]) -> f64 {
+    if samples.is_empty() {
+        return f64::NEG_INFINITY;
+    }
+    let mean_square = samples
+        .iter()
+        .map(|&sample| (sample as f64 / i32::MAX as f64).powi(2))
+        .sum::<f64>()
+        / samples.len() as f64;
+    10.0 * mean_square.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silent_channels() {
+        let loud = vec![i32::MAX / 10, -i32::MAX / 10];
+        let quiet = vec![1000, -1000];
+        let silent = silent_channels(
+            &[loud, quiet, vec![0, 0]],
+            &[InputChannel(2), InputChannel(5), InputChannel(6)],
+            -60.0,
+        );
+
+        assert_eq!(silent.len(), 2);
+        assert_eq!(silent[0].0, InputChannel(5));
+        assert!((silent[0].1 + 126.6).abs() < 0.1);
+        assert_eq!(silent[1].0, InputChannel(6));
+        assert_eq!(silent[1].1, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_silent_channels_message() {
+        let silent = SilentChannels {
+            channels: vec![(InputChannel(3), -95.0)],
+            threshold_dbfs: -80.0,
+        };
+        assert_eq!(
+            silent.to_string(),
+            "Recording is silent on input 3 (-95.0 dBFS). Check the amplifiers and cables, or lower the threshold of -80 dBFS."
+        );
+    }
+}
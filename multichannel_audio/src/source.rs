@@ -0,0 +1,224 @@
+use std::f64::consts::TAU;
+#[cfg(feature = "device")]
+use std::time::Duration;
+
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+use crate::sample_formats::Sample;
+
+/// The length of each chunk generated ahead of the output callback, in seconds.
+#[cfg(feature = "device")]
+const CHUNK_SECONDS: f64 = 0.1;
+
+/// A signal generated one frame at a time, e.g. an oscillator, noise or a sweep generator.
+///
+/// Sources are played with `AudioInstance::play_source` without materializing the whole signal,
+/// so they can be infinite or procedurally generated.
+pub trait SignalSource {
+    /// Write the next frame, one f32 sample from -1.0 to 1.0 per output channel.
+    fn next_frame(&mut self, out: &mut [f32]);
+
+    /// Whether the source has no more frames. Infinite sources never finish.
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+impl<F: FnMut(&mut [f32])> SignalSource for F {
+    fn next_frame(&mut self, out: &mut [f32]) {
+        self(out)
+    }
+}
+
+/// A sine wave played on every channel. The phase carries on between calls to `play_source`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SineSource {
+    frequency: f64,
+    amplitude: f64,
+    sample_rate: u32,
+    phase: f64,
+}
+
+impl SineSource {
+    /// # Arguments
+    /// frequency: f64 - the frequency of the sine wave in Hz
+    /// amplitude: f64 - the peak amplitude, from 0.0 to 1.0
+    /// sample_rate: u32 - the sample rate the source is played at
+    pub fn new(frequency: f64, amplitude: f64, sample_rate: u32) -> Self {
+        SineSource {
+            frequency,
+            amplitude,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+}
+
+impl SignalSource for SineSource {
+    fn next_frame(&mut self, out: &mut [f32]) {
+        let sample = (self.amplitude * self.phase.sin()) as f32;
+        out.fill(sample);
+        self.phase = (self.phase + TAU * self.frequency / self.sample_rate as f64) % TAU;
+    }
+}
+
+/// Play the samples of an iterator on every channel, e.g. f64 samples from -1.0 to 1.0 or
+/// full-scale i32 samples. The source finishes when the iterator does.
+pub struct IterSource<I: Iterator> {
+    samples: I,
+    next: Option<I::Item>,
+}
+
+impl<I: Iterator> IterSource<I>
+where
+    I::Item: Sample,
+{
+    pub fn new(samples: impl IntoIterator<IntoIter = I>) -> Self {
+        let mut samples = samples.into_iter();
+        let next = samples.next();
+        IterSource { samples, next }
+    }
+}
+
+impl<I: Iterator> SignalSource for IterSource<I>
+where
+    I::Item: Sample,
+{
+    fn next_frame(&mut self, out: &mut [f32]) {
+        let sample = self.next.map_or(0.0, |s| f32::from_i32(s.to_i32()));
+        out.fill(sample);
+        self.next = self.samples.next();
+    }
+
+    fn is_finished(&self) -> bool {
+        self.next.is_none()
+    }
+}
+
+/// Generate up to `frames` frames of a source, e.g. to save part of it to a file.
+///
+/// # Returns
+/// One vector of full-scale i32 samples per channel, shorter than `frames` if the source finished
+pub fn render_source(
+    source: &mut impl SignalSource,
+    channels: usize,
+    frames: usize,
+) -> Vec<Vec<i32>> {
+    let mut chunk = vec![Vec::with_capacity(frames); channels];
+    let mut frame = vec![0.0f32; channels];
+    for _ in 0..frames {
+        if source.is_finished() {
+            break;
+        }
+        frame.fill(0.0);
+        source.next_frame(&mut frame);
+        for (channel, &sample) in chunk.iter_mut().zip(frame.iter()) {
+            channel.push(sample.to_i32());
+        }
+    }
+    chunk
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Play a generated signal without materializing it.
+    ///
+    /// The source is rendered in short chunks on the calling thread, and only one chunk is queued
+    /// behind the one playing, so the chunks play back-to-back through the output queue without
+    /// gaps. Blocks until the source has finished playing.
+    ///
+    /// # Arguments
+    /// source: &mut impl SignalSource - the signal to play, with one sample per output channel in
+    /// each frame. It can be played again to carry on where it stopped.
+    /// duration: Option<f64> - the number of seconds to play for, or None to play until the source
+    /// finishes
+    ///
+    /// # Errors
+    /// Returns an error if the duration is negative
+    /// Returns an error if a chunk is blocked by the safety interlock
+    pub fn play_source(
+        &self,
+        source: &mut impl SignalSource,
+        duration: Option<f64>,
+    ) -> Result<(), anyhow::Error> {
+        let mut remaining = match duration {
+            Some(duration) if duration.is_nan() || duration < 0.0 => {
+                return Err(anyhow::anyhow!("The duration must not be negative"));
+            }
+            Some(duration) => Some((duration * self.sample_rate as f64).round() as usize),
+            None => None,
+        };
+
+        let channels = self.number_of_output_channels as usize;
+        let chunk_frames = (self.sample_rate as f64 * CHUNK_SECONDS) as usize;
+        while remaining != Some(0) && !source.is_finished() {
+            let frames = remaining.map_or(chunk_frames, |remaining| remaining.min(chunk_frames));
+            let chunk = render_source(source, channels, frames);
+            if let Some(ref mut remaining) = remaining {
+                *remaining -= frames;
+            }
+
+            // keep a single chunk queued behind the one playing
+            while self.queued() > 0 {
+                self.check_healthy()?;
+                std::thread::sleep(Duration::from_millis(2));
+            }
+            if let Err(error) = self.enqueue(chunk) {
+                self.clear_queue();
+                return Err(error);
+            }
+        }
+
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_source() {
+        let mut source = IterSource::new(vec![0.5f64, -0.5, 1.0]);
+        let chunk = render_source(&mut source, 2, 2);
+        assert_eq!(chunk[0], vec![0.5f32.to_i32(), (-0.5f32).to_i32()]);
+        assert_eq!(chunk[0], chunk[1]);
+
+        let chunk = render_source(&mut source, 2, 2);
+        assert_eq!(chunk[1], vec![i32::MAX]);
+        assert!(source.is_finished());
+
+        let mut sine = SineSource::new(12000.0, 1.0, 48000);
+        let chunk = render_source(&mut sine, 1, 4);
+        assert_eq!(chunk[0][0], 0);
+        assert_eq!(chunk[0][1], i32::MAX);
+        assert!(chunk[0][2].abs() < 1000);
+    }
+
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_play_source() {
+        use crate::backend::MockDevice;
+        use crate::builder::AudioInstanceBuilder;
+
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(1, 2))
+            .build()
+            .unwrap();
+
+        let mut frames = 0;
+        let mut counter = |out: &mut [f32]| {
+            assert_eq!(out.len(), 2);
+            frames += 1;
+        };
+        audio_instance
+            .play_source(&mut counter, Some(0.25))
+            .unwrap();
+        assert_eq!(frames, audio_instance.sample_rate() as usize / 4);
+
+        let mut source = IterSource::new(std::iter::repeat(0.1f64).take(1000));
+        audio_instance.play_source(&mut source, None).unwrap();
+        assert!(source.is_finished());
+        assert!(audio_instance.play_source(&mut source, Some(-1.0)).is_err());
+    }
+}
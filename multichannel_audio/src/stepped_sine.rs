@@ -0,0 +1,387 @@
+use std::f64::consts::TAU;
+use std::ops::Range;
+
+#[cfg(feature = "device")]
+use crate::audio_class::AudioInstance;
+#[cfg(feature = "device")]
+use crate::channel::OutputSelector;
+use crate::conversions::db_to_linear;
+#[cfg(feature = "device")]
+use crate::distortion::tone_level_dbfs;
+use crate::fades::apply_fades;
+#[cfg(feature = "device")]
+use crate::methods::format_signal_for_multichannel;
+
+/// The length of the fades at each end of a step in seconds, so the frequency changes don't click.
+const FADE_SECONDS: f64 = 0.005;
+/// The fewest periods of a tone a hold can have and still be measured.
+const MIN_HOLD_PERIODS: f64 = 8.0;
+
+/// The timing of one step of a `SteppedSine`, in frames from the start of the sequence.
+///
+/// Each step fades in, settles, holds and then fades out. Only the hold is meant to be analysed;
+/// the settle lets the device, the room and any filters reach their steady state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SineStep {
+    /// The frequency in Hz
+    pub frequency: f64,
+    /// The first frame of the step
+    pub start: usize,
+    /// The number of frames from the start of the step to the start of the hold, including the
+    /// fade in
+    pub settle_frames: usize,
+    /// The number of frames to analyse
+    pub hold_frames: usize,
+}
+
+impl SineStep {
+    /// The frames of the hold, from the start of the sequence.
+    pub fn hold_range(&self) -> Range<usize> {
+        let hold_start = self.start + self.settle_frames;
+        hold_start..hold_start + self.hold_frames
+    }
+}
+
+/// A sequence of sines, one frequency at a time, e.g. to measure a frequency response or
+/// distortion with a high signal-to-noise ratio at each frequency.
+///
+/// # Example
+/// ```
+/// use multichannel_audio::stepped_sine::{log_spaced_frequencies, SteppedSine};
+///
+/// let frequencies = log_spaced_frequencies(100.0, 10000.0, 3);
+/// let sequence = SteppedSine::new(&frequencies, 48000, 0.2, 0.5, -12.0).unwrap();
+/// let signal = sequence.generate();
+/// assert_eq!(signal.len(), sequence.len());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SteppedSine {
+    steps: Vec<SineStep>,
+    sample_rate: u32,
+    level_dbfs: f64,
+    fade_frames: usize,
+}
+
+impl SteppedSine {
+    /// Lay out a sequence of steps.
+    ///
+    /// # Arguments
+    /// frequencies: &[f64] - the frequency of each step in Hz, in the order they are played
+    /// fs: u32 - the sample rate
+    /// settle_seconds: f64 - the time each step plays before its hold
+    /// hold_seconds: f64 - the time each step is held for analysis
+    /// level_dbfs: f64 - the peak level of the sines in dBFS
+    ///
+    /// # Errors
+    /// Returns an error if there are no frequencies, or one is not between 0 Hz and the Nyquist
+    /// frequency
+    /// Returns an error if the hold is shorter than 8 periods of a frequency, or the settle is
+    /// shorter than the fade in
+    /// Returns an error if the level is above 0 dBFS
+    pub fn new(
+        frequencies: &[f64],
+        fs: u32,
+        settle_seconds: f64,
+        hold_seconds: f64,
+        level_dbfs: f64,
+    ) -> Result<Self, anyhow::Error> {
+        if frequencies.is_empty() {
+            return Err(anyhow::Error::msg("A stepped sine needs at least one step"));
+        }
+        if level_dbfs.is_nan() || level_dbfs > 0.0 {
+            return Err(anyhow::Error::msg("The level must not be above 0 dBFS"));
+        }
+        let fade_frames = (FADE_SECONDS * fs as f64) as usize;
+        let settle_frames = (settle_seconds.max(0.0) * fs as f64) as usize;
+        let hold_frames = (hold_seconds.max(0.0) * fs as f64) as usize;
+        if settle_frames < fade_frames {
+            return Err(anyhow::anyhow!(
+                "The settle time must be at least {} s",
+                FADE_SECONDS
+            ));
+        }
+
+        let nyquist = fs as f64 / 2.0;
+        let mut steps = Vec::with_capacity(frequencies.len());
+        let mut start = 0;
+        for &frequency in frequencies {
+            if frequency.is_nan() || frequency <= 0.0 || frequency >= nyquist {
+                return Err(anyhow::anyhow!(
+                    "The frequency {} Hz is not between 0 Hz and {} Hz",
+                    frequency,
+                    nyquist
+                ));
+            }
+            if (hold_frames as f64) * frequency / (fs as f64) < MIN_HOLD_PERIODS {
+                return Err(anyhow::anyhow!(
+                    "A hold of {} s is too short to measure {} Hz",
+                    hold_seconds,
+                    frequency
+                ));
+            }
+            steps.push(SineStep {
+                frequency,
+                start,
+                settle_frames,
+                hold_frames,
+            });
+            start += settle_frames + hold_frames + fade_frames;
+        }
+
+        Ok(SteppedSine {
+            steps,
+            sample_rate: fs,
+            level_dbfs,
+            fade_frames,
+        })
+    }
+
+    pub fn steps(&self) -> &[SineStep] {
+        &self.steps
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The length of the whole sequence in frames.
+    pub fn len(&self) -> usize {
+        self.steps
+            .last()
+            .map_or(0, |step| self.step_length(step) + step.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Generate one step on its own, from its fade in to the end of its fade out.
+    pub fn step_signal(&self, step: &SineStep) -> Vec<i32> {
+        let amplitude = db_to_linear(self.level_dbfs) * i32::MAX as f64;
+        let omega = TAU * step.frequency / self.sample_rate as f64;
+        let mut signal: Vec<i32> = (0..self.step_length(step))
+            .map(|n| (amplitude * (omega * n as f64).sin()).round() as i32)
+            .collect();
+        apply_fades(&mut signal, 1, self.fade_frames, self.fade_frames);
+        signal
+    }
+
+    /// Generate the whole sequence, each step following the last.
+    pub fn generate(&self) -> Vec<i32> {
+        self.steps
+            .iter()
+            .flat_map(|step| self.step_signal(step))
+            .collect()
+    }
+
+    /// Take the hold of each step out of a recording of the sequence.
+    ///
+    /// # Arguments
+    /// recording: &[T] - one channel, recorded from the start of the sequence
+    ///
+    /// # Errors
+    /// Returns an error if the recording ends before the last hold
+    pub fn slice<'a, T>(&self, recording: &'a [T]) -> Result<Vec<&'a [T]>, anyhow::Error> {
+        self.steps
+            .iter()
+            .map(|step| {
+                recording.get(step.hold_range()).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "The recording of {} frames ends before the hold of the {} Hz step",
+                        recording.len(),
+                        step.frequency
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn step_length(&self, step: &SineStep) -> usize {
+        step.settle_frames + step.hold_frames + self.fade_frames
+    }
+}
+
+/// Frequencies spaced evenly on a log scale, e.g. for the steps of a `SteppedSine`.
+///
+/// # Arguments
+/// start: f64 - the first frequency in Hz
+/// end: f64 - the last frequency in Hz. It is included if it falls on a step
+/// steps_per_octave: u32 - the number of frequencies in each octave, e.g. 3 for third-octaves
+///
+/// # Returns
+/// The frequencies from start to end, or an empty vector if the range or step is invalid
+pub fn log_spaced_frequencies(start: f64, end: f64, steps_per_octave: u32) -> Vec<f64> {
+    if !(start > 0.0 && end >= start) || steps_per_octave == 0 {
+        return Vec::new();
+    }
+    let steps = (steps_per_octave as f64 * (end / start).log2() + 1e-9).floor() as usize;
+    (0..=steps)
+        .map(|k| start * 2f64.powf(k as f64 / steps_per_octave as f64))
+        .collect()
+}
+
+/// The recording of one step of a `SteppedSine` on every input channel.
+#[cfg(feature = "device")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepResponse {
+    pub step: SineStep,
+    /// The hold of each input channel
+    pub recording: Vec<Vec<i32>>,
+    /// The level of the tone in the hold of each input channel in dBFS
+    pub levels_dbfs: Vec<f64>,
+}
+
+#[cfg(feature = "device")]
+impl AudioInstance {
+    /// Play a stepped sine on one output while recording, and return the hold of every step.
+    ///
+    /// The sequence is played in one go and the recording is sliced with `SteppedSine::slice`, so
+    /// the settle time must be longer than the round trip latency of the device, or the holds will
+    /// catch the end of the previous step.
+    ///
+    /// # Arguments
+    /// sequence: &SteppedSine - the steps to play
+    /// output: impl OutputSelector - the output channel to play on, by number or label
+    ///
+    /// # Errors
+    /// Returns an error if the sequence was made for a different sample rate
+    /// Returns an error if the output channel is out of range or an unknown label
+    /// Returns an error if the playback is blocked by the safety interlock
+    ///
+    /// # Example
+    /// ```no_run
+    /// use multichannel_audio::audio_class::AudioInstance;
+    /// use multichannel_audio::channel::OutputChannel;
+    /// use multichannel_audio::stepped_sine::{log_spaced_frequencies, SteppedSine};
+    ///
+    /// let audio_instance = AudioInstance::new(48000).unwrap();
+    /// let frequencies = log_spaced_frequencies(20.0, 20000.0, 3);
+    /// let sequence = SteppedSine::new(&frequencies, 48000, 0.2, 0.5, -12.0).unwrap();
+    /// for response in audio_instance
+    ///     .measure_stepped_sine(&sequence, OutputChannel(1))
+    ///     .unwrap()
+    /// {
+    ///     println!("{} Hz: {:?} dBFS", response.step.frequency, response.levels_dbfs);
+    /// }
+    /// ```
+    pub fn measure_stepped_sine(
+        &self,
+        sequence: &SteppedSine,
+        output: impl OutputSelector,
+    ) -> Result<Vec<StepResponse>, anyhow::Error> {
+        if sequence.sample_rate() != self.sample_rate {
+            return Err(anyhow::anyhow!(
+                "The sequence is for {} Hz but the device runs at {} Hz",
+                sequence.sample_rate(),
+                self.sample_rate
+            ));
+        }
+        let output_data = format_signal_for_multichannel(
+            sequence.generate(),
+            self.output_channel(output)?,
+            self.number_of_output_channels as usize,
+        )?;
+        let recorded_data = self.play_record(output_data)?;
+
+        let holds = recorded_data
+            .iter()
+            .map(|channel| sequence.slice(channel))
+            .collect::<Result<Vec<_>, _>>()?;
+        sequence
+            .steps()
+            .iter()
+            .enumerate()
+            .map(|(index, step)| {
+                let recording: Vec<Vec<i32>> = holds
+                    .iter()
+                    .map(|channel| channel[index].to_vec())
+                    .collect();
+                let levels_dbfs = recording
+                    .iter()
+                    .map(|channel| tone_level_dbfs(channel, self.sample_rate, step.frequency))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(StepResponse {
+                    step: *step,
+                    recording,
+                    levels_dbfs,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stepped_sine_timing() {
+        let sequence = SteppedSine::new(&[100.0, 1000.0], 48000, 0.1, 0.2, -6.0).unwrap();
+        let steps = sequence.steps();
+        assert_eq!(steps[0].start, 0);
+        // settle, hold and fade out
+        assert_eq!(steps[1].start, 4800 + 9600 + 240);
+        assert_eq!(steps[1].hold_range(), 19440..29040);
+        assert_eq!(sequence.len(), 2 * 14640);
+
+        let signal = sequence.generate();
+        assert_eq!(signal.len(), sequence.len());
+        // silent at the joins, full level in the holds
+        assert_eq!(signal[0], 0);
+        assert_eq!(signal[14640], 0);
+        let holds = sequence.slice(&signal).unwrap();
+        assert_eq!(holds[1], &signal[19440..29040]);
+        let peak = holds[1]
+            .iter()
+            .map(|sample| sample.unsigned_abs())
+            .max()
+            .unwrap();
+        assert!((peak as f64 / i32::MAX as f64 - 0.5).abs() < 0.01);
+
+        assert!(sequence.slice(&signal[..20000]).is_err());
+        assert!(SteppedSine::new(&[], 48000, 0.1, 0.2, -6.0).is_err());
+        assert!(SteppedSine::new(&[30.0], 48000, 0.1, 0.2, -6.0).is_err());
+        assert!(SteppedSine::new(&[100.0], 48000, 0.001, 0.2, -6.0).is_err());
+        assert!(SteppedSine::new(&[100.0], 48000, 0.1, 0.2, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_log_spaced_frequencies() {
+        let frequencies = log_spaced_frequencies(125.0, 1000.0, 2);
+        assert_eq!(frequencies.len(), 7);
+        assert!((frequencies[1] - 125.0 * 2f64.sqrt()).abs() < 1e-9);
+        assert!((frequencies[6] - 1000.0).abs() < 1e-9);
+        assert!(log_spaced_frequencies(1000.0, 100.0, 3).is_empty());
+    }
+
+    #[cfg(feature = "device")]
+    #[test]
+    fn test_measure_stepped_sine() {
+        use crate::backend::{ChannelModel, MockDevice};
+        use crate::builder::AudioInstanceBuilder;
+        use crate::channel::{InputChannel, OutputChannel};
+
+        // input 2 hears the output at half the level, a little late
+        let speaker = ChannelModel::from_output(OutputChannel(1));
+        let device = MockDevice::new(2, 2)
+            .channel(InputChannel(1), speaker)
+            .channel(InputChannel(2), speaker.delay(64).gain(0.5));
+        let audio_instance = AudioInstanceBuilder::new().mock(device).build().unwrap();
+
+        let sequence = SteppedSine::new(&[500.0, 2000.0], 48000, 0.05, 0.1, -6.0).unwrap();
+        let responses = audio_instance
+            .measure_stepped_sine(&sequence, OutputChannel(1))
+            .unwrap();
+        assert_eq!(responses.len(), 2);
+        for response in &responses {
+            assert_eq!(response.recording[0].len(), 4800);
+            assert!((response.levels_dbfs[0] + 6.0).abs() < 0.2);
+            assert!((response.levels_dbfs[0] - response.levels_dbfs[1] - 6.02).abs() < 0.1);
+        }
+
+        let other_rate = SteppedSine::new(&[500.0], 44100, 0.05, 0.1, -6.0).unwrap();
+        assert!(audio_instance
+            .measure_stepped_sine(&other_rate, OutputChannel(1))
+            .is_err());
+    }
+}
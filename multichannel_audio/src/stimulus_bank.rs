@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::methods;
+
+/// Header information about a stimulus, read without loading its samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StimulusInfo {
+    pub channels: u16,
+    /// The length of the stimulus in samples per channel
+    pub length: usize,
+    pub sample_rate: u32,
+}
+
+/// A directory of WAV stimuli, looked up by name and loaded on demand.
+///
+/// Indexing a directory only lists the files, so large listening-test stimulus sets are cheap to
+/// open. Each stimulus is read, validated against the sample rate of the bank and cached the first
+/// time it is requested.
+pub struct StimulusBank {
+    sample_rate: u32,
+    paths: BTreeMap<String, PathBuf>,
+    cache: Mutex<HashMap<String, Arc<Vec<Vec<i32>>>>>,
+}
+
+impl StimulusBank {
+    /// Index every `.wav` file in a directory. Stimuli are named by their file name without the
+    /// extension. Subdirectories are not searched.
+    ///
+    /// # Arguments
+    /// path: &Path - the directory of stimuli
+    /// fs: u32 - the sample rate every stimulus must have
+    ///
+    /// # Errors
+    /// Returns an error if the directory can't be read
+    pub fn from_dir(path: &Path, fs: u32) -> Result<Self, anyhow::Error> {
+        let mut paths = BTreeMap::new();
+        for entry in std::fs::read_dir(path)? {
+            let file_path = entry?.path();
+            let is_wav = file_path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"));
+            if !file_path.is_file() || !is_wav {
+                continue;
+            }
+
+            if let Some(name) = file_path.file_stem().and_then(|stem| stem.to_str()) {
+                paths.insert(name.to_string(), file_path.clone());
+            }
+        }
+
+        Ok(StimulusBank {
+            sample_rate: fs,
+            paths,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The names of every stimulus in the bank, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.paths.keys().map(|name| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.paths.contains_key(name)
+    }
+
+    /// Read the header of a stimulus and check its sample rate, without loading the samples.
+    ///
+    /// # Errors
+    /// Returns an error if the stimulus doesn't exist, can't be read or has the wrong sample rate
+    pub fn info(&self, name: &str) -> Result<StimulusInfo, anyhow::Error> {
+        let reader = hound::WavReader::open(self.path(name)?)?;
+        let spec = reader.spec();
+        if spec.sample_rate != self.sample_rate {
+            return Err(anyhow::anyhow!(
+                "Stimulus {} has a sample rate of {} Hz, expected {} Hz",
+                name,
+                spec.sample_rate,
+                self.sample_rate
+            ));
+        }
+
+        Ok(StimulusInfo {
+            channels: spec.channels,
+            length: reader.duration() as usize,
+            sample_rate: spec.sample_rate,
+        })
+    }
+
+    /// Get a stimulus by name, loading it from disk if it is not cached.
+    ///
+    /// # Returns
+    /// A vector of channels where each channel is a vector of samples
+    ///
+    /// # Errors
+    /// Returns an error if the stimulus doesn't exist, can't be read or has the wrong sample rate
+    pub fn get(&self, name: &str) -> Result<Arc<Vec<Vec<i32>>>, anyhow::Error> {
+        if let Some(stimulus) = self.cache.lock().unwrap().get(name) {
+            return Ok(Arc::clone(stimulus));
+        }
+
+        // the reader would resample a stimulus at the wrong sample rate
+        self.info(name)?;
+
+        // load without holding the lock so other stimuli can be fetched in the meantime
+        let stimulus = Arc::new(
+            methods::read_wave_file_channels(self.path(name)?, self.sample_rate)
+                .map_err(|e| anyhow::anyhow!("Failed to load stimulus {}: {}", name, e))?,
+        );
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), Arc::clone(&stimulus));
+        Ok(stimulus)
+    }
+
+    /// Check every stimulus in the bank can be used, without loading the samples.
+    ///
+    /// # Returns
+    /// The names of the stimuli that failed and why
+    pub fn validate(&self) -> Vec<(String, anyhow::Error)> {
+        self.names()
+            .filter_map(|name| self.info(name).err().map(|e| (name.to_string(), e)))
+            .collect()
+    }
+
+    /// Remove a stimulus from the cache. It is loaded again the next time it is requested.
+    pub fn evict(&self, name: &str) {
+        self.cache.lock().unwrap().remove(name);
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    fn path(&self, name: &str) -> Result<&Path, anyhow::Error> {
+        self.paths
+            .get(name)
+            .map(|path| path.as_path())
+            .ok_or(anyhow::anyhow!("Stimulus {} is not in the bank", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_bank_dir(test_name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "multichannel_audio_{}_{}",
+            test_name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let save = |name: &str, data: Vec<Vec<i32>>, fs: u32| {
+            methods::save_channels_to_wav(data, dir.join(name).to_str().unwrap(), fs).unwrap();
+        };
+        save("tone.wav", vec![vec![1, 2, 3], vec![4, 5, 6]], 48000);
+        save("noise.WAV", vec![vec![7, 8]], 48000);
+        save("wrong_rate.wav", vec![vec![9]], 44100);
+        std::fs::write(dir.join("notes.txt"), "not a stimulus").unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn test_from_dir_indexes_wav_files() {
+        let dir = create_bank_dir("index");
+        let bank = StimulusBank::from_dir(&dir, 48000).unwrap();
+
+        assert_eq!(
+            bank.names().collect::<Vec<_>>(),
+            vec!["noise", "tone", "wrong_rate"]
+        );
+        assert!(!bank.contains("notes"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_loads_and_caches() {
+        let dir = create_bank_dir("get");
+        let bank = StimulusBank::from_dir(&dir, 48000).unwrap();
+
+        let tone = bank.get("tone").unwrap();
+        assert_eq!(tone.len(), 2);
+        assert_eq!(tone[1].len(), 3);
+        assert!(Arc::ptr_eq(&tone, &bank.get("tone").unwrap()));
+        assert!(bank.get("missing").is_err());
+        assert!(bank.get("wrong_rate").is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_reports_wrong_sample_rate() {
+        let dir = create_bank_dir("validate");
+        let bank = StimulusBank::from_dir(&dir, 48000).unwrap();
+
+        assert_eq!(bank.info("tone").unwrap().length, 3);
+        let failures = bank.validate();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "wrong_rate");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
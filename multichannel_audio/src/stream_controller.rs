@@ -1,32 +1,391 @@
+use std::collections::VecDeque;
+use std::fmt;
 use std::fmt::Formatter;
-use std::sync::{mpsc, Arc, Mutex};
-use std::{fmt, thread};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{InputCallbackInfo, OutputCallbackInfo, Stream};
+use cpal::{
+    InputCallbackInfo, InputStreamTimestamp, OutputCallbackInfo, OutputStreamTimestamp, Stream,
+    StreamInstant,
+};
 
-lazy_static::lazy_static!(
-    static ref INPUT_STREAM_STATE: Arc<Mutex<StreamState>> = Arc::new(Mutex::new(StreamState::Stopped));
-    static ref OUTPUT_STREAM_STATE: Arc<Mutex<StreamState>> = Arc::new(Mutex::new(StreamState::Stopped));
-);
+use crate::backend::{AudioBackend, StreamState, StreamWorker};
+use crate::callback_load::{CallbackMonitor, StreamDirection};
+use crate::channel::InputChannel;
+use crate::device_monitor::disconnected_error;
+use crate::dither::{quantize, Dither, DITHER_SEED};
+use crate::events::{stream_error_handler, AudioEvent, EventHub};
+use crate::frame_queue::{FrameQueueSlot, FrameRing};
+use crate::input_processing::{InputChain, InputChainLane};
+use crate::limiter::{Limiter, LimiterLane};
+use crate::mixer::{Mixer, MixerLane};
+use crate::passthrough::{Passthrough, PassthroughLane};
+use crate::pre_record::PreRecordBuffer;
+use crate::progress::ProgressMonitor;
+use crate::sample_formats::Sample;
+use crate::signal::Signal;
+use crate::timestamp_map::BufferTimestamp;
+use crate::trigger::LevelTrigger;
+
+/// Call a stream building function with the sample type matching a cpal sample format.
+macro_rules! with_sample_type {
+    ($sample_format:expr, $function:ident($($arg:expr),* $(,)?)) => {
+        match $sample_format {
+            cpal::SampleFormat::I8 => $function::<i8>($($arg),*),
+            cpal::SampleFormat::I16 => $function::<i16>($($arg),*),
+            cpal::SampleFormat::I32 => $function::<i32>($($arg),*),
+            cpal::SampleFormat::U8 => $function::<u8>($($arg),*),
+            cpal::SampleFormat::U16 => $function::<u16>($($arg),*),
+            cpal::SampleFormat::F32 => $function::<f32>($($arg),*),
+            cpal::SampleFormat::F64 => $function::<f64>($($arg),*),
+            sample_format => Err(anyhow::anyhow!(
+                "Sample format {} is not supported",
+                sample_format
+            )),
+        }
+    };
+}
+
+pub(crate) use with_sample_type;
+
+/// Gate between the user thread and the output callback signalling whether audio is playing.
+///
+/// The output callback only touches the atomic flag, so it never blocks on a mutex the user
+/// thread may be holding. The mutex and condvar are only used by the user thread to sleep while
+/// waiting. Since the callback notifies without taking the lock, a wakeup can be missed, so
+/// waiting also polls the flag on a short timeout, which is also when it checks that the device
+/// is still connected.
+pub(crate) struct PlayGate {
+    playing: AtomicBool,
+    lock: Mutex<()>,
+    cvar: Condvar,
+}
+
+impl PlayGate {
+    pub fn new(playing: bool) -> Self {
+        PlayGate {
+            playing: AtomicBool::new(playing),
+            lock: Mutex::new(()),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /// Start playing. Called from the user thread.
+    pub fn start(&self) {
+        self.playing.store(true, Ordering::Release);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Acquire)
+    }
+
+    /// Stop playing and wake any waiting threads. Called from the output callback.
+    ///
+    /// Returns true if the gate was playing.
+    pub fn finish(&self) -> bool {
+        let was_playing = self
+            .playing
+            .compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        if was_playing {
+            self.cvar.notify_all();
+        }
+        was_playing
+    }
+
+    /// Block until the output callback has finished playing.
+    ///
+    /// # Arguments
+    /// healthy: &AtomicBool - cleared when the device is disconnected
+    ///
+    /// # Errors
+    /// Returns an error if the device is disconnected first. The playback is abandoned
+    pub fn wait(&self, healthy: &AtomicBool) -> Result<(), anyhow::Error> {
+        let mut guard = self.lock.lock().unwrap();
+        while self.is_playing() {
+            if !healthy.load(Ordering::Acquire) {
+                self.playing.store(false, Ordering::Release);
+                return Err(disconnected_error());
+            }
+            guard = self
+                .cvar
+                .wait_timeout(guard, HEALTH_CHECK_INTERVAL)
+                .unwrap()
+                .0;
+        }
+        Ok(())
+    }
+}
+
+/// Shared between the user thread and the output callback to start playback at a stream time.
+///
+/// The callback records the stream time of every callback against `Instant::now()`, so the user
+/// thread can convert an `Instant` to a stream time. The mutexes are only locked with `try_lock` in
+/// the callback so it never blocks.
+#[derive(Default)]
+pub(crate) struct PlaybackSchedule {
+    start: Mutex<Option<StreamInstant>>,
+    reference: Mutex<Option<(Instant, StreamInstant)>>,
+    /// The time the first sample of the last buffer to start was played, on the system clock
+    started: Mutex<Option<Instant>>,
+}
+
+impl PlaybackSchedule {
+    /// Start the next buffer at the given stream time. Called from the user thread.
+    pub fn schedule(&self, start: StreamInstant) {
+        *self.start.lock().unwrap() = Some(start);
+    }
+
+    /// The stream time of the most recent output callback and the instant it ran.
+    pub fn reference(&self) -> Option<(Instant, StreamInstant)> {
+        *self.reference.lock().unwrap()
+    }
+
+    /// Record the time of a callback. Called from the output callback.
+    fn update_reference(&self, callback: StreamInstant) {
+        if let Ok(mut reference) = self.reference.try_lock() {
+            *reference = Some((Instant::now(), callback));
+        }
+    }
+
+    /// The time the first sample of the last buffer to start was played, on the system clock.
+    pub fn started(&self) -> Option<Instant> {
+        *self.started.lock().unwrap()
+    }
+
+    /// Forget the start of the last buffer. Called from the user thread before playing.
+    pub fn clear_started(&self) {
+        *self.started.lock().unwrap() = None;
+    }
+
+    /// Record when a buffer starts playing. Called from the output callback.
+    fn set_started(&self, started: Instant) {
+        if let Ok(mut current) = self.started.try_lock() {
+            *current = Some(started);
+        }
+    }
+
+    /// Take the scheduled start, if any. Called from the output callback.
+    fn take_start(&self) -> Option<StreamInstant> {
+        self.start
+            .try_lock()
+            .ok()
+            .and_then(|mut start| start.take())
+    }
+}
+
+/// A continuous background signal mixed underneath everything the output callback plays.
+///
+/// The user thread hands over a new signal and sets `changed`. The callback only picks it up with
+/// `try_lock`, and keeps its own copy so it never holds the lock while writing samples.
+pub(crate) struct BackgroundLane {
+    /// The next interleaved background signal. An empty signal stops the background
+    signal: Mutex<Option<Vec<i32>>>,
+    changed: AtomicBool,
+    /// The linear gain as the bits of an f32
+    gain: AtomicU32,
+}
+
+impl Default for BackgroundLane {
+    fn default() -> Self {
+        BackgroundLane {
+            signal: Mutex::new(None),
+            changed: AtomicBool::new(false),
+            gain: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+}
+
+impl BackgroundLane {
+    /// Replace the background signal. Called from the user thread.
+    pub fn set_signal(&self, signal: Vec<i32>) {
+        *self.signal.lock().unwrap() = Some(signal);
+        self.changed.store(true, Ordering::Release);
+    }
+
+    pub fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    /// Take a new background signal if one has been set. Called from the output callback.
+    fn take_signal(&self) -> Option<Vec<i32>> {
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        match self.signal.try_lock() {
+            Ok(mut signal) => signal.take(),
+            Err(_) => {
+                // try again at the next callback
+                self.changed.store(true, Ordering::Release);
+                None
+            }
+        }
+    }
+}
+
+/// The output callback plays the buffer once.
+pub(crate) const LOOP_OFF: u8 = 0;
+/// The output callback wraps around to the start of the buffer when it reaches the end.
+pub(crate) const LOOP_ON: u8 = 1;
+/// The output callback stops playing the looped buffer at the next callback.
+pub(crate) const LOOP_STOP: u8 = 2;
+
+/// How often the user thread checks that the device is still connected while it waits on the
+/// callbacks.
+pub(crate) const HEALTH_CHECK_INTERVAL: Duration = Duration::from_millis(10);
 
 pub(crate) enum StreamCommand {
     Play,
     Stop,
 }
 
+/// Where the input callback sends recorded samples instead of the input buffer, for recordings
+/// written straight to disk.
+///
+/// The ring is allocated before the recording starts, so the callback never allocates or blocks
+/// on the writer thread. Blocks the writer can't take in time are dropped and counted by the ring.
+pub(crate) struct CaptureSink {
+    /// The ring the writer thread reads the recorded samples from
+    pub ring: Arc<FrameRing>,
+    /// The device channel of each sample sent for a frame, or empty to send every channel
+    pub channels: Vec<usize>,
+    /// Set by the writer thread once it has stopped reading
+    pub writer_closed: Arc<AtomicBool>,
+    /// The number of frames still to record
+    pub remaining_frames: usize,
+    /// The number of frames in the whole recording
+    pub total_frames: usize,
+}
+
+/// A recording into memory: the samples the input callback has stored so far, and which channels
+/// of each frame it stores.
+///
+/// The channels are chosen when the recording starts and travel with it, so changing the enabled
+/// input channels while a recording runs can't change the layout of its samples.
+#[derive(Debug, Default)]
+pub(crate) struct InputCapture {
+    pub samples: Vec<i32>,
+    /// The device channel of each stored sample of a frame, or empty to store every channel
+    pub channels: Vec<usize>,
+    /// The number of samples stored for each frame
+    pub frame_size: usize,
+}
+
+impl InputCapture {
+    /// An empty recording with room for a number of frames.
+    ///
+    /// # Arguments
+    /// channels: Vec<usize> - the device channel of each sample to store, or empty for every channel
+    /// device_channels: usize - the number of input channels of the device
+    /// frames: usize - the number of frames to record
+    pub fn new(channels: Vec<usize>, device_channels: usize, frames: usize) -> Self {
+        let frame_size = match channels.len() {
+            0 => device_channels,
+            enabled => enabled,
+        };
+        InputCapture {
+            samples: Vec::with_capacity(frames * frame_size),
+            channels,
+            frame_size,
+        }
+    }
+
+    /// The input channels stored in each frame, in order.
+    pub fn input_channels(&self) -> Vec<InputChannel> {
+        match self.channels.as_slice() {
+            [] => (0..self.frame_size).map(InputChannel::from_index).collect(),
+            enabled => enabled
+                .iter()
+                .copied()
+                .map(InputChannel::from_index)
+                .collect(),
+        }
+    }
+
+    /// Split the interleaved samples into one vector per stored channel.
+    pub fn into_channels(self) -> Vec<Vec<i32>> {
+        if self.frame_size == 0 {
+            return Vec::new();
+        }
+        let mut channel_recordings: Vec<Vec<i32>> =
+            vec![Vec::with_capacity(self.samples.len() / self.frame_size); self.frame_size];
+        for chunk in self.samples.chunks_exact(self.frame_size) {
+            for (channel, &sample) in channel_recordings.iter_mut().zip(chunk) {
+                channel.push(sample);
+            }
+        }
+        channel_recordings
+    }
+}
+
 /// The possible types of audio stream.
 ///
 /// Input streams are used to record and output streams are used to play audio.
+///
+/// Duplex streams play and record on the same device, and start capturing in the same
+/// audio callback cycle that playback starts. On backends that drive input and output from a
+/// single callback (ASIO, JACK) this removes the run-to-run jitter between play and record.
 #[derive(Clone)]
 pub enum StreamType {
     Input {
         record_wait: Arc<(Mutex<bool>, std::sync::Condvar)>,
-        input_buffer: Arc<Mutex<Vec<i32>>>,
+        input_buffer: Arc<Mutex<InputCapture>>,
+        capture_timestamps: Arc<Mutex<Vec<BufferTimestamp>>>,
+        capture_sink: Arc<Mutex<Option<CaptureSink>>>,
+        input_chain: Arc<InputChainLane>,
+        input_tap: Arc<FrameQueueSlot>,
+        frame_queue: Arc<FrameQueueSlot>,
+        pre_record: Arc<PreRecordBuffer>,
+        trigger: Arc<Mutex<Option<LevelTrigger>>>,
+        monitor: Arc<CallbackMonitor>,
+        progress: Arc<ProgressMonitor>,
+        events: Arc<EventHub>,
     },
     Output {
-        output_buffer: Arc<Mutex<Vec<i32>>>,
-        play_wait: Arc<(Mutex<bool>, std::sync::Condvar)>,
+        output_buffer: Arc<Mutex<Signal>>,
+        play_gate: Arc<PlayGate>,
+        buffer_frames: Arc<AtomicUsize>,
+        loop_state: Arc<AtomicU8>,
+        output_queue: Arc<Mutex<VecDeque<Signal>>>,
+        schedule: Arc<PlaybackSchedule>,
+        background: Arc<BackgroundLane>,
+        dither: Arc<AtomicU8>,
+        limiter: Arc<LimiterLane>,
+        mixer: Arc<MixerLane>,
+        monitor: Arc<CallbackMonitor>,
+        progress: Arc<ProgressMonitor>,
+        events: Arc<EventHub>,
+    },
+    Duplex {
+        record_wait: Arc<(Mutex<bool>, std::sync::Condvar)>,
+        input_buffer: Arc<Mutex<InputCapture>>,
+        capture_timestamps: Arc<Mutex<Vec<BufferTimestamp>>>,
+        capture_sink: Arc<Mutex<Option<CaptureSink>>>,
+        input_chain: Arc<InputChainLane>,
+        input_tap: Arc<FrameQueueSlot>,
+        frame_queue: Arc<FrameQueueSlot>,
+        pre_record: Arc<PreRecordBuffer>,
+        trigger: Arc<Mutex<Option<LevelTrigger>>>,
+        output_buffer: Arc<Mutex<Signal>>,
+        play_gate: Arc<PlayGate>,
+        buffer_frames: Arc<AtomicUsize>,
+        loop_state: Arc<AtomicU8>,
+        output_queue: Arc<Mutex<VecDeque<Signal>>>,
+        schedule: Arc<PlaybackSchedule>,
+        background: Arc<BackgroundLane>,
+        dither: Arc<AtomicU8>,
+        limiter: Arc<LimiterLane>,
+        mixer: Arc<MixerLane>,
+        passthrough: Arc<PassthroughLane>,
+        monitor: Arc<CallbackMonitor>,
+        progress: Arc<ProgressMonitor>,
+        events: Arc<EventHub>,
     },
 }
 
@@ -35,242 +394,783 @@ impl fmt::Debug for StreamType {
         match self {
             StreamType::Input { .. } => write!(f, "Input"),
             StreamType::Output { .. } => write!(f, "Output"),
+            StreamType::Duplex { .. } => write!(f, "Duplex"),
         }
     }
 }
 
-/// The possible states of an audio stream.
-///
-/// Playing means the stream is currently running.
-///
-/// Stopped means the stream is not currently running.
-#[derive(Clone, Debug, PartialEq, Copy)]
-pub enum StreamState {
-    Playing,
-    Stopped,
-}
-
-#[derive(Clone)]
 pub(crate) struct StreamController {
-    command_sender: mpsc::Sender<StreamCommand>,
-    stream_type: StreamType,
+    worker: StreamWorker,
 }
 
 impl Drop for StreamController {
     fn drop(&mut self) {
-        self.send_command(StreamCommand::Stop);
+        if let Err(error) = self.close() {
+            tracing::warn!(%error, "the streams did not shut down cleanly");
+        }
     }
 }
 
 impl StreamController {
-    pub fn new(stream_type: StreamType, device: cpal::Device, config: cpal::StreamConfig) -> Self {
-        let (sender, receiver) = mpsc::channel();
-        let config_clone = config.clone(); // Clone output_config
-
-        let stream_type_clone = stream_type.clone();
-        thread::spawn(move || {
-            let mut stream: Option<Stream> = None; // Initially, there's no stream
-
-            for command in receiver {
-                match command {
-                    StreamCommand::Play => {
-                        if stream.is_none() {
-                            // Create the stream here, if not already created
-                            match stream_type {
-                                StreamType::Input {
-                                    ref record_wait,
-                                    ref input_buffer,
-                                } => {
-                                    let new_stream = create_input_stream(
-                                        device.clone(),
-                                        config.clone(),
-                                        Arc::clone(&record_wait.clone()),
-                                        Arc::clone(&input_buffer.clone()),
-                                    );
-                                    stream = Some(new_stream.unwrap());
-                                }
-                                StreamType::Output {
-                                    ref output_buffer,
-                                    ref play_wait,
-                                } => {
-                                    let new_stream = create_output_stream(
-                                        &device,
-                                        config_clone.clone(),
-                                        Arc::clone(&output_buffer.clone()),
-                                        Arc::clone(&play_wait.clone()),
-                                    );
-                                    stream = Some(new_stream.unwrap());
-                                }
-                            }
-                        }
-                        if let Some(ref s) = stream {
-                            s.play().unwrap();
-                        }
-                    }
-                    StreamCommand::Stop => {
-                        if let Some(ref s) = stream {
-                            s.pause().unwrap();
-                        }
-                    }
+    pub fn new(
+        stream_type: StreamType,
+        device: cpal::Device,
+        config: cpal::StreamConfig,
+        sample_format: cpal::SampleFormat,
+    ) -> Self {
+        Self::spawn(
+            stream_type,
+            device,
+            (config.clone(), sample_format),
+            (config, sample_format),
+        )
+    }
+
+    /// Create a stream controller that owns both the input and output stream of a device.
+    ///
+    /// `stream_type` should be `StreamType::Duplex`.
+    pub fn new_duplex(
+        stream_type: StreamType,
+        device: cpal::Device,
+        output_config: (cpal::StreamConfig, cpal::SampleFormat),
+        input_config: (cpal::StreamConfig, cpal::SampleFormat),
+    ) -> Self {
+        Self::spawn(stream_type, device, output_config, input_config)
+    }
+
+    /// Spawn the thread that owns the streams.
+    ///
+    /// Streams are built with the sample format of the device and converted to and from the i32
+    /// buffers in the callbacks.
+    fn spawn(
+        stream_type: StreamType,
+        device: cpal::Device,
+        (config, output_format): (cpal::StreamConfig, cpal::SampleFormat),
+        (input_config, input_format): (cpal::StreamConfig, cpal::SampleFormat),
+    ) -> Self {
+        let worker = StreamWorker::spawn(move |receiver| {
+            // The output callback of a duplex stream starts the capture, so build the input first
+            let build_streams = || -> Result<Vec<Stream>, anyhow::Error> {
+                let mut streams = Vec::new();
+                if let Some(callback) = InputCallback::new(
+                    &stream_type,
+                    input_config.channels as usize,
+                    input_config.sample_rate.0,
+                ) {
+                    streams.push(with_sample_type!(
+                        input_format,
+                        create_input_stream(&device, &input_config, callback)
+                    )?);
                 }
+                if let Some(callback) = OutputCallback::new(
+                    &stream_type,
+                    config.channels as usize,
+                    config.sample_rate.0,
+                ) {
+                    streams.push(with_sample_type!(
+                        output_format,
+                        create_output_stream(&device, &config, callback)
+                    )?);
+                }
+                Ok(streams)
+            };
+
+            // Initially, there are no streams. Duplex controllers own both an input and an output stream
+            let mut streams: Vec<Stream> = Vec::new();
+
+            for request in receiver {
+                let result = match request.command {
+                    StreamCommand::Play => play_streams(&mut streams, build_streams),
+                    StreamCommand::Stop => streams
+                        .iter()
+                        .try_for_each(|s| s.pause())
+                        .map_err(anyhow::Error::from),
+                };
+                request.reply(result);
             }
         });
 
-        StreamController {
-            command_sender: sender,
-            stream_type: stream_type_clone,
-        }
+        StreamController { worker }
     }
+}
 
-    pub fn send_command(&self, command: StreamCommand) {
-        match command {
-            StreamCommand::Play => match self.stream_type {
-                StreamType::Input { .. } => {
-                    *INPUT_STREAM_STATE.lock().unwrap() = StreamState::Playing;
-                }
-                StreamType::Output { .. } => {
-                    *OUTPUT_STREAM_STATE.lock().unwrap() = StreamState::Playing;
-                }
-            },
-            StreamCommand::Stop => match self.stream_type {
-                StreamType::Input { .. } => {
-                    *INPUT_STREAM_STATE.lock().unwrap() = StreamState::Stopped;
-                }
-                StreamType::Output { .. } => {
-                    *OUTPUT_STREAM_STATE.lock().unwrap() = StreamState::Stopped;
-                }
-            },
-        }
+impl AudioBackend for StreamController {
+    fn send_command(&self, command: StreamCommand) -> Result<(), anyhow::Error> {
+        self.worker.send(command)
+    }
 
-        self.command_sender.send(command).unwrap();
+    fn get_state(&self) -> StreamState {
+        self.worker.state()
     }
 
-    pub fn get_state(&self) -> StreamState {
-        match self.stream_type {
-            StreamType::Input { .. } => *INPUT_STREAM_STATE.lock().unwrap(),
-            StreamType::Output { .. } => *OUTPUT_STREAM_STATE.lock().unwrap(),
+    fn close(&self) -> Result<(), anyhow::Error> {
+        if self.get_state() == StreamState::Closed {
+            return Ok(());
+        }
+        // the streams are dropped on the thread once it has handled the commands left
+        if let Err(error) = self.send_command(StreamCommand::Stop) {
+            tracing::warn!(%error, "failed to stop the streams before closing them");
         }
+        self.worker.close()
     }
 }
 
-fn create_input_stream(
-    device: cpal::Device,
-    input_config: cpal::StreamConfig,
-    record_wait_clone: Arc<(Mutex<bool>, std::sync::Condvar)>,
-    input_buffer_clone: Arc<Mutex<Vec<i32>>>,
-) -> Result<Stream, anyhow::Error> {
-    let temp_input_stream = device.build_input_stream(
-        &input_config,
-        move |data: &[i32], _: &InputCallbackInfo| {
-            let (record_wait, cvar) = &*record_wait_clone;
-            // if we are not currently recording, don't do anything
-            // this is so we don't continually record data and fill up the buffer unnecessarily
-            if !(*record_wait.lock().unwrap()) {
+/// The input callback, separate from the stream so it can also be run by the mock backend.
+pub(crate) struct InputCallback {
+    record_wait: Arc<(Mutex<bool>, std::sync::Condvar)>,
+    input_buffer: Arc<Mutex<InputCapture>>,
+    capture_timestamps: Arc<Mutex<Vec<BufferTimestamp>>>,
+    capture_sink: Arc<Mutex<Option<CaptureSink>>>,
+    input_chain_lane: Arc<InputChainLane>,
+    /// The filters and decimation the callback runs, taken from the lane when they change
+    input_chain: InputChain,
+    /// One recorded frame while it is filtered, kept so the callback doesn't allocate
+    processed_frame: Vec<i32>,
+    input_tap: Arc<FrameQueueSlot>,
+    frame_queue: Arc<FrameQueueSlot>,
+    pre_record: Arc<PreRecordBuffer>,
+    trigger: Arc<Mutex<Option<LevelTrigger>>>,
+    /// For duplex streams, where the input is handed to the output callback to monitor it
+    passthrough: Option<Arc<PassthroughLane>>,
+    monitor: Arc<CallbackMonitor>,
+    progress: Arc<ProgressMonitor>,
+    events: Arc<EventHub>,
+    channels: usize,
+    sample_rate: u32,
+}
+
+impl InputCallback {
+    /// The input callback of a stream type, or None for output streams.
+    pub fn new(stream_type: &StreamType, channels: usize, sample_rate: u32) -> Option<Self> {
+        let passthrough = match stream_type {
+            StreamType::Duplex { passthrough, .. } => Some(Arc::clone(passthrough)),
+            _ => None,
+        };
+        match stream_type {
+            StreamType::Input {
+                record_wait,
+                input_buffer,
+                capture_timestamps,
+                capture_sink,
+                input_chain,
+                input_tap,
+                frame_queue,
+                pre_record,
+                trigger,
+                monitor,
+                progress,
+                events,
+            }
+            | StreamType::Duplex {
+                record_wait,
+                input_buffer,
+                capture_timestamps,
+                capture_sink,
+                input_chain,
+                input_tap,
+                frame_queue,
+                pre_record,
+                trigger,
+                monitor,
+                progress,
+                events,
+                ..
+            } => Some(InputCallback {
+                record_wait: Arc::clone(record_wait),
+                input_buffer: Arc::clone(input_buffer),
+                capture_timestamps: Arc::clone(capture_timestamps),
+                capture_sink: Arc::clone(capture_sink),
+                input_chain_lane: Arc::clone(input_chain),
+                input_chain: InputChain::default(),
+                processed_frame: Vec::with_capacity(channels),
+                input_tap: Arc::clone(input_tap),
+                frame_queue: Arc::clone(frame_queue),
+                pre_record: Arc::clone(pre_record),
+                trigger: Arc::clone(trigger),
+                passthrough,
+                monitor: Arc::clone(monitor),
+                progress: Arc::clone(progress),
+                events: Arc::clone(events),
+                channels,
+                sample_rate,
+            }),
+            StreamType::Output { .. } => None,
+        }
+    }
+
+    /// Handle a buffer of input.
+    ///
+    /// # Arguments
+    /// data: &[T] - the interleaved samples of the buffer
+    /// timestamp: Option<InputStreamTimestamp> - the timestamps from the driver, or None without a driver
+    pub fn process<T: Sample>(&mut self, data: &[T], timestamp: Option<InputStreamTimestamp>) {
+        let channels = self.channels;
+        let sample_rate = self.sample_rate;
+        let _timer = self
+            .monitor
+            .time(StreamDirection::Input, data.len() / channels, sample_rate);
+        if let Some(timestamp) = timestamp {
+            self.monitor.gaps(StreamDirection::Input).count(
+                timestamp.capture,
+                data.len() / channels,
+                sample_rate,
+            );
+            if let Some(latency) = timestamp.callback.duration_since(&timestamp.capture) {
+                self.monitor
+                    .timing(StreamDirection::Input)
+                    .set_latency(latency);
+            }
+        }
+
+        // the convolution monitor gets every buffer, whether or not we are recording
+        if let Ok(tap) = self.input_tap.try_lock() {
+            if let Some(ref ring) = *tap {
+                ring.push(data, channels);
+            }
+        }
+
+        // and so does the frame queue
+        if let Ok(queue) = self.frame_queue.try_lock() {
+            if let Some(ref ring) = *queue {
+                ring.push(data, channels);
+            }
+        }
+
+        // and so does passthrough, which monitors the input while the instance is idle
+        if let Some(ref passthrough) = self.passthrough {
+            passthrough.push(data, channels);
+        }
+
+        let (record_wait, cvar) = &*self.record_wait;
+        // if we are not currently recording, don't do anything
+        // this is so we don't continually record data and fill up the buffer unnecessarily
+        {
+            let mut recording = record_wait.lock().unwrap();
+            if !*recording {
+                // keep the latest input for recordings with a pre-roll. The lock is held so
+                // no frames are missed when a recording takes the buffer and starts
+                self.pre_record.push(data);
+
+                // a triggered recording starts from the next buffer once the level crosses
+                // the threshold. The trigger keeps the frames of this buffer
+                if let Ok(mut trigger) = self.trigger.try_lock() {
+                    if let Some(ref mut trigger) = *trigger {
+                        if trigger.check(data, channels) {
+                            *recording = true;
+                        }
+                    }
+                }
+                return;
+            }
+        }
+        // recordings to disk hand the samples to a writer thread instead of keeping them. The
+        // user thread only locks the sink before and after a recording
+        if let Ok(mut capture_sink) = self.capture_sink.try_lock() {
+            if let Some(ref mut sink) = *capture_sink {
+                let frames = (data.len() / channels).min(sink.remaining_frames);
+                let recorded = &data[..frames * channels];
+                if sink.channels.is_empty() {
+                    sink.ring
+                        .push_samples(recorded.iter().map(|&sample| sample.to_i32()));
+                } else {
+                    let selected = &sink.channels;
+                    sink.ring
+                        .push_samples(recorded.chunks_exact(channels).flat_map(|frame| {
+                            selected.iter().map(move |&channel| frame[channel].to_i32())
+                        }));
+                }
+                sink.remaining_frames -= frames;
+                self.progress.update(
+                    StreamDirection::Input,
+                    sink.total_frames - sink.remaining_frames,
+                    sink.total_frames,
+                    sample_rate,
+                );
+                // finish the recording early if the writer thread has stopped
+                if sink.writer_closed.load(Ordering::Acquire) || sink.remaining_frames == 0 {
+                    sink.ring.flush();
+                    self.events.send(AudioEvent::RecordFinished {
+                        frames: sink.total_frames - sink.remaining_frames,
+                    });
+                    *capture_sink = None;
+                    *record_wait.lock().unwrap() = false;
+                    cvar.notify_all();
+                }
                 return;
             }
-            let mut input_buffer = input_buffer_clone.lock().unwrap();
+        }
+
+        let mut capture_guard = self.input_buffer.lock().unwrap();
+        // the channels were chosen when the recording started
+        let capture = &mut *capture_guard;
+        let frame_size = capture.frame_size;
+        if frame_size == 0 {
+            return;
+        }
+        let enabled_channels = &capture.channels;
+        let input_buffer = &mut capture.samples;
+
+        // note when each buffer of the recording was captured. The user thread empties the
+        // timestamps and reserves room for them when the recording starts, so pushing within the
+        // capacity never allocates
+        if let Some(timestamp) = timestamp {
+            if let Ok(mut timestamps) = self.capture_timestamps.try_lock() {
+                if timestamps.len() < timestamps.capacity() {
+                    let capture_delay = timestamp
+                        .callback
+                        .duration_since(&timestamp.capture)
+                        .unwrap_or_default();
+                    let now = Instant::now();
+                    timestamps.push(BufferTimestamp {
+                        sample_index: input_buffer.len() / frame_size,
+                        capture: timestamp.capture,
+                        host_time: now.checked_sub(capture_delay).unwrap_or(now),
+                    });
+                }
+            }
+        }
 
+        if let Some(input_chain) = self.input_chain_lane.take_chain() {
+            self.input_chain = input_chain;
+        }
+        let input_chain = &mut self.input_chain;
+        if input_buffer.is_empty() {
+            input_chain.reset();
+        }
+
+        let finished = if input_chain.is_active() {
+            // filter and decimate each frame as it arrives
+            let processed = &mut self.processed_frame;
+            processed.resize(frame_size, 0);
+            for frame in data.chunks_exact(channels) {
+                if input_buffer.len() + frame_size > input_buffer.capacity() {
+                    break;
+                }
+                if enabled_channels.is_empty() {
+                    for (sample, &value) in processed.iter_mut().zip(frame) {
+                        *sample = value.to_i32();
+                    }
+                } else {
+                    for (sample, &channel) in processed.iter_mut().zip(enabled_channels.iter()) {
+                        *sample = frame[channel].to_i32();
+                    }
+                }
+                if input_chain.process_frame(processed, enabled_channels) {
+                    input_buffer.extend_from_slice(processed);
+                }
+            }
+            input_buffer.capacity() > 0 && input_buffer.len() + frame_size > input_buffer.capacity()
+        } else if enabled_channels.is_empty() {
             if input_buffer.len() + data.len() < input_buffer.capacity() {
                 // if we have room, keep recording
-                input_buffer.extend_from_slice(data);
+                input_buffer.extend(data.iter().map(|&sample| sample.to_i32()));
+                false
             } else if input_buffer.capacity() > 0 {
                 // add as much as we can to the buffer
                 let remaining_capacity = input_buffer.capacity() - input_buffer.len();
-                input_buffer.extend_from_slice(&data[..remaining_capacity]);
-                // we are done with input_buffer, drop it to prevent deadlock
-                drop(input_buffer);
-
-                // we have recorded all we need, notify the main thread
-                *record_wait.lock().unwrap() = false;
-                cvar.notify_all();
+                input_buffer.extend(
+                    data[..remaining_capacity]
+                        .iter()
+                        .map(|&sample| sample.to_i32()),
+                );
+                true
+            } else {
+                false
             }
-        },
-        err_fn,
-        None,
-    )?;
-    Ok(temp_input_stream)
+        } else {
+            // only store the enabled channels of each frame
+            for frame in data.chunks_exact(channels) {
+                if input_buffer.len() + frame_size > input_buffer.capacity() {
+                    break;
+                }
+                input_buffer.extend(
+                    enabled_channels
+                        .iter()
+                        .map(|&channel| frame[channel].to_i32()),
+                );
+            }
+            input_buffer.capacity() > 0 && input_buffer.len() + frame_size > input_buffer.capacity()
+        };
+        if input_buffer.capacity() > 0 {
+            self.progress.update(
+                StreamDirection::Input,
+                input_buffer.len() / frame_size,
+                input_buffer.capacity() / frame_size,
+                sample_rate,
+            );
+        }
+
+        if finished {
+            let frames = input_buffer.len() / frame_size;
+            // we are done with input_buffer, drop it to prevent deadlock
+            drop(capture_guard);
+
+            // we have recorded all we need, notify the main thread
+            self.events.send(AudioEvent::RecordFinished { frames });
+            *record_wait.lock().unwrap() = false;
+            cvar.notify_all();
+        }
+    }
 }
 
-fn create_output_stream(
-    device: &cpal::Device,
-    output_config: cpal::StreamConfig,
-    output_buffer: Arc<Mutex<Vec<i32>>>,
-    play_wait: Arc<(Mutex<bool>, std::sync::Condvar)>,
-) -> Result<Stream, anyhow::Error> {
-    // create a local buffer for the callback to avoid locking the mutex buffer so much
-    let mut callback_output_buffer = Vec::<i32>::new();
-    let mut output_buffer_iterator = 0;
+/// The output callback and the position it has reached in the signal it is playing.
+pub(crate) struct OutputCallback {
+    output_buffer: Arc<Mutex<Signal>>,
+    play_gate: Arc<PlayGate>,
+    buffer_frames: Arc<AtomicUsize>,
+    loop_state: Arc<AtomicU8>,
+    output_queue: Arc<Mutex<VecDeque<Signal>>>,
+    schedule: Arc<PlaybackSchedule>,
+    background: Arc<BackgroundLane>,
+    dither: Arc<AtomicU8>,
+    /// The state of the dither noise
+    dither_state: u64,
+    limiter_lane: Arc<LimiterLane>,
+    limiter: Option<Limiter>,
+    /// The mixed samples of a callback, before they are limited and converted
+    mixed_buffer: Vec<i32>,
+    /// For duplex streams, mixes the monitored input into the output
+    passthrough: Option<Passthrough>,
+    monitor: Arc<CallbackMonitor>,
+    progress: Arc<ProgressMonitor>,
+    events: Arc<EventHub>,
+    /// For duplex streams, set to start the capture when playback starts
+    capture_start: Option<Arc<(Mutex<bool>, std::sync::Condvar)>>,
+    channels: usize,
+    sample_rate: u32,
+    // a local buffer for the callback to avoid locking the mutex buffer so much
+    callback_output_buffer: Signal,
+    output_buffer_iterator: usize,
+    // samples of silence to write before a scheduled buffer starts
+    delay_samples: usize,
+    background_buffer: Vec<i32>,
+    background_iterator: usize,
+    mixer_lane: Arc<MixerLane>,
+    mixer: Mixer,
+}
 
-    let temp_output_stream = device.build_output_stream(
-        &output_config,
-        move |data: &mut [i32], _: &OutputCallbackInfo| {
-            let (play_wait_bool, _) = &*play_wait;
-            // if we aren't currently playing, don't do anything
-            if !(*play_wait_bool.lock().unwrap()) {
-                for i in 0..data.len() {
-                    data[i] = 0;
+impl OutputCallback {
+    /// The output callback of a stream type, or None for input streams.
+    pub fn new(stream_type: &StreamType, channels: usize, sample_rate: u32) -> Option<Self> {
+        let (capture_start, passthrough) = match stream_type {
+            StreamType::Duplex {
+                record_wait,
+                passthrough,
+                ..
+            } => (
+                Some(Arc::clone(record_wait)),
+                Some(Passthrough::new(Arc::clone(passthrough))),
+            ),
+            _ => (None, None),
+        };
+        match stream_type {
+            StreamType::Output {
+                output_buffer,
+                play_gate,
+                buffer_frames,
+                loop_state,
+                output_queue,
+                schedule,
+                background,
+                dither,
+                limiter,
+                mixer,
+                monitor,
+                progress,
+                events,
+            }
+            | StreamType::Duplex {
+                output_buffer,
+                play_gate,
+                buffer_frames,
+                loop_state,
+                output_queue,
+                schedule,
+                background,
+                dither,
+                limiter,
+                mixer,
+                monitor,
+                progress,
+                events,
+                ..
+            } => Some(OutputCallback {
+                output_buffer: Arc::clone(output_buffer),
+                play_gate: Arc::clone(play_gate),
+                buffer_frames: Arc::clone(buffer_frames),
+                loop_state: Arc::clone(loop_state),
+                output_queue: Arc::clone(output_queue),
+                schedule: Arc::clone(schedule),
+                background: Arc::clone(background),
+                dither: Arc::clone(dither),
+                dither_state: DITHER_SEED,
+                limiter_lane: Arc::clone(limiter),
+                limiter: None,
+                mixed_buffer: Vec::new(),
+                passthrough,
+                monitor: Arc::clone(monitor),
+                progress: Arc::clone(progress),
+                events: Arc::clone(events),
+                capture_start,
+                channels,
+                sample_rate,
+                callback_output_buffer: Signal::default(),
+                output_buffer_iterator: 0,
+                delay_samples: 0,
+                background_buffer: Vec::new(),
+                background_iterator: 0,
+                mixer_lane: Arc::clone(mixer),
+                mixer: Mixer::default(),
+            }),
+            StreamType::Input { .. } => None,
+        }
+    }
+
+    /// Fill a buffer of output.
+    ///
+    /// # Arguments
+    /// data: &mut [T] - the interleaved samples of the buffer
+    /// timestamp: Option<OutputStreamTimestamp> - the timestamps from the driver, or None without a driver
+    pub fn process<T: Sample>(&mut self, data: &mut [T], timestamp: Option<OutputStreamTimestamp>) {
+        let channels = self.channels;
+        let sample_rate = self.sample_rate;
+        let _timer = self
+            .monitor
+            .time(StreamDirection::Output, data.len() / channels, sample_rate);
+        if let Some(timestamp) = timestamp {
+            self.monitor.gaps(StreamDirection::Output).count(
+                timestamp.playback,
+                data.len() / channels,
+                sample_rate,
+            );
+            self.schedule.update_reference(timestamp.callback);
+            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                self.monitor
+                    .timing(StreamDirection::Output)
+                    .set_latency(latency);
+            }
+        }
+
+        // record the buffer size the driver actually granted
+        self.buffer_frames
+            .store(data.len() / channels, Ordering::Relaxed);
+
+        // if we aren't currently playing, don't do anything
+        if !self.play_gate.is_playing() {
+            for sample in data.iter_mut() {
+                *sample = T::from_i32(0);
+            }
+        }
+
+        // check if we have enough data in the callback buffer
+        if self.callback_output_buffer.is_empty() {
+            // we don't have enough data, we need to get new data from the buffer
+            self.callback_output_buffer = self.output_buffer.lock().unwrap().clone();
+
+            // otherwise start on the next queued buffer
+            if self.callback_output_buffer.is_empty() && self.play_gate.is_playing() {
+                if let Some(next) = self.output_queue.lock().unwrap().pop_front() {
+                    self.callback_output_buffer = next;
                 }
             }
 
-            // check if we have enough data in the callback buffer
-            if callback_output_buffer.is_empty() {
-                // we don't have enough data, we need to get new data from the buffer
-                callback_output_buffer = output_buffer.lock().unwrap().clone();
+            // reset the output buffer iterator
+            self.output_buffer_iterator = 0;
+
+            // delay a scheduled buffer until its start time. The first sample of this callback
+            // is played at the playback timestamp
+            if !self.callback_output_buffer.is_empty() {
+                if let Some(start) = self.schedule.take_start() {
+                    let delay = timestamp
+                        .and_then(|timestamp| start.duration_since(&timestamp.playback))
+                        .unwrap_or_default();
+                    self.delay_samples =
+                        (delay.as_secs_f64() * sample_rate as f64).round() as usize * channels;
+                }
+
+                // note when the first sample reaches the device, for aligning recordings
+                let output_delay = timestamp
+                    .and_then(|timestamp| timestamp.playback.duration_since(&timestamp.callback))
+                    .unwrap_or_default();
+                let delay = Duration::from_secs_f64(
+                    (self.delay_samples / channels) as f64 / sample_rate as f64,
+                );
+                self.schedule
+                    .set_started(Instant::now() + output_delay + delay);
+                self.events.send(AudioEvent::PlaybackStarted);
+            }
 
-                // reset the output buffer iterator
-                output_buffer_iterator = 0;
+            // in duplex mode, start capturing in the same callback cycle that playback starts
+            if let Some(ref capture_start) = self.capture_start {
+                if !self.callback_output_buffer.is_empty() {
+                    *capture_start.0.lock().unwrap() = true;
+                }
             }
+        }
 
-            // iterate over the chunk and the corresponding channel of data
-            let mut to_clear_buffer = false;
+        // iterate over the chunk and the corresponding channel of data
+        let mut to_clear_buffer = false;
 
-            // get the next chunk of data to write
-            let number_of_samples = std::cmp::min(
-                data.len(),
-                callback_output_buffer.len() - output_buffer_iterator,
-            );
-            let end_index = output_buffer_iterator + number_of_samples;
+        // stopping a loop skips straight to the end of the signal
+        if self
+            .loop_state
+            .compare_exchange(LOOP_STOP, LOOP_OFF, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            self.output_buffer_iterator = self.callback_output_buffer.len();
+        }
+        let looping = self.loop_state.load(Ordering::Acquire) == LOOP_ON;
 
-            let chunk_data = &callback_output_buffer[output_buffer_iterator..end_index];
+        if let Some(signal) = self.background.take_signal() {
+            self.background_buffer = signal;
+            self.background_iterator = 0;
+        }
+        let background_gain = self.background.gain();
+        let dither = Dither::from_bits(self.dither.load(Ordering::Relaxed));
+        if let Some(settings) = self.limiter_lane.take_settings() {
+            self.limiter = settings.map(|settings| Limiter::new(settings, channels, sample_rate));
+        }
 
-            for i in 0..data.len() {
-                if i >= chunk_data.len() {
-                    // we have reached the end of the signal, signal that we should stop
-                    data[i] = 0;
+        let mut mixed_buffer = std::mem::take(&mut self.mixed_buffer);
+        mixed_buffer.resize(data.len(), 0);
 
-                    // only send the signal to stop playing if we are currently playing
-                    let (play_wait, cvar) = &*play_wait;
-                    let mut play_wait = play_wait.lock().unwrap();
-                    if *play_wait {
-                        *play_wait = false;
-                        cvar.notify_all();
+        for sample in mixed_buffer.iter_mut() {
+            // wrap around seamlessly when looping
+            if looping
+                && !self.callback_output_buffer.is_empty()
+                && self.output_buffer_iterator >= self.callback_output_buffer.len()
+            {
+                self.output_buffer_iterator = 0;
+            }
 
-                        // clear the local buffer
-                        to_clear_buffer = true;
+            // move straight on to the next queued buffer so there is no gap between them
+            if !looping
+                && self.output_buffer_iterator >= self.callback_output_buffer.len()
+                && self.play_gate.is_playing()
+            {
+                // hold the queue lock so enqueue can't add a buffer between the check and finish.
+                // While enqueue holds it, try again at the next sample rather than block
+                if let Ok(mut queue) = self.output_queue.try_lock() {
+                    match queue.pop_front() {
+                        Some(next) => {
+                            self.callback_output_buffer = next;
+                            self.output_buffer_iterator = 0;
+                        }
+                        None => {
+                            if self.play_gate.finish() {
+                                to_clear_buffer = true;
+                                self.events.send(AudioEvent::PlaybackFinished);
+                            }
+                        }
                     }
-                } else {
-                    // just write as normal
-                    data[i] = chunk_data[i];
                 }
             }
 
-            output_buffer_iterator += number_of_samples;
-
-            // clear the buffer if we have reached the end of the signal
-            if to_clear_buffer {
-                callback_output_buffer.clear();
-                output_buffer_iterator = 0;
+            let foreground = if self.delay_samples > 0 {
+                // waiting for the scheduled start
+                self.delay_samples -= 1;
+                0
+            } else if self.output_buffer_iterator < self.callback_output_buffer.len() {
+                // just write as normal
+                self.output_buffer_iterator += 1;
+                self.callback_output_buffer[self.output_buffer_iterator - 1]
+            } else {
+                // we have reached the end of the signal
+                0
+            };
 
-                let empty_vector = Vec::new();
-                *output_buffer.lock().unwrap() = empty_vector;
+            // mix in the background, which loops for as long as it is set
+            let mut mixed = foreground;
+            if !self.background_buffer.is_empty() {
+                let background_sample = (self.background_buffer[self.background_iterator] as f32
+                    * background_gain) as i32;
+                mixed = foreground.saturating_add(background_sample);
+                self.background_iterator =
+                    (self.background_iterator + 1) % self.background_buffer.len();
             }
+            *sample = mixed;
+        }
+
+        // the signals started with play_mixed play on top of everything else
+        self.mixer.mix(&self.mixer_lane, &mut mixed_buffer);
+
+        if let Some(ref mut passthrough) = self.passthrough {
+            passthrough.mix(&mut mixed_buffer, channels);
+        }
+
+        // the limiter is the last stage, so nothing after it can push the output over the ceiling
+        if let Some(ref mut limiter) = self.limiter {
+            self.limiter_lane
+                .add_clips(limiter.process(&mut mixed_buffer));
+        }
+        for (sample, &mixed) in data.iter_mut().zip(&mixed_buffer) {
+            *sample = quantize(mixed, dither, &mut self.dither_state);
+        }
+        self.mixed_buffer = mixed_buffer;
+
+        if !self.callback_output_buffer.is_empty() {
+            self.progress.update(
+                StreamDirection::Output,
+                self.output_buffer_iterator / channels,
+                self.callback_output_buffer.len() / channels,
+                sample_rate,
+            );
+        }
+
+        // clear the buffer if we have reached the end of the signal
+        if to_clear_buffer {
+            self.callback_output_buffer = Signal::default();
+            self.output_buffer_iterator = 0;
+
+            *self.output_buffer.lock().unwrap() = Signal::default();
+        }
+    }
+}
+
+/// Start the streams of a controller, building them first if they haven't been.
+fn play_streams(
+    streams: &mut Vec<Stream>,
+    build_streams: impl Fn() -> Result<Vec<Stream>, anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    if streams.is_empty() {
+        *streams = build_streams()?;
+    }
+    for s in streams.iter() {
+        s.play()?;
+    }
+    Ok(())
+}
+
+fn create_input_stream<T: Sample + cpal::SizedSample>(
+    device: &cpal::Device,
+    input_config: &cpal::StreamConfig,
+    mut callback: InputCallback,
+) -> Result<Stream, anyhow::Error> {
+    let error_handler = stream_error_handler(Arc::clone(&callback.events));
+    let temp_input_stream = device.build_input_stream(
+        input_config,
+        move |data: &[T], info: &InputCallbackInfo| {
+            callback.process(data, Some(info.timestamp()));
         },
-        err_fn,
+        error_handler,
         None,
     )?;
-    Ok(temp_output_stream)
+    Ok(temp_input_stream)
 }
 
-fn err_fn(err: cpal::StreamError) {
-    println!("an error occurred on stream: {}", err);
+fn create_output_stream<T: Sample + cpal::SizedSample>(
+    device: &cpal::Device,
+    output_config: &cpal::StreamConfig,
+    mut callback: OutputCallback,
+) -> Result<Stream, anyhow::Error> {
+    let error_handler = stream_error_handler(Arc::clone(&callback.events));
+    let temp_output_stream = device.build_output_stream(
+        output_config,
+        move |data: &mut [T], info: &OutputCallbackInfo| {
+            callback.process(data, Some(info.timestamp()));
+        },
+        error_handler,
+        None,
+    )?;
+    Ok(temp_output_stream)
 }
@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use cpal::StreamInstant;
+
+use crate::audio_class::AudioInstance;
+use crate::callback_load::StreamDirection;
+
+/// Counts of the glitches of the streams since the instance was created or the counts were reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamStats {
+    pub output_callbacks: usize,
+    /// The number of times the device ran out of data to play, leaving a gap in the output
+    pub output_underruns: usize,
+    /// The total number of frames missing from the output in those gaps
+    pub output_frames_lost: usize,
+    pub input_callbacks: usize,
+    /// The number of times the device had to drop input, leaving a gap in recordings
+    pub input_overruns: usize,
+    /// The total number of frames missing from the input in those gaps
+    pub input_frames_dropped: usize,
+}
+
+impl StreamStats {
+    /// Whether any audio has been lost.
+    pub fn has_glitches(&self) -> bool {
+        self.output_underruns > 0 || self.input_overruns > 0
+    }
+}
+
+/// Finds gaps in a stream from the timestamps the driver gives each callback.
+///
+/// Each buffer should start where the last one ended. When the callback is late, the device
+/// plays silence or drops input, and the next buffer starts later than expected.
+#[derive(Debug, Default)]
+pub(crate) struct GapCounter {
+    /// The start of the last buffer and its number of frames
+    last: Mutex<Option<(StreamInstant, usize)>>,
+    callbacks: AtomicUsize,
+    gaps: AtomicUsize,
+    lost_frames: AtomicUsize,
+}
+
+impl GapCounter {
+    /// Count a buffer. Called from the audio callbacks.
+    ///
+    /// # Arguments
+    /// start: StreamInstant - the capture time of an input buffer or the playback time of an output buffer
+    /// frames: usize - the number of frames in the buffer
+    /// sample_rate: u32 - the sample rate of the stream
+    pub fn count(&self, start: StreamInstant, frames: usize, sample_rate: u32) {
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        let Ok(mut last) = self.last.try_lock() else {
+            return;
+        };
+        if let Some((last_start, last_frames)) = *last {
+            if let Some(elapsed) = start.duration_since(&last_start) {
+                let lost = lost_frames(elapsed, last_frames, sample_rate);
+                if lost > 0 {
+                    self.gaps.fetch_add(1, Ordering::Relaxed);
+                    self.lost_frames.fetch_add(lost, Ordering::Relaxed);
+                }
+            }
+        }
+        *last = Some((start, frames));
+    }
+
+    fn reset(&self) {
+        *self.last.lock().unwrap() = None;
+        self.callbacks.store(0, Ordering::Relaxed);
+        self.gaps.store(0, Ordering::Relaxed);
+        self.lost_frames.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The number of frames missing between two buffers.
+///
+/// Driver timestamps jitter, so gaps shorter than half the previous buffer are ignored.
+fn lost_frames(elapsed: Duration, expected_frames: usize, sample_rate: u32) -> usize {
+    let elapsed_frames = (elapsed.as_secs_f64() * sample_rate as f64).round() as usize;
+    let gap = elapsed_frames.saturating_sub(expected_frames);
+    if gap > expected_frames / 2 {
+        gap
+    } else {
+        0
+    }
+}
+
+impl AudioInstance {
+    /// Counts of the underruns and overruns of the streams since the instance was created or
+    /// `reset_stream_stats` was called.
+    ///
+    /// Gaps are found from the timestamps the driver gives each callback, so they are only as
+    /// reliable as the driver's timestamps.
+    pub fn stream_stats(&self) -> StreamStats {
+        let output = self.callback_monitor.gaps(StreamDirection::Output);
+        let input = self.callback_monitor.gaps(StreamDirection::Input);
+        StreamStats {
+            output_callbacks: output.callbacks.load(Ordering::Relaxed),
+            output_underruns: output.gaps.load(Ordering::Relaxed),
+            output_frames_lost: output.lost_frames.load(Ordering::Relaxed),
+            input_callbacks: input.callbacks.load(Ordering::Relaxed),
+            input_overruns: input.gaps.load(Ordering::Relaxed),
+            input_frames_dropped: input.lost_frames.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Set the counts of `stream_stats` back to 0.
+    pub fn reset_stream_stats(&self) {
+        self.callback_monitor.gaps(StreamDirection::Output).reset();
+        self.callback_monitor.gaps(StreamDirection::Input).reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lost_frames() {
+        let buffer = Duration::from_millis(10);
+        // back-to-back buffers and small jitter are not gaps
+        assert_eq!(lost_frames(buffer, 480, 48000), 0);
+        assert_eq!(lost_frames(Duration::from_millis(12), 480, 48000), 0);
+        // a missed buffer
+        assert_eq!(lost_frames(buffer * 2, 480, 48000), 480);
+        assert_eq!(lost_frames(Duration::ZERO, 480, 48000), 0);
+    }
+}
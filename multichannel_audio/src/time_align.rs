@@ -1,151 +1,1052 @@
+#[cfg(feature = "device")]
 use crate::audio_class::AudioInstance;
 
 use super::methods;
+use crate::channel::{ChannelIndex, InputChannel, OutputChannel};
+#[cfg(feature = "device")]
+use crate::channel::{InputSelector, OutputSelector};
+use crate::sample_formats::Sample;
 use anyhow::Result;
 
+/// The duration of the built-in timing chirp in seconds.
+const CHIRP_DURATION: f64 = 0.5;
+
+/// The longest timing chirp that `find_start` can find, in seconds.
+const MAX_CHIRP_DURATION: f64 = 1.0;
+
+/// The fraction of its peak a sample of the timing chirp must reach to count as a peak.
+const TRIGGER_THRESHOLD: f64 = 0.2;
+
+/// The default duration of the silence at the start of an aligned measurement in seconds.
+const GAP_DURATION: f64 = 0.5;
+
+/// The default longest round-trip latency the timing chirp is searched for, in seconds.
+const MAX_LATENCY: f64 = 1.0;
+
+/// Where the timing chirp is played relative to the training signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChirpPlacement {
+    /// The chirp is played after the gap, and the training signal starts where it ends
+    #[default]
+    BeforeSignal,
+    /// The training signal is played after the gap, and the chirp straight after it. Use this if
+    /// the device under test has turn-on transients that would disturb the chirp
+    AfterSignal,
+}
+
+/// How the timing chirp of an aligned measurement is played.
+///
+/// An aligned measurement plays `gap_duration` seconds of silence, then the chirp and the
+/// training signal in the order set by `chirp_placement`, then `trailing_silence` seconds of
+/// silence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlignmentConfig {
+    /// The peak level of the chirp in dBFS. Lower it if the chirp overdrives the loopback input
+    pub chirp_level_dbfs: f64,
+    /// The duration of the chirp in seconds, up to 1 second. The built-in chirp is stretched or
+    /// squeezed to this duration
+    pub chirp_duration: f64,
+    /// The silence before anything is played in seconds, so noise when the recording starts is
+    /// not mistaken for the chirp
+    pub gap_duration: f64,
+    pub chirp_placement: ChirpPlacement,
+    /// The silence after everything has played in seconds, e.g. to record a reverb tail
+    pub trailing_silence: f64,
+    /// The longest round-trip latency the chirp is searched for in seconds, 1 second by default.
+    /// `TimingInfo::max_latency` gives a tighter bound from the latency the backend reports
+    pub max_latency: f64,
+}
+
+impl Default for AlignmentConfig {
+    fn default() -> Self {
+        AlignmentConfig {
+            chirp_level_dbfs: 0.0,
+            chirp_duration: CHIRP_DURATION,
+            gap_duration: GAP_DURATION,
+            chirp_placement: ChirpPlacement::default(),
+            trailing_silence: 0.0,
+            max_latency: MAX_LATENCY,
+        }
+    }
+}
+
+impl AlignmentConfig {
+    /// The timing chirp at the level and duration of the config.
+    ///
+    /// # Errors
+    /// Returns an error if the level is above 0 dBFS or the duration is not between 0 and 1 second
+    pub fn chirp(&self, fs: u32) -> Result<Vec<i32>, anyhow::Error> {
+        if self.chirp_level_dbfs > 0.0 || self.chirp_level_dbfs.is_nan() {
+            return Err(anyhow::anyhow!(
+                "The chirp level must be 0 dBFS or less, got {}",
+                self.chirp_level_dbfs
+            ));
+        }
+        let length = (self.chirp_duration * fs as f64).round();
+        if !(self.chirp_duration <= MAX_CHIRP_DURATION && length >= 2.0) {
+            return Err(anyhow::anyhow!(
+                "The chirp duration must be between 0 and {} seconds, got {}",
+                MAX_CHIRP_DURATION,
+                self.chirp_duration
+            ));
+        }
+
+        let chirp = stretch(&read_chirp(fs)?, length as usize);
+        let gain = 10f64.powf(self.chirp_level_dbfs / 20.0);
+        Ok(chirp
+            .into_iter()
+            .map(|sample| (sample as f64 * gain).round() as i32)
+            .collect())
+    }
+
+    /// Check the gap, trailing silence and maximum latency.
+    ///
+    /// # Errors
+    /// Returns an error if the gap or trailing silence is negative or not a number
+    /// Returns an error if the maximum latency is not more than 0 seconds
+    pub fn check_layout(&self) -> Result<(), anyhow::Error> {
+        if !(self.max_latency.is_finite() && self.max_latency > 0.0) {
+            return Err(anyhow::anyhow!(
+                "The max latency must be more than 0 seconds, got {}",
+                self.max_latency
+            ));
+        }
+        for (name, duration) in [
+            ("gap", self.gap_duration),
+            ("trailing silence", self.trailing_silence),
+        ] {
+            if !duration.is_finite() || duration < 0.0 {
+                return Err(anyhow::anyhow!(
+                    "The {} must be 0 seconds or more, got {}",
+                    name,
+                    duration
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of samples of silence at the start.
+    pub fn gap_length(&self, fs: u32) -> usize {
+        (self.gap_duration * fs as f64).round() as usize
+    }
+
+    /// The sample the training signal starts at in an assembled output signal.
+    ///
+    /// # Errors
+    /// Returns an error if the chirp is invalid. See `chirp`
+    pub fn signal_start(&self, fs: u32) -> Result<usize, anyhow::Error> {
+        Ok(match self.chirp_placement {
+            ChirpPlacement::BeforeSignal => self.gap_length(fs) + self.chirp(fs)?.len(),
+            ChirpPlacement::AfterSignal => self.gap_length(fs),
+        })
+    }
+
+    /// The number of samples from the last peak of the chirp to the sample after its end, so the
+    /// last peak found in a recording gives the end of the chirp exactly.
+    fn end_offset(&self, fs: u32) -> Result<usize, anyhow::Error> {
+        let chirp = self.chirp(fs)?;
+        let peak = chirp.iter().copied().max().unwrap_or(0) as f64;
+        let last_peak = chirp
+            .iter()
+            .rposition(|&sample| sample as f64 >= TRIGGER_THRESHOLD * peak)
+            .unwrap_or(0);
+        Ok(chirp.len() - last_peak)
+    }
+}
+
+/// A played stimulus and the aligned recording on a shared time base.
+///
+/// The stimulus and every response channel are the same length and sample 0 of each is the
+/// start of the stimulus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignedPair {
+    /// The training signal as it was played
+    pub stimulus: Vec<i32>,
+    /// The aligned recording, one vector per input channel
+    pub response: Vec<Vec<i32>>,
+}
+
+#[cfg(feature = "device")]
 impl AudioInstance {
     /// Play and record simultaneously with loopback timing signal.
     ///
-    /// See the play and record functions for more details.
-    pub fn aligned_play_record(
+    /// The training signal can be any sample type, e.g. f32 from -1.0 to 1.0, and the aligned
+    /// recording is returned in the same type. The channels can be given by number or by label,
+    /// e.g. `"Loopback"`. See the play and record functions for more details.
+    #[tracing::instrument(skip(self, training_signal), err)]
+    pub fn aligned_play_record<T: Sample>(
+        &self,
+        training_signal: Vec<T>,
+        training_channel: impl OutputSelector,
+        timing_channel_out: impl OutputSelector,
+        timing_channel_in: impl InputSelector,
+        number_of_output_channels: usize,
+    ) -> Result<Vec<Vec<T>>, anyhow::Error> {
+        let training_channel = self.output_channel(training_channel)?;
+        let timing_channel_out = self.output_channel(timing_channel_out)?;
+        let timing_channel_in = self.input_channel(timing_channel_in)?;
+        let config = self.alignment_config();
+        let duration = training_signal.len() as f64 / self.sample_rate as f64;
+        let training_signal: Vec<i32> = training_signal.into_iter().map(T::to_i32).collect();
+        let output_data = assemble_signal_with_config(
+            &training_signal,
+            duration as usize,
+            training_channel,
+            timing_channel_out,
+            self.sample_rate,
+            number_of_output_channels,
+            &config,
+        )?;
+        let mut recorded_data = self.play_record(output_data)?;
+        let aligned_data = align_with_layout(
+            &mut recorded_data,
+            timing_channel_in,
+            &config,
+            self.sample_rate,
+            duration as usize * self.sample_rate as usize,
+        )?;
+        Ok(aligned_data
+            .into_iter()
+            .map(|channel| channel.into_iter().map(T::from_i32).collect())
+            .collect())
+    }
+
+    /// Play and record with loopback timing, returning the stimulus and response on the same time base.
+    ///
+    /// This is the same measurement as `aligned_play_record`, but also returns the training signal
+    /// exactly as it was played, trimmed or padded with zeros to the length of the aligned recording.
+    /// This is what transfer function estimation needs.
+    #[tracing::instrument(skip(self, training_signal), err)]
+    pub fn aligned_pair(
         &self,
         training_signal: Vec<i32>,
-        training_channel: usize,
-        timing_channel_out: usize,
-        timing_channel_in: usize,
+        training_channel: impl OutputSelector,
+        timing_channel_out: impl OutputSelector,
+        timing_channel_in: impl InputSelector,
         number_of_output_channels: usize,
-    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    ) -> Result<AlignedPair, anyhow::Error> {
+        let training_channel = self.output_channel(training_channel)?;
+        let timing_channel_out = self.output_channel(timing_channel_out)?;
+        let timing_channel_in = self.input_channel(timing_channel_in)?;
+        let config = self.alignment_config();
         let duration = training_signal.len() as f64 / self.sample_rate as f64;
-        let output_data = self
-            .assemble_signal_with_loopback(
-                &training_signal,
-                duration as usize,
-                training_channel,
-                timing_channel_out,
-                self.sample_rate,
-                number_of_output_channels,
-            )
-            .unwrap();
+        let output_data = assemble_signal_with_config(
+            &training_signal,
+            duration as usize,
+            training_channel,
+            timing_channel_out,
+            self.sample_rate,
+            number_of_output_channels,
+            &config,
+        )?;
+
+        let signal_length = duration as usize * self.sample_rate as usize;
+        let stimulus_start = config.signal_start(self.sample_rate)?;
+        let played = output_data[training_channel.index()?]
+            [stimulus_start..stimulus_start + signal_length]
+            .to_vec();
+
         let mut recorded_data = self.play_record(output_data)?;
-        let aligned_data = self.align_with_loopback(&mut recorded_data, timing_channel_in)?;
-        Ok(aligned_data)
+        let response = align_with_layout(
+            &mut recorded_data,
+            timing_channel_in,
+            &config,
+            self.sample_rate,
+            signal_length,
+        )?;
+
+        Ok(pair_with_response(played, response))
     }
 
-    fn assemble_signal_with_loopback(
+    /// Play and record with loopback timing, guaranteeing the full response is returned.
+    ///
+    /// `aligned_play_record` records for exactly as long as it plays, so the device latency cuts off
+    /// the end of the response. This pads the playback with the round-trip latency plus `tail`
+    /// seconds of silence, aligns the recording using the loopback chirp and trims every channel to
+    /// exactly the length of the training signal plus the tail. The training signal is played
+    /// once, whatever its length.
+    ///
+    /// The latency measured by `measure_latency` is used if it has been measured, otherwise
+    /// `max_latency`.
+    ///
+    /// # Arguments
+    /// max_latency: f64 - the longest round-trip latency to allow for if it hasn't been measured, in
+    /// seconds
+    /// tail: f64 - the time to keep after the end of the training signal, in seconds
+    ///
+    /// # Errors
+    /// Returns an error if the latency is longer than the measured latency or `max_latency`
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip(self, training_signal), err)]
+    pub fn aligned_play_record_full_response(
         &self,
-        training_signal: &Vec<i32>,
-        duration: usize,
-        training_channel: usize,
-        timing_output: usize,
-        fs: u32,
+        training_signal: Vec<i32>,
+        training_channel: impl OutputSelector,
+        timing_channel_out: impl OutputSelector,
+        timing_channel_in: impl InputSelector,
         number_of_output_channels: usize,
+        max_latency: f64,
+        tail: f64,
     ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
-        let timing_index = timing_output - 1;
-        let training_index = training_channel - 1;
+        let training_channel = self.output_channel(training_channel)?;
+        let timing_channel_out = self.output_channel(timing_channel_out)?;
+        let timing_channel_in = self.input_channel(timing_channel_in)?;
+        let fs = self.sample_rate as f64;
+        let signal_length = training_signal.len();
+        let tail_length = (tail.max(0.0) * fs).round() as usize;
+        let latency_length = match self.latency() {
+            Some(latency) => latency.samples,
+            None => (max_latency.max(0.0) * fs).ceil() as usize,
+        };
+        let response_length = signal_length + tail_length;
+
+        let config = self.alignment_config();
+        let mut output_data = assemble_signal_with_length(
+            &training_signal,
+            signal_length,
+            training_channel,
+            timing_channel_out,
+            self.sample_rate,
+            number_of_output_channels,
+            &config,
+        )?;
+
+        // keep playing silence so the recording covers the latency and the tail
+        for channel in output_data.iter_mut() {
+            channel.resize(channel.len() + latency_length + tail_length, 0);
+        }
+
+        let mut recorded_data = self.play_record(output_data)?;
+        // align_with_layout would cut a chirp after the signal off with the tail
+        let start_sample = find_signal_start(
+            &recorded_data,
+            timing_channel_in,
+            &config,
+            self.sample_rate,
+            signal_length,
+        )?;
+        for channel in recorded_data.iter_mut() {
+            channel.drain(..start_sample.min(channel.len()));
+        }
+        trim_to_length(recorded_data, response_length)
+    }
+
+    /// Set the timing chirp and layout of the aligned measurements.
+    ///
+    /// # Errors
+    /// Returns an error if the chirp level or duration is invalid. See `AlignmentConfig::chirp`
+    /// Returns an error if the gap or trailing silence is invalid
+    pub fn set_alignment_config(&self, config: AlignmentConfig) -> Result<(), anyhow::Error> {
+        config.chirp(self.sample_rate)?;
+        config.check_layout()?;
+        *self.alignment.lock().unwrap() = config;
+        Ok(())
+    }
+
+    pub fn alignment_config(&self) -> AlignmentConfig {
+        *self.alignment.lock().unwrap()
+    }
+}
+
+/// Assemble the output signal for an aligned measurement.
+///
+/// The output starts with half a second of silence, then the timing chirp on `timing_output`,
+/// then the training signal on `training_channel`, looped to fill `duration` seconds.
+/// The signal can be any sample type, e.g. f32 from -1.0 to 1.0. Use
+/// `assemble_signal_with_config` for another layout.
+///
+/// # Errors
+/// Returns an error if either channel is out of range
+pub fn assemble_signal_with_loopback<T: Sample>(
+    training_signal: &Vec<T>,
+    duration: usize,
+    training_channel: OutputChannel,
+    timing_output: OutputChannel,
+    fs: u32,
+    number_of_output_channels: usize,
+) -> Result<Vec<Vec<T>>, anyhow::Error> {
+    assemble_signal_with_config(
+        training_signal,
+        duration,
+        training_channel,
+        timing_output,
+        fs,
+        number_of_output_channels,
+        &AlignmentConfig::default(),
+    )
+}
+
+/// Assemble the output signal for an aligned measurement with the timing chirp and layout of a
+/// config.
+///
+/// The training signal is looped to fill `duration` seconds, and placed with the chirp between
+/// the gap and the trailing silence of the config. See `assemble_signal_with_loopback`.
+///
+/// # Errors
+/// Returns an error if either channel is out of range or the config is invalid
+pub fn assemble_signal_with_config<T: Sample>(
+    training_signal: &[T],
+    duration: usize,
+    training_channel: OutputChannel,
+    timing_output: OutputChannel,
+    fs: u32,
+    number_of_output_channels: usize,
+    config: &AlignmentConfig,
+) -> Result<Vec<Vec<T>>, anyhow::Error> {
+    assemble_signal_with_length(
+        training_signal,
+        duration * fs as usize,
+        training_channel,
+        timing_output,
+        fs,
+        number_of_output_channels,
+        config,
+    )
+}
+
+/// `assemble_signal_with_config` with a training section of `length` samples rather than a whole
+/// number of seconds.
+fn assemble_signal_with_length<T: Sample>(
+    training_signal: &[T],
+    length: usize,
+    training_channel: OutputChannel,
+    timing_output: OutputChannel,
+    fs: u32,
+    number_of_output_channels: usize,
+    config: &AlignmentConfig,
+) -> Result<Vec<Vec<T>>, anyhow::Error> {
+    let training_index = ChannelIndex::output(training_channel, number_of_output_channels)?.get();
+
+    let silence = T::from_i32(0);
+    let mut training_vec = vec![vec![silence; length]; number_of_output_channels];
+
+    // loop the training signal to fill the duration
+    let mut training_signal = training_signal.to_vec();
+    if training_signal.len() < length {
+        let mut training_signal_loop = training_signal.clone();
+        while training_signal_loop.len() < length {
+            training_signal_loop.extend(training_signal.iter());
+        }
+        training_signal = training_signal_loop;
+    }
+
+    // Populate outer_vec[0] with as much of signal as possible
+    for (i, &value) in training_signal.iter().enumerate() {
+        if i < length {
+            training_vec[training_index][i] = value;
+        } else {
+            break;
+        }
+    }
+
+    // Read chirp from wave file, at the level and duration of the config
+    let chirp = config.chirp(fs)?;
+
+    // Format chirp for multichannel
+    let mut chirp_vec: Vec<Vec<T>> =
+        methods::format_signal_for_multichannel(chirp, timing_output, number_of_output_channels)?
+            .into_iter()
+            .map(|channel| channel.into_iter().map(T::from_i32).collect())
+            .collect();
 
-        let mut training_vec = vec![vec![0i32; duration * fs as usize]; number_of_output_channels];
+    config.check_layout()?;
 
-        // loop the training signal to fill the duration
-        let mut training_signal = training_signal.clone();
-        if training_signal.len() < duration * fs as usize {
-            let mut training_signal_loop = training_signal.clone();
-            while training_signal_loop.len() < duration * fs as usize {
-                training_signal_loop.extend(training_signal.iter());
+    // silence at the start to null any noise when the recording initializes
+    let mut output = vec![vec![silence; config.gap_length(fs)]; number_of_output_channels];
+    let trailing_length = (config.trailing_silence * fs as f64).round() as usize;
+
+    for ((channel, chirp_channel), training_channel) in
+        output.iter_mut().zip(&mut chirp_vec).zip(&mut training_vec)
+    {
+        match config.chirp_placement {
+            ChirpPlacement::BeforeSignal => {
+                channel.append(chirp_channel);
+                channel.append(training_channel);
+            }
+            ChirpPlacement::AfterSignal => {
+                channel.append(training_channel);
+                channel.append(chirp_channel);
             }
-            training_signal = training_signal_loop;
         }
+        channel.resize(channel.len() + trailing_length, silence);
+    }
 
-        // Populate outer_vec[0] with as much of signal as possible
-        for (i, &value) in training_signal.iter().enumerate() {
-            if i < duration * fs as usize {
-                training_vec[training_index as usize][i] = value as i32;
+    Ok(output)
+}
+
+/// Find the end of the default timing chirp in a loopback recording at a sample rate.
+#[cfg(feature = "device")]
+pub(crate) fn find_start(loopback: &mut Vec<i32>, fs: u32) -> Result<usize, anyhow::Error> {
+    let config = AlignmentConfig::default();
+    let latest = config.signal_start(fs)? + (config.max_latency * fs as f64) as usize;
+    find_chirp_end(
+        loopback,
+        config.gap_length(fs),
+        latest,
+        config.end_offset(fs)?,
+    )
+}
+
+/// Find the end of the timing chirp in a loopback recording.
+///
+/// # Arguments
+/// loopback: &mut Vec<i32> - the recording of the timing channel
+/// skip: usize - the number of samples at the start to ignore, i.e. the silent gap
+/// latest: usize - the last sample the chirp can peak at
+/// end_offset: usize - the number of samples from the last peak of the chirp to its end
+fn find_chirp_end(
+    loopback: &mut Vec<i32>,
+    skip: usize,
+    latest: usize,
+    end_offset: usize,
+) -> Result<usize, anyhow::Error> {
+    // Convert loopback to f64 values for normalization
+    let mut loopback_f64: Vec<f64> = loopback.iter().map(|&x| x as f64).collect();
+
+    // Remove any noise at the start
+    for val in loopback_f64.iter_mut().take(skip) {
+        *val = 0.0;
+    }
+
+    // Normalize loopback
+    let max = loopback_f64.iter().cloned().fold(f64::NAN, f64::max);
+    for val in &mut loopback_f64 {
+        *val /= max;
+    }
+
+    // Find indices of values greater than the trigger threshold
+    let trigger: Vec<usize> = loopback_f64
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &val)| {
+            if val >= TRIGGER_THRESHOLD {
+                Some(i)
             } else {
-                break;
+                None
             }
+        })
+        .collect();
+
+    // if trigger is later than the chirp can be, signal is corrupted
+    if trigger.len() == 0 || trigger[trigger.len() - 1] > latest {
+        return Err(anyhow::anyhow!(
+        "Timing trigger is later than sample {}. Signal is corrupted likely due to timing channel assign error.",
+        latest
+    ));
+    }
+
+    // Calculate start sample
+    let start_sample = trigger[trigger.len() - 1] + end_offset; // Add the offset to ensure we are at the start of the signal
+    tracing::debug!(start_sample, "found the end of the timing chirp");
+
+    Ok(start_sample)
+}
+
+/// Align a recording using the timing chirp recorded on `timing_channel`.
+///
+/// Removes everything up to the end of the chirp from every channel. The recording can be any
+/// sample type, e.g. f32 from -1.0 to 1.0.
+///
+/// # Arguments
+/// array: &mut Vec<Vec<T>> - the recording, one vector per input channel
+/// timing_channel: InputChannel - the input channel the chirp was recorded on
+/// fs: u32 - the sample rate of the recording
+pub fn align_with_loopback<T: Sample>(
+    array: &mut Vec<Vec<T>>,
+    timing_channel: InputChannel,
+    fs: u32,
+) -> Result<Vec<Vec<T>>, anyhow::Error> {
+    align_with_config(array, timing_channel, &AlignmentConfig::default(), fs)
+}
+
+/// Align a recording using a timing chirp played with `assemble_signal_with_config`.
+///
+/// The chirp is assumed to be before the signal. Use `align_with_layout` otherwise. See
+/// `align_with_loopback`.
+///
+/// # Errors
+/// Returns an error if the chirp of the config is played after the signal
+pub fn align_with_config<T: Sample>(
+    array: &mut Vec<Vec<T>>,
+    timing_channel: InputChannel,
+    config: &AlignmentConfig,
+    fs: u32,
+) -> Result<Vec<Vec<T>>, anyhow::Error> {
+    if config.chirp_placement == ChirpPlacement::AfterSignal {
+        return Err(anyhow::Error::msg(
+            "A chirp after the signal needs the signal length. Use align_with_layout",
+        ));
+    }
+    align_with_layout(array, timing_channel, config, fs, 0)
+}
+
+/// Align a recording of a signal assembled with `assemble_signal_with_config`.
+///
+/// With the chirp before the signal, everything up to the end of the chirp is removed from every
+/// channel. With the chirp after the signal, the start of the signal is found from the end of the
+/// chirp, and every channel is trimmed to `signal_length` so the chirp is not part of the result.
+///
+/// # Arguments
+/// array: &mut Vec<Vec<T>> - the recording, one vector per input channel
+/// timing_channel: InputChannel - the input channel the chirp was recorded on
+/// config: &AlignmentConfig - the config the signal was assembled with
+/// fs: u32 - the sample rate of the recording
+/// signal_length: usize - the number of samples of training signal that were played
+///
+/// # Errors
+/// Returns an error if the chirp is not found where the layout puts it
+#[tracing::instrument(skip(array, config), err)]
+pub fn align_with_layout<T: Sample>(
+    array: &mut Vec<Vec<T>>,
+    timing_channel: InputChannel,
+    config: &AlignmentConfig,
+    fs: u32,
+    signal_length: usize,
+) -> Result<Vec<Vec<T>>, anyhow::Error> {
+    let start_sample = find_signal_start(array, timing_channel, config, fs, signal_length)?;
+
+    // Remove the first start_sample elements from each channel
+    for channel in array.iter_mut() {
+        channel.drain(..start_sample.min(channel.len()));
+        if config.chirp_placement == ChirpPlacement::AfterSignal {
+            channel.truncate(signal_length);
         }
+    }
 
-        // Read chirp from wave file
-        let chirp_bytes = include_bytes!("../assets/chirp.wav").to_vec();
-        let chirp = methods::read_wave_file_dart(chirp_bytes, fs).unwrap();
+    Ok(array.clone())
+}
 
-        // Format chirp for multichannel
-        let mut chirp_vec =
-            methods::format_signal_for_multichannel(chirp, timing_index, number_of_output_channels);
+/// Find the sample the training signal starts at in a recording of a signal assembled with
+/// `assemble_signal_with_config`. See `align_with_layout`.
+fn find_signal_start<T: Sample>(
+    array: &[Vec<T>],
+    timing_channel: InputChannel,
+    config: &AlignmentConfig,
+    fs: u32,
+    signal_length: usize,
+) -> Result<usize, anyhow::Error> {
+    config.check_layout()?;
+    let timing_index = ChannelIndex::input(timing_channel, array.len())?.get();
+    let mut loopback: Vec<i32> = array[timing_index].iter().map(|&x| x.to_i32()).collect();
 
-        // Create a vector of zeros of size fs. We need this to null of any noise when the recording initializes
-        let mut gap = vec![vec![0i32; fs as usize / 2]; number_of_output_channels];
+    let chirp_length = config.chirp(fs)?.len();
+    let mut latest =
+        config.gap_length(fs) + chirp_length + (config.max_latency * fs as f64) as usize;
+    if config.chirp_placement == ChirpPlacement::AfterSignal {
+        latest += signal_length;
+    }
 
-        // assemble the final training signal
-        // Check that all vectors have the same number of channels
-        assert_eq!(gap.len(), chirp_vec.len());
-        assert_eq!(gap.len(), training_vec.len());
+    // Find the start sample
+    let chirp_end = find_chirp_end(
+        &mut loopback,
+        config.gap_length(fs),
+        latest,
+        config.end_offset(fs)?,
+    )?;
+    match config.chirp_placement {
+        ChirpPlacement::BeforeSignal => Ok(chirp_end),
+        ChirpPlacement::AfterSignal => chirp_end
+            .checked_sub(chirp_length + signal_length)
+            .ok_or_else(|| {
+                anyhow::anyhow!("The timing chirp ends before the signal could have been played")
+            }),
+    }
+}
 
-        // Append chirp_vec and training_vec to the inner vectors of gap
-        for ((gap_channel, chirp_channel), training_channel) in
-            gap.iter_mut().zip(&mut chirp_vec).zip(&mut training_vec)
-        {
-            gap_channel.append(chirp_channel);
-            gap_channel.append(training_channel);
+/// Put a played stimulus on the time base of an aligned recording.
+///
+/// The stimulus is trimmed or padded with zeros to the length of the recording.
+pub fn pair_with_response(mut stimulus: Vec<i32>, response: Vec<Vec<i32>>) -> AlignedPair {
+    let length = response.first().map_or(0, |channel| channel.len());
+    stimulus.resize(length, 0);
+
+    AlignedPair { stimulus, response }
+}
+
+/// Read the timing chirp played on the loopback channel.
+pub(crate) fn read_chirp(fs: u32) -> Result<Vec<i32>, anyhow::Error> {
+    let chirp_bytes = include_bytes!("../assets/chirp.wav").to_vec();
+    Ok(methods::read_wave_file_dart(chirp_bytes, fs)?)
+}
+
+/// Stretch or squeeze a signal to a length with linear interpolation.
+fn stretch(signal: &[i32], length: usize) -> Vec<i32> {
+    if signal.len() == length || signal.len() < 2 {
+        return signal.to_vec();
+    }
+    let step = (signal.len() - 1) as f64 / (length - 1) as f64;
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = (position as usize).min(signal.len() - 2);
+            let fraction = position - index as f64;
+            (signal[index] as f64 * (1.0 - fraction) + signal[index + 1] as f64 * fraction).round()
+                as i32
+        })
+        .collect()
+}
+
+/// Trim every channel to exactly `length` samples.
+///
+/// Returns an error if any channel is shorter than `length`, since the response would be incomplete.
+#[cfg(feature = "device")]
+fn trim_to_length(mut array: Vec<Vec<i32>>, length: usize) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    for channel in array.iter_mut() {
+        if channel.len() < length {
+            return Err(anyhow::anyhow!(
+                "Recording is {} samples short of the full response. The latency is longer than allowed for.",
+                length - channel.len()
+            ));
         }
+        channel.truncate(length);
+    }
+
+    Ok(array)
+}
 
-        return Ok(gap);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "device")]
+    use crate::{
+        backend::{ChannelModel, MockDevice},
+        builder::AudioInstanceBuilder,
+    };
+    #[cfg(feature = "device")]
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_pair_with_response_pads_stimulus() {
+        let pair = pair_with_response(vec![1, 2, 3], vec![vec![4, 5, 6, 7, 8], vec![0; 5]]);
+
+        assert_eq!(pair.stimulus, vec![1, 2, 3, 0, 0]);
+        assert_eq!(pair.response[0].len(), pair.stimulus.len());
+    }
+
+    #[test]
+    fn test_pair_with_response_trims_stimulus() {
+        let pair = pair_with_response(vec![1, 2, 3, 4, 5], vec![vec![6, 7]]);
+
+        assert_eq!(pair.stimulus, vec![1, 2]);
     }
 
-    fn find_start(&self, loopback: &mut Vec<i32>) -> Result<usize, anyhow::Error> {
-        // Convert loopback to f64 values for normalization
-        let mut loopback_f64: Vec<f64> = loopback.iter().map(|&x| x as f64).collect();
+    #[test]
+    fn test_alignment_config_chirp() {
+        let short = |chirp_level_dbfs| AlignmentConfig {
+            chirp_level_dbfs,
+            chirp_duration: 0.25,
+            ..Default::default()
+        };
+        let full = short(0.0).chirp(48000).unwrap();
+        let quiet = short(-6.0).chirp(48000).unwrap();
+
+        assert_eq!(
+            AlignmentConfig::default().chirp(48000).unwrap(),
+            read_chirp(48000).unwrap()
+        );
+        assert_eq!(quiet.len(), 12000);
+        let peak = |chirp: &[i32]| chirp.iter().map(|x| x.unsigned_abs()).max().unwrap() as f64;
+        assert!((peak(&quiet) / peak(&full) - 0.501).abs() < 0.001);
 
-        // Remove any noise at the start
-        for val in loopback_f64.iter_mut().take(24000) {
-            *val = 0.0;
+        for invalid in [(1.0, 0.5), (0.0, 0.0), (0.0, 1.5)] {
+            let config = AlignmentConfig {
+                chirp_level_dbfs: invalid.0,
+                chirp_duration: invalid.1,
+                ..Default::default()
+            };
+            assert!(config.chirp(48000).is_err());
         }
+    }
 
-        // Normalize loopback
-        let max = loopback_f64.iter().cloned().fold(f64::NAN, f64::max);
-        for val in &mut loopback_f64 {
-            *val /= max;
+    #[test]
+    fn test_align_with_config_loopback() {
+        let training: Vec<i32> = (0..48000).map(|i| i % 1000 + 1).collect();
+        for config in [
+            AlignmentConfig::default(),
+            AlignmentConfig {
+                chirp_level_dbfs: -20.0,
+                chirp_duration: 0.2,
+                ..Default::default()
+            },
+        ] {
+            let output = assemble_signal_with_config(
+                &training,
+                1,
+                OutputChannel(1),
+                OutputChannel(2),
+                48000,
+                2,
+                &config,
+            )
+            .unwrap();
+
+            // a perfect loopback of both channels with no latency
+            let mut recording = output.clone();
+            let aligned =
+                align_with_config(&mut recording, InputChannel(2), &config, 48000).unwrap();
+            let skipped = output[0].len() - aligned[0].len();
+            let training_start = 24000 + config.chirp(48000).unwrap().len();
+            assert_eq!(skipped, training_start);
         }
+    }
 
-        // Find indices of values greater than 0.2
-        let trigger: Vec<usize> = loopback_f64
+    #[test]
+    fn test_alignment_layout() {
+        let training: Vec<i32> = (0..48000).map(|i| i % 1000 + 1).collect();
+        let config = AlignmentConfig {
+            gap_duration: 0.25,
+            chirp_placement: ChirpPlacement::AfterSignal,
+            trailing_silence: 0.1,
+            ..Default::default()
+        };
+        let output = assemble_signal_with_config(
+            &training,
+            1,
+            OutputChannel(1),
+            OutputChannel(2),
+            48000,
+            2,
+            &config,
+        )
+        .unwrap();
+        let chirp_length = config.chirp(48000).unwrap().len();
+        assert_eq!(config.signal_start(48000).unwrap(), 12000);
+        assert_eq!(output[0].len(), 12000 + 48000 + chirp_length + 4800);
+        assert_eq!(output[0][12000..60000], training[..]);
+        assert!(output[1][..60000].iter().all(|&sample| sample == 0));
+
+        // a loopback with 100 samples of latency
+        let mut recording: Vec<Vec<i32>> = output
             .iter()
-            .enumerate()
-            .filter_map(|(i, &val)| if val >= 0.2 { Some(i) } else { None })
+            .map(|channel| [vec![0; 100], channel.clone()].concat())
             .collect();
+        let aligned =
+            align_with_layout(&mut recording, InputChannel(2), &config, 48000, 48000).unwrap();
+        assert_eq!(aligned[0], training);
 
-        // if trigger is later than 2 seconds in, signal is corrupted
-        if trigger.len() == 0 || trigger[trigger.len() - 1] > 96000 {
-            return Err(anyhow::anyhow!(
-            "Timing trigger is later than 2 seconds. Signal is corrupted likely due to timing channel assign error."
-        ));
+        // the chirp arrives after the maximum latency
+        let short_window = AlignmentConfig {
+            max_latency: 0.001,
+            ..config
+        };
+        assert!(
+            align_with_layout(&mut recording, InputChannel(2), &short_window, 48000, 48000)
+                .is_err()
+        );
+
+        let mut recording = output.clone();
+        assert!(align_with_config(&mut recording, InputChannel(2), &config, 48000).is_err());
+
+        let negative = AlignmentConfig {
+            gap_duration: -0.1,
+            ..Default::default()
+        };
+        assert!(negative.check_layout().is_err());
+        let no_window = AlignmentConfig {
+            max_latency: 0.0,
+            ..Default::default()
+        };
+        assert!(no_window.check_layout().is_err());
+    }
+
+    #[test]
+    fn test_align_f32() {
+        let training: Vec<f32> = (0..48000).map(|i| (i % 100) as f32 / 200.0).collect();
+        let output = assemble_signal_with_loopback(
+            &training,
+            1,
+            OutputChannel(2),
+            OutputChannel(1),
+            48000,
+            2,
+        )
+        .unwrap();
+        let training_start = 24000 + AlignmentConfig::default().chirp(48000).unwrap().len();
+        assert_eq!(output[1][training_start + 10], training[10]);
+
+        let mut recording = output.clone();
+        let aligned = align_with_loopback(&mut recording, InputChannel(1), 48000).unwrap();
+        let skipped = output[0].len() - aligned[0].len();
+        assert_eq!(skipped, training_start);
+    }
+
+    #[test]
+    fn test_align_sample_rates() {
+        for fs in [44100, 96000, 192000] {
+            let training: Vec<i32> = (0..fs).map(|i| (i % 1000 + 1) as i32).collect();
+            let output = assemble_signal_with_loopback(
+                &training,
+                1,
+                OutputChannel(1),
+                OutputChannel(2),
+                fs,
+                2,
+            )
+            .unwrap();
+            let config = AlignmentConfig::default();
+            let training_start = config.signal_start(fs).unwrap();
+            assert_eq!(
+                training_start,
+                fs as usize / 2 + config.chirp(fs).unwrap().len()
+            );
+
+            // a loopback with 10 ms of latency
+            let latency = fs as usize / 100;
+            let mut recording: Vec<Vec<i32>> = output
+                .iter()
+                .map(|channel| [vec![0; latency], channel.clone()].concat())
+                .collect();
+            let recorded_length = recording[0].len();
+            let aligned = align_with_loopback(&mut recording, InputChannel(2), fs).unwrap();
+            let skipped = recorded_length - aligned[0].len();
+            assert_eq!(skipped, training_start + latency, "{} Hz", fs);
         }
+    }
 
-        // Calculate start sample
-        let start_sample = trigger[trigger.len() - 1] + 15; // Add 15 samples to ensure we are at the start of the signal
-        println!("start_sample: {}", start_sample);
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_trim_to_length() {
+        let array = vec![vec![1, 2, 3, 4, 5], vec![6, 7, 8, 9, 10]];
+        let trimmed = trim_to_length(array, 3).unwrap();
 
-        Ok(start_sample)
+        assert_eq!(trimmed, vec![vec![1, 2, 3], vec![6, 7, 8]]);
     }
 
-    fn align_with_loopback(
-        &self,
-        array: &mut Vec<Vec<i32>>,
-        timing_channel: usize,
-    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
-        // Subtract 1 from timing_channel as Rust uses 0-based indexing
-        let timing_channel = timing_channel
-            .checked_sub(1)
-            .ok_or(anyhow::anyhow!("timing_channel must be greater than 0"))?;
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_trim_to_length_too_short() {
+        let array = vec![vec![1, 2, 3], vec![4, 5]];
+        let result = trim_to_length(array, 3);
 
-        // Find the start sample
-        let start_sample = self.find_start(&mut array[timing_channel])?;
-        // println!("Start sample: {}", start_sample);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_full_response_chirp_placement() {
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(MockDevice::new(2, 2).delay(64))
+            .duplex(true)
+            .build()
+            .unwrap();
+        let mut training = vec![0i32; 48000];
+        training[100] = 100_000_000;
 
-        // Remove the first start_sample elements from each channel
-        for channel in array.iter_mut() {
-            channel.drain(..start_sample);
+        for chirp_placement in [ChirpPlacement::BeforeSignal, ChirpPlacement::AfterSignal] {
+            audio_instance
+                .set_alignment_config(AlignmentConfig {
+                    chirp_placement,
+                    ..Default::default()
+                })
+                .unwrap();
+            let response = audio_instance
+                .aligned_play_record_full_response(
+                    training.clone(),
+                    OutputChannel(1),
+                    OutputChannel(2),
+                    InputChannel(2),
+                    2,
+                    0.1,
+                    0.1,
+                )
+                .unwrap();
+            assert_eq!(response[0].len(), 52800, "{:?}", chirp_placement);
+            assert_eq!(response[0][..48000], training[..], "{:?}", chirp_placement);
         }
+    }
 
-        Ok(array.clone())
+    #[test]
+    #[cfg(feature = "device")]
+    fn test_full_response_measured_latency() {
+        // input 3 hears output 1 2000 samples late, so the end of its response is in the tail
+        let device = MockDevice::new(3, 2).delay(64).channel(
+            InputChannel(3),
+            ChannelModel::from_output(OutputChannel(1)).delay(2000),
+        );
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(device)
+            .duplex(true)
+            .build()
+            .unwrap();
+        audio_instance
+            .measure_latency(OutputChannel(2), InputChannel(2))
+            .unwrap();
+
+        // 1.5 seconds, with an impulse in the last half second
+        let mut training = vec![0i32; 72000];
+        training[100] = 100_000_000;
+        training[71000] = 50_000_000;
+        let response = audio_instance
+            .aligned_play_record_full_response(
+                training,
+                OutputChannel(1),
+                OutputChannel(2),
+                InputChannel(2),
+                2,
+                0.0,
+                0.1,
+            )
+            .unwrap();
+        assert_eq!(response[0].len(), 72000 + 4800);
+        let impulses = |channel: &[i32]| -> Vec<(usize, i32)> {
+            channel
+                .iter()
+                .copied()
+                .enumerate()
+                .filter(|&(_, sample)| sample != 0)
+                .collect()
+        };
+        assert_eq!(
+            impulses(&response[0]),
+            vec![(100, 100_000_000), (71000, 50_000_000)]
+        );
+        assert_eq!(
+            impulses(&response[2]),
+            vec![(2100, 100_000_000), (73000, 50_000_000)]
+        );
+    }
+
+    #[cfg(feature = "device")]
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        /// An impulse measured through a simulated device lands where the channel model puts it,
+        /// whatever the latency of the device
+        #[test]
+        fn test_simulated_aligned_play_record(
+            latency in 0usize..4800,
+            delay in 0usize..480,
+            gain in 0.1f64..1.0,
+            seed in any::<u64>(),
+        ) {
+            let device = MockDevice::new(2, 2)
+                .delay(latency)
+                .buffer_frames(1024)
+                .noise(2, seed)
+                .channel(
+                    InputChannel(1),
+                    ChannelModel::from_output(OutputChannel(1)).delay(delay).gain(gain),
+                );
+            let audio_instance = AudioInstanceBuilder::new()
+                .mock(device)
+                .duplex(true)
+                .build()
+                .unwrap();
+
+            let mut training = vec![0i32; 48000];
+            training[100] = 100_000_000;
+            let aligned = audio_instance
+                .aligned_play_record(training, OutputChannel(1), OutputChannel(2), InputChannel(2), 2)
+                .unwrap();
+
+            let (peak_index, &peak) = aligned[0]
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, sample)| sample.unsigned_abs())
+                .unwrap();
+            prop_assert_eq!(peak_index, 100 + delay);
+            prop_assert!((peak as f64 - 1e8 * gain).abs() <= 3.0, "{}", peak);
+        }
     }
 }
@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use cpal::StreamInstant;
+
+/// The time a buffer of a recording was captured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferTimestamp {
+    /// The index of the first sample of the buffer in each recorded channel
+    pub sample_index: usize,
+    /// The time the first sample was captured, on the clock of the input stream
+    pub capture: StreamInstant,
+    /// The time the first sample was captured, on the system clock
+    pub host_time: Instant,
+}
+
+/// Maps sample indices of a recording to the times they were captured.
+///
+/// Every callback of the input stream adds a timestamp, so drift between the audio clock and the
+/// system clock is corrected once per buffer. Times between buffers are calculated from the sample
+/// rate. Use this to fuse recordings with data from external acquisition hardware.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampMap {
+    sample_rate: u32,
+    buffers: Vec<BufferTimestamp>,
+}
+
+impl TimestampMap {
+    pub(crate) fn new(sample_rate: u32, buffers: Vec<BufferTimestamp>) -> Self {
+        TimestampMap {
+            sample_rate,
+            buffers,
+        }
+    }
+
+    /// The timestamp of every buffer in the recording, in order.
+    pub fn buffers(&self) -> &[BufferTimestamp] {
+        &self.buffers
+    }
+
+    /// The time a sample was captured, on the system clock.
+    ///
+    /// Returns None if the recording has no timestamps.
+    pub fn host_time(&self, sample_index: usize) -> Option<Instant> {
+        let buffer = self.buffer_containing(sample_index)?;
+        Some(buffer.host_time + self.duration(sample_index - buffer.sample_index))
+    }
+
+    /// The time a sample was captured, on the clock of the input stream.
+    pub fn stream_time(&self, sample_index: usize) -> Option<StreamInstant> {
+        let buffer = self.buffer_containing(sample_index)?;
+        buffer
+            .capture
+            .add(self.duration(sample_index - buffer.sample_index))
+    }
+
+    /// The index of the sample captured at a time on the system clock.
+    ///
+    /// Returns None if the time is before the recording started.
+    pub fn sample_index(&self, host_time: Instant) -> Option<usize> {
+        let position = self
+            .buffers
+            .partition_point(|buffer| buffer.host_time <= host_time);
+        let buffer = self.buffers.get(position.checked_sub(1)?)?;
+
+        let offset = (host_time - buffer.host_time).as_secs_f64() * self.sample_rate as f64;
+        Some(buffer.sample_index + offset.round() as usize)
+    }
+
+    /// The last buffer that starts at or before a sample.
+    fn buffer_containing(&self, sample_index: usize) -> Option<&BufferTimestamp> {
+        let position = self
+            .buffers
+            .partition_point(|buffer| buffer.sample_index <= sample_index);
+        self.buffers.get(position.checked_sub(1)?)
+    }
+
+    fn duration(&self, samples: usize) -> Duration {
+        Duration::from_secs_f64(samples as f64 / self.sample_rate as f64)
+    }
+}
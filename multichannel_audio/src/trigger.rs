@@ -0,0 +1,337 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::channel::InputChannel;
+use crate::conversions::dbfs_to_i32;
+use crate::pre_record::select_channels;
+use crate::sample_formats::Sample;
+
+/// How often `record_on_external_trigger` polls the trigger.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Something outside the program that starts a recording, e.g. a foot switch, a GPIO line or a
+/// message from other lab equipment.
+pub trait Trigger: Send + Sync {
+    /// Whether the trigger has fired. Polled until it returns true.
+    fn armed(&self) -> bool;
+}
+
+impl Trigger for AtomicBool {
+    fn armed(&self) -> bool {
+        self.load(Ordering::Acquire)
+    }
+}
+
+impl<T: Trigger + ?Sized> Trigger for Arc<T> {
+    fn armed(&self) -> bool {
+        (**self).armed()
+    }
+}
+
+/// Fires when a GPIO input reads high, using the Linux sysfs interface.
+///
+/// The line must already be exported and set as an input, e.g. with
+/// `echo 17 > /sys/class/gpio/export`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpioTrigger {
+    value_path: PathBuf,
+    active_low: bool,
+}
+
+impl GpioTrigger {
+    /// # Arguments
+    /// value_path: impl Into<PathBuf> - the value file of the line, e.g. `/sys/class/gpio/gpio17/value`
+    pub fn new(value_path: impl Into<PathBuf>) -> Self {
+        GpioTrigger {
+            value_path: value_path.into(),
+            active_low: false,
+        }
+    }
+
+    /// Fire when the line reads low instead, e.g. for a switch to ground with a pull-up.
+    pub fn active_low(mut self) -> Self {
+        self.active_low = true;
+        self
+    }
+}
+
+impl Trigger for GpioTrigger {
+    fn armed(&self) -> bool {
+        match std::fs::read_to_string(&self.value_path) {
+            Ok(value) => (value.trim() == "1") != self.active_low,
+            // an unreadable line never fires
+            Err(_) => false,
+        }
+    }
+}
+
+/// Fires when a line of text is received, from a serial port, a pipe or the keyboard.
+///
+/// A thread reads the lines, so `armed` never blocks. Once fired, the trigger stays armed.
+pub struct LineTrigger {
+    fired: Arc<AtomicBool>,
+}
+
+impl LineTrigger {
+    /// Fire on the first line read from a reader, e.g. a serial device opened as a file.
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        let fired = Arc::new(AtomicBool::new(false));
+        let fired_clone = Arc::clone(&fired);
+        std::thread::spawn(move || {
+            let mut line = String::new();
+            if let Ok(read) = BufReader::new(reader).read_line(&mut line) {
+                if read > 0 {
+                    fired_clone.store(true, Ordering::Release);
+                }
+            }
+        });
+        LineTrigger { fired }
+    }
+
+    /// Fire when Enter is pressed.
+    pub fn keyboard() -> Self {
+        Self::new(std::io::stdin())
+    }
+}
+
+impl Trigger for LineTrigger {
+    fn armed(&self) -> bool {
+        self.fired.load(Ordering::Acquire)
+    }
+}
+
+/// Watches an input channel for the level crossing that starts a triggered recording.
+///
+/// The input callback checks every frame while nothing is being recorded. When the trigger
+/// fires, it keeps the pre-roll and the rest of that callback, and the callback starts the
+/// recording from the next buffer.
+#[derive(Debug)]
+pub(crate) struct LevelTrigger {
+    /// The 0-based index of the channel to watch
+    channel: usize,
+    /// The absolute sample value that fires the trigger
+    threshold: u32,
+    /// The number of interleaved samples to keep from before the trigger
+    pre_roll: usize,
+    /// The most recent frames of every channel
+    history: VecDeque<i32>,
+    /// The pre-roll and the frames of the callback the trigger fired in, once it has fired
+    captured: Vec<i32>,
+    /// The number of frames of pre-roll at the start of `captured`
+    captured_pre_roll: usize,
+    fired: bool,
+}
+
+impl LevelTrigger {
+    fn new(channel: usize, threshold: u32, pre_roll: usize) -> Self {
+        LevelTrigger {
+            channel,
+            threshold,
+            pre_roll,
+            history: VecDeque::with_capacity(pre_roll),
+            captured: Vec::new(),
+            captured_pre_roll: 0,
+            fired: false,
+        }
+    }
+
+    /// Check the frames of a callback for the threshold crossing. Called from the input callback.
+    ///
+    /// # Returns
+    /// True if the trigger fired in this callback
+    pub fn check<T: Sample>(&mut self, data: &[T], channels: usize) -> bool {
+        if self.fired {
+            return false;
+        }
+        for (index, frame) in data.chunks_exact(channels).enumerate() {
+            let Some(&sample) = frame.get(self.channel) else {
+                return false;
+            };
+            if sample.to_i32().unsigned_abs() >= self.threshold {
+                self.captured_pre_roll = self.history.len() / channels;
+                self.captured = self.history.drain(..).collect();
+                self.captured.extend(
+                    data[index * channels..]
+                        .iter()
+                        .map(|&sample| sample.to_i32()),
+                );
+                self.fired = true;
+                return true;
+            }
+
+            self.history
+                .extend(frame.iter().map(|&sample| sample.to_i32()));
+            let excess = self.history.len().saturating_sub(self.pre_roll);
+            self.history.drain(..excess);
+        }
+        false
+    }
+}
+
+impl AudioInstance {
+    /// Wait for the level of an input channel to cross a threshold, then record.
+    ///
+    /// The input callback watches the channel, so the recording starts on the exact frame that
+    /// crossed the threshold. Use this to record acoustic events. This blocks until the trigger
+    /// fires and the recording is complete. The pre-roll isn't passed through the input filters.
+    ///
+    /// # Arguments
+    /// threshold_db: f64 - the peak level in dBFS that starts the recording
+    /// channel: InputChannel - the channel to watch
+    /// duration: f64 - the duration to record from the trigger, in seconds
+    /// pre_roll: f64 - the time to keep from before the trigger, in seconds
+    ///
+    /// # Returns
+    /// A tuple of the recording, with the pre-roll first, and the number of pre-roll samples in
+    /// each channel. The pre-roll is shorter than requested if the trigger fired sooner
+    ///
+    /// # Errors
+    /// Returns an error if the channel is out of range or decimation is set
+    pub fn record_on_trigger(
+        &self,
+        threshold_db: f64,
+        channel: InputChannel,
+        duration: f64,
+        pre_roll: f64,
+    ) -> Result<(Vec<Vec<i32>>, usize), anyhow::Error> {
+        let channels = self.number_of_input_channels as usize;
+        let index = self.input_index(channel)?.get();
+        if self.recorded_sample_rate() != self.sample_rate {
+            return Err(anyhow::Error::msg(
+                "Triggered recordings can't be used while decimating",
+            ));
+        }
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        let fs = self.sample_rate as f64;
+        let threshold = dbfs_to_i32(threshold_db) as u32;
+        let pre_roll_frames = (pre_roll.max(0.0) * fs) as usize;
+        let duration_frames = (duration.max(0.0) * fs) as usize;
+
+        self.start_capture(self.input_capture(duration));
+
+        let (lock, _) = &*self.record_wait_pair;
+        let recording = lock.lock().unwrap();
+        *self.trigger.lock().unwrap() = Some(LevelTrigger::new(
+            index,
+            threshold,
+            pre_roll_frames * channels,
+        ));
+        // the callback starts recording when the trigger fires, and notifies when it is complete
+        let triggered = self.wait_for_recording(recording, |recording| {
+            !recording
+                && self
+                    .trigger
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|trigger| trigger.fired)
+        });
+        if let Err(error) = triggered {
+            *self.trigger.lock().unwrap() = None;
+            return Err(error);
+        }
+
+        let trigger = self.trigger.lock().unwrap().take().unwrap();
+        let pre_roll_length = trigger.captured_pre_roll;
+
+        let capture = self.take_capture();
+        let captured = select_channels(&trigger.captured, channels, &capture.input_channels());
+        let mut channel_recordings = capture.into_channels();
+        for (channel, mut captured) in channel_recordings.iter_mut().zip(captured) {
+            captured.append(channel);
+            captured.truncate(pre_roll_length + duration_frames);
+            *channel = captured;
+        }
+
+        Ok((channel_recordings, pre_roll_length))
+    }
+
+    /// Wait for an external trigger, then record.
+    ///
+    /// The trigger is polled every millisecond, so the recording starts within about a
+    /// millisecond and one buffer of the trigger firing.
+    ///
+    /// # Arguments
+    /// trigger: &dyn Trigger - the trigger to wait for
+    /// duration: f64 - the duration of the recording in seconds
+    /// timeout: Option<Duration> - how long to wait for the trigger, or None to wait forever
+    ///
+    /// # Errors
+    /// Returns an error if the trigger doesn't fire before the timeout
+    pub fn record_on_external_trigger(
+        &self,
+        trigger: &dyn Trigger,
+        duration: f64,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+        // start the stream now so it is running when the trigger fires
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        let start = Instant::now();
+        while !trigger.armed() {
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                return Err(anyhow::anyhow!(
+                    "The trigger didn't fire within {:?}",
+                    timeout.unwrap()
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        self.record(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpio_trigger() {
+        let path =
+            std::env::temp_dir().join(format!("multichannel_audio_gpio_{}", std::process::id()));
+        let trigger = GpioTrigger::new(&path);
+        assert!(!trigger.armed());
+
+        std::fs::write(&path, "1\n").unwrap();
+        assert!(trigger.armed());
+        assert!(!trigger.clone().active_low().armed());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_line_trigger() {
+        let trigger = LineTrigger::new(std::io::Cursor::new("start\n"));
+        let start = Instant::now();
+        while !trigger.armed() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        // an empty reader never fires
+        let trigger = LineTrigger::new(std::io::empty());
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!trigger.armed());
+    }
+
+    #[test]
+    fn test_level_trigger_keeps_pre_roll() {
+        // watch the second of two channels, keeping two frames of pre-roll
+        let mut trigger = LevelTrigger::new(1, 100, 4);
+
+        assert!(!trigger.check(&[1i32, 10, 2, 20, 3, 30], 2));
+        assert!(trigger.check(&[4i32, -150, 5, 50], 2));
+        assert_eq!(trigger.captured, vec![2, 20, 3, 30, 4, -150, 5, 50]);
+        assert_eq!(trigger.captured_pre_roll, 2);
+
+        // a fired trigger ignores later callbacks
+        assert!(!trigger.check(&[6i32, 500], 2));
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant};
+
+use crate::audio_class::{AudioInstance, StreamControllerType};
+use crate::callback_load::StreamDirection;
+
+/// The number of consecutive on-time callbacks before a stream counts as ready.
+const STABLE_CALLBACKS: usize = 16;
+
+/// How far the interval between callbacks can be from the buffer duration, as a fraction of the
+/// buffer duration, for the callback to count as on time.
+const JITTER_TOLERANCE: f64 = 0.5;
+
+/// A stream stops counting as ready when no callback has arrived for this many buffer durations.
+const STALE_BUFFERS: u32 = 4;
+
+/// How often `warm_up` checks the streams.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Counts the callbacks of a stream that arrive one buffer duration after the previous one.
+///
+/// Drivers often deliver the first callbacks of a new stream in bursts or after long gaps while
+/// they spin up, which is when playback glitches.
+#[derive(Debug, Default)]
+pub(crate) struct ArrivalTracker {
+    /// The arrival time and buffer duration of the last callback
+    last: Option<(Instant, Duration)>,
+    /// The number of consecutive callbacks that arrived on time
+    stable: usize,
+}
+
+impl ArrivalTracker {
+    /// Record the arrival of a callback. Called from the audio callbacks.
+    pub fn arrive(&mut self, at: Instant, deadline: Duration) {
+        if let Some((last, last_deadline)) = self.last {
+            let interval = at.saturating_duration_since(last).as_secs_f64();
+            let expected = last_deadline.as_secs_f64();
+            if (interval - expected).abs() <= expected * JITTER_TOLERANCE {
+                self.stable += 1;
+            } else {
+                self.stable = 0;
+            }
+        }
+        self.last = Some((at, deadline));
+    }
+
+    /// Whether enough callbacks in a row have arrived on time, and the last one was recent.
+    pub fn is_stable(&self, now: Instant) -> bool {
+        match self.last {
+            Some((last, deadline)) => {
+                self.stable >= STABLE_CALLBACKS
+                    && now.saturating_duration_since(last) <= deadline * STALE_BUFFERS
+            }
+            None => false,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = ArrivalTracker::default();
+    }
+}
+
+impl AudioInstance {
+    /// Whether a stream is running and its callbacks are arriving at a steady rate.
+    ///
+    /// A stream is ready after 16 callbacks in a row have each arrived within half a buffer of
+    /// when they were expected.
+    pub fn is_stream_ready(&self, direction: StreamDirection) -> bool {
+        self.callback_monitor
+            .arrivals(direction)
+            .lock()
+            .unwrap()
+            .is_stable(Instant::now())
+    }
+
+    /// Start the streams and wait until their callbacks arrive at a steady rate.
+    ///
+    /// Call this after creating the instance so the first `play` doesn't glitch while the driver
+    /// is still spinning up. The output plays silence and the input is discarded while waiting,
+    /// apart from the pre-record buffer if it is on. Nothing should be played or recorded until
+    /// this returns.
+    ///
+    /// # Arguments
+    /// timeout: Duration - the longest time to wait for the streams to settle
+    ///
+    /// # Errors
+    /// Returns an error if a device has been disconnected
+    /// Returns an error if the streams don't settle within the timeout
+    pub fn warm_up(&self, timeout: Duration) -> Result<(), anyhow::Error> {
+        self.ensure_stream_running(StreamControllerType::Output)?;
+        self.ensure_stream_running(StreamControllerType::Input)?;
+
+        let directions = [StreamDirection::Input, StreamDirection::Output];
+        // callbacks from before the streams were restarted don't count
+        for direction in directions {
+            self.callback_monitor
+                .arrivals(direction)
+                .lock()
+                .unwrap()
+                .reset();
+        }
+
+        let start = Instant::now();
+        while !directions
+            .iter()
+            .all(|&direction| self.is_stream_ready(direction))
+        {
+            if start.elapsed() >= timeout {
+                return Err(anyhow::anyhow!(
+                    "The streams didn't settle within {:?}",
+                    timeout
+                ));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steady_callbacks_are_stable() {
+        let buffer = Duration::from_millis(10);
+        let start = Instant::now();
+        let mut tracker = ArrivalTracker::default();
+
+        // a burst of callbacks while the driver spins up
+        for _ in 0..4 {
+            tracker.arrive(start, buffer);
+        }
+        let mut now = start;
+        for _ in 0..STABLE_CALLBACKS {
+            assert!(!tracker.is_stable(now));
+            now += buffer;
+            tracker.arrive(now, buffer);
+        }
+        assert!(tracker.is_stable(now));
+
+        // one late callback starts the count again
+        now += buffer * 3;
+        tracker.arrive(now, buffer);
+        assert!(!tracker.is_stable(now));
+    }
+
+    #[test]
+    fn test_stopped_stream_is_not_stable() {
+        let buffer = Duration::from_millis(10);
+        let mut now = Instant::now();
+        let mut tracker = ArrivalTracker::default();
+        for _ in 0..=STABLE_CALLBACKS {
+            tracker.arrive(now, buffer);
+            now += buffer;
+        }
+        assert!(tracker.is_stable(now));
+        assert!(!tracker.is_stable(now + buffer * 10));
+    }
+}
@@ -0,0 +1,150 @@
+use wasm_bindgen::prelude::*;
+
+use crate::channel::{InputChannel, OutputChannel};
+use crate::{methods, time_align};
+
+// wasm-bindgen can't pass nested vectors, so multichannel data is passed as one flat vector with
+// the channels one after another.
+
+/// Generate a sine wave signal.
+#[wasm_bindgen]
+pub fn generate_sine_wave(frequency: u32, duration: f32, fs: u32) -> Vec<i32> {
+    methods::generate_sine_wave(frequency, duration, fs)
+}
+
+/// Generate a white noise signal.
+#[wasm_bindgen]
+pub fn generate_gaussian_white_noise(duration_seconds: f32, fs: u32) -> Vec<i32> {
+    methods::generate_gaussian_white_noise(duration_seconds, fs, None)
+}
+
+/// Read a WAV file from a byte array.
+#[wasm_bindgen]
+pub fn read_wave_file(byte_data: Vec<u8>, fs: u32) -> Result<Vec<i32>, JsValue> {
+    methods::read_wave_file_dart(byte_data, fs).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Assemble the output signal for an aligned measurement.
+///
+/// Channel numbers are 1-based. Returns the channels one after another.
+/// See `time_align::assemble_signal_with_loopback`.
+#[wasm_bindgen]
+pub fn assemble_signal_with_loopback(
+    training_signal: Vec<i32>,
+    duration: usize,
+    training_channel: usize,
+    timing_output: usize,
+    fs: u32,
+    number_of_output_channels: usize,
+) -> Result<Vec<i32>, JsValue> {
+    let output_data = time_align::assemble_signal_with_loopback(
+        &training_signal,
+        duration,
+        OutputChannel(training_channel),
+        OutputChannel(timing_output),
+        fs,
+        number_of_output_channels,
+    )
+    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    Ok(output_data.concat())
+}
+
+/// Align a recording using the timing chirp recorded on `timing_channel`.
+///
+/// `recording` holds `number_of_channels` channels one after another, and the aligned channels
+/// are returned the same way. `timing_channel` is 1-based and `fs` is the sample rate of the
+/// recording. See `time_align::align_with_loopback`.
+#[wasm_bindgen]
+pub fn align_with_loopback(
+    recording: Vec<i32>,
+    number_of_channels: usize,
+    timing_channel: usize,
+    fs: u32,
+) -> Result<Vec<i32>, JsValue> {
+    let mut channels = split_channels(&recording, number_of_channels)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let aligned_data =
+        time_align::align_with_loopback(&mut channels, InputChannel(timing_channel), fs)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    Ok(aligned_data.concat())
+}
+
+/// Split channels passed one after another into separate channels.
+fn split_channels(
+    recording: &[i32],
+    number_of_channels: usize,
+) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    if recording.is_empty() {
+        return Err(anyhow::Error::msg("The recording is empty"));
+    }
+    if number_of_channels == 0 || recording.len() % number_of_channels != 0 {
+        return Err(anyhow::Error::msg(
+            "Recording length must be a multiple of the number of channels",
+        ));
+    }
+    Ok(recording
+        .chunks_exact(recording.len() / number_of_channels)
+        .map(|channel| channel.to_vec())
+        .collect())
+}
+
+// JsValue errors can only be created on wasm32, so the tests check the errors before conversion
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_channels() {
+        let channels = split_channels(&[1, 2, 3, 4, 5, 6], 3).unwrap();
+        assert_eq!(channels, [vec![1, 2], vec![3, 4], vec![5, 6]]);
+
+        assert!(split_channels(&[], 2).is_err());
+        assert!(split_channels(&[1, 2, 3], 0).is_err());
+        assert!(split_channels(&[1, 2, 3], 2).is_err());
+    }
+
+    #[test]
+    fn test_align_with_loopback() {
+        let training: Vec<i32> = (0..48000).map(|i| (i % 1000 + 1) * 1000).collect();
+        let output = assemble_signal_with_loopback(training.clone(), 1, 1, 2, 48000, 2).unwrap();
+        assert_eq!(output.len() % 2, 0);
+        let length = output.len() / 2;
+
+        let aligned = align_with_loopback(output, 2, 2, 48000).unwrap();
+        assert_eq!(aligned.len() % 2, 0);
+        let aligned_length = aligned.len() / 2;
+        assert!(aligned_length < length);
+        // the training signal starts each aligned channel, give or take a few samples
+        assert!((0..=6).any(|offset| aligned[offset..offset + 100] == training[3..103]));
+    }
+
+    #[test]
+    fn test_read_wave_file() {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for sample in [1, -2, 3] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        assert_eq!(
+            read_wave_file(bytes.into_inner(), 48000).unwrap(),
+            [1, -2, 3]
+        );
+    }
+
+    #[test]
+    fn test_generators() {
+        assert_eq!(generate_sine_wave(1000, 0.5, 48000).len(), 24000);
+        assert_eq!(generate_gaussian_white_noise(0.5, 48000).len(), 24000);
+    }
+}
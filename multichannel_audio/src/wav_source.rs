@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use hound::SampleFormat;
+
+use crate::methods::split_channels;
+use crate::sample_formats::Sample;
+use crate::source::SignalSource;
+
+/// A WAV file read from disk a chunk or a frame at a time, so large files are never held in
+/// memory as a whole.
+///
+/// Samples are scaled to full-scale i32, as by `read_wave_file_channels`. A `WavSource` can be
+/// played with `AudioInstance::play_source`, which plays the first channels of the file on the
+/// first output channels.
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use multichannel_audio::wav_source::WavSource;
+///
+/// let mut source = WavSource::open(Path::new("stimulus.wav")).unwrap();
+/// while let Some(chunk) = source.read_chunk(48000).unwrap() {
+///     // one vector per channel of the file, up to a second long
+///     assert_eq!(chunk.len(), source.spec().channels as usize);
+/// }
+/// ```
+pub struct WavSource {
+    reader: hound::WavReader<BufReader<File>>,
+    channels: usize,
+    sample_format: SampleFormat,
+    bits_per_sample: u16,
+    /// The number of frames read
+    position: u32,
+}
+
+impl WavSource {
+    /// Open a WAV file without reading its samples.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened, or its sample format is not supported
+    pub fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        match (spec.sample_format, spec.bits_per_sample) {
+            (SampleFormat::Int, bits) if bits <= 32 => {}
+            (SampleFormat::Float, 32) => {}
+            _ => return Err(hound::Error::Unsupported.into()),
+        }
+
+        Ok(WavSource {
+            reader,
+            channels: spec.channels as usize,
+            sample_format: spec.sample_format,
+            bits_per_sample: spec.bits_per_sample,
+            position: 0,
+        })
+    }
+
+    pub fn spec(&self) -> hound::WavSpec {
+        self.reader.spec()
+    }
+
+    /// The number of frames in the file.
+    pub fn frames(&self) -> u32 {
+        self.reader.duration()
+    }
+
+    /// The number of frames that have been read.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Move to a frame, e.g. 0 to read the file again.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read
+    pub fn seek(&mut self, frame: u32) -> Result<(), anyhow::Error> {
+        let frame = frame.min(self.frames());
+        self.reader.seek(frame)?;
+        self.position = frame;
+        Ok(())
+    }
+
+    /// Read up to `frames` frames, or None at the end of the file.
+    ///
+    /// # Returns
+    /// One vector per channel of the file, shorter than `frames` at the end of the file
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read
+    pub fn read_chunk(&mut self, frames: usize) -> Result<Option<Vec<Vec<i32>>>, hound::Error> {
+        let interleaved = self.read_samples(frames * self.channels)?;
+        if interleaved.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(split_channels(&interleaved, self.channels)))
+    }
+
+    /// Read up to `samples` interleaved samples, scaled to full-scale i32.
+    fn read_samples(&mut self, samples: usize) -> Result<Vec<i32>, hound::Error> {
+        let interleaved: Vec<i32> = match self.sample_format {
+            SampleFormat::Int => {
+                let shift = 32 - self.bits_per_sample as u32;
+                self.reader
+                    .samples::<i32>()
+                    .take(samples)
+                    .map(|sample| sample.map(|s| s << shift))
+                    .collect::<Result<_, _>>()?
+            }
+            SampleFormat::Float => self
+                .reader
+                .samples::<f32>()
+                .take(samples)
+                .map(|sample| sample.map(|s| (s * i32::MAX as f32) as i32))
+                .collect::<Result<_, _>>()?,
+        };
+        self.position += (interleaved.len() / self.channels) as u32;
+        Ok(interleaved)
+    }
+}
+
+impl SignalSource for WavSource {
+    /// Write the next frame of the file. Output channels the file doesn't have are silent, and
+    /// channels of the file past the last output channel are skipped.
+    fn next_frame(&mut self, out: &mut [f32]) {
+        let frame = match self.read_samples(self.channels) {
+            Ok(frame) => frame,
+            Err(error) => {
+                // a source can't fail, so end it where the file can't be read
+                tracing::warn!(%error, "failed to read the WAV file, stopping it");
+                self.seek(self.frames()).ok();
+                Vec::new()
+            }
+        };
+        out.fill(0.0);
+        for (out, &sample) in out.iter_mut().zip(&frame) {
+            *out = f32::from_i32(sample);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position >= self.frames()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods;
+    use crate::source::render_source;
+
+    #[test]
+    fn test_wav_source() {
+        let path = std::env::temp_dir().join(format!(
+            "multichannel_audio_wav_source_{}.wav",
+            std::process::id()
+        ));
+        let channels = vec![(0..10).collect::<Vec<i32>>(), (10..20).collect()];
+        methods::save_channels_to_wav(channels, path.to_str().unwrap(), 48000).unwrap();
+        let expected = methods::read_wave_file_channels(&path, 48000).unwrap();
+
+        let mut source = WavSource::open(&path).unwrap();
+        assert_eq!(source.frames(), 10);
+        let first = source.read_chunk(4).unwrap().unwrap();
+        let second = source.read_chunk(4).unwrap().unwrap();
+        assert_eq!(source.position(), 8);
+        let third = source.read_chunk(4).unwrap().unwrap();
+        assert!(source.read_chunk(4).unwrap().is_none());
+        assert!(source.is_finished());
+
+        assert_eq!(first[1], expected[1][..4]);
+        assert_eq!(second[0], expected[0][4..8]);
+        assert_eq!(third[0], expected[0][8..]);
+
+        // as a signal source, on three output channels
+        source.seek(8).unwrap();
+        let rendered = render_source(&mut source, 3, 10);
+        assert_eq!(rendered[0].len(), 2);
+        assert_eq!(rendered[1], expected[1][8..]);
+        assert_eq!(rendered[2], vec![0, 0]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+use std::io::Write;
+
+use multichannel_audio::audio_class::AudioInstance;
+use multichannel_audio::channel::{InputChannel, OutputChannel};
+use multichannel_audio::conversions::{db_to_linear, linear_to_db};
+use multichannel_audio::fades::apply_fades;
+use multichannel_audio::methods::generate_sine_wave;
+
+/// The frequency of the identification beeps in Hz.
+const TONE_FREQUENCY: u32 = 1000;
+/// The duration of the beep for each unit of the channel number in seconds.
+const SHORT_BEEP: f64 = 0.12;
+/// The duration of the beep for each ten of the channel number in seconds.
+const LONG_BEEP: f64 = 0.4;
+/// The silence after each beep in seconds.
+const BEEP_GAP: f64 = 0.15;
+/// The fade in and out of each beep in seconds, so the beeps don't click.
+const BEEP_FADE: f64 = 0.005;
+/// The length of each block of input the meters show, in seconds.
+const METER_INTERVAL: f64 = 0.1;
+/// The lowest level the meters show in dBFS. Inputs that stay below it count as silent.
+const METER_FLOOR_DBFS: f64 = -60.0;
+/// The number of characters in the bar of each meter.
+const METER_WIDTH: usize = 5;
+
+/// The tone that identifies an output by ear: a long beep for each ten of the channel number,
+/// then a short beep for each unit. Output 12 is one long beep and two short ones.
+///
+/// # Arguments
+/// channel: OutputChannel - the output to identify
+/// fs: u32 - the sample rate
+/// level_dbfs: f64 - the peak level of the beeps
+pub fn identification_tone(channel: OutputChannel, fs: u32, level_dbfs: f64) -> Vec<i32> {
+    let beeps = std::iter::repeat_n(LONG_BEEP, channel.0 / 10)
+        .chain(std::iter::repeat_n(SHORT_BEEP, channel.0 % 10));
+    let gain = db_to_linear(level_dbfs);
+    let fade = (BEEP_FADE * fs as f64) as usize;
+
+    let mut tone = Vec::new();
+    for duration in beeps {
+        let mut beep = generate_sine_wave(TONE_FREQUENCY, duration as f32, fs);
+        for sample in beep.iter_mut() {
+            *sample = (*sample as f64 * gain) as i32;
+        }
+        apply_fades(&mut beep, 1, fade, fade);
+        tone.extend(beep);
+        tone.resize(tone.len() + (BEEP_GAP * fs as f64) as usize, 0);
+    }
+    tone
+}
+
+/// The peak level of a block of samples in dBFS.
+fn peak_dbfs(samples: &[i32]) -> f64 {
+    let peak = samples
+        .iter()
+        .map(|&sample| sample.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+    linear_to_db(peak as f64 / i32::MAX as f64)
+}
+
+/// A line of meters, one per input channel, e.g. ` 1 ###   2 #     3       `.
+fn meter_line(levels_dbfs: &[f64]) -> String {
+    levels_dbfs
+        .iter()
+        .enumerate()
+        .map(|(index, &level)| {
+            let fraction = ((level - METER_FLOOR_DBFS) / -METER_FLOOR_DBFS).clamp(0.0, 1.0);
+            let bar = "#".repeat((fraction * METER_WIDTH as f64).ceil() as usize);
+            format!("{:>2} {:<width$}", index + 1, bar, width = METER_WIDTH)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The input with the highest level, if any input was above the floor of the meters.
+fn loudest_input(levels_dbfs: &[f64]) -> Option<(InputChannel, f64)> {
+    levels_dbfs
+        .iter()
+        .enumerate()
+        .filter(|(_, &level)| level > METER_FLOOR_DBFS)
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, &level)| (InputChannel::from_index(index), level))
+}
+
+/// Play the identification tone on an output while showing meters for every input.
+///
+/// # Returns
+/// The input the tone was loudest on and its peak level, or None if no input received it
+pub fn check_output(
+    audio_instance: &AudioInstance,
+    output: OutputChannel,
+    level_dbfs: f64,
+) -> Result<Option<(InputChannel, f64)>, anyhow::Error> {
+    let tone = identification_tone(output, audio_instance.sample_rate(), level_dbfs);
+    let mut output_data = vec![vec![0; tone.len()]; audio_instance.channels_out() as usize];
+    output_data[audio_instance.output_index(output)?.get()] = tone;
+
+    let mut loudest = vec![f64::NEG_INFINITY; audio_instance.channels_in() as usize];
+    let tone = audio_instance.play_mixed(output_data)?;
+    // keep metering for one more block, so the end of the tone arrives despite the latency
+    let mut blocks_after_tone = 1;
+    while blocks_after_tone > 0 {
+        if !tone.is_playing() {
+            blocks_after_tone -= 1;
+        }
+        let levels: Vec<f64> = audio_instance
+            .record(METER_INTERVAL)?
+            .iter()
+            .map(|channel| peak_dbfs(channel))
+            .collect();
+        for (loudest, &level) in loudest.iter_mut().zip(&levels) {
+            *loudest = loudest.max(level);
+        }
+        print!("\r{}", meter_line(&levels));
+        std::io::stdout().flush()?;
+    }
+    println!();
+    Ok(loudest_input(&loudest))
+}
+
+/// Check every output in turn, printing the input each one arrives on.
+///
+/// # Arguments
+/// outputs: &[OutputChannel] - the outputs to check, in order
+/// level_dbfs: f64 - the peak level of the identification tones
+/// step: bool - wait for Enter before each output
+pub fn channel_check(
+    audio_instance: &AudioInstance,
+    outputs: &[OutputChannel],
+    level_dbfs: f64,
+    step: bool,
+) -> Result<(), anyhow::Error> {
+    let labels = audio_instance.channel_labels();
+    for &output in outputs {
+        let name = match labels.output_label(output) {
+            Some(label) => format!("{output} ({label})"),
+            None => output.to_string(),
+        };
+        if step {
+            print!("Press Enter to play {name}");
+            std::io::stdout().flush()?;
+            std::io::stdin().read_line(&mut String::new())?;
+        } else {
+            println!("Playing {name}");
+        }
+
+        match check_output(audio_instance, output, level_dbfs)? {
+            Some((input, level)) => {
+                let input_name = match labels.input_label(input) {
+                    Some(label) => format!("{input} ({label})"),
+                    None => input.to_string(),
+                };
+                println!("  loudest on {input_name} at {level:.1} dBFS");
+            }
+            None => println!("  not received on any input"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multichannel_audio::backend::{ChannelModel, MockDevice};
+    use multichannel_audio::builder::AudioInstanceBuilder;
+
+    /// The length of each beep of a tone in samples. The sine crosses zero within a beep, so a
+    /// beep only ends at a longer run of silence.
+    fn beeps(tone: &[i32]) -> Vec<usize> {
+        let mut beeps: Vec<(usize, usize)> = Vec::new();
+        for (index, _) in tone.iter().enumerate().filter(|(_, &sample)| sample != 0) {
+            match beeps.last_mut() {
+                Some((_, end)) if index - *end < 100 => *end = index,
+                _ => beeps.push((index, index)),
+            }
+        }
+        beeps
+            .into_iter()
+            .map(|(start, end)| end - start + 1)
+            .collect()
+    }
+
+    #[test]
+    fn test_identification_tone() {
+        let fs = 48000;
+        let tone = identification_tone(OutputChannel(12), fs, -6.0);
+        let lengths = beeps(&tone);
+        assert_eq!(lengths.len(), 3);
+        assert!(lengths[0] > lengths[1]);
+        assert!((peak_dbfs(&tone) - -6.0).abs() < 0.1);
+
+        assert_eq!(
+            beeps(&identification_tone(OutputChannel(3), fs, -6.0)).len(),
+            3
+        );
+        assert_eq!(
+            beeps(&identification_tone(OutputChannel(10), fs, -6.0)).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_meters() {
+        assert_eq!(
+            meter_line(&[0.0, -30.0, -90.0]),
+            " 1 #####  2 ###    3      "
+        );
+        assert_eq!(
+            loudest_input(&[-40.0, -12.0, f64::NEG_INFINITY]),
+            Some((InputChannel(2), -12.0))
+        );
+        assert_eq!(loudest_input(&[-70.0, f64::NEG_INFINITY]), None);
+    }
+
+    #[test]
+    fn test_check_output() {
+        // outputs 1 and 2 are wired to inputs 2 and 1
+        let audio_instance = AudioInstanceBuilder::new()
+            .mock(
+                MockDevice::new(2, 2)
+                    .channel(InputChannel(1), ChannelModel::from_output(OutputChannel(2)))
+                    .channel(InputChannel(2), ChannelModel::from_output(OutputChannel(1))),
+            )
+            .build()
+            .unwrap();
+        let (input, level) = check_output(&audio_instance, OutputChannel(1), -20.0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(input, InputChannel(2));
+        assert!((level - -20.0).abs() < 0.5);
+
+        let (input, _) = check_output(&audio_instance, OutputChannel(2), -20.0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(input, InputChannel(1));
+    }
+}
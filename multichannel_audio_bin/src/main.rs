@@ -0,0 +1,432 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+
+use multichannel_audio::audio_class::AudioInstance;
+use multichannel_audio::channel::{
+    ChannelSelection, InputChannel, InputSelector, OutputChannel, OutputSelector,
+};
+use multichannel_audio::config::AudioConfig;
+use multichannel_audio::context::AudioContext;
+use multichannel_audio::methods::{
+    interleave_on_channels, read_wave_file_channels, save_channels_to_wav, split_channels,
+};
+use multichannel_audio::pcm::{PcmFormat, PcmSource};
+use multichannel_audio::profile::HardwareProfile;
+
+mod channel_check;
+
+/// Play and record multichannel audio from the command line.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    device: DeviceArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// The device to use. Settings given here override the ones in the profile.
+#[derive(Debug, Args)]
+struct DeviceArgs {
+    /// A hardware profile saved as TOML, for the device, sample rate, channel labels and loopback
+    #[arg(long, global = true)]
+    profile: Option<PathBuf>,
+    /// The audio host, e.g. "ASIO" or "CoreAudio". The default host of the platform otherwise
+    #[arg(long, global = true)]
+    host: Option<String>,
+    /// The output device. The default output device of the host otherwise
+    #[arg(long, global = true)]
+    device: Option<String>,
+    /// The input device, if it is different to the output device
+    #[arg(long, global = true)]
+    input_device: Option<String>,
+    /// The sample rate in Hz, 48000 if the profile doesn't set one
+    #[arg(long, global = true)]
+    sample_rate: Option<u32>,
+    /// Open every channel of the device, e.g. for ASIO drivers that default to 2 channels
+    #[arg(long, global = true)]
+    all_channels: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the devices of the host
+    ListDevices,
+    /// Play a WAV file
+    Play {
+        /// The WAV file to play
+        wav: PathBuf,
+        /// The output channel to play each channel of the file on, by number or label. The
+        /// first outputs otherwise
+        #[arg(long = "channel")]
+        channels: Vec<Channel>,
+    },
+    /// Record to a WAV file
+    Record {
+        /// The length of the recording in seconds
+        seconds: f64,
+        /// The WAV file to save the recording to
+        #[arg(long)]
+        out: PathBuf,
+        /// The input channels to record, by number or label. Every input otherwise
+        #[arg(long = "channel")]
+        channels: Vec<Channel>,
+    },
+    /// Play a WAV file and record every input while it plays
+    PlayRecord {
+        /// The WAV file to play
+        wav: PathBuf,
+        /// The WAV file to save the recording to
+        #[arg(long)]
+        out: PathBuf,
+        /// The output channel to play each channel of the file on, by number or label. The
+        /// first outputs otherwise
+        #[arg(long = "channel")]
+        channels: Vec<Channel>,
+    },
+    /// Play raw interleaved PCM from stdin, e.g. from sox or ffmpeg, until the stream ends
+    PlayRaw {
+        /// The sample format of the stream: s16le, s24le, s32le or f32le
+        #[arg(long, default_value = "s16le")]
+        format: PcmFormat,
+        /// The number of channels in the stream, which play on the first outputs. Every output
+        /// otherwise
+        #[arg(long)]
+        channels: Option<usize>,
+    },
+    /// Record raw interleaved PCM to stdout, e.g. for sox or ffmpeg
+    RecordRaw {
+        /// The sample format to write: s16le, s24le, s32le or f32le
+        #[arg(long, default_value = "s16le")]
+        format: PcmFormat,
+        /// The length of the recording in seconds. Records until stdout is closed otherwise
+        #[arg(long)]
+        seconds: Option<f64>,
+        /// The input channels to record, by number or label. Every input otherwise
+        #[arg(long = "channel")]
+        channels: Vec<Channel>,
+    },
+    /// Play an identification tone on each output in turn while metering every input, to check
+    /// the wiring of a rig. Output N beeps once long for each ten and once short for each unit
+    ChannelCheck {
+        /// The outputs to check, by number or label. Every output otherwise
+        #[arg(long = "channel")]
+        channels: Vec<Channel>,
+        /// The peak level of the tones in dBFS
+        #[arg(long, default_value_t = -20.0, allow_negative_numbers = true)]
+        level: f64,
+        /// Wait for Enter before each output
+        #[arg(long)]
+        step: bool,
+    },
+    /// Play a mono stimulus with the loopback timing chirp and save the aligned recording
+    AlignMeasure {
+        /// The mono WAV file to play
+        wav: PathBuf,
+        /// The WAV file to save the aligned recording to
+        #[arg(long)]
+        out: PathBuf,
+        /// The output channel to play the stimulus on
+        #[arg(long)]
+        channel: Channel,
+        /// The output channel of the loopback. The first loopback of the profile otherwise
+        #[arg(long)]
+        loopback_out: Option<Channel>,
+        /// The input channel of the loopback. The first loopback of the profile otherwise
+        #[arg(long)]
+        loopback_in: Option<Channel>,
+    },
+}
+
+/// A channel given on the command line, by its 1-based number or by its label.
+#[derive(Debug, Clone, PartialEq)]
+enum Channel {
+    Number(usize),
+    Label(String),
+}
+
+impl std::str::FromStr for Channel {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(number) => Channel::Number(number),
+            Err(_) => Channel::Label(s.to_string()),
+        })
+    }
+}
+
+impl OutputSelector for Channel {
+    fn selection(&self) -> ChannelSelection<'_, OutputChannel> {
+        match self {
+            Channel::Number(number) => ChannelSelection::Channel(OutputChannel(*number)),
+            Channel::Label(label) => ChannelSelection::Label(label),
+        }
+    }
+}
+
+impl InputSelector for Channel {
+    fn selection(&self) -> ChannelSelection<'_, InputChannel> {
+        match self {
+            Channel::Number(number) => ChannelSelection::Channel(InputChannel(*number)),
+            Channel::Label(label) => ChannelSelection::Label(label),
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(cli) {
+        eprintln!("Error: {err:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), anyhow::Error> {
+    let profile = match cli.device.profile {
+        Some(ref path) => HardwareProfile::load(path)?,
+        None => HardwareProfile::default(),
+    };
+    let config = device_config(&profile.config, &cli.device);
+
+    match cli.command {
+        Command::ListDevices => list_devices(&config),
+        Command::Play { wav, channels } => {
+            let audio_instance = open(&config)?;
+            let output_data = read_stimulus(&audio_instance, &wav, &channels)?;
+            audio_instance.play(output_data)
+        }
+        Command::Record {
+            seconds,
+            out,
+            channels,
+        } => {
+            let audio_instance = open(&config)?;
+            let recording = if channels.is_empty() {
+                audio_instance.record(seconds)?
+            } else {
+                audio_instance.record_channels(seconds, &channels)?
+            };
+            save(recording, &out, audio_instance.sample_rate())
+        }
+        Command::PlayRecord { wav, out, channels } => {
+            let audio_instance = open(&config)?;
+            let output_data = read_stimulus(&audio_instance, &wav, &channels)?;
+            let recording = audio_instance.play_record(output_data)?;
+            save(recording, &out, audio_instance.sample_rate())
+        }
+        Command::PlayRaw { format, channels } => {
+            let audio_instance = open(&config)?;
+            let channels = channels.unwrap_or(audio_instance.channels_out() as usize);
+            let mut source = PcmSource::new(std::io::stdin().lock(), format, channels);
+            audio_instance.play_source(&mut source, None)
+        }
+        Command::RecordRaw {
+            format,
+            seconds,
+            channels,
+        } => {
+            let audio_instance = open(&config)?;
+            if !channels.is_empty() {
+                audio_instance.set_enabled_input_channels(&channels)?;
+            }
+            let stdout = std::io::BufWriter::new(std::io::stdout());
+            audio_instance.record_pcm(stdout, seconds, format)
+        }
+        Command::ChannelCheck {
+            channels,
+            level,
+            step,
+        } => {
+            let audio_instance = open(&config)?;
+            let outputs = if channels.is_empty() {
+                (1..=audio_instance.channels_out() as usize)
+                    .map(OutputChannel)
+                    .collect()
+            } else {
+                channels
+                    .iter()
+                    .map(|channel| audio_instance.output_channel(channel))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            channel_check::channel_check(&audio_instance, &outputs, level, step)
+        }
+        Command::AlignMeasure {
+            wav,
+            out,
+            channel,
+            loopback_out,
+            loopback_in,
+        } => {
+            let audio_instance = open(&config)?;
+            let loopback = profile.loopbacks.first();
+            let loopback_out = match (loopback_out, loopback) {
+                (Some(channel), _) => audio_instance.output_channel(channel)?,
+                (None, Some(loopback)) => loopback.output,
+                (None, None) => anyhow::bail!("Give --loopback-out or a profile with a loopback"),
+            };
+            let loopback_in = match (loopback_in, loopback) {
+                (Some(channel), _) => audio_instance.input_channel(channel)?,
+                (None, Some(loopback)) => loopback.input,
+                (None, None) => anyhow::bail!("Give --loopback-in or a profile with a loopback"),
+            };
+
+            let mut stimulus = read_wave_file_channels(&wav, audio_instance.sample_rate())?;
+            if stimulus.len() != 1 {
+                anyhow::bail!(
+                    "The stimulus must be mono, {} has {} channels",
+                    wav.display(),
+                    stimulus.len()
+                );
+            }
+            let recording = audio_instance.aligned_play_record(
+                stimulus.remove(0),
+                channel,
+                loopback_out,
+                loopback_in,
+                audio_instance.channels_out() as usize,
+            )?;
+            save(recording, &out, audio_instance.sample_rate())
+        }
+    }
+}
+
+/// The settings of the profile with the ones given on the command line on top.
+fn device_config(profile: &AudioConfig, args: &DeviceArgs) -> AudioConfig {
+    let mut config = profile.clone();
+    config.host = args.host.clone().or(config.host);
+    config.device = args.device.clone().or(config.device);
+    config.input_device = args.input_device.clone().or(config.input_device);
+    config.sample_rate = args.sample_rate.or(config.sample_rate);
+    config.all_channels |= args.all_channels;
+    config
+}
+
+fn context(config: &AudioConfig) -> Result<AudioContext, anyhow::Error> {
+    match config.host {
+        Some(ref host) => AudioContext::from_host_name(host),
+        None => Ok(AudioContext::default_host()),
+    }
+}
+
+fn open(config: &AudioConfig) -> Result<AudioInstance, anyhow::Error> {
+    // the config only sets a context for a named host, so use the default host otherwise
+    config.builder()?.context(&context(config)?).build()
+}
+
+fn list_devices(config: &AudioConfig) -> Result<(), anyhow::Error> {
+    let context = context(config)?;
+    println!("Output devices:");
+    for name in context.output_device_names()? {
+        println!("  {name}");
+    }
+    println!("Input devices:");
+    for name in context.input_device_names()? {
+        println!("  {name}");
+    }
+    Ok(())
+}
+
+/// Read a WAV file and lay its channels out on the outputs of the device.
+fn read_stimulus(
+    audio_instance: &AudioInstance,
+    path: &Path,
+    channels: &[Channel],
+) -> Result<Vec<Vec<i32>>, anyhow::Error> {
+    let file_channels = read_wave_file_channels(path, audio_instance.sample_rate())?;
+    let outputs = if channels.is_empty() {
+        (1..=file_channels.len()).map(OutputChannel).collect()
+    } else {
+        channels
+            .iter()
+            .map(|channel| audio_instance.output_channel(channel))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    let output_channels = audio_instance.channels_out() as usize;
+    let interleaved = interleave_on_channels(&file_channels, &outputs, output_channels)?;
+    Ok(split_channels(&interleaved, output_channels))
+}
+
+fn save(recording: Vec<Vec<i32>>, path: &Path, sample_rate: u32) -> Result<(), anyhow::Error> {
+    let filename = path.to_str().ok_or(anyhow::anyhow!(
+        "{} is not a valid file name",
+        path.display()
+    ))?;
+    save_channels_to_wav(recording, filename, sample_rate)?;
+    println!("Saved {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from([
+            "multichannel_audio_bin",
+            "play",
+            "sweep.wav",
+            "--channel",
+            "3",
+            "--channel",
+            "Speaker R",
+            "--sample-rate",
+            "96000",
+        ])
+        .unwrap();
+        assert_eq!(cli.device.sample_rate, Some(96000));
+        let Command::Play { wav, channels } = cli.command else {
+            panic!("expected the play command");
+        };
+        assert_eq!(wav, PathBuf::from("sweep.wav"));
+        assert_eq!(
+            channels,
+            vec![Channel::Number(3), Channel::Label("Speaker R".to_string())]
+        );
+
+        assert!(Cli::try_parse_from(["multichannel_audio_bin", "record", "5"]).is_err());
+
+        let cli =
+            Cli::try_parse_from(["multichannel_audio_bin", "record-raw", "--format", "f32le"])
+                .unwrap();
+        let Command::RecordRaw {
+            format, seconds, ..
+        } = cli.command
+        else {
+            panic!("expected the record-raw command");
+        };
+        assert_eq!(format, PcmFormat::F32Le);
+        assert_eq!(seconds, None);
+        assert!(
+            Cli::try_parse_from(["multichannel_audio_bin", "play-raw", "--format", "u8"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_device_config() {
+        let mut profile = AudioConfig::new();
+        profile.device = Some("bench interface".to_string());
+        profile.sample_rate = Some(44100);
+        let cli = Cli::try_parse_from([
+            "multichannel_audio_bin",
+            "--sample-rate",
+            "48000",
+            "list-devices",
+        ])
+        .unwrap();
+
+        let config = device_config(&profile, &cli.device);
+        assert_eq!(config.device.as_deref(), Some("bench interface"));
+        assert_eq!(config.sample_rate, Some(48000));
+        assert!(!config.all_channels);
+
+        let cli = Cli::try_parse_from(["multichannel_audio_bin", "list-devices", "--all-channels"])
+            .unwrap();
+        assert!(device_config(&profile, &cli.device).all_channels);
+    }
+}